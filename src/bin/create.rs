@@ -1,12 +1,15 @@
-use std::{
-    fs::File,
-    io::{BufRead, BufReader},
-    path::PathBuf,
-};
+use std::{fs::File, io::BufReader, path::PathBuf};
 
-use aws_config::{meta::region::RegionProviderChain, BehaviorVersion};
-use aws_sdk_s3::{config::Region, primitives::ByteStream, Client};
-use clap::Parser;
+use aws_sdk_s3::{
+    primitives::ByteStream,
+    types::{ObjectCannedAcl, ServerSideEncryption},
+};
+use clap::{builder::PossibleValuesParser, Parser};
+use s3kv::{
+    index,
+    lines::{self, BoundedLines},
+    path::Prefix,
+};
 use tracing::info;
 
 #[derive(Debug, Parser)]
@@ -15,12 +18,23 @@ struct Opt {
     #[arg(long)]
     region: String,
 
+    /// Send requests to this S3-compatible endpoint (e.g. `http://localhost:9000` for MinIO)
+    /// instead of AWS.
+    #[arg(long)]
+    endpoint_url: Option<String>,
+
+    /// Address the bucket by path (`endpoint/bucket/key`) rather than by subdomain, as MinIO and
+    /// many S3 proxies require. On by default with `--endpoint-url`; pass
+    /// `--force-path-style=false` for an endpoint that addresses buckets by subdomain.
+    #[arg(long, num_args = 0..=1, default_missing_value = "true")]
+    force_path_style: Option<bool>,
+
     /// The name of the bucket.
     #[arg(long)]
     bucket: String,
 
     #[arg(long)]
-    prefix: String,
+    prefix: Prefix,
 
     #[arg(long)]
     input: PathBuf,
@@ -28,8 +42,34 @@ struct Opt {
     #[arg(long)]
     output: PathBuf,
 
+    /// The longest input line to read. Longer lines are an error, or skipped with
+    /// `--skip-long-lines`; either way they're never held in memory.
+    #[arg(long, default_value_t = lines::DEFAULT_MAX_LINE_BYTES)]
+    max_line_bytes: usize,
+
+    /// Skip (and count) lines longer than `--max-line-bytes` instead of failing.
+    #[arg(long, default_value_t = false)]
+    skip_long_lines: bool,
+
+    /// Refuse to write into an existing, non-empty `--output`. This is the default.
+    #[arg(long, default_value_t = false, conflicts_with = "overwrite")]
+    no_clobber: bool,
+
+    /// Remove an existing `--output` before writing, instead of refusing.
+    #[arg(long, default_value_t = false)]
+    overwrite: bool,
+
     #[arg(long, default_value_t = false)]
     skip_s3: bool,
+
+    /// Canned ACL to apply to the uploaded records.
+    #[arg(long, value_parser = PossibleValuesParser::new(ObjectCannedAcl::values()))]
+    acl: Option<String>,
+
+    /// Encrypt the uploaded records with this KMS key (SSE-KMS), given as a key ID, ARN or
+    /// alias.
+    #[arg(long)]
+    sse_kms_key_id: Option<String>,
 }
 
 #[tokio::main]
@@ -38,29 +78,35 @@ async fn main() -> anyhow::Result<()> {
 
     let Opt {
         region,
+        endpoint_url,
+        force_path_style,
         bucket,
         prefix,
         input,
         output,
+        max_line_bytes,
+        skip_long_lines,
+        no_clobber: _,
+        overwrite,
         skip_s3,
+        acl,
+        sse_kms_key_id,
     } = Opt::parse();
+    let acl = acl.as_deref().map(ObjectCannedAcl::from);
 
+    index::prepare_output(&output, overwrite)?;
     let mut db_opts = rocksdb::Options::default();
     db_opts.create_if_missing(true);
     db_opts.set_compression_type(rocksdb::DBCompressionType::Zstd);
     let db = rocksdb::DB::open(&db_opts, output)?;
 
-    let region_provider = RegionProviderChain::first_try(Region::new(region));
-    let shared_config = aws_config::defaults(BehaviorVersion::v2024_03_28())
-        .region(region_provider)
-        .load()
-        .await;
-    let client = Client::new(&shared_config);
+    let client = s3kv::s3_client(&region, endpoint_url.as_deref(), force_path_style).await;
 
     info!("opening {:?}", input);
     let fin = BufReader::new(File::open(input)?);
-    for line in fin.lines() {
-        let line = line?;
+    let mut lines = BoundedLines::new(fin, max_line_bytes, skip_long_lines);
+    for entry in &mut lines {
+        let (_, line) = entry?;
         let parsed: serde_json::Value = serde_json::from_str(&line)?;
         let digest = ring::digest::digest(&ring::digest::SHA256, line.as_bytes());
         let name = hex::encode(digest.as_ref());
@@ -83,13 +129,27 @@ async fn main() -> anyhow::Result<()> {
             client
                 .put_object()
                 .bucket(&bucket)
-                .key(&format!("{}/{}", prefix, name))
+                .key(prefix.join(&name))
+                .set_acl(acl.clone())
+                .set_server_side_encryption(
+                    sse_kms_key_id
+                        .is_some()
+                        .then_some(ServerSideEncryption::AwsKms),
+                )
+                .set_ssekms_key_id(sse_kms_key_id.clone())
                 .body(ByteStream::from(line.into_bytes()))
                 .send()
                 .await
                 .unwrap();
         }
     }
+    if lines.skipped() > 0 {
+        info!(
+            "skipped {} lines longer than {} bytes",
+            lines.skipped(),
+            max_line_bytes
+        );
+    }
 
     Ok(())
 }
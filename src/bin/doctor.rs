@@ -0,0 +1,145 @@
+use aws_sdk_s3::{config::ProvideCredentials, error::SdkError, Client};
+use aws_smithy_types::error::display::DisplayErrorContext;
+use clap::Parser;
+use s3kv::{
+    blob::{Blobstore, S3Client},
+    doctor::{self, Check, Checklist},
+    path::Prefix,
+};
+
+#[derive(Debug, Parser)]
+struct Args {
+    /// The AWS Region.
+    #[arg(long)]
+    region: String,
+
+    /// Send requests to this S3-compatible endpoint (e.g. `http://localhost:9000` for MinIO)
+    /// instead of AWS.
+    #[arg(long)]
+    endpoint_url: Option<String>,
+
+    /// Address the bucket by path (`endpoint/bucket/key`) rather than by subdomain, as MinIO and
+    /// many S3 proxies require. On by default with `--endpoint-url`; pass
+    /// `--force-path-style=false` for an endpoint that addresses buckets by subdomain.
+    #[arg(long, num_args = 0..=1, default_missing_value = "true")]
+    force_path_style: Option<bool>,
+
+    /// The name of the bucket.
+    #[arg(long)]
+    bucket: String,
+
+    #[arg(long)]
+    prefix: Prefix,
+
+    /// Which index to check, as published by `etl --index-version`.
+    #[arg(long, default_value = "default")]
+    index_version: String,
+
+    /// How many of the index's records to check the blocks of.
+    #[arg(long, default_value_t = 16)]
+    sample: usize,
+}
+
+const CREDENTIALS: &str = "credentials";
+const BUCKET: &str = "bucket";
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    tracing_subscriber::fmt::init();
+
+    let args = Args::try_parse()?;
+
+    let shared_config = s3kv::load_aws_config(&args.region, args.endpoint_url.as_deref()).await;
+    let client = s3kv::s3_client_for(&shared_config, args.force_path_style);
+
+    let mut checklist = Checklist::default();
+    let credentials = match shared_config.credentials_provider() {
+        Some(provider) => provider
+            .provide_credentials()
+            .await
+            .err()
+            .map(|err| DisplayErrorContext(err).to_string()),
+        None => Some("no credentials provider is configured".to_owned()),
+    };
+    let healthy = match credentials {
+        None => {
+            checklist.push(Check::pass(CREDENTIALS, "resolved"));
+            check_bucket(&client, &args, &mut checklist).await
+        }
+        Some(err) => {
+            checklist.push(Check::fail(
+                CREDENTIALS,
+                err,
+                "set AWS_PROFILE or AWS_ACCESS_KEY_ID/AWS_SECRET_ACCESS_KEY, or run `aws sso login`",
+            ));
+            checklist.push(Check::skipped(BUCKET));
+            false
+        }
+    };
+    if healthy {
+        let blob = S3Client::new(client.clone(), &args.bucket).with_prefix(&args.prefix);
+        let dataset = doctor::check_dataset(blob, &args.index_version, args.sample).await;
+        checklist.checks.extend(dataset.checks);
+    }
+
+    print!("{}", checklist);
+    if !checklist.all_passed() {
+        anyhow::bail!("some checks failed");
+    }
+    Ok(())
+}
+
+// Checks that the bucket exists, can be read, and lives in `--region`. Returns whether it does.
+async fn check_bucket(client: &Client, args: &Args, checklist: &mut Checklist) -> bool {
+    let err = match client.head_bucket().bucket(&args.bucket).send().await {
+        Ok(_) => {
+            checklist.push(Check::pass(
+                BUCKET,
+                format!("{} is in {}", args.bucket, args.region),
+            ));
+            return true;
+        }
+        Err(err) => err,
+    };
+    let check = match &err {
+        SdkError::ServiceError(service) => {
+            let response = service.raw();
+            let actual_region = response
+                .headers()
+                .get("x-amz-bucket-region")
+                .unwrap_or("another region");
+            match response.status().as_u16() {
+                301 => Check::fail(
+                    BUCKET,
+                    format!(
+                        "{} is in {}, not {}",
+                        args.bucket, actual_region, args.region
+                    ),
+                    format!("pass --region {}", actual_region),
+                ),
+                403 => Check::fail(
+                    BUCKET,
+                    format!("access to {} is denied", args.bucket),
+                    "grant these credentials s3:ListBucket and s3:GetObject on the bucket",
+                ),
+                404 => Check::fail(
+                    BUCKET,
+                    format!("{} does not exist", args.bucket),
+                    "check the spelling of --bucket",
+                ),
+                status => Check::fail(
+                    BUCKET,
+                    format!("HEAD {} failed with status {}", args.bucket, status),
+                    "retry, or check the bucket in the S3 console",
+                ),
+            }
+        }
+        other => Check::fail(
+            BUCKET,
+            format!("couldn't reach S3: {}", other),
+            "check network access to S3 (proxies, VPC endpoints)",
+        ),
+    };
+    checklist.push(check);
+    false
+}
@@ -1,16 +1,29 @@
 use std::{
     fs::File,
-    io::{BufRead, BufReader},
+    io::{BufReader, BufWriter, Write},
     path::PathBuf,
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
-use aws_config::{meta::region::RegionProviderChain, BehaviorVersion};
-use aws_sdk_s3::{config::Region, primitives::ByteStream, Client};
-use clap::Parser;
+use anyhow::{bail, Context};
+use aws_sdk_s3::types::{ObjectCannedAcl, ServerSideEncryption, StorageClass};
+use clap::{builder::PossibleValuesParser, Parser};
 use rocksdb::SstFileWriter;
 use s3kv::{
-    blob::{Blobstore, S3Client},
-    block::{BlockWriter, S3BlockWriter, S3BlockWriterArgs},
+    blob::{self, Blobstore, CompressionDict, RetryBudget, S3Client},
+    block::{self, BlockWriter, IndexValue, S3BlockWriter, S3BlockWriterArgs},
+    bloom::{self, BloomFilter},
+    dedupe::{self, Deduper},
+    index,
+    lines::{self, BoundedLines},
+    lint::{LintArgs, Linter},
+    manifest::Manifest,
+    partition::{Partitioner, ShardedIndexWriter},
+    path::Prefix,
+    record,
+    sort::ExternalSorter,
+    sparse::{SparseIndex, SparseIndexWriter},
+    units,
 };
 use tracing::{debug, info};
 
@@ -19,19 +32,202 @@ struct Args {
     #[arg(long)]
     input: PathBuf,
 
+    /// The longest input line to read. Longer lines are an error, or skipped with
+    /// `--skip-long-lines`; either way they're never held in memory.
+    #[arg(long, default_value_t = lines::DEFAULT_MAX_LINE_BYTES)]
+    max_line_bytes: usize,
+
+    /// Skip (and count) lines longer than `--max-line-bytes` instead of failing.
+    #[arg(long, default_value_t = false)]
+    skip_long_lines: bool,
+
     /// The AWS Region.
     #[arg(long)]
     region: String,
 
+    /// Send requests to this S3-compatible endpoint (e.g. `http://localhost:9000` for MinIO)
+    /// instead of AWS.
+    #[arg(long)]
+    endpoint_url: Option<String>,
+
+    /// Address the bucket by path (`endpoint/bucket/key`) rather than by subdomain, as MinIO and
+    /// many S3 proxies require. On by default with `--endpoint-url`; pass
+    /// `--force-path-style=false` for an endpoint that addresses buckets by subdomain.
+    #[arg(long, num_args = 0..=1, default_missing_value = "true")]
+    force_path_style: Option<bool>,
+
     /// The name of the bucket.
     #[arg(long)]
     bucket: String,
 
     #[arg(long)]
-    prefix: String,
+    prefix: Prefix,
 
     #[arg(long, default_value_t = 1_000_000)]
     block_size: usize,
+
+    /// Abort once the dataset would have more than this many block objects, which usually
+    /// means `--block-size` is far too small for the input.
+    #[arg(long)]
+    max_block_objects: Option<usize>,
+
+    /// Canned ACL to apply to the uploaded blocks and index.
+    #[arg(long, value_parser = PossibleValuesParser::new(ObjectCannedAcl::values()))]
+    acl: Option<String>,
+
+    /// The S3 storage class for the uploaded blocks and index, e.g. `STANDARD_IA` or
+    /// `INTELLIGENT_TIERING` for datasets that are written once and rarely read.
+    #[arg(long, value_parser = PossibleValuesParser::new(StorageClass::values()))]
+    storage_class: Option<String>,
+
+    /// Send a `Content-MD5` header with each block and manifest upload, so S3 rejects any that
+    /// were corrupted in transit.
+    #[arg(long, default_value_t = false)]
+    content_md5: bool,
+
+    /// Encrypt everything uploaded (blocks, manifest, index) with this KMS key (SSE-KMS), given
+    /// as a key ID, ARN or alias.
+    #[arg(long)]
+    sse_kms_key_id: Option<String>,
+
+    /// Upload blocks larger than this many bytes in parts (multipart upload), several at a
+    /// time. Needed for blocks over 5 GiB, and faster well before that.
+    #[arg(long, default_value_t = blob::DEFAULT_MULTIPART_THRESHOLD)]
+    multipart_threshold: usize,
+
+    /// How many times to try each S3 request. Failures that look transient (timeouts,
+    /// throttling, 5xx) are retried with exponential backoff; others fail straight away.
+    #[arg(long, default_value_t = 3)]
+    max_attempts: usize,
+
+    /// Stop retrying failed S3 requests once retries make up more than this fraction (e.g. 0.1)
+    /// of the run's requests, so that an outage fails the run fast instead of stalling it.
+    #[arg(long)]
+    retry_budget: Option<f64>,
+
+    /// Reject any line that is not a JSON object.
+    #[arg(long, default_value_t = false)]
+    strict_json: bool,
+
+    /// A dot-separated path (e.g. `properties.BLKLOT`) that every record must contain.
+    /// May be repeated. Only checked with `--strict-json`.
+    #[arg(long, requires = "strict_json")]
+    require_field: Vec<String>,
+
+    /// The dot-separated path of the field to key each record by. If repeated, records are
+    /// keyed by the tuple of fields, encoded so that keys sort in tuple order; scan these with
+    /// `--key-input-encoding tuple`.
+    #[arg(long, default_value = "properties.BLKLOT")]
+    key_field: Vec<String>,
+
+    /// A dot-separated path (e.g. `properties.OWNER`) to remove from every record before it is
+    /// stored. May be repeated. Keys are extracted after fields are dropped.
+    #[arg(long)]
+    drop_field: Vec<String>,
+
+    /// Store each distinct record only once: a record identical to an earlier one isn't written
+    /// again, and its key points at the earlier copy.
+    #[arg(long, default_value_t = false)]
+    dedupe_records: bool,
+
+    /// How many distinct records `--dedupe-records` remembers (about 80 bytes each). Duplicates
+    /// of records it has since forgotten are stored again.
+    #[arg(long, default_value_t = dedupe::DEFAULT_CACHE_SIZE, requires = "dedupe_records")]
+    dedupe_cache_size: usize,
+
+    /// Record each key's source line number in the index alongside its location.
+    #[arg(long, default_value_t = false)]
+    store_line_number: bool,
+
+    /// Publish the index as `index/<name>.sst`. Readers pick a version with `--index-version`.
+    #[arg(long, default_value = "default")]
+    index_version: String,
+
+    /// Also publish the index in parts of at most this many entries, with a sparse index of
+    /// where each part begins, so that point lookups (e.g. `scan --contains`) download one small
+    /// part instead of the whole index. Without it, the sparse index of any parts left by an
+    /// earlier run is removed, so readers go back to the whole index.
+    #[arg(long)]
+    index_part_entries: Option<usize>,
+
+    /// Also publish the index in shards, one per shard of this partitioner that has keys
+    /// (`first-byte`, `prefix-len:<n>` or `hash:<seed>:<shards>`), and record it in the manifest
+    /// so that point lookups download only the shard a key belongs to.
+    #[arg(long)]
+    partitioner: Option<Partitioner>,
+
+    /// Store the index SST zstd-compressed (at `--compression-level`), which is recorded in the
+    /// manifest so readers decompress it after downloading. Smaller to store and download, at
+    /// the cost of decompressing it on every load.
+    #[arg(long, default_value_t = false)]
+    compress_index: bool,
+
+    /// Build the index with an external merge sort, buffering at most this many bytes of
+    /// keys and locations in memory, instead of in a temporary RocksDB.
+    #[arg(long, conflicts_with = "index_db")]
+    sort_buffer_size: Option<usize>,
+
+    /// Append to the dataset already at `--prefix` instead of replacing it, keeping its index
+    /// in a RocksDB at this directory between runs. New entries are merged into the DB, winning
+    /// over any earlier ones for the same key, and the DB is then exported as the index. If the
+    /// directory is empty, it is first seeded from the published index.
+    #[arg(long)]
+    index_db: Option<PathBuf>,
+
+    /// Mark every record as expiring this long after ingestion (e.g. `30days`, `12h`).
+    /// Readers skip expired records.
+    #[arg(long, value_parser = humantime::parse_duration)]
+    ttl: Option<Duration>,
+
+    /// End each block with a footer of record offsets, so a reader holding a block can index
+    /// straight into any record in it.
+    #[arg(long, default_value_t = false)]
+    block_footer: bool,
+
+    /// Let records larger than `--block-size` continue into the following blocks, instead of
+    /// overflowing a block of their own.
+    #[arg(long, default_value_t = false)]
+    spanning_records: bool,
+
+    /// Check the whole input for problems (bad JSON, missing or duplicate keys, records larger
+    /// than a block) and report them, without writing anything or contacting S3.
+    #[arg(long, default_value_t = false)]
+    validate_only: bool,
+
+    /// As records are ingested, write a line to this file for each of them with its key and
+    /// the location it was assigned (`key\tblock_id\toffset`), for spot-checking the index.
+    #[arg(long)]
+    emit_locations: Option<PathBuf>,
+
+    /// Print byte counts in the run summary as KiB/MiB/GiB.
+    #[arg(long, default_value_t = false)]
+    human_readable: bool,
+
+    /// The zstd level to compress blocks at (1-22, or 0 for zstd's default). Higher levels make
+    /// smaller blocks but slow down ingestion; reading is unaffected.
+    #[arg(long, default_value_t = 0, allow_negative_numbers = true)]
+    compression_level: i32,
+
+    /// Store blocks smaller than this many bytes uncompressed, as zstd's frame overhead can make
+    /// tiny blocks (typically the last one) bigger. Readers handle either kind of block.
+    #[arg(long, default_value_t = 0)]
+    min_compress_size: usize,
+
+    /// Compress blocks against this previously-trained zstd dictionary. A copy is stored with
+    /// the dataset and referenced from its manifest, so readers pick it up automatically.
+    #[arg(long)]
+    compression_dict_from: Option<PathBuf>,
+
+    /// Also publish a Bloom filter over every key in the index, as `index/keys.bloom`, so
+    /// `scan --contains` can rule out most absent keys without downloading the index. Without
+    /// it, any filter left by an earlier run is removed, since it would no longer be accurate.
+    #[arg(long, default_value_t = false)]
+    bloom_filter: bool,
+
+    /// How often the Bloom filter may answer "maybe" for an absent key. Lower rates make
+    /// larger filters: 1% costs about 10 bits per key.
+    #[arg(long, default_value_t = bloom::DEFAULT_FALSE_POSITIVE_RATE, requires = "bloom_filter")]
+    bloom_false_positive_rate: f64,
 }
 
 #[tokio::main]
@@ -40,73 +236,354 @@ async fn main() -> anyhow::Result<()> {
 
     let args = Args::try_parse()?;
 
-    let region_provider = RegionProviderChain::first_try(Region::new(args.region));
-    let shared_config = aws_config::defaults(BehaviorVersion::v2024_03_28())
-        .region(region_provider)
-        .load()
-        .await;
-    let client = Client::new(&shared_config);
+    if args.validate_only {
+        return validate(&args);
+    }
+
+    let client = s3kv::s3_client(
+        &args.region,
+        args.endpoint_url.as_deref(),
+        args.force_path_style,
+    )
+    .await;
+    let acl = args.acl.as_deref().map(ObjectCannedAcl::from);
+    let storage_class = args.storage_class.as_deref().map(StorageClass::from);
+    let sse = args
+        .sse_kms_key_id
+        .is_some()
+        .then_some(ServerSideEncryption::AwsKms);
+
+    let mut sorter = args.sort_buffer_size.map(ExternalSorter::new);
+    let expires_at = match args.ttl {
+        Some(ttl) => Some(
+            (SystemTime::now() + ttl)
+                .duration_since(UNIX_EPOCH)?
+                .as_secs(),
+        ),
+        None => None,
+    };
+
+    let s3_client = S3Client {
+        acl: acl.clone(),
+        content_md5: args.content_md5,
+        sse: sse.clone(),
+        kms_key_id: args.sse_kms_key_id.clone(),
+        storage_class: storage_class.clone(),
+        multipart_threshold: args.multipart_threshold,
+        ..S3Client::new(client.clone(), &args.bucket)
+    };
+    // Shared by every store, so that retries are counted across the whole run.
+    let budget = args
+        .retry_budget
+        .map(|ratio| RetryBudget::new(ratio, blob::DEFAULT_RETRY_BURST));
+    let with_retry = |store: S3Client| {
+        let store = store.with_retry(args.max_attempts);
+        match &budget {
+            Some(budget) => store.with_budget(budget.clone()),
+            None => store,
+        }
+    };
+    let s3 = with_retry(s3_client.clone());
+    // Labelled for the console's sake. Blocks below `--min-compress-size` are stored raw, but
+    // they're few and small.
+    let zstd = with_retry(s3_client.clone().with_content_type("application/zstd"));
+
+    let compression_dict = match &args.compression_dict_from {
+        Some(path) => {
+            let dict = CompressionDict::new(std::fs::read(path)?);
+            debug!("pushing compression dictionary {}", dict.id);
+            s3.clone()
+                .with_prefix(&args.prefix)
+                .put_if_changed(&CompressionDict::key(&dict.id), dict.data())
+                .await?;
+            Some(dict)
+        }
+        None => None,
+    };
+
+    let existing = match &args.index_db {
+        Some(_) => Manifest::load(&mut s3.clone().with_prefix(&args.prefix)).await?,
+        None => None,
+    };
+    if let Some(existing) = &existing {
+        // Appended blocks must be readable with the settings the manifest already records.
+        existing
+            .check_appendable(
+                args.block_size,
+                args.block_footer,
+                args.spanning_records,
+                compression_dict.as_ref().map(|dict| dict.id.as_str()),
+            )
+            .with_context(|| format!("can't append to {}", args.prefix))?;
+    } else if args.index_db.is_some() {
+        // Without a manifest there's no telling where the blocks end, and a fresh writer would
+        // overwrite them from block 0.
+        let first = block::block_name(0);
+        let mut blocks = s3
+            .clone()
+            .with_prefix(&args.prefix)
+            .with_prefix(&block::block_prefix());
+        if blocks.exists(first.as_str()).await? {
+            bail!(
+                "{} has blocks but no manifest, so --index-db can't append to it",
+                args.prefix
+            );
+        }
+    }
 
     let db_dir = tempfile::TempDir::new()?;
-    let mut db_opts = rocksdb::Options::default();
-    db_opts.create_if_missing(true);
-    db_opts.set_compression_type(rocksdb::DBCompressionType::Zstd);
-    let db = rocksdb::DB::open(&db_opts, db_dir.path())?;
-
-    let mut block_writer = S3BlockWriter::new(S3BlockWriterArgs {
-        client: Box::new(
-            S3Client {
-                client: client.clone(),
-                bucket: args.bucket.clone(),
+    let db_opts = index::merge_db_options();
+    let db = match &args.index_db {
+        Some(dir) => {
+            let seeded = std::fs::read_dir(dir).is_ok_and(|mut entries| entries.next().is_some());
+            if seeded || existing.is_none() {
+                rocksdb::DB::open(&db_opts, dir)?
+            } else {
+                info!("seeding {:?} from index {}.sst", dir, args.index_version);
+                let mut dataset = s3.clone().with_prefix(&args.prefix);
+                let name = index::index_key(&args.index_version)?;
+                let compressed = existing.as_ref().is_some_and(|m| m.compressed_index);
+                index::download(&mut dataset, &name, compressed, &db_opts, dir).await?
             }
-            .with_compression()
-            .with_prefix(&format!("{}/block", args.prefix)),
-        ),
+        }
+        None => rocksdb::DB::open(&db_opts, db_dir.path())?,
+    };
+
+    let blocks = zstd
+        .clone()
+        .with_compression_dict(compression_dict.clone())
+        .with_level(args.compression_level)
+        .with_min_size(args.min_compress_size)
+        .with_prefix(&args.prefix)
+        .with_prefix(&block::block_prefix());
+    debug!("writing blocks through {}", blocks.describe());
+    let block_args = S3BlockWriterArgs {
+        client: Box::new(blocks),
         block_size: args.block_size,
-    });
+        footer: args.block_footer,
+        spanning: args.spanning_records,
+        max_blocks: args.max_block_objects,
+    };
+    // Blocks stored raw by an earlier run are still there.
+    let raw_blocks = args.min_compress_size > 0 || existing.as_ref().is_some_and(|m| m.raw_blocks);
+    let mut block_writer = match existing {
+        Some(existing) => S3BlockWriter::resume(block_args, existing.blocks),
+        None => S3BlockWriter::new(block_args),
+    };
 
+    let mut locations = match &args.emit_locations {
+        Some(path) => Some(BufWriter::new(File::create(path)?)),
+        None => None,
+    };
+    let mut deduper = args
+        .dedupe_records
+        .then(|| Deduper::new(args.dedupe_cache_size))
+        .transpose()?;
+    let mut records = 0;
+    let mut record_bytes = 0;
     info!("opening {:?}", args.input);
-    let fin = BufReader::new(File::open(args.input)?);
-    for line in fin.lines() {
-        let line = line?;
-        let loc = block_writer.append(line.as_bytes()).await?;
-
-        let parsed: serde_json::Value = serde_json::from_str(&line)?;
-        let primary_key = parsed
-            .get("properties")
-            .unwrap()
-            .get("BLKLOT")
-            .unwrap()
-            .as_str()
-            .unwrap();
-        let mut write_opts = rocksdb::WriteOptions::default();
-        write_opts.disable_wal(true);
-        db.put_opt(primary_key, loc.encode(), &write_opts)?;
+    let fin = BufReader::new(File::open(&args.input)?);
+    let mut lines = BoundedLines::new(fin, args.max_line_bytes, args.skip_long_lines);
+    for entry in &mut lines {
+        let (line_number, mut line) = entry?;
+        let mut parsed: serde_json::Value = serde_json::from_str(&line)?;
+        if !args.drop_field.is_empty() {
+            record::drop_fields(&mut parsed, &args.drop_field);
+            line = serde_json::to_string(&parsed)?;
+        }
+        if args.strict_json {
+            record::validate(&parsed, &args.require_field)
+                .with_context(|| format!("invalid record on line {}", line_number))?;
+        }
+        let loc = match &mut deduper {
+            Some(deduper) => deduper.append(&mut block_writer, line.as_bytes()).await?,
+            None => block_writer.append(line.as_bytes()).await?,
+        };
+        records += 1;
+        record_bytes += line.len() as u64;
+
+        let primary_key = record::key(&parsed, &args.key_field)
+            .with_context(|| format!("invalid key on line {}", line_number))?;
+        if let Some(locations) = &mut locations {
+            locations.write_all(block::location_line(&primary_key, &loc).as_bytes())?;
+        }
+        let value = IndexValue {
+            location: loc,
+            line_number: args.store_line_number.then_some(line_number),
+            expires_at,
+        };
+        if let Some(sorter) = &mut sorter {
+            sorter.push(&primary_key, &value.encode())?;
+        } else {
+            let mut write_opts = rocksdb::WriteOptions::default();
+            write_opts.disable_wal(true);
+            if args.index_db.is_some() {
+                db.merge_opt(&primary_key, value.encode(), &write_opts)?;
+            } else {
+                db.put_opt(&primary_key, value.encode(), &write_opts)?;
+            }
+        }
+    }
+    if lines.skipped() > 0 {
+        info!(
+            "skipped {} lines longer than {} bytes",
+            lines.skipped(),
+            args.max_line_bytes
+        );
+    }
+    if let Some(deduper) = &deduper {
+        info!(
+            "deduplicated {} of {} records",
+            deduper.deduplicated(),
+            records
+        );
     }
     block_writer.flush().await?;
     db.flush()?;
+    if let Some(mut locations) = locations {
+        locations.flush()?;
+    }
 
+    let manifest = Manifest {
+        block_size: args.block_size,
+        blocks: block_writer.blocks().to_vec(),
+        compression_dict: compression_dict.map(|dict| dict.id),
+        block_footer: args.block_footer,
+        spanning_records: args.spanning_records,
+        compressed_index: args.compress_index,
+        raw_blocks,
+        partitioner: args.partitioner,
+    };
+    let mut dataset = s3.clone().with_prefix(&args.prefix);
+
+    // Appending merges into keys from earlier runs, so only the DB knows how many there are.
+    let mut filter = args.bloom_filter.then(|| {
+        let num_keys = match &args.index_db {
+            Some(_) => db.iterator(rocksdb::IteratorMode::Start).count(),
+            None => records,
+        };
+        BloomFilter::new(num_keys, args.bloom_false_positive_rate)
+    });
     debug!("rewriting index");
     let index_file = tempfile::NamedTempFile::new()?;
     let mut index_writer = SstFileWriter::create(&db_opts);
     index_writer.open(index_file.path())?;
-    for entry in db.iterator(rocksdb::IteratorMode::Start) {
-        let (k, v) = entry?;
-        index_writer.put(k, v)?;
+    let mut parts = match args.index_part_entries {
+        Some(entries_per_part) => Some(SparseIndexWriter::new(entries_per_part, db_opts.clone())?),
+        None => None,
+    };
+    let mut shards = match args.partitioner {
+        Some(partitioner) => Some(ShardedIndexWriter::new(partitioner, &db_opts)?),
+        None => None,
+    };
+    let mut write = |k: &[u8], v: &[u8]| -> anyhow::Result<()> {
+        if let Some(filter) = &mut filter {
+            filter.insert(k);
+        }
+        if let Some(parts) = &mut parts {
+            parts.push(k, v)?;
+        }
+        if let Some(shards) = &mut shards {
+            shards.push(k, v)?;
+        }
+        Ok(index_writer.put(k, v)?)
+    };
+    if let Some(sorter) = sorter {
+        for entry in sorter.finish()? {
+            let (k, v) = entry?;
+            write(&k, &v)?;
+        }
+    } else {
+        for entry in db.iterator(rocksdb::IteratorMode::Start) {
+            let (k, v) = entry?;
+            write(&k, &v)?;
+        }
     }
     index_writer.finish()?;
-    debug!("pushing index default.sst");
-    let index_body = ByteStream::read_from()
-        .path(index_file.path())
-        .build()
-        .await?;
-    client
-        .put_object()
-        .bucket(&args.bucket)
-        .key(format!("{}/index/default.sst", args.prefix))
-        .body(index_body)
-        .send()
-        .await?;
+    let index_name = index::index_key(&args.index_version)?;
+    debug!("pushing index {}", index_name);
+    // The SST is read whole rather than streamed from the file: a stream can't be replayed, so
+    // it couldn't be retried or uploaded in parts past `--multipart-threshold`.
+    let index_body = std::fs::read(index_file.path())?;
+    if args.compress_index {
+        let mut index_store = zstd
+            .with_compression_level(args.compression_level)
+            .with_prefix(&args.prefix);
+        index_store.put_owned(&index_name, index_body).await?;
+    } else {
+        let mut index_store = with_retry(s3_client.with_content_type("application/octet-stream"))
+            .with_prefix(&args.prefix);
+        index_store.put_owned(&index_name, index_body).await?;
+    }
+    match filter {
+        Some(filter) => {
+            debug!("pushing {}", BloomFilter::KEY);
+            dataset.put(BloomFilter::KEY, &filter.encode()).await?;
+        }
+        None if dataset.exists(BloomFilter::KEY).await? => {
+            info!("removing the stale {}", BloomFilter::KEY);
+            dataset.delete(BloomFilter::KEY).await?;
+        }
+        None => {}
+    }
+    let sparse_key = SparseIndex::key(&args.index_version)?;
+    match parts {
+        Some(parts) => {
+            let index = parts.publish(&mut dataset, &args.index_version).await?;
+            info!("published the index in {} parts", index.first_keys.len());
+        }
+        None if dataset.exists(&sparse_key).await? => {
+            info!("removing the stale {}", sparse_key);
+            dataset.delete(&sparse_key).await?;
+        }
+        None => {}
+    }
+    if let Some(shards) = shards {
+        let published = shards.publish(&mut dataset, &args.index_version).await?;
+        info!("published the index in {} shards", published);
+    }
+    // Last, so that a manifest never lists blocks the published index doesn't reference. If an
+    // append fails before this, a rerun resumes after the old manifest's blocks, overwriting the
+    // failed run's blocks rather than appending the records a second time.
+    debug!("pushing {}", Manifest::KEY);
+    dataset.put(Manifest::KEY, &manifest.encode()?).await?;
 
+    info!(
+        "ingested {} records ({}) into {} blocks",
+        records,
+        units::format_bytes(record_bytes, args.human_readable),
+        manifest.blocks.len()
+    );
+    Ok(())
+}
+
+fn validate(args: &Args) -> anyhow::Result<()> {
+    let mut linter = Linter::new(LintArgs {
+        key_fields: args.key_field.clone(),
+        drop_fields: args.drop_field.clone(),
+        strict_json: args.strict_json,
+        required_fields: args.require_field.clone(),
+        // Spanning records can be any size.
+        max_record_size: if args.spanning_records {
+            usize::MAX
+        } else {
+            args.block_size
+        },
+    });
+    info!("validating {:?}", args.input);
+    let fin = BufReader::new(File::open(&args.input)?);
+    // Every over-long line is reported, so they're always skipped here.
+    let mut lines = BoundedLines::new(fin, args.max_line_bytes, true);
+    for entry in &mut lines {
+        let (line_number, line) = entry?;
+        linter.oversized_through(line_number - 1);
+        linter.check(&line);
+    }
+    linter.oversized_through(lines.line_number());
+    let report = linter.finish();
+    print!("{}", report);
+    if !report.is_clean() {
+        bail!("{:?} has {} problems", args.input, report.num_problems());
+    }
     Ok(())
 }
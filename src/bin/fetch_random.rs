@@ -1,16 +1,23 @@
-use std::{collections::HashMap, io::Write, time::Instant};
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    time::{Duration, Instant},
+};
 
-use aws_config::{meta::region::RegionProviderChain, BehaviorVersion};
-use aws_sdk_s3::{config::Region, Client};
+use anyhow::anyhow;
 use clap::Parser;
-use hdrhistogram::Histogram;
 use rand::{seq::SliceRandom, SeedableRng};
 use rocksdb::IteratorMode;
 use s3kv::{
     blob::{Blobstore, S3Client},
-    block::{BlockReader, Location, S3BlockReader, S3BlockReaderArgs},
+    block::{self, BlockReader, Location, S3BlockReader, S3BlockReaderArgs},
+    index,
+    manifest::Manifest,
+    path::Prefix,
+    stats::{Interval, LatencyStats},
+    units,
 };
-use tracing::debug;
+use tracing::{debug, info, warn};
 
 #[derive(Debug, Parser)]
 struct Args {
@@ -18,18 +25,57 @@ struct Args {
     #[arg(long)]
     region: String,
 
+    /// Send requests to this S3-compatible endpoint (e.g. `http://localhost:9000` for MinIO)
+    /// instead of AWS.
+    #[arg(long)]
+    endpoint_url: Option<String>,
+
+    /// Address the bucket by path (`endpoint/bucket/key`) rather than by subdomain, as MinIO and
+    /// many S3 proxies require. On by default with `--endpoint-url`; pass
+    /// `--force-path-style=false` for an endpoint that addresses buckets by subdomain.
+    #[arg(long, num_args = 0..=1, default_missing_value = "true")]
+    force_path_style: Option<bool>,
+
     /// The name of the bucket.
     #[arg(long)]
     bucket: String,
 
     #[arg(long)]
-    prefix: String,
+    prefix: Prefix,
 
     #[arg(long, default_value_t = 1_000_000)]
     block_size: usize,
 
+    /// How many blocks to keep cached in memory (0 for none).
     #[arg(long, default_value_t = 0)]
     cache_size: usize,
+
+    /// Before measuring, load blocks into the cache until it's full, so the stats reflect
+    /// warm-cache reads.
+    #[arg(long, default_value_t = false)]
+    prewarm: bool,
+
+    /// Which index to read, as published by `etl --index-version`.
+    #[arg(long, default_value = "default")]
+    index_version: String,
+
+    /// Keep the ingested index here, keyed by its ETag, so later runs against an unchanged
+    /// index skip the download and ingest.
+    #[arg(long)]
+    index_cache_dir: Option<PathBuf>,
+
+    /// The most files the local copy of the index may hold open at once (-1 for no limit).
+    #[arg(long, default_value_t = index::DEFAULT_MAX_OPEN_FILES, allow_negative_numbers = true)]
+    rocksdb_max_open_files: i32,
+
+    /// Read the index from this local SST instead of downloading it. Blocks are still read
+    /// from S3.
+    #[arg(long, conflicts_with_all = ["index_cache_dir", "index_version"])]
+    index_file: Option<PathBuf>,
+
+    /// Report latency stats every this many seconds, rather than after every 100 fetches.
+    #[arg(long)]
+    stats_interval: Option<u64>,
 }
 
 #[tokio::main]
@@ -38,36 +84,74 @@ async fn main() -> anyhow::Result<()> {
 
     let args = Args::try_parse()?;
 
-    let region_provider = RegionProviderChain::first_try(Region::new(args.region));
-    let shared_config = aws_config::defaults(BehaviorVersion::v2024_03_28())
-        .region(region_provider)
-        .load()
-        .await;
-    let client = Client::new(&shared_config);
-    let mut blob = S3Client {
-        client,
-        bucket: args.bucket,
-    }
-    .with_prefix(&args.prefix);
-
+    let client = s3kv::s3_client(
+        &args.region,
+        args.endpoint_url.as_deref(),
+        args.force_path_style,
+    )
+    .await;
+    let metered = S3Client::new(client.clone(), &args.bucket).with_metrics();
+    let mut blob = metered.clone().with_prefix(&args.prefix);
+
+    let manifest = Manifest::load(&mut blob).await?;
+    let compressed = manifest
+        .as_ref()
+        .is_some_and(|manifest| manifest.compressed_index);
     let db_dir = tempfile::TempDir::new()?;
-    let mut db_opts = rocksdb::Options::default();
-    db_opts.create_if_missing(true);
-    db_opts.set_compression_type(rocksdb::DBCompressionType::Zstd);
+    let mut db_opts = index::reader_db_options(args.rocksdb_max_open_files);
     db_opts.set_use_direct_reads(true);
-    let db = rocksdb::DB::open(&db_opts, db_dir.path())?;
-
-    debug!("downloading index default.sst");
-    let index_body = blob.must_get("index/default.sst").await?;
-    let mut index_file = tempfile::NamedTempFile::new()?;
-    index_file.write_all(&index_body)?;
-    index_file.flush()?;
-    debug!("ingesting index default.sst");
-    db.ingest_external_file(vec![index_file.path()])?;
-
+    let index_name = index::index_key(&args.index_version)?;
+    let db = match (&args.index_file, &args.index_cache_dir) {
+        (Some(index_file), _) => index::ingest(index_file, &db_opts, db_dir.path())?,
+        (None, Some(cache_dir)) => {
+            let index_key = args.prefix.join(&index_name);
+            let etag = index::etag(&client, &args.bucket, &index_key)
+                .await?
+                .ok_or_else(|| anyhow!("index {} has no ETag", index_key))?;
+            index::open_cached(
+                &mut blob,
+                &index_name,
+                compressed,
+                &etag,
+                &db_opts,
+                cache_dir,
+            )
+            .await?
+        }
+        (None, None) => {
+            index::download(&mut blob, &index_name, compressed, &db_opts, db_dir.path()).await?
+        }
+    };
+
+    let compression_dict = match &manifest {
+        Some(manifest) => manifest.compression_dict(&mut blob).await?,
+        None => None,
+    };
+    let footer = manifest
+        .as_ref()
+        .is_some_and(|manifest| manifest.block_footer);
+    let spanning = manifest
+        .as_ref()
+        .is_some_and(|manifest| manifest.spanning_records);
+    let blocks = blob
+        .with_prefix(&block::block_prefix())
+        .with_compression_dict(compression_dict);
+    let blocks: Box<dyn Blobstore> = if args.cache_size > 0 {
+        Box::new(blocks.with_caching(args.cache_size))
+    } else {
+        Box::new(blocks)
+    };
+    debug!("reading blocks through {}", blocks.describe());
     let mut block_reader = S3BlockReader::new(S3BlockReaderArgs {
-        client: Box::new(blob.with_prefix("block").with_compression()),
+        client: blocks,
+        footer,
+        spanning,
     });
+    if args.prewarm {
+        let block_ids: Option<Vec<usize>> =
+            manifest.map(|manifest| manifest.blocks.iter().map(|b| b.block_id).collect());
+        prewarm(&mut block_reader, block_ids, args.cache_size).await?;
+    }
 
     let mut samples = HashMap::new();
     for entry in db.iterator(IteratorMode::Start) {
@@ -78,23 +162,66 @@ async fn main() -> anyhow::Result<()> {
     let samples: Vec<Vec<u8>> = samples.into_values().collect();
 
     let mut prng = rand::rngs::SmallRng::seed_from_u64(42);
-    let mut hist: Histogram<u32> = Histogram::new(5)?;
+    let mut stats = LatencyStats::new(Instant::now())?;
+    let mut interval = args
+        .stats_interval
+        .map(|secs| Interval::new(Duration::from_secs(secs), Instant::now()));
     loop {
-        for _ in 0..100 {
-            let start = Instant::now();
+        let start = Instant::now();
 
-            if let Some(v) = db.get(samples.choose(&mut prng).unwrap())? {
-                let loc = Location::decode(&v)?;
-                let _ = block_reader.fetch(&loc).await?;
-            }
+        if let Some(v) = db.get(samples.choose(&mut prng).unwrap())? {
+            let loc = Location::decode(&v)?;
+            let _ = block_reader.fetch(&loc).await?;
+        }
 
-            hist.record(start.elapsed().as_nanos() as u64)?;
+        let now = Instant::now();
+        stats.record(now - start)?;
+        let due = match &mut interval {
+            Some(interval) => interval.tick(now),
+            None => stats.window_len() >= 100,
+        };
+        if due {
+            match block_reader.cache_stats() {
+                Some(cache) => debug!(
+                    "{}; {}; cache: {}",
+                    stats.report(now),
+                    metered.snapshot(),
+                    cache
+                ),
+                None => debug!("{}; {}", stats.report(now), metered.snapshot()),
+            }
         }
-        debug!(
-            "fetches={} mean={:.1}us p99={:.1}us",
-            hist.len(),
-            hist.mean() * 1e-3,
-            hist.value_at_quantile(0.99) as f64 * 1e-3
-        );
     }
 }
+
+// Loads as many blocks as the cache holds (all of `block_ids`, if known), warning if that
+// leaves some out.
+async fn prewarm(
+    block_reader: &mut S3BlockReader,
+    block_ids: Option<Vec<usize>>,
+    capacity: usize,
+) -> anyhow::Result<()> {
+    let start = Instant::now();
+    let prewarmed = match block_ids {
+        Some(block_ids) => {
+            if block_ids.len() > capacity {
+                warn!(
+                    "the cache holds {} blocks, so only that many of {} will be prewarmed",
+                    capacity,
+                    block_ids.len()
+                );
+            }
+            block_reader
+                .prewarm(block_ids.into_iter().take(capacity))
+                .await?
+        }
+        None => block_reader.prewarm(0..capacity).await?,
+    };
+    info!(
+        "prewarmed {} blocks ({}) in {:?}",
+        prewarmed.blocks,
+        units::human_bytes(prewarmed.bytes),
+        start.elapsed()
+    );
+    Ok(())
+}
@@ -6,8 +6,11 @@
 #![allow(clippy::result_large_err)]
 
 use aws_config::{meta::region::RegionProviderChain, BehaviorVersion};
+use std::time::{Duration, SystemTime};
+
 use aws_sdk_s3::{config::Region, meta::PKG_VERSION, Client, Error};
 use clap::Parser;
+use s3kv::{units, watch::Listing};
 
 #[derive(Debug, Parser)]
 struct Opt {
@@ -22,6 +25,27 @@ struct Opt {
     /// Whether to display additional information.
     #[arg(short, long)]
     verbose: bool,
+
+    /// Print the number and total size of the objects instead of listing them.
+    #[arg(long)]
+    summary: bool,
+
+    /// Print sizes as KiB/MiB/GiB rather than raw byte counts.
+    #[arg(long)]
+    human_readable: bool,
+
+    /// Re-list the objects every `--interval` seconds and print what changed since the last
+    /// poll, e.g. to watch `etl` write a dataset. Runs until interrupted.
+    #[arg(long, conflicts_with = "summary")]
+    watch: bool,
+
+    /// Seconds between polls with `--watch`.
+    #[arg(long, default_value_t = 5, requires = "watch")]
+    interval: u64,
+
+    /// Only watch objects under this prefix.
+    #[arg(long, requires = "watch")]
+    prefix: Option<String>,
 }
 
 // Lists the objects in a bucket.
@@ -37,6 +61,61 @@ async fn show_objects(client: &Client, bucket: &str) -> Result<(), Error> {
 }
 // snippet-end:[s3.rust.list-objects]
 
+async fn summarize_objects(
+    client: &Client,
+    bucket: &str,
+    human_readable: bool,
+) -> Result<(), Error> {
+    let mut pages = client
+        .list_objects_v2()
+        .bucket(bucket)
+        .into_paginator()
+        .send();
+    let mut count = 0;
+    let mut total_bytes = 0;
+    while let Some(page) = pages.next().await {
+        for object in page?.contents() {
+            count += 1;
+            total_bytes += object.size().unwrap_or_default() as u64;
+        }
+    }
+    println!("Objects:           {}", count);
+    println!(
+        "Total size:        {}",
+        units::format_bytes(total_bytes, human_readable)
+    );
+    Ok(())
+}
+
+async fn watch_objects(
+    client: &Client,
+    bucket: &str,
+    prefix: Option<&str>,
+    interval: Duration,
+    human_readable: bool,
+) -> anyhow::Result<()> {
+    let mut prev = Listing::fetch(client, bucket, prefix).await?;
+    println!(
+        "{} {} objects, {}",
+        humantime::format_rfc3339_seconds(SystemTime::now()),
+        prev.objects.len(),
+        units::format_bytes(prev.total_bytes(), human_readable)
+    );
+    loop {
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => return Ok(()),
+            _ = tokio::time::sleep(interval) => {}
+        }
+        let listing = Listing::fetch(client, bucket, prefix).await?;
+        println!(
+            "{} {}",
+            humantime::format_rfc3339_seconds(SystemTime::now()),
+            listing.since(&prev).summary(human_readable)
+        );
+        prev = listing;
+    }
+}
+
 /// Lists the objects in an Amazon S3 bucket.
 /// # Arguments
 ///
@@ -48,13 +127,18 @@ async fn show_objects(client: &Client, bucket: &str) -> Result<(), Error> {
 ///   If the environment variable is not set, defaults to **us-west-2**.
 /// * `[-v]` - Whether to display additional information.
 #[tokio::main]
-async fn main() -> Result<(), Error> {
+async fn main() -> anyhow::Result<()> {
     tracing_subscriber::fmt::init();
 
     let Opt {
         region,
         bucket,
         verbose,
+        summary,
+        human_readable,
+        watch,
+        interval,
+        prefix,
     } = Opt::parse();
 
     let region_provider = RegionProviderChain::first_try(Region::new(region));
@@ -77,5 +161,19 @@ async fn main() -> Result<(), Error> {
         .await;
     let client = Client::new(&shared_config);
 
-    show_objects(&client, &bucket).await
+    if watch {
+        let interval = Duration::from_secs(interval);
+        watch_objects(
+            &client,
+            &bucket,
+            prefix.as_deref(),
+            interval,
+            human_readable,
+        )
+        .await
+    } else if summary {
+        Ok(summarize_objects(&client, &bucket, human_readable).await?)
+    } else {
+        Ok(show_objects(&client, &bucket).await?)
+    }
 }
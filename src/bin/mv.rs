@@ -0,0 +1,77 @@
+use clap::Parser;
+use s3kv::{
+    blob::{Blobstore, S3Client},
+    path::Prefix,
+};
+use tracing::{debug, info};
+
+/// Moves every object under one prefix to another, e.g. to promote a staged dataset.
+#[derive(Debug, Parser)]
+struct Args {
+    /// The AWS Region.
+    #[arg(long)]
+    region: String,
+
+    /// Send requests to this S3-compatible endpoint (e.g. `http://localhost:9000` for MinIO)
+    /// instead of AWS.
+    #[arg(long)]
+    endpoint_url: Option<String>,
+
+    /// Address the bucket by path (`endpoint/bucket/key`) rather than by subdomain, as MinIO and
+    /// many S3 proxies require. On by default with `--endpoint-url`; pass
+    /// `--force-path-style=false` for an endpoint that addresses buckets by subdomain.
+    #[arg(long, num_args = 0..=1, default_missing_value = "true")]
+    force_path_style: Option<bool>,
+
+    /// The name of the bucket.
+    #[arg(long)]
+    bucket: String,
+
+    /// The prefix to move objects from.
+    #[arg(long)]
+    from: Prefix,
+
+    /// The prefix to move objects to.
+    #[arg(long)]
+    to: Prefix,
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    tracing_subscriber::fmt::init();
+
+    let args = Args::try_parse()?;
+
+    let client = s3kv::s3_client(
+        &args.region,
+        args.endpoint_url.as_deref(),
+        args.force_path_style,
+    )
+    .await;
+    let mut blob = S3Client::new(client.clone(), &args.bucket);
+
+    let from = format!("{}/", args.from);
+    let mut pages = client
+        .list_objects_v2()
+        .bucket(&args.bucket)
+        .prefix(&from)
+        .into_paginator()
+        .send();
+    let mut keys = Vec::new();
+    while let Some(page) = pages.next().await {
+        for object in page?.contents() {
+            keys.extend(object.key().map(str::to_owned));
+        }
+    }
+
+    for src in &keys {
+        let Some(rel) = args.from.strip(src) else {
+            continue;
+        };
+        let dst = args.to.join(rel);
+        debug!("moving {} to {}", src, dst);
+        blob.rename(src, &dst).await?;
+    }
+    info!("moved {} objects", keys.len());
+    Ok(())
+}
@@ -1,14 +1,33 @@
-use std::io::Write;
+use std::{
+    collections::HashMap,
+    io::Write,
+    path::PathBuf,
+    time::{Duration, Instant, SystemTime},
+};
 
-use aws_config::{meta::region::RegionProviderChain, BehaviorVersion};
-use aws_sdk_s3::{config::Region, Client};
+use anyhow::{anyhow, bail};
+use aws_sdk_s3::Client;
 use clap::Parser;
-use rocksdb::{IteratorMode, ReadOptions};
 use s3kv::{
-    blob::{Blobstore, S3Client},
-    block::{BlockReader, Location, S3BlockReader, S3BlockReaderArgs},
+    blob::{
+        AdaptiveCacheArgs, Blobstore, CacheStats, KeyStats, RetryBudget, S3Client,
+        DEFAULT_RETRY_BURST,
+    },
+    block::{self, BlockReader, IndexValue, S3BlockReader, S3BlockReaderArgs},
+    bloom::{BloomFilter, Membership},
+    index::{self, Follower},
+    key::{self, KeyEncoding},
+    limit::{ByteLimit, LimitUnit},
+    manifest::Manifest,
+    output::{self, OutputFormat, Separator},
+    path::Prefix,
+    sparse::SparseIndex,
+    units,
 };
-use tracing::debug;
+use tracing::{debug, info, warn};
+
+// How many blocks the cache holds, unless it's sized with `--target-hit-ratio`.
+const CACHE_BLOCKS: usize = 16;
 
 #[derive(Debug, Parser)]
 struct Args {
@@ -16,12 +35,23 @@ struct Args {
     #[arg(long)]
     region: String,
 
+    /// Send requests to this S3-compatible endpoint (e.g. `http://localhost:9000` for MinIO)
+    /// instead of AWS.
+    #[arg(long)]
+    endpoint_url: Option<String>,
+
+    /// Address the bucket by path (`endpoint/bucket/key`) rather than by subdomain, as MinIO and
+    /// many S3 proxies require. On by default with `--endpoint-url`; pass
+    /// `--force-path-style=false` for an endpoint that addresses buckets by subdomain.
+    #[arg(long, num_args = 0..=1, default_missing_value = "true")]
+    force_path_style: Option<bool>,
+
     /// The name of the bucket.
     #[arg(long)]
     bucket: String,
 
     #[arg(long)]
-    prefix: String,
+    prefix: Prefix,
 
     #[arg(long, default_value_t = 1_000_000)]
     block_size: usize,
@@ -32,11 +62,172 @@ struct Args {
     #[arg(long)]
     end: Option<String>,
 
+    /// Only scan keys starting with this prefix (spelled per `--key-input-encoding`).
+    #[arg(long, conflicts_with_all = ["start", "end", "no_index"])]
+    key_prefix: Option<String>,
+
+    /// Scan from the largest key down, e.g. to get the most recent of some timestamped keys.
+    #[arg(long, default_value_t = false, conflicts_with_all = ["follow", "partitions", "into_db", "no_index"])]
+    reverse: bool,
+
+    /// Stop after emitting this many records.
+    #[arg(long, conflicts_with_all = ["partitions", "into_db"])]
+    limit: Option<usize>,
+
     #[arg(long, default_value_t = false)]
     keys_only: bool,
 
     #[arg(long, default_value_t = false)]
     quiet: bool,
+
+    /// After reaching the end of the index, keep polling it for changes and emit keys that
+    /// sort after the last one emitted, like `tail -f`. Appended keys that sort before it are
+    /// never emitted.
+    #[arg(long, default_value_t = false)]
+    follow: bool,
+
+    /// Seconds between index checks in `--follow` mode.
+    #[arg(long, default_value_t = 5)]
+    poll_interval: u64,
+
+    /// Abort the scan (with a non-zero exit) if it hasn't finished within this many seconds.
+    #[arg(long)]
+    deadline: Option<u64>,
+
+    /// Which index to read, as published by `etl --index-version`.
+    #[arg(long, default_value = "default")]
+    index_version: String,
+
+    /// List the published index versions and exit.
+    #[arg(long, default_value_t = false)]
+    list_index_versions: bool,
+
+    /// Print whether the dataset has a record for this key (spelled per
+    /// `--key-input-encoding`) and exit. If the dataset has a Bloom filter (`etl --bloom-filter`),
+    /// most absent keys are ruled out without downloading the index; if it has a sparse index
+    /// (`etl --index-part-entries`) or was sharded (`etl --partitioner`), only one part or shard
+    /// of the index is downloaded.
+    #[arg(long)]
+    contains: Option<String>,
+
+    /// Also emit records whose TTL has passed.
+    #[arg(long, default_value_t = false)]
+    include_expired: bool,
+
+    /// How `--start`, `--end` and `--key-prefix` are spelled: `utf8`, `hex`, `base64`, or
+    /// `tuple` (comma-separated segments of a composite key).
+    #[arg(long, default_value_t = KeyEncoding::Utf8)]
+    key_input_encoding: KeyEncoding,
+
+    /// Keep the ingested index here, keyed by its ETag, so later runs against an unchanged
+    /// index skip the download and ingest.
+    #[arg(long)]
+    index_cache_dir: Option<PathBuf>,
+
+    /// The most files the local copy of the index may hold open at once (-1 for no limit).
+    #[arg(long, default_value_t = index::DEFAULT_MAX_OPEN_FILES, allow_negative_numbers = true)]
+    rocksdb_max_open_files: i32,
+
+    /// Read the index from this local SST instead of downloading it. Blocks are still read
+    /// from S3. The SST must be uncompressed, even if `etl --compress-index` stored it
+    /// compressed.
+    #[arg(long, conflicts_with_all = ["index_cache_dir", "follow", "index_version"])]
+    index_file: Option<PathBuf>,
+
+    /// Ignore the index and dump every record by walking the blocks in order, keyed by
+    /// `block_id:offset`. For recovering a dataset whose index is lost. Only `--limit` and
+    /// `--deadline` bound the dump.
+    #[arg(long, default_value_t = false, conflicts_with_all = ["keys_only", "follow", "start", "end", "limit_bytes", "into_db", "partitions", "prewarm", "checksum_retries", "io_stats", "stats"])]
+    no_index: bool,
+
+    /// Bound the block cache by the total size of the blocks it holds rather than by count,
+    /// for datasets whose blocks vary a lot in size. Blocks bigger than this aren't cached.
+    #[arg(long, conflicts_with_all = ["target_hit_ratio", "prewarm"])]
+    cache_bytes: Option<usize>,
+
+    /// Size the block cache automatically, between `--cache-min` and `--cache-max` blocks,
+    /// aiming for this hit ratio. Without it, the cache holds a fixed 16 blocks.
+    #[arg(long, requires_all = ["cache_min", "cache_max"])]
+    target_hit_ratio: Option<f64>,
+
+    #[arg(long, requires = "target_hit_ratio")]
+    cache_min: Option<usize>,
+
+    #[arg(long, requires = "target_hit_ratio")]
+    cache_max: Option<usize>,
+
+    /// Check every block against the SHA-256 in the manifest, re-fetching a mismatched block
+    /// up to this many times before failing.
+    #[arg(long)]
+    checksum_retries: Option<usize>,
+
+    /// Stop re-fetching mismatched blocks once re-fetches make up more than this fraction
+    /// (e.g. 0.1) of the run's block reads, so that widespread corruption fails fast.
+    #[arg(long, requires = "checksum_retries")]
+    retry_budget: Option<f64>,
+
+    /// Split the key range into this many partitions of about equal size, each scanned
+    /// concurrently with its own block cache. Output from different partitions is interleaved,
+    /// but each partition's records come out in key order.
+    #[arg(long, default_value_t = 1, conflicts_with = "follow")]
+    partitions: usize,
+
+    /// Instead of printing records, copy them into a local RocksDB at this path as
+    /// `key -> record`. Keys already in it are skipped, so an interrupted copy can be resumed by
+    /// rerunning. Use `--partitions` to fetch concurrently.
+    #[arg(long, conflicts_with = "keys_only")]
+    into_db: Option<PathBuf>,
+
+    /// Stop once this many bytes have been emitted. The record that crosses the limit is
+    /// still emitted.
+    #[arg(long, conflicts_with = "into_db")]
+    limit_bytes: Option<u64>,
+
+    /// What `--limit-bytes` counts: `output` (the printed lines, even with `--quiet`) or
+    /// `records` (the raw records; `--keys-only` fetches none).
+    #[arg(long, default_value_t = LimitUnit::Output)]
+    limit_bytes_of: LimitUnit,
+
+    /// `text` prints `key -> record` lines; `framed` writes each key and record preceded by its
+    /// length as a LEB128 varint, which survives records containing any bytes at all (see
+    /// `s3kv::output::read_framed`). With `--keys-only`, framed records are empty.
+    #[arg(long, default_value_t = OutputFormat::Text)]
+    output_format: OutputFormat,
+
+    /// What `text` output writes after each entry instead of a newline, e.g. `\0`.
+    #[arg(long, default_value = "\\n")]
+    output_separator: Separator,
+
+    /// Before scanning, load blocks into the cache until it's full (`--cache-min` blocks, with
+    /// `--target-hit-ratio`), so reads are measured against a warm cache.
+    #[arg(long, default_value_t = false, conflicts_with = "partitions")]
+    prewarm: bool,
+
+    /// Instead of emitting records, print a line for each block holding any of the range's
+    /// records: `block_id`, how many of them it holds, and the smallest and largest of their
+    /// offsets, separated by tabs. Only the index is read, so this is a cheap way to see how
+    /// many blocks (and so reads) a scan of the range would cost.
+    #[arg(long, default_value_t = false, conflicts_with_all = ["follow", "partitions", "into_db", "no_index", "prewarm"])]
+    group_by_block: bool,
+
+    /// When done, print to stderr how many requests the scan made to S3 and how many bytes
+    /// they moved, and how often blocks were served from the cache instead.
+    #[arg(long, default_value_t = false)]
+    io_stats: bool,
+
+    /// When done, print to stderr a line for each block that was downloaded: `block_id`, how
+    /// many times it was, and the bytes downloaded and decompressed, separated by tabs. Blocks
+    /// are listed biggest download first, so skewed block sizes and cache thrash stand out.
+    #[arg(long, default_value_t = false)]
+    stats: bool,
+}
+
+/// The downloads of one block, as `--stats` reports them.
+#[derive(Debug, Default)]
+struct BlockStats {
+    fetches: u64,
+    bytes: u64,
+    decompressed_bytes: u64,
 }
 
 #[tokio::main]
@@ -45,64 +236,554 @@ async fn main() -> anyhow::Result<()> {
 
     let args = Args::try_parse()?;
 
-    let region_provider = RegionProviderChain::first_try(Region::new(args.region));
-    let shared_config = aws_config::defaults(BehaviorVersion::v2024_03_28())
-        .region(region_provider)
-        .load()
-        .await;
-    let client = Client::new(&shared_config);
-    let mut blob = S3Client {
-        client,
-        bucket: args.bucket,
+    let client = s3kv::s3_client(
+        &args.region,
+        args.endpoint_url.as_deref(),
+        args.force_path_style,
+    )
+    .await;
+
+    if args.list_index_versions {
+        for version in list_index_versions(&client, &args.bucket, &args.prefix).await? {
+            println!("{}", version);
+        }
+        return Ok(());
     }
-    .with_prefix(&args.prefix);
 
-    let db_dir = tempfile::TempDir::new()?;
-    let mut db_opts = rocksdb::Options::default();
-    db_opts.create_if_missing(true);
-    db_opts.set_compression_type(rocksdb::DBCompressionType::Zstd);
-    let db = rocksdb::DB::open(&db_opts, db_dir.path())?;
+    if let Some(key) = &args.contains {
+        return contains(&args, &client, key).await;
+    }
 
-    debug!("downloading index default.sst");
-    let index_body = blob.must_get("index/default.sst").await?;
-    let mut index_file = tempfile::NamedTempFile::new()?;
-    let _ = index_file.write(&index_body)?;
-    debug!("ingesting index default.sst");
-    db.ingest_external_file(vec![index_file.path()])?;
+    let mut emitted = 0;
+    let run = async {
+        if args.no_index {
+            scan_blocks(&args, &client, &mut emitted).await
+        } else {
+            scan(&args, &client, &mut emitted).await
+        }
+    };
+    let Some(deadline) = args.deadline else {
+        return run.await;
+    };
+    let result = tokio::time::timeout(Duration::from_secs(deadline), run).await;
+    match result {
+        Ok(result) => result,
+        Err(_) => {
+            std::io::stdout().flush()?;
+            bail!(
+                "deadline of {}s exceeded after emitting {} records",
+                deadline,
+                emitted
+            )
+        }
+    }
+}
 
-    let mut block_reader = S3BlockReader::new(S3BlockReaderArgs {
-        client: Box::new(
-            blob.with_prefix("block")
-                .with_compression()
-                .with_caching(16),
-        ),
+// Answers `--contains` from the Bloom filter if it can, and from the index if it can't.
+async fn contains(args: &Args, client: &Client, key: &str) -> anyhow::Result<()> {
+    let key = args.key_input_encoding.decode(key)?;
+    let mut blob = S3Client::new(client.clone(), &args.bucket).with_prefix(&args.prefix);
+    if let Some(filter) = BloomFilter::load(&mut blob).await? {
+        if filter.contains(&key) == Membership::Absent {
+            debug!("ruled out by {}", BloomFilter::KEY);
+            println!("absent");
+            return Ok(());
+        }
+    }
+    let db_opts = index::reader_db_options(args.rocksdb_max_open_files);
+    let manifest = Manifest::load(&mut blob).await?;
+    let partitioner = manifest.as_ref().and_then(|manifest| manifest.partitioner);
+    let sparse = match partitioner {
+        Some(_) => None,
+        None => SparseIndex::load(&mut blob, &args.index_version).await?,
+    };
+    let value = match (partitioner, sparse) {
+        (Some(partitioner), _) => {
+            partitioner
+                .get(&mut blob, &args.index_version, &key, &db_opts)
+                .await?
+        }
+        (None, Some(sparse)) => {
+            sparse
+                .get(&mut blob, &args.index_version, &key, &db_opts)
+                .await?
+        }
+        (None, None) => {
+            let db_dir = tempfile::TempDir::new()?;
+            let index_name = index::index_key(&args.index_version)?;
+            let compressed = manifest.is_some_and(|manifest| manifest.compressed_index);
+            let db = index::download(&mut blob, &index_name, compressed, &db_opts, db_dir.path())
+                .await?;
+            db.get(&key)?
+        }
+    };
+    let present = match value {
+        Some(v) => args.include_expired || !IndexValue::decode(&v)?.is_expired(SystemTime::now()),
+        None => false,
+    };
+    println!("{}", if present { "present" } else { "absent" });
+    Ok(())
+}
+
+async fn scan(args: &Args, client: &Client, emitted: &mut usize) -> anyhow::Result<()> {
+    let s3 = S3Client::new(client.clone(), &args.bucket).with_metrics();
+    let index_name = index::index_key(&args.index_version)?;
+    let index_key = args.prefix.join(&index_name);
+
+    let start = args
+        .start
+        .as_deref()
+        .map(|s| args.key_input_encoding.decode(s))
+        .transpose()?;
+    let end = args
+        .end
+        .as_deref()
+        .map(|s| args.key_input_encoding.decode(s))
+        .transpose()?;
+    let (start, end) = match &args.key_prefix {
+        Some(prefix) => {
+            let prefix = args.key_input_encoding.decode(prefix)?;
+            let upper = key::prefix_upper_bound(&prefix);
+            (Some(prefix), upper)
+        }
+        None => (start, end),
+    };
+
+    let limit = args.limit_bytes.map(ByteLimit::new);
+    let out = match &args.into_db {
+        Some(path) => Some(rocksdb::DB::open(&index::db_options(), path)?),
+        None => None,
+    };
+    let mut follower = Follower::new(match args.index_file {
+        Some(_) => None,
+        None => index::etag(client, &args.bucket, &index_key).await?,
     });
+    // Summed over every reader, since each has its own cache.
+    let mut cache_stats = CacheStats::default();
+    let mut block_stats = HashMap::new();
+    // Shared by every reader this run builds, including those built on a reload.
+    let budget = args
+        .retry_budget
+        .map(|ratio| RetryBudget::new(ratio, DEFAULT_RETRY_BURST));
+    loop {
+        let mut blob = s3.clone().with_prefix(&args.prefix);
+        let db_dir = tempfile::TempDir::new()?;
+        let db_opts = index::reader_db_options(args.rocksdb_max_open_files);
+        let manifest = Manifest::load(&mut blob).await?;
+        let compressed = manifest
+            .as_ref()
+            .is_some_and(|manifest| manifest.compressed_index);
+        let db = match (&args.index_file, &args.index_cache_dir) {
+            (Some(index_file), _) => index::ingest(index_file, &db_opts, db_dir.path())?,
+            (None, Some(cache_dir)) => {
+                let etag = follower
+                    .etag()
+                    .ok_or_else(|| anyhow!("index {} has no ETag", index_key))?;
+                index::open_cached(
+                    &mut blob,
+                    &index_name,
+                    compressed,
+                    etag,
+                    &db_opts,
+                    cache_dir,
+                )
+                .await?
+            }
+            (None, None) => {
+                index::download(&mut blob, &index_name, compressed, &db_opts, db_dir.path()).await?
+            }
+        };
+        if args.group_by_block {
+            let usage =
+                index::block_usage(&db, start.as_deref(), end.as_deref(), args.include_expired)?;
+            for block in &usage {
+                println!(
+                    "{}\t{}\t{}\t{}",
+                    block.block_id, block.records, block.min_offset, block.max_offset
+                );
+            }
+            info!("the range spans {} blocks", usage.len());
+            break;
+        }
+        let compression_dict = match &manifest {
+            Some(manifest) => manifest.compression_dict(&mut blob).await?,
+            None => None,
+        };
+        // With no retries requested, nothing is checked and blocks pass straight through.
+        let (checksums, attempts) = match (&manifest, args.checksum_retries) {
+            (Some(manifest), Some(retries)) => (manifest.block_checksums(), retries + 1),
+            (None, Some(_)) => bail!("--checksum-retries needs a dataset with a manifest"),
+            _ => (HashMap::new(), 1),
+        };
+        let footer = manifest
+            .as_ref()
+            .is_some_and(|manifest| manifest.block_footer);
+        let spanning = manifest
+            .as_ref()
+            .is_some_and(|manifest| manifest.spanning_records);
+        let block_ids: Option<Vec<usize>> =
+            manifest.map(|manifest| manifest.blocks.iter().map(|b| b.block_id).collect());
+
+        // The readers (and their caches) are rebuilt on every reload in case blocks were
+        // rewritten. Each comes with meters of the blocks it downloads, before and after they're
+        // decompressed, which count per block only with `--stats`.
+        let block_reader = || {
+            let raw = s3
+                .clone()
+                .with_prefix(&args.prefix)
+                .with_prefix(&block::block_prefix())
+                .with_metrics();
+            let raw = if args.stats {
+                raw.with_key_stats()
+            } else {
+                raw
+            };
+            let decoded = raw
+                .clone()
+                .with_compression_dict(compression_dict.clone())
+                .with_metrics();
+            let decoded = if args.stats {
+                decoded.with_key_stats()
+            } else {
+                decoded
+            };
+            let blocks = decoded
+                .clone()
+                .with_checksum_retries(checksums.clone(), attempts);
+            let blocks = match &budget {
+                Some(budget) => blocks.with_budget(budget.clone()),
+                None => blocks,
+            };
+            let blocks = match (args.target_hit_ratio, args.cache_min, args.cache_max) {
+                (Some(target_hit_ratio), Some(min), Some(max)) => {
+                    blocks.with_adaptive_caching(AdaptiveCacheArgs {
+                        min,
+                        max,
+                        target_hit_ratio,
+                    })
+                }
+                _ => match args.cache_bytes {
+                    Some(max_bytes) => blocks.with_caching_bytes(max_bytes),
+                    None => blocks.with_caching(CACHE_BLOCKS),
+                },
+            };
+            debug!("reading blocks through {}", blocks.describe());
+            let block_reader = S3BlockReader::new(S3BlockReaderArgs {
+                client: Box::new(blocks),
+                footer,
+                spanning,
+            });
+            (block_reader, raw, decoded)
+        };
+
+        let lower = follower.lower_bound(start.as_deref());
+        let lower = lower.as_deref();
+        if args.partitions > 1 {
+            let points = index::split_points(&db, args.partitions, lower, end.as_deref())?;
+            debug!("scanning {} partitions", points.len() + 1);
+            let mut bounds: Vec<Option<&[u8]>> = vec![lower];
+            bounds.extend(points.iter().map(|point| Some(point.as_slice())));
+            bounds.push(end.as_deref());
+            let partitions = bounds.windows(2).map(|range| {
+                let (lower, upper) = (range[0], range[1]);
+                let sinks = Sinks {
+                    out: out.as_ref(),
+                    limit: limit.as_ref(),
+                };
+                let db = &db;
+                let (mut block_reader, raw, decoded) = block_reader();
+                async move {
+                    let mut emitted = 0;
+                    scan_range(
+                        args,
+                        db,
+                        &mut block_reader,
+                        sinks,
+                        lower,
+                        upper,
+                        &mut emitted,
+                    )
+                    .await?;
+                    let downloads = (raw.key_stats(), decoded.key_stats());
+                    anyhow::Ok((emitted, block_reader.cache_stats(), downloads))
+                }
+            });
+            for (count, stats, (raw, decoded)) in futures::future::try_join_all(partitions).await? {
+                *emitted += count;
+                cache_stats += stats.unwrap_or_default();
+                tally_blocks(&mut block_stats, raw, decoded);
+            }
+        } else {
+            let (mut block_reader, raw, decoded) = block_reader();
+            if args.prewarm {
+                let capacity = match args.target_hit_ratio {
+                    Some(_) => args.cache_min.unwrap_or(CACHE_BLOCKS),
+                    None => CACHE_BLOCKS,
+                };
+                prewarm(&mut block_reader, block_ids, capacity).await?;
+            }
+            let sinks = Sinks {
+                out: out.as_ref(),
+                limit: limit.as_ref(),
+            };
+            let upper = end.as_deref();
+            let last = scan_range(args, &db, &mut block_reader, sinks, lower, upper, emitted);
+            follower.emitted(last.await?);
+            cache_stats += block_reader.cache_stats().unwrap_or_default();
+            tally_blocks(&mut block_stats, raw.key_stats(), decoded.key_stats());
+        }
 
-    let mut read_opts = ReadOptions::default();
-    if let Some(start) = args.start {
-        read_opts.set_iterate_lower_bound(start.as_bytes());
+        // Copies are written without a WAL, so they're only durable once flushed.
+        if let Some(out) = &out {
+            out.flush()?;
+        }
+
+        let limited = limit.as_ref().is_some_and(ByteLimit::reached)
+            || args.limit.is_some_and(|limit| *emitted >= limit);
+        if !args.follow || limited {
+            break;
+        }
+        std::io::stdout().flush()?;
+        let poll_interval = Duration::from_secs(args.poll_interval);
+        follower
+            .wait_for_change(poll_interval, || {
+                index::etag(client, &args.bucket, &index_key)
+            })
+            .await?;
+    }
+    if args.io_stats {
+        eprintln!("{}", s3.snapshot());
+        eprintln!("block cache: {}", cache_stats);
     }
-    if let Some(end) = args.end {
-        read_opts.set_iterate_upper_bound(end.as_bytes());
+    if args.stats {
+        let mut block_stats: Vec<_> = block_stats.into_iter().collect();
+        block_stats.sort_by_key(|(block_id, stats)| (std::cmp::Reverse(stats.bytes), *block_id));
+        for (block_id, stats) in block_stats {
+            eprintln!(
+                "{}\t{}\t{}\t{}",
+                block_id, stats.fetches, stats.bytes, stats.decompressed_bytes
+            );
+        }
     }
-    for entry in db.iterator_opt(IteratorMode::Start, read_opts) {
+    Ok(())
+}
+
+// Adds a reader's downloads, as counted by its meters of the blocks before and after they're
+// decompressed, to the totals of each block.
+fn tally_blocks(
+    stats: &mut HashMap<usize, BlockStats>,
+    raw: HashMap<String, KeyStats>,
+    decoded: HashMap<String, KeyStats>,
+) {
+    for (name, raw) in raw {
+        let Some(block_id) = block::block_id(&name) else {
+            continue;
+        };
+        let block = stats.entry(block_id).or_default();
+        block.fetches += raw.gets;
+        block.bytes += raw.bytes_read;
+        block.decompressed_bytes += decoded.get(&name).map_or(0, |decoded| decoded.bytes_read);
+    }
+}
+
+/// Where `scan_range` sends records, other than stdout.
+#[derive(Clone, Copy)]
+struct Sinks<'a> {
+    /// Copy records here instead of printing them.
+    out: Option<&'a rocksdb::DB>,
+    /// Stop once this is reached.
+    limit: Option<&'a ByteLimit>,
+}
+
+/// Emits the live entries of `db` in `[lower, upper)`. Returns the last key emitted.
+async fn scan_range(
+    args: &Args,
+    db: &rocksdb::DB,
+    block_reader: &mut S3BlockReader,
+    sinks: Sinks<'_>,
+    lower: Option<&[u8]>,
+    upper: Option<&[u8]>,
+    emitted: &mut usize,
+) -> anyhow::Result<Option<Vec<u8>>> {
+    if let Some(out) = sinks.out {
+        // Copies skip keys that are already present, so there's no cursor to track.
+        let include_expired = args.include_expired;
+        *emitted +=
+            index::materialize(db, block_reader, out, lower, upper, include_expired).await?;
+        return Ok(None);
+    }
+    let mut last: Option<Vec<u8>> = None;
+    for entry in index::range(db, lower, upper, args.reverse) {
+        if sinks.limit.is_some_and(ByteLimit::reached) {
+            break;
+        }
+        if args.limit.is_some_and(|limit| *emitted >= limit) {
+            break;
+        }
         let (k, v) = entry?;
-        let loc = Location::decode(&v)?;
+        let value = IndexValue::decode(&v)?;
+        if !args.include_expired && value.is_expired(SystemTime::now()) {
+            continue;
+        }
+        let loc = value.location;
+        let line = value
+            .line_number
+            .map(|n| format!(" (line {})", n))
+            .unwrap_or_default();
 
-        if args.keys_only {
-            if !args.quiet {
-                println!("{} --> {:?}", std::str::from_utf8(&k)?, loc);
-            }
+        let record = if args.keys_only {
+            Vec::new()
         } else {
-            let record = block_reader.fetch(&loc).await?;
-            if !args.quiet {
-                println!(
-                    "{} -> {}",
-                    std::str::from_utf8(&k)?,
-                    std::str::from_utf8(&record)?
+            block_reader.fetch(&loc).await?
+        };
+        let output = render(args, &k, &record, || {
+            let key = std::str::from_utf8(&k)?;
+            Ok(if args.keys_only {
+                format!("{}{} --> {:?}", key, line, loc)
+            } else {
+                format!("{}{} -> {}", key, line, std::str::from_utf8(&record)?)
+            })
+        })?;
+        if !args.quiet {
+            std::io::stdout().write_all(&output)?;
+        }
+        if let Some(limit) = sinks.limit {
+            limit.add(match args.limit_bytes_of {
+                LimitUnit::Output => output.len() as u64,
+                LimitUnit::Records => record.len() as u64,
+            });
+        }
+        last = Some(k.to_vec());
+        *emitted += 1;
+    }
+    Ok(last)
+}
+
+// Loads as many blocks as the cache holds (all of `block_ids`, if known), warning if that
+// leaves some out.
+async fn prewarm(
+    block_reader: &mut S3BlockReader,
+    block_ids: Option<Vec<usize>>,
+    capacity: usize,
+) -> anyhow::Result<()> {
+    let start = Instant::now();
+    let prewarmed = match block_ids {
+        Some(block_ids) => {
+            if block_ids.len() > capacity {
+                warn!(
+                    "the cache holds {} blocks, so only that many of {} will be prewarmed",
+                    capacity,
+                    block_ids.len()
                 );
             }
+            block_reader
+                .prewarm(block_ids.into_iter().take(capacity))
+                .await?
+        }
+        None => block_reader.prewarm(0..capacity).await?,
+    };
+    info!(
+        "prewarmed {} blocks ({}) in {:?}",
+        prewarmed.blocks,
+        units::human_bytes(prewarmed.bytes),
+        start.elapsed()
+    );
+    Ok(())
+}
+
+/// Formats an entry per `--output-format`, with `text` giving it as `text` output.
+fn render(
+    args: &Args,
+    key: &[u8],
+    record: &[u8],
+    text: impl FnOnce() -> anyhow::Result<String>,
+) -> anyhow::Result<Vec<u8>> {
+    let mut output = Vec::new();
+    match args.output_format {
+        OutputFormat::Text => {
+            output.extend(text()?.into_bytes());
+            output.extend_from_slice(&args.output_separator.0);
+        }
+        OutputFormat::Framed => {
+            output::write_framed(&mut output, key, record)?;
+        }
+    }
+    Ok(output)
+}
+
+// Dumps every block in `block_id` order. The blocks are found by listing them rather than by
+// counting up from 0, so that a missing block doesn't hide the ones after it.
+async fn scan_blocks(args: &Args, client: &Client, emitted: &mut usize) -> anyhow::Result<()> {
+    let mut blob = S3Client::new(client.clone(), &args.bucket).with_prefix(&args.prefix);
+    let manifest = Manifest::load(&mut blob).await?;
+    let compression_dict = match &manifest {
+        Some(manifest) => manifest.compression_dict(&mut blob).await?,
+        None => None,
+    };
+    let footer = manifest
+        .as_ref()
+        .is_some_and(|manifest| manifest.block_footer);
+    let spanning = manifest.is_some_and(|manifest| manifest.spanning_records);
+    let mut blocks = blob.with_prefix(&block::block_prefix());
+    let mut block_ids: Vec<usize> = blocks
+        .list("")
+        .await?
+        .iter()
+        .filter_map(|name| block::block_id(name))
+        .collect();
+    block_ids.sort_unstable();
+    let mut block_reader = S3BlockReader::new(S3BlockReaderArgs {
+        client: Box::new(blocks.with_compression_dict(compression_dict)),
+        footer,
+        spanning,
+    });
+
+    for block_id in block_ids {
+        let Some(records) = block_reader.fetch_block(block_id).await? else {
+            warn!("block {} was listed but is gone", block_id);
+            continue;
+        };
+        debug!("scanned block {} ({} records)", block_id, records.len());
+        for (loc, record) in records {
+            if args.limit.is_some_and(|limit| *emitted >= limit) {
+                return Ok(());
+            }
+            if !args.quiet {
+                let key = format!("{}:{}", loc.block_id, loc.offset);
+                let output = render(args, key.as_bytes(), &record, || {
+                    Ok(format!("{} -> {}", key, std::str::from_utf8(&record)?))
+                })?;
+                std::io::stdout().write_all(&output)?;
+            }
+            *emitted += 1;
         }
     }
     Ok(())
 }
+
+async fn list_index_versions(
+    client: &Client,
+    bucket: &str,
+    prefix: &Prefix,
+) -> anyhow::Result<Vec<String>> {
+    let index_prefix = prefix.join("index/");
+    let mut pages = client
+        .list_objects_v2()
+        .bucket(bucket)
+        .prefix(&index_prefix)
+        .into_paginator()
+        .send();
+    let mut versions = Vec::new();
+    while let Some(page) = pages.next().await {
+        for object in page?.contents() {
+            let name = object.key().unwrap_or_default();
+            if let Some(version) = name
+                .strip_prefix(&index_prefix)
+                .and_then(|name| name.strip_suffix(".sst"))
+            {
+                versions.push(version.to_owned());
+            }
+        }
+    }
+    Ok(versions)
+}
@@ -1,10 +1,10 @@
-use std::{
-    fs::File,
-    io::{BufRead, BufReader},
-    path::PathBuf,
-};
+use std::{fs::File, io::BufReader, path::PathBuf};
 
 use clap::{Parser, Subcommand};
+use s3kv::{
+    index::{self, ConflictPolicy},
+    lines::{self, BoundedLines},
+};
 use tracing::info;
 
 #[derive(Debug, Parser)]
@@ -32,8 +32,18 @@ enum Command {
 
         #[arg(long)]
         output: PathBuf,
+
+        /// The longest input line to read. Longer lines are an error, or skipped with
+        /// `--skip-long-lines`; either way they're never held in memory.
+        #[arg(long, default_value_t = lines::DEFAULT_MAX_LINE_BYTES)]
+        max_line_bytes: usize,
+
+        /// Skip (and count) lines longer than `--max-line-bytes` instead of failing.
+        #[arg(long, default_value_t = false)]
+        skip_long_lines: bool,
     },
 
+    /// Merge SSTs, whose key ranges may overlap, into one SST.
     #[command(name = "merge")]
     Merge {
         #[arg(long)]
@@ -41,6 +51,16 @@ enum Command {
 
         #[arg(long)]
         output: PathBuf,
+
+        /// Before merging, check that each input's keys are strictly increasing, which the merge
+        /// assumes but doesn't verify.
+        #[arg(long)]
+        sort_check: bool,
+
+        /// For a key in several inputs, keep the value from the first input that has it. By
+        /// default the last one wins, as if the inputs were written in order.
+        #[arg(long)]
+        first_wins: bool,
     },
 }
 
@@ -55,8 +75,18 @@ fn main() -> anyhow::Result<()> {
             }
         }
         Command::Compact { input } => compact_db(input)?,
-        Command::MakeSst { input, output } => make_sst(input, output)?,
-        Command::Merge { input, output } => merge_ssts(input, output)?,
+        Command::MakeSst {
+            input,
+            output,
+            max_line_bytes,
+            skip_long_lines,
+        } => make_sst(input, output, max_line_bytes, skip_long_lines)?,
+        Command::Merge {
+            input,
+            output,
+            sort_check,
+            first_wins,
+        } => merge_ssts(input, output, sort_check, first_wins)?,
     };
     Ok(())
 }
@@ -78,7 +108,12 @@ fn compact_db(input: PathBuf) -> anyhow::Result<()> {
     Ok(())
 }
 
-fn make_sst(input: PathBuf, output: PathBuf) -> anyhow::Result<()> {
+fn make_sst(
+    input: PathBuf,
+    output: PathBuf,
+    max_line_bytes: usize,
+    skip_long_lines: bool,
+) -> anyhow::Result<()> {
     let mut db_opts = rocksdb::Options::default();
     db_opts.create_if_missing(true);
     db_opts.set_compression_type(rocksdb::DBCompressionType::Zstd);
@@ -87,8 +122,9 @@ fn make_sst(input: PathBuf, output: PathBuf) -> anyhow::Result<()> {
 
     info!("opening {:?}", input);
     let fin = BufReader::new(File::open(input)?);
-    for line in fin.lines() {
-        let line = line?;
+    let mut lines = BoundedLines::new(fin, max_line_bytes, skip_long_lines);
+    for entry in &mut lines {
+        let (_, line) = entry?;
         let parsed: serde_json::Value = serde_json::from_str(&line)?;
         let digest = ring::digest::digest(&ring::digest::SHA256, line.as_bytes());
         let primary_key = parsed
@@ -100,15 +136,40 @@ fn make_sst(input: PathBuf, output: PathBuf) -> anyhow::Result<()> {
             .unwrap();
         db.put(primary_key, digest)?;
     }
+    if lines.skipped() > 0 {
+        info!(
+            "skipped {} lines longer than {} bytes",
+            lines.skipped(),
+            max_line_bytes
+        );
+    }
     db.finish()?;
     Ok(())
 }
 
-fn merge_ssts(inputs: Vec<PathBuf>, output: PathBuf) -> anyhow::Result<()> {
-    let mut db_opts = rocksdb::Options::default();
-    db_opts.create_if_missing(true);
-    db_opts.set_compression_type(rocksdb::DBCompressionType::Zstd);
-    let db = rocksdb::DB::open(&db_opts, output)?;
-    db.ingest_external_file(inputs)?;
+fn merge_ssts(
+    inputs: Vec<PathBuf>,
+    output: PathBuf,
+    sort_check: bool,
+    first_wins: bool,
+) -> anyhow::Result<()> {
+    if sort_check {
+        for input in &inputs {
+            info!("checking {:?} is sorted", input);
+            let scratch = tempfile::tempdir()?;
+            s3kv::index::check_sorted_sst(input, scratch.path())?;
+        }
+    }
+    let policy = if first_wins {
+        ConflictPolicy::FirstWins
+    } else {
+        ConflictPolicy::LastWins
+    };
+    let conflicts = index::merge_ssts(&inputs, &output, policy)?;
+    info!(
+        "merged {} inputs; {} keys were in more than one",
+        inputs.len(),
+        conflicts
+    );
     Ok(())
 }
@@ -0,0 +1,100 @@
+use clap::Parser;
+use s3kv::{
+    blob::{Blobstore, S3Client},
+    block::{block_name, block_prefix},
+    manifest::Manifest,
+    path::Prefix,
+};
+use tracing::debug;
+
+#[derive(Debug, Parser)]
+struct Args {
+    /// The AWS Region.
+    #[arg(long)]
+    region: String,
+
+    /// Send requests to this S3-compatible endpoint (e.g. `http://localhost:9000` for MinIO)
+    /// instead of AWS.
+    #[arg(long)]
+    endpoint_url: Option<String>,
+
+    /// Address the bucket by path (`endpoint/bucket/key`) rather than by subdomain, as MinIO and
+    /// many S3 proxies require. On by default with `--endpoint-url`; pass
+    /// `--force-path-style=false` for an endpoint that addresses buckets by subdomain.
+    #[arg(long, num_args = 0..=1, default_missing_value = "true")]
+    force_path_style: Option<bool>,
+
+    /// The name of the bucket.
+    #[arg(long)]
+    bucket: String,
+
+    #[arg(long)]
+    prefix: Prefix,
+
+    /// Check each block with a HEAD instead of downloading it: that it exists, is the length it
+    /// was stored at, and was stored with the length and checksum the manifest records. Blocks
+    /// written without that metadata always fail; check those without `--fast`.
+    #[arg(long, default_value_t = false)]
+    fast: bool,
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    tracing_subscriber::fmt::init();
+
+    let args = Args::try_parse()?;
+
+    let client = s3kv::s3_client(
+        &args.region,
+        args.endpoint_url.as_deref(),
+        args.force_path_style,
+    )
+    .await;
+    let mut blob = S3Client::new(client.clone(), &args.bucket).with_prefix(&args.prefix);
+
+    let manifest = Manifest::decode(&blob.must_get(Manifest::KEY).await?)?;
+    let compression_dict = manifest.compression_dict(&mut blob).await?;
+    let mut blocks = blob
+        .with_prefix(&block_prefix())
+        .with_compression_dict(compression_dict);
+
+    let mut failures = 0;
+    for entry in &manifest.blocks {
+        let name = block_name(entry.block_id);
+        debug!("verifying block {}", name);
+        let result = if args.fast {
+            let head = client
+                .head_object()
+                .bucket(&args.bucket)
+                .key(args.prefix.join(block_prefix().join(&name)))
+                .send()
+                .await;
+            match head {
+                Ok(head) => entry.verify_head(
+                    head.content_length().unwrap_or_default() as u64,
+                    &head.metadata().cloned().unwrap_or_default(),
+                ),
+                Err(err) => Err(err.into_service_error().into()),
+            }
+        } else {
+            match blocks.get(&name).await {
+                Ok(Some(contents)) => entry.verify(&contents),
+                Ok(None) => Err(anyhow::anyhow!("block {} is missing", entry.block_id)),
+                Err(err) => Err(err),
+            }
+        };
+        if let Err(err) = result {
+            println!("FAIL {}: {}", name, err);
+            failures += 1;
+        }
+    }
+    println!(
+        "verified {} blocks, {} failures",
+        manifest.blocks.len(),
+        failures
+    );
+    if failures > 0 {
+        anyhow::bail!("{} blocks failed verification", failures);
+    }
+    Ok(())
+}
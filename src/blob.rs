@@ -1,33 +1,243 @@
-use std::{borrow::Cow, io, num::NonZeroUsize, path::PathBuf, str::FromStr};
+use std::{
+    borrow::Cow,
+    collections::{hash_map::Entry, BTreeMap, HashMap},
+    io::{self, Read, Write},
+    num::NonZeroUsize,
+    ops::Range,
+    os::raw::c_int,
+    path::PathBuf,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
 
-use anyhow::anyhow;
+use anyhow::{anyhow, bail, Context};
 use async_trait::async_trait;
-use aws_sdk_s3::{operation::get_object::GetObjectError, primitives::ByteStream};
+use aws_sdk_s3::{
+    config::http::HttpResponse,
+    error::{ConnectorError, ProvideErrorMetadata, SdkError},
+    operation::{
+        complete_multipart_upload::CompleteMultipartUploadError, copy_object::CopyObjectError,
+        create_multipart_upload::CreateMultipartUploadError, get_object::GetObjectError,
+        head_object::HeadObjectError, list_objects_v2::ListObjectsV2Error,
+        put_object::PutObjectError, upload_part::UploadPartError,
+    },
+    primitives::ByteStream,
+    types::{
+        CompletedMultipartUpload, CompletedPart, ObjectCannedAcl, ServerSideEncryption,
+        StorageClass,
+    },
+};
+use base64::Engine;
+use bytes::Bytes;
+use futures::{
+    future::{BoxFuture, Shared},
+    stream::FuturesUnordered,
+    FutureExt, StreamExt, TryStreamExt,
+};
+use http_body::Frame;
+use http_body_util::StreamBody;
 use lru::LruCache;
-use once_cell::sync::OnceCell;
+use md5::{Digest, Md5};
+use rand::Rng;
 use tokio::{
     fs::File,
-    io::{AsyncReadExt, AsyncWriteExt},
+    io::{AsyncRead, AsyncReadExt, AsyncSeekExt, AsyncWriteExt},
 };
+use tokio_util::io::ReaderStream;
 use tracing::debug;
 
+use crate::{path::Prefix, units};
+
+/// Optional features of a `Blobstore`, so callers can pick the cheapest way to do something.
+/// Everything is off by default.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub struct BlobstoreCapabilities {
+    /// Reading part of a blob without fetching all of it.
+    pub ranged_reads: bool,
+    /// Copying a blob within the store without its bytes passing through this process, which
+    /// makes `copy` and `rename` cheap.
+    pub server_side_copy: bool,
+    /// `put_if_changed` without downloading the existing blob.
+    pub conditional_puts: bool,
+    /// Uploading one blob as several parts.
+    pub multipart: bool,
+    /// Keeping the metadata given to `put_with_metadata` with the blob.
+    pub object_metadata: bool,
+}
+
 #[async_trait]
 pub trait Blobstore: Sync + Send + std::fmt::Debug {
     async fn get<'a>(&'a mut self, key: &str) -> anyhow::Result<Option<Cow<'a, [u8]>>>;
     async fn put(&mut self, key: &str, blob: &[u8]) -> anyhow::Result<()>;
 
+    /// Like `get`, but hands out a reference-counted `Bytes` that can be cloned cheaply and
+    /// passed on to code that wants owned, `'static` data.
+    async fn get_bytes(&mut self, key: &str) -> anyhow::Result<Option<Bytes>> {
+        Ok(self
+            .get(key)
+            .await?
+            .map(|blob| Bytes::from(blob.into_owned())))
+    }
+
+    /// Fetches several blobs at once, returning them in the same order as `keys`. Stores that can
+    /// fetch in parallel override this; by default each `get` is awaited in turn.
+    async fn get_many(&mut self, keys: &[String]) -> anyhow::Result<Vec<Option<Vec<u8>>>> {
+        let mut blobs = Vec::with_capacity(keys.len());
+        for key in keys {
+            blobs.push(self.get(key).await?.map(Cow::into_owned));
+        }
+        Ok(blobs)
+    }
+
+    /// Like `get`, but hands back a reader so the blob can be consumed as it arrives rather
+    /// than buffered whole. By default the blob is fetched with `get` and read from memory.
+    async fn get_stream(
+        &mut self,
+        key: &str,
+    ) -> anyhow::Result<Option<Box<dyn AsyncRead + Unpin + Send>>> {
+        let Some(blob) = self.get(key).await? else {
+            return Ok(None);
+        };
+        Ok(Some(Box::new(io::Cursor::new(blob.into_owned()))))
+    }
+
+    /// Reads the bytes of `range` within a blob, or as many of them as it has. Stores with
+    /// `ranged_reads` override this; by default the whole blob is fetched with `get`.
+    async fn get_range(&mut self, key: &str, range: Range<u64>) -> anyhow::Result<Option<Vec<u8>>> {
+        let Some(blob) = self.get(key).await? else {
+            return Ok(None);
+        };
+        let end = range.end.min(blob.len() as u64);
+        let start = range.start.min(end);
+        Ok(Some(blob[start as usize..end as usize].to_vec()))
+    }
+
     async fn must_get(&mut self, key: &str) -> anyhow::Result<Cow<[u8]>> {
         let blob = self.get(key).await?;
         Ok(blob.ok_or_else(|| anyhow!("no such blob: {}", key))?)
     }
 
-    fn with_prefix(self, prefix: &str) -> Prefixed<Self>
+    /// Like `put`, but takes ownership of `blob` so implementations can avoid copying it.
+    async fn put_owned(&mut self, key: &str, blob: Vec<u8>) -> anyhow::Result<()> {
+        self.put(key, &blob).await
+    }
+
+    /// Like `put_owned`, but also stores `metadata` with the blob where the store supports it
+    /// (`object_metadata`), so that it can be checked with a `HEAD` later. Other stores drop it.
+    async fn put_with_metadata(
+        &mut self,
+        key: &str,
+        blob: Vec<u8>,
+        _metadata: &HashMap<String, String>,
+    ) -> anyhow::Result<()> {
+        self.put_owned(key, blob).await
+    }
+
+    /// Writes the contents of `body` without requiring the caller to buffer them first. `len`,
+    /// if known, is the number of bytes `body` will produce.
+    async fn put_stream(
+        &mut self,
+        key: &str,
+        mut body: Box<dyn AsyncRead + Unpin + Send + Sync>,
+        len: Option<u64>,
+    ) -> anyhow::Result<()> {
+        let mut blob = Vec::with_capacity(len.unwrap_or(0) as usize);
+        body.read_to_end(&mut blob).await?;
+        self.put(key, &blob).await
+    }
+
+    /// Whether there's a blob at `key`, without downloading it where the store can help it.
+    async fn exists(&mut self, key: &str) -> anyhow::Result<bool> {
+        Ok(self.get(key).await?.is_some())
+    }
+
+    async fn delete(&mut self, key: &str) -> anyhow::Result<()> {
+        Err(anyhow!("{:?} does not support deleting {}", self, key))
+    }
+
+    /// The keys of every blob whose key starts with `prefix`, in order.
+    async fn list(&mut self, prefix: &str) -> anyhow::Result<Vec<String>> {
+        Err(anyhow!("{:?} does not support listing {}", self, prefix))
+    }
+
+    /// Moves `src` to `dst`, replacing anything already at `dst`. Fails if `src` doesn't exist.
+    async fn rename(&mut self, src: &str, dst: &str) -> anyhow::Result<()> {
+        self.copy(src, dst).await?;
+        self.delete(src).await
+    }
+
+    /// Copies `src` to `dst`, replacing anything already at `dst`. Fails if `src` doesn't exist.
+    /// By default the blob is read and written back; stores with `server_side_copy` don't
+    /// download it.
+    async fn copy(&mut self, src: &str, dst: &str) -> anyhow::Result<()> {
+        let blob = self.must_get(src).await?.into_owned();
+        self.put_owned(dst, blob).await
+    }
+
+    /// Writes `blob` unless the stored object already has identical contents. Returns whether an
+    /// upload happened.
+    async fn put_if_changed(&mut self, key: &str, blob: &[u8]) -> anyhow::Result<bool> {
+        let unchanged = self
+            .get(key)
+            .await?
+            .is_some_and(|existing| *existing == *blob);
+        if unchanged {
+            return Ok(false);
+        }
+        self.put(key, blob).await?;
+        Ok(true)
+    }
+
+    /// Writes `blob` only if the stored object's ETag is `etag`, so that writers racing to
+    /// update the same key can't clobber each other. Returns whether the write happened; it
+    /// doesn't if the object has changed (or is missing) since its ETag was read.
+    async fn put_if_match(&mut self, key: &str, _blob: &[u8], _etag: &str) -> anyhow::Result<bool> {
+        Err(anyhow!(
+            "{:?} does not support conditional writes to {}",
+            self,
+            key
+        ))
+    }
+
+    /// Writes `blob` only if nothing is stored at `key` yet, so that writers that accidentally
+    /// share a prefix can't clobber each other's blobs. Returns whether the write happened.
+    async fn put_if_absent(&mut self, key: &str, _blob: &[u8]) -> anyhow::Result<bool> {
+        Err(anyhow!(
+            "{:?} does not support conditional writes to {}",
+            self,
+            key
+        ))
+    }
+
+    /// A one-line rendering of the store and everything it wraps, outermost first, e.g.
+    /// `Caching(16) -> Prefixed("foo") -> S3Client(bucket=my-bucket)`.
+    fn describe(&self) -> String {
+        format!("{:?}", self)
+    }
+
+    /// What the store supports natively. Decorators report what survives their wrapping.
+    fn capabilities(&self) -> BlobstoreCapabilities {
+        BlobstoreCapabilities::default()
+    }
+
+    /// How well the cache is doing, if this store is a `Caching`. Only the outermost store is
+    /// asked, so that the stats survive boxing (e.g. into an `S3BlockReader`).
+    fn cache_stats(&self) -> Option<CacheStats> {
+        None
+    }
+
+    /// Scopes every key to `prefix`, e.g. `block/0a` becomes `dataset/block/0a`.
+    fn with_prefix(self, prefix: &Prefix) -> Prefixed<Self>
     where
         Self: Sized,
     {
         Prefixed {
             underlying: self,
-            prefix: prefix.to_owned(),
+            prefix: prefix.clone(),
         }
     }
 
@@ -35,7 +245,123 @@ pub trait Blobstore: Sync + Send + std::fmt::Debug {
     where
         Self: Sized,
     {
-        Compressed { underlying: self }
+        self.with_codec(Codec::Zstd)
+    }
+
+    /// Like `with_compression`, but at the given zstd level (1-22, or 0 for zstd's default).
+    /// Higher levels make smaller blobs at the cost of slower writes; reads are unaffected.
+    fn with_compression_level(self, level: i32) -> Compressed<Self>
+    where
+        Self: Sized,
+    {
+        self.with_compression().with_level(level)
+    }
+
+    /// Like `with_compression`, but with any of the supported algorithms. Readers must use the
+    /// same codec; a blob written with another one fails to read.
+    fn with_codec(self, codec: Codec) -> Compressed<Self>
+    where
+        Self: Sized,
+    {
+        Compressed {
+            underlying: self,
+            codec,
+            dict: None,
+            level: 0,
+            min_size: 0,
+        }
+    }
+
+    /// Like `with_compression`, but (if `dict` is set) compressing against a trained zstd
+    /// dictionary. Readers must be given the same dictionary.
+    fn with_compression_dict(self, dict: Option<CompressionDict>) -> Compressed<Self>
+    where
+        Self: Sized,
+    {
+        Compressed {
+            underlying: self,
+            codec: Codec::Zstd,
+            dict,
+            level: 0,
+            min_size: 0,
+        }
+    }
+
+    fn with_compression_detection(self) -> MaybeCompressed<Self>
+    where
+        Self: Sized,
+    {
+        MaybeCompressed {
+            underlying: self,
+            raw_marker: false,
+        }
+    }
+
+    /// Stores a checksum with each blob, computed with `algorithm`, and checks it whenever the
+    /// blob is read. Blobs written with any algorithm can be read back.
+    fn with_checksum_algo(self, algorithm: ChecksumAlgorithm) -> Checksummed<Self>
+    where
+        Self: Sized,
+    {
+        Checksummed {
+            underlying: self,
+            algorithm,
+        }
+    }
+
+    /// Like `with_checksum_algo`, with CRC32C, so that corruption anywhere between here and
+    /// the store fails the read with an `IntegrityError`. With compression, put this under
+    /// it (`.with_integrity().with_compression()`), so that the compressed bytes are checked
+    /// before anything tries to decompress them.
+    fn with_integrity(self) -> Checksummed<Self>
+    where
+        Self: Sized,
+    {
+        self.with_checksum_algo(ChecksumAlgorithm::Crc32c)
+    }
+
+    /// Checks each blob named in `expected` (a map from key to the hex SHA-256 of its contents)
+    /// as it is read, re-fetching up to `attempts` times in total before reporting a mismatch.
+    fn with_checksum_retries(
+        self,
+        expected: HashMap<String, String>,
+        attempts: usize,
+    ) -> ChecksumRetrying<Self>
+    where
+        Self: Sized,
+    {
+        ChecksumRetrying {
+            underlying: self,
+            expected,
+            attempts,
+            budget: None,
+        }
+    }
+
+    /// Retries failed operations that look transient (see `is_transient`), making up to
+    /// `max_attempts` in total, with exponential backoff between them.
+    fn with_retry(self, max_attempts: usize) -> Retrying<Self>
+    where
+        Self: Sized,
+    {
+        Retrying {
+            underlying: self,
+            max_attempts,
+            base_delay: Duration::from_millis(100),
+            budget: None,
+        }
+    }
+
+    /// Counts the requests and bytes that pass through, for `Metered::snapshot`. Put it under
+    /// any `Caching` to count only what reaches the backend.
+    fn with_metrics(self) -> Metered<Self>
+    where
+        Self: Sized,
+    {
+        Metered {
+            underlying: self,
+            counters: Arc::default(),
+        }
     }
 
     fn with_caching(self, capacity: usize) -> Caching<Self>
@@ -45,13 +371,109 @@ pub trait Blobstore: Sync + Send + std::fmt::Debug {
         Caching {
             underlying: self,
             cache: LruCache::new(NonZeroUsize::new(capacity).unwrap()),
+            adaptive: None,
+            ttl: None,
+            max_bytes: None,
+            bytes: 0,
+            stats: CacheStats::default(),
+        }
+    }
+
+    /// Like `with_caching`, but bounded by the total size of what's cached (blobs and their
+    /// keys) rather than by how many entries there are, for blobs that vary a lot in size. A
+    /// blob bigger than `max_bytes` on its own is passed through without being cached.
+    fn with_caching_bytes(self, max_bytes: usize) -> Caching<Self>
+    where
+        Self: Sized,
+    {
+        Caching {
+            underlying: self,
+            cache: LruCache::unbounded(),
+            adaptive: None,
+            ttl: None,
+            max_bytes: Some(max_bytes),
+            bytes: 0,
+            stats: CacheStats::default(),
+        }
+    }
+
+    /// Like `with_caching`, but a blob (or a blob's absence) is only trusted for `ttl` after it
+    /// was fetched, and is then fetched again, so blobs that are overwritten in place are seen.
+    fn with_caching_ttl(self, capacity: usize, ttl: Duration) -> Caching<Self>
+    where
+        Self: Sized,
+    {
+        Caching {
+            ttl: Some(ttl),
+            ..self.with_caching(capacity)
+        }
+    }
+
+    /// Like `with_caching`, but starts small and resizes the cache within `args.min..=args.max`
+    /// according to how much the access pattern reuses blobs.
+    fn with_adaptive_caching(self, args: AdaptiveCacheArgs) -> Caching<Self>
+    where
+        Self: Sized,
+    {
+        Caching {
+            underlying: self,
+            cache: LruCache::new(NonZeroUsize::new(args.min).unwrap()),
+            adaptive: Some(Adaptive {
+                ghosts: LruCache::new(NonZeroUsize::new(args.max).unwrap()),
+                args,
+                lookups: 0,
+                hits: 0,
+                ghost_hits: 0,
+            }),
+            ttl: None,
+            max_bytes: None,
+            bytes: 0,
+            stats: CacheStats::default(),
+        }
+    }
+
+    /// Shares one backend operation between concurrent `get`s of the same key, and between
+    /// concurrent `put`s of the same contents to the same key. Clones share their in-flight
+    /// operations, so hand each task its own clone. A read that overlaps a `put` of the same
+    /// key waits for the put to land; other writes aren't tracked, so a read that overlaps one
+    /// may see either version. Ranged, batched and streamed reads go straight to the backend.
+    fn with_coalescing(self) -> Coalescing<Self>
+    where
+        Self: Sized + Clone + 'static,
+    {
+        Coalescing {
+            underlying: self,
+            gets: Arc::default(),
+            puts: Arc::default(),
         }
     }
 }
 
-#[derive(Debug)]
+/// The ETag S3 gives an object uploaded in one part: the quoted hex MD5 of its contents.
+/// `LocalFilesystem` uses the same for `put_if_match`.
+pub fn content_etag(blob: &[u8]) -> String {
+    format!("\"{}\"", hex::encode(Md5::digest(blob)))
+}
+
+#[derive(Debug, Clone)]
 pub struct LocalFilesystem {
     pub base: PathBuf,
+    /// `fsync` each blob after writing it, so it survives a crash once `put` returns. Off by
+    /// default, since it makes writes much slower and tests don't need it.
+    pub sync_on_put: bool,
+}
+
+impl LocalFilesystem {
+    // Where to write `key`, creating its directory if need be: like S3, a key such as
+    // `prefix/block/01` can be written without making `prefix/block` first.
+    async fn writable_path(&self, key: &str) -> anyhow::Result<PathBuf> {
+        let mut path = self.base.clone();
+        path.push(PathBuf::from_str(key)?);
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        Ok(path)
+    }
 }
 
 #[async_trait]
@@ -74,24 +496,324 @@ impl Blobstore for LocalFilesystem {
         Ok(Some(Cow::Owned(blob)))
     }
 
+    async fn get_stream(
+        &mut self,
+        key: &str,
+    ) -> anyhow::Result<Option<Box<dyn AsyncRead + Unpin + Send>>> {
+        let mut path = self.base.clone();
+        path.push(key);
+        match File::open(path).await {
+            Ok(file) => Ok(Some(Box::new(file))),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    async fn get_range(&mut self, key: &str, range: Range<u64>) -> anyhow::Result<Option<Vec<u8>>> {
+        let mut path = self.base.clone();
+        path.push(key);
+        let mut file = match File::open(path).await {
+            Ok(file) => file,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err.into()),
+        };
+        // Seeking past the end is fine; the read then just comes back empty.
+        file.seek(io::SeekFrom::Start(range.start)).await?;
+        let mut blob = Vec::new();
+        file.take(range.end.saturating_sub(range.start))
+            .read_to_end(&mut blob)
+            .await?;
+        Ok(Some(blob))
+    }
+
     async fn put(&mut self, key: &str, blob: &[u8]) -> anyhow::Result<()> {
+        let path = self.writable_path(key).await?;
+        let mut file = File::create(path).await?;
+        file.write_all(blob).await?;
+        // A tokio `File` finishes writes in the background; flush so the blob is there on return.
+        file.flush().await?;
+        if self.sync_on_put {
+            file.sync_all().await?;
+        }
+        Ok(())
+    }
+
+    async fn put_stream(
+        &mut self,
+        key: &str,
+        mut body: Box<dyn AsyncRead + Unpin + Send + Sync>,
+        _len: Option<u64>,
+    ) -> anyhow::Result<()> {
+        let path = self.writable_path(key).await?;
+        let mut file = File::create(path).await?;
+        tokio::io::copy(&mut body, &mut file).await?;
+        file.flush().await?;
+        if self.sync_on_put {
+            file.sync_all().await?;
+        }
+        Ok(())
+    }
+
+    async fn exists(&mut self, key: &str) -> anyhow::Result<bool> {
         let mut path = self.base.clone();
         path.push(PathBuf::from_str(key)?);
-        let mut file = File::create(path).await?;
+        match tokio::fs::metadata(path).await {
+            Ok(metadata) => Ok(metadata.is_file()),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(false),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    async fn delete(&mut self, key: &str) -> anyhow::Result<()> {
+        let mut path = self.base.clone();
+        path.push(PathBuf::from_str(key)?);
+        tokio::fs::remove_file(path).await?;
+        Ok(())
+    }
+
+    async fn list(&mut self, prefix: &str) -> anyhow::Result<Vec<String>> {
+        let mut keys = Vec::new();
+        let mut dirs = vec![self.base.clone()];
+        while let Some(dir) = dirs.pop() {
+            let mut entries = tokio::fs::read_dir(&dir).await?;
+            while let Some(entry) = entries.next_entry().await? {
+                let path = entry.path();
+                if entry.file_type().await?.is_dir() {
+                    dirs.push(path);
+                    continue;
+                }
+                let key = path
+                    .strip_prefix(&self.base)?
+                    .to_str()
+                    .ok_or_else(|| anyhow!("{:?} isn't a valid key", path))?;
+                if key.starts_with(prefix) {
+                    keys.push(key.to_owned());
+                }
+            }
+        }
+        keys.sort();
+        Ok(keys)
+    }
+
+    async fn rename(&mut self, src: &str, dst: &str) -> anyhow::Result<()> {
+        let mut from = self.base.clone();
+        from.push(PathBuf::from_str(src)?);
+        let to = self.writable_path(dst).await?;
+        match tokio::fs::rename(from, to).await {
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                Err(anyhow!("no such blob: {}", src))
+            }
+            other => Ok(other?),
+        }
+    }
+    async fn copy(&mut self, src: &str, dst: &str) -> anyhow::Result<()> {
+        let mut from = self.base.clone();
+        from.push(PathBuf::from_str(src)?);
+        let to = self.writable_path(dst).await?;
+        match tokio::fs::copy(from, to).await {
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                Err(anyhow!("no such blob: {}", src))
+            }
+            other => Ok(other.map(|_| ())?),
+        }
+    }
+    async fn put_if_match(&mut self, key: &str, blob: &[u8], etag: &str) -> anyhow::Result<bool> {
+        // Checked and written separately, so this only guards against writers in this process
+        // that don't overlap.
+        let Some(existing) = self.get(key).await? else {
+            return Ok(false);
+        };
+        if content_etag(&existing).trim_matches('"') != etag.trim_matches('"') {
+            return Ok(false);
+        }
+        self.put(key, blob).await?;
+        Ok(true)
+    }
+    async fn put_if_absent(&mut self, key: &str, blob: &[u8]) -> anyhow::Result<bool> {
+        let path = self.writable_path(key).await?;
+        let created = tokio::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(path)
+            .await;
+        let mut file = match created {
+            Ok(file) => file,
+            Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => return Ok(false),
+            Err(err) => return Err(err.into()),
+        };
         file.write_all(blob).await?;
+        file.flush().await?;
+        if self.sync_on_put {
+            file.sync_all().await?;
+        }
+        Ok(true)
+    }
+    fn describe(&self) -> String {
+        format!("LocalFilesystem({:?})", self.base)
+    }
+    fn capabilities(&self) -> BlobstoreCapabilities {
+        BlobstoreCapabilities {
+            ranged_reads: true,
+            ..BlobstoreCapabilities::default()
+        }
+    }
+}
+
+/// Keeps blobs in a `HashMap`, for tests and benchmarks that don't want to touch the disk or S3.
+/// It behaves like `LocalFilesystem`, and cloning it snapshots every blob.
+#[derive(Clone, Debug, Default)]
+pub struct MemoryStore {
+    pub map: HashMap<String, Vec<u8>>,
+}
+
+#[async_trait]
+impl Blobstore for MemoryStore {
+    async fn get(&mut self, key: &str) -> anyhow::Result<Option<Cow<[u8]>>> {
+        Ok(self.map.get(key).map(|blob| Cow::Borrowed(blob.as_slice())))
+    }
+    async fn put(&mut self, key: &str, blob: &[u8]) -> anyhow::Result<()> {
+        self.map.insert(key.to_owned(), blob.to_vec());
+        Ok(())
+    }
+    async fn put_owned(&mut self, key: &str, blob: Vec<u8>) -> anyhow::Result<()> {
+        self.map.insert(key.to_owned(), blob);
+        Ok(())
+    }
+    async fn exists(&mut self, key: &str) -> anyhow::Result<bool> {
+        Ok(self.map.contains_key(key))
+    }
+    async fn delete(&mut self, key: &str) -> anyhow::Result<()> {
+        match self.map.remove(key) {
+            Some(_) => Ok(()),
+            None => Err(anyhow!("no such blob: {}", key)),
+        }
+    }
+    async fn list(&mut self, prefix: &str) -> anyhow::Result<Vec<String>> {
+        let mut keys: Vec<String> = self
+            .map
+            .keys()
+            .filter(|key| key.starts_with(prefix))
+            .cloned()
+            .collect();
+        keys.sort();
+        Ok(keys)
+    }
+    async fn rename(&mut self, src: &str, dst: &str) -> anyhow::Result<()> {
+        let blob = self
+            .map
+            .remove(src)
+            .ok_or_else(|| anyhow!("no such blob: {}", src))?;
+        self.map.insert(dst.to_owned(), blob);
+        Ok(())
+    }
+    async fn copy(&mut self, src: &str, dst: &str) -> anyhow::Result<()> {
+        let blob = self
+            .map
+            .get(src)
+            .ok_or_else(|| anyhow!("no such blob: {}", src))?
+            .clone();
+        self.map.insert(dst.to_owned(), blob);
         Ok(())
     }
+    async fn put_if_match(&mut self, key: &str, blob: &[u8], etag: &str) -> anyhow::Result<bool> {
+        let Some(existing) = self.map.get_mut(key) else {
+            return Ok(false);
+        };
+        if content_etag(existing).trim_matches('"') != etag.trim_matches('"') {
+            return Ok(false);
+        }
+        *existing = blob.to_vec();
+        Ok(true)
+    }
+    async fn put_if_absent(&mut self, key: &str, blob: &[u8]) -> anyhow::Result<bool> {
+        match self.map.entry(key.to_owned()) {
+            Entry::Occupied(_) => Ok(false),
+            Entry::Vacant(entry) => {
+                entry.insert(blob.to_vec());
+                Ok(true)
+            }
+        }
+    }
+    fn describe(&self) -> String {
+        format!("MemoryStore({} blobs)", self.map.len())
+    }
+    fn capabilities(&self) -> BlobstoreCapabilities {
+        BlobstoreCapabilities {
+            ranged_reads: true,
+            ..BlobstoreCapabilities::default()
+        }
+    }
 }
 
+/// The metadata key under which `S3Client::put_with_metadata` records the length of the object
+/// it stored, so that a `HEAD` can tell whether the object is still the one that was written.
+pub const STORED_LEN_METADATA: &str = "s3kv-stored-len";
+
+/// The default `S3Client::multipart_threshold`.
+pub const DEFAULT_MULTIPART_THRESHOLD: usize = 16 << 20;
+
+/// The default `S3Client::multipart_part_size`. S3 requires every part but the last to be at
+/// least 5 MiB.
+pub const DEFAULT_MULTIPART_PART_SIZE: usize = 8 << 20;
+
+// How many parts of one blob are uploaded at once.
+const MULTIPART_CONCURRENCY: usize = 4;
+
 #[derive(Clone, Debug)]
 pub struct S3Client {
     pub client: aws_sdk_s3::Client,
     pub bucket: String,
+    /// Canned ACL applied to every object written through this client.
+    pub acl: Option<ObjectCannedAcl>,
+    /// Send a `Content-MD5` header with each buffered upload, so S3 rejects a body that was
+    /// corrupted in transit. Streamed uploads (`put_stream`) are sent without one.
+    pub content_md5: bool,
+    /// Server-side encryption for every object written through this client. Reads need
+    /// nothing extra, since S3 decrypts transparently.
+    pub sse: Option<ServerSideEncryption>,
+    /// The KMS key to encrypt with, when `sse` is `aws:kms`. Without it, S3 uses the account's
+    /// default key.
+    pub kms_key_id: Option<String>,
+    /// The storage class for every object written through this client, e.g. `STANDARD_IA` for
+    /// datasets that are rarely read. Without it, S3 uses `STANDARD`.
+    pub storage_class: Option<StorageClass>,
+    /// The `Content-Type` of every object written through this client, e.g. `application/zstd`
+    /// for compressed blocks. Only metadata: reads ignore it. Without it, S3 uses
+    /// `application/octet-stream`.
+    pub content_type: Option<String>,
+    /// How many requests `get_many` keeps in flight at once.
+    pub get_many_concurrency: usize,
+    /// Blobs larger than this are uploaded in parts of `multipart_part_size` bytes, several at
+    /// a time, since a single upload is slow for large blobs and fails outright over 5 GiB.
+    pub multipart_threshold: usize,
+    pub multipart_part_size: usize,
 }
 
-#[async_trait]
-impl Blobstore for S3Client {
-    async fn get(&mut self, key: &str) -> anyhow::Result<Option<Cow<[u8]>>> {
+impl S3Client {
+    pub fn new(client: aws_sdk_s3::Client, bucket: impl Into<String>) -> Self {
+        Self {
+            client,
+            bucket: bucket.into(),
+            acl: None,
+            content_md5: false,
+            sse: None,
+            kms_key_id: None,
+            storage_class: None,
+            content_type: None,
+            get_many_concurrency: 16,
+            multipart_threshold: DEFAULT_MULTIPART_THRESHOLD,
+            multipart_part_size: DEFAULT_MULTIPART_PART_SIZE,
+        }
+    }
+
+    pub fn with_content_type(self, content_type: impl Into<String>) -> Self {
+        Self {
+            content_type: Some(content_type.into()),
+            ..self
+        }
+    }
+
+    async fn fetch(&self, key: &str) -> anyhow::Result<Option<Bytes>> {
         debug!("fetching blob {}", key);
         let resp = self
             .client
@@ -99,181 +821,4693 @@ impl Blobstore for S3Client {
             .bucket(&self.bucket)
             .key(key)
             .send()
-            .await
-            .map_err(|e| e.into_service_error());
+            .await;
+        // The `SdkError` is kept whole, rather than reduced to its service error, so that
+        // `is_transient` can see the HTTP status.
         match resp {
-            Ok(output) => Ok(Some(Cow::Owned(output.body.collect().await?.to_vec()))),
-            Err(GetObjectError::NoSuchKey(_)) => Ok(None),
-            Err(other) => Err(other.into()),
+            Ok(output) => Ok(Some(output.body.collect().await?.into_bytes())),
+            Err(err) if matches!(err.as_service_error(), Some(GetObjectError::NoSuchKey(_))) => {
+                Ok(None)
+            }
+            Err(err) => Err(err.into()),
         }
     }
 
-    async fn put(&mut self, key: &str, blob: &[u8]) -> anyhow::Result<()> {
-        self.client
-            .put_object()
+    fn put_error(&self, err: SdkError<PutObjectError, HttpResponse>) -> anyhow::Error {
+        // Buckets with Object Ownership set to "bucket owner enforced" reject any ACL.
+        if err.code() == Some("AccessControlListNotSupported") {
+            return anyhow!(
+                "bucket {} has ACLs disabled (object ownership is enforced), so objects cannot be written with acl {:?}",
+                self.bucket,
+                self.acl
+            );
+        }
+        err.into()
+    }
+
+    fn content_md5(&self, blob: &[u8]) -> Option<String> {
+        self.content_md5
+            .then(|| base64::engine::general_purpose::STANDARD.encode(Md5::digest(blob)))
+    }
+
+    // Uploads `blob` in parts. If any of it fails, the upload is aborted, so that S3 doesn't
+    // keep (and bill for) the parts that did make it.
+    async fn put_multipart(
+        &self,
+        key: &str,
+        blob: Bytes,
+        metadata: Option<HashMap<String, String>>,
+    ) -> anyhow::Result<()> {
+        debug!("uploading blob {} in parts", key);
+        let upload = self
+            .client
+            .create_multipart_upload()
             .bucket(&self.bucket)
             .key(key)
-            .body(ByteStream::from(blob.to_vec()))
+            .set_acl(self.acl.clone())
+            .set_server_side_encryption(self.sse.clone())
+            .set_ssekms_key_id(self.kms_key_id.clone())
+            .set_storage_class(self.storage_class.clone())
+            .set_content_type(self.content_type.clone())
+            .set_metadata(metadata)
             .send()
             .await?;
-        Ok(())
+        let upload_id = upload
+            .upload_id()
+            .ok_or_else(|| anyhow!("S3 started an upload of {} with no upload id", key))?;
+        let result = self.upload_parts(key, upload_id, blob).await;
+        if result.is_err() {
+            debug!("aborting upload {} of {}", upload_id, key);
+            let abort = self
+                .client
+                .abort_multipart_upload()
+                .bucket(&self.bucket)
+                .key(key)
+                .upload_id(upload_id)
+                .send()
+                .await;
+            if let Err(err) = abort {
+                debug!("failed to abort upload {}: {}", upload_id, err);
+            }
+        }
+        result
+    }
+
+    async fn put_object(
+        &self,
+        key: &str,
+        blob: Vec<u8>,
+        metadata: Option<HashMap<String, String>>,
+    ) -> anyhow::Result<()> {
+        if blob.len() > self.multipart_threshold {
+            return self.put_multipart(key, Bytes::from(blob), metadata).await;
+        }
+        let content_md5 = self.content_md5(&blob);
+        let resp = self
+            .client
+            .put_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .set_acl(self.acl.clone())
+            .set_server_side_encryption(self.sse.clone())
+            .set_ssekms_key_id(self.kms_key_id.clone())
+            .set_storage_class(self.storage_class.clone())
+            .set_content_type(self.content_type.clone())
+            .set_content_md5(content_md5)
+            .set_metadata(metadata)
+            .body(ByteStream::from(blob))
+            .send()
+            .await;
+        resp.map(|_| ()).map_err(|err| self.put_error(err))
+    }
+
+    async fn upload_parts(&self, key: &str, upload_id: &str, blob: Bytes) -> anyhow::Result<()> {
+        let part_size = self.multipart_part_size.max(1);
+        let mut pending = (0..blob.len()).step_by(part_size).zip(1..);
+        let mut in_flight = FuturesUnordered::new();
+        let mut parts = Vec::new();
+        loop {
+            while in_flight.len() < MULTIPART_CONCURRENCY {
+                let Some((start, part_number)) = pending.next() else {
+                    break;
+                };
+                let part = blob.slice(start..blob.len().min(start + part_size));
+                let upload = self
+                    .client
+                    .upload_part()
+                    .bucket(&self.bucket)
+                    .key(key)
+                    .upload_id(upload_id)
+                    .part_number(part_number)
+                    .set_content_md5(self.content_md5(&part))
+                    .body(ByteStream::from(part))
+                    .send();
+                in_flight.push(async move { (part_number, upload.await) });
+            }
+            let Some((part_number, resp)) = in_flight.next().await else {
+                break;
+            };
+            let resp =
+                resp.with_context(|| format!("uploading part {} of {}", part_number, key))?;
+            parts.push(
+                CompletedPart::builder()
+                    .part_number(part_number)
+                    .set_e_tag(resp.e_tag().map(str::to_owned))
+                    .build(),
+            );
+        }
+        parts.sort_by_key(|part| part.part_number());
+        self.client
+            .complete_multipart_upload()
+            .bucket(&self.bucket)
+            .key(key)
+            .upload_id(upload_id)
+            .multipart_upload(
+                CompletedMultipartUpload::builder()
+                    .set_parts(Some(parts))
+                    .build(),
+            )
+            .send()
+            .await?;
+        Ok(())
     }
 }
 
-#[derive(Debug)]
+// The `x-amz-copy-source` of `key`, which must be URL-encoded: keys can hold characters like the
+// `+` and `:` of a timestamped prefix. Only the slashes between segments are left as they are.
+fn copy_source(bucket: &str, key: &str) -> String {
+    let mut source = format!("{}/", bucket);
+    for &b in key.as_bytes() {
+        if b.is_ascii_alphanumeric() || b"-._~/".contains(&b) {
+            source.push(b as char);
+        } else {
+            source += &format!("%{:02X}", b);
+        }
+    }
+    source
+}
+
+#[async_trait]
+impl Blobstore for S3Client {
+    async fn get(&mut self, key: &str) -> anyhow::Result<Option<Cow<[u8]>>> {
+        Ok(self
+            .get_bytes(key)
+            .await?
+            .map(|blob| Cow::Owned(blob.into())))
+    }
+
+    async fn get_bytes(&mut self, key: &str) -> anyhow::Result<Option<Bytes>> {
+        self.fetch(key).await
+    }
+
+    async fn get_stream(
+        &mut self,
+        key: &str,
+    ) -> anyhow::Result<Option<Box<dyn AsyncRead + Unpin + Send>>> {
+        debug!("streaming blob {}", key);
+        let resp = self
+            .client
+            .get_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .send()
+            .await;
+        match resp {
+            Ok(output) => Ok(Some(Box::new(output.body.into_async_read()))),
+            Err(err) if matches!(err.as_service_error(), Some(GetObjectError::NoSuchKey(_))) => {
+                Ok(None)
+            }
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    async fn get_range(&mut self, key: &str, range: Range<u64>) -> anyhow::Result<Option<Vec<u8>>> {
+        // An empty range can't be spelled as a `Range` header.
+        if range.is_empty() {
+            return Ok(self.exists(key).await?.then(Vec::new));
+        }
+        debug!("fetching {:?} of blob {}", range, key);
+        let resp = self
+            .client
+            .get_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .range(format!("bytes={}-{}", range.start, range.end - 1))
+            .send()
+            .await;
+        match resp {
+            Ok(output) => Ok(Some(output.body.collect().await?.into_bytes().into())),
+            // The range starts past the end of the blob.
+            Err(err) if err.code() == Some("InvalidRange") => Ok(Some(Vec::new())),
+            Err(err) if matches!(err.as_service_error(), Some(GetObjectError::NoSuchKey(_))) => {
+                Ok(None)
+            }
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    async fn get_many(&mut self, keys: &[String]) -> anyhow::Result<Vec<Option<Vec<u8>>>> {
+        let this = &*self;
+        let mut blobs = vec![None; keys.len()];
+        let mut pending = keys.iter().enumerate();
+        let mut in_flight = FuturesUnordered::new();
+        loop {
+            while in_flight.len() < this.get_many_concurrency.max(1) {
+                let Some((i, key)) = pending.next() else {
+                    break;
+                };
+                in_flight.push(async move { (i, this.fetch(key).await) });
+            }
+            let Some((i, blob)) = in_flight.next().await else {
+                return Ok(blobs);
+            };
+            blobs[i] = blob?.map(Vec::from);
+        }
+    }
+
+    async fn put(&mut self, key: &str, blob: &[u8]) -> anyhow::Result<()> {
+        self.put_owned(key, blob.to_vec()).await
+    }
+
+    async fn put_owned(&mut self, key: &str, blob: Vec<u8>) -> anyhow::Result<()> {
+        self.put_object(key, blob, None).await
+    }
+
+    async fn put_with_metadata(
+        &mut self,
+        key: &str,
+        blob: Vec<u8>,
+        metadata: &HashMap<String, String>,
+    ) -> anyhow::Result<()> {
+        let mut metadata = metadata.clone();
+        metadata.insert(STORED_LEN_METADATA.to_owned(), blob.len().to_string());
+        self.put_object(key, blob, Some(metadata)).await
+    }
+
+    async fn put_stream(
+        &mut self,
+        key: &str,
+        mut body: Box<dyn AsyncRead + Unpin + Send + Sync>,
+        len: Option<u64>,
+    ) -> anyhow::Result<()> {
+        // S3 needs the length up front for a plain (non-chunked) upload.
+        let Some(len) = len else {
+            let mut blob = Vec::new();
+            body.read_to_end(&mut blob).await?;
+            return self.put(key, &blob).await;
+        };
+        // Note that a streamed body can't be replayed, so the SDK is unable to retry this upload.
+        let frames = ReaderStream::new(body).map_ok(Frame::data);
+        let resp = self
+            .client
+            .put_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .set_acl(self.acl.clone())
+            .set_server_side_encryption(self.sse.clone())
+            .set_ssekms_key_id(self.kms_key_id.clone())
+            .set_storage_class(self.storage_class.clone())
+            .set_content_type(self.content_type.clone())
+            .content_length(len as i64)
+            .body(ByteStream::from_body_1_x(StreamBody::new(frames)))
+            .send()
+            .await;
+        resp.map(|_| ()).map_err(|err| self.put_error(err))
+    }
+
+    async fn exists(&mut self, key: &str) -> anyhow::Result<bool> {
+        debug!("checking for blob {}", key);
+        let resp = self
+            .client
+            .head_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .send()
+            .await;
+        match resp {
+            Ok(_) => Ok(true),
+            Err(err) if matches!(err.as_service_error(), Some(HeadObjectError::NotFound(_))) => {
+                Ok(false)
+            }
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    async fn list(&mut self, prefix: &str) -> anyhow::Result<Vec<String>> {
+        debug!("listing blobs under {}", prefix);
+        // The paginator follows continuation tokens, since each page holds at most 1000 keys.
+        let mut pages = self
+            .client
+            .list_objects_v2()
+            .bucket(&self.bucket)
+            .prefix(prefix)
+            .into_paginator()
+            .send();
+        let mut keys = Vec::new();
+        while let Some(page) = pages.next().await {
+            for object in page?.contents() {
+                keys.extend(object.key().map(str::to_owned));
+            }
+        }
+        Ok(keys)
+    }
+
+    async fn delete(&mut self, key: &str) -> anyhow::Result<()> {
+        self.client
+            .delete_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .send()
+            .await?;
+        Ok(())
+    }
+
+    async fn rename(&mut self, src: &str, dst: &str) -> anyhow::Result<()> {
+        self.copy(src, dst).await?;
+        self.delete(src).await
+    }
+
+    async fn copy(&mut self, src: &str, dst: &str) -> anyhow::Result<()> {
+        // A server-side copy, so the bytes never leave S3.
+        let resp = self
+            .client
+            .copy_object()
+            .bucket(&self.bucket)
+            .copy_source(copy_source(&self.bucket, src))
+            .key(dst)
+            .set_acl(self.acl.clone())
+            .set_server_side_encryption(self.sse.clone())
+            .set_ssekms_key_id(self.kms_key_id.clone())
+            .set_storage_class(self.storage_class.clone())
+            .send()
+            .await;
+        match resp {
+            Ok(_) => Ok(()),
+            Err(err) if err.code() == Some("NoSuchKey") => Err(anyhow!("no such blob: {}", src)),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    async fn put_if_changed(&mut self, key: &str, blob: &[u8]) -> anyhow::Result<bool> {
+        let resp = self
+            .client
+            .head_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .send()
+            .await;
+        let etag = match resp {
+            Ok(output) => output.e_tag,
+            Err(err) if matches!(err.as_service_error(), Some(HeadObjectError::NotFound(_))) => {
+                None
+            }
+            Err(err) => return Err(err.into()),
+        };
+        // Single-part uploads have the (quoted) hex MD5 of the body as their ETag. Multipart
+        // ETags look like `"<md5 of part md5s>-<parts>"` and never match, so those always upload.
+        let digest = hex::encode(Md5::digest(blob));
+        if etag.is_some_and(|etag| etag.trim_matches('"') == digest) {
+            debug!("skipping unchanged blob {}", key);
+            return Ok(false);
+        }
+        self.put(key, blob).await?;
+        Ok(true)
+    }
+    async fn put_if_match(&mut self, key: &str, blob: &[u8], etag: &str) -> anyhow::Result<bool> {
+        let content_md5 = self.content_md5(blob);
+        let resp = self
+            .client
+            .put_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .if_match(etag)
+            .set_acl(self.acl.clone())
+            .set_server_side_encryption(self.sse.clone())
+            .set_ssekms_key_id(self.kms_key_id.clone())
+            .set_storage_class(self.storage_class.clone())
+            .set_content_type(self.content_type.clone())
+            .set_content_md5(content_md5)
+            .body(ByteStream::from(blob.to_vec()))
+            .send()
+            .await;
+        let status = resp
+            .as_ref()
+            .err()
+            .and_then(|err| err.raw_response())
+            .map(|raw| raw.status().as_u16());
+        match status {
+            // 412: the ETag didn't match. 409: a concurrent conditional write won. 404: there's
+            // nothing to match.
+            Some(412 | 409 | 404) => {
+                debug!("not overwriting {}, which has changed", key);
+                Ok(false)
+            }
+            _ => resp.map(|_| true).map_err(|err| self.put_error(err)),
+        }
+    }
+    async fn put_if_absent(&mut self, key: &str, blob: &[u8]) -> anyhow::Result<bool> {
+        let content_md5 = self.content_md5(blob);
+        let resp = self
+            .client
+            .put_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .if_none_match("*")
+            .set_acl(self.acl.clone())
+            .set_server_side_encryption(self.sse.clone())
+            .set_ssekms_key_id(self.kms_key_id.clone())
+            .set_storage_class(self.storage_class.clone())
+            .set_content_type(self.content_type.clone())
+            .set_content_md5(content_md5)
+            .body(ByteStream::from(blob.to_vec()))
+            .send()
+            .await;
+        let status = resp
+            .as_ref()
+            .err()
+            .and_then(|err| err.raw_response())
+            .map(|raw| raw.status().as_u16());
+        match status {
+            // 412: the object exists. 409: a concurrent conditional write won.
+            Some(412 | 409) => {
+                debug!("not overwriting {}, which already exists", key);
+                Ok(false)
+            }
+            _ => resp.map(|_| true).map_err(|err| self.put_error(err)),
+        }
+    }
+    fn describe(&self) -> String {
+        format!("S3Client(bucket={})", self.bucket)
+    }
+    fn capabilities(&self) -> BlobstoreCapabilities {
+        BlobstoreCapabilities {
+            ranged_reads: true,
+            server_side_copy: true,
+            conditional_puts: true,
+            multipart: true,
+            object_metadata: true,
+        }
+    }
+}
+
+/// Maps a key to the URL it can be fetched from.
+pub type UrlFor = Box<dyn Fn(&str) -> String + Send + Sync>;
+
+/// Reads blobs over plain HTTP(S), e.g. from pre-signed S3 GET URLs, so that a consumer who was
+/// only handed URLs needs no AWS credentials. Read-only: `put` always fails.
+pub struct PresignedStore {
+    client: HttpsClient,
+    url_for: UrlFor,
+}
+
+type HttpsClient = hyper::Client<hyper_rustls::HttpsConnector<hyper::client::HttpConnector>>;
+
+fn https_client() -> HttpsClient {
+    let connector = hyper_rustls::HttpsConnectorBuilder::new()
+        .with_native_roots()
+        .https_or_http()
+        .enable_http1()
+        .build();
+    hyper::Client::builder().build(connector)
+}
+
+impl PresignedStore {
+    pub fn new(url_for: impl Fn(&str) -> String + Send + Sync + 'static) -> Self {
+        Self {
+            client: https_client(),
+            url_for: Box::new(url_for),
+        }
+    }
+}
+
+impl std::fmt::Debug for PresignedStore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PresignedStore").finish_non_exhaustive()
+    }
+}
+
+#[async_trait]
+impl Blobstore for PresignedStore {
+    async fn get(&mut self, key: &str) -> anyhow::Result<Option<Cow<[u8]>>> {
+        Ok(self
+            .get_bytes(key)
+            .await?
+            .map(|blob| Cow::Owned(blob.into())))
+    }
+    async fn get_bytes(&mut self, key: &str) -> anyhow::Result<Option<Bytes>> {
+        let url: hyper::Uri = (self.url_for)(key).parse()?;
+        // The URL isn't logged: a pre-signed one is as good as a credential.
+        debug!("fetching blob {}", key);
+        let resp = self.client.get(url).await?;
+        match resp.status() {
+            hyper::StatusCode::NOT_FOUND => Ok(None),
+            status if status.is_success() => {
+                Ok(Some(hyper::body::to_bytes(resp.into_body()).await?))
+            }
+            status => Err(anyhow!(
+                "fetching blob {} failed with status {}",
+                key,
+                status
+            )),
+        }
+    }
+    async fn put(&mut self, key: &str, _: &[u8]) -> anyhow::Result<()> {
+        Err(anyhow!("{:?} is read-only, can't write {}", self, key))
+    }
+    fn describe(&self) -> String {
+        "PresignedStore".to_owned()
+    }
+}
+
+/// Reads (and, if the server allows it, writes) blobs at `{base_url}/{key}` on a plain HTTP(S)
+/// object server, such as a CDN that supports `Range` requests but no S3 API.
+pub struct HttpStore {
+    client: HttpsClient,
+    base_url: String,
+}
+
+impl HttpStore {
+    pub fn new(base_url: impl Into<String>) -> Self {
+        Self {
+            client: https_client(),
+            base_url: base_url.into(),
+        }
+    }
+
+    fn url(&self, key: &str) -> anyhow::Result<hyper::Uri> {
+        let url = format!("{}/{}", self.base_url.trim_end_matches('/'), key);
+        url.parse()
+            .map_err(|err| anyhow!("{:?} isn't a valid URL: {}", url, err))
+    }
+}
+
+impl std::fmt::Debug for HttpStore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("HttpStore")
+            .field("base_url", &self.base_url)
+            .finish_non_exhaustive()
+    }
+}
+
+#[async_trait]
+impl Blobstore for HttpStore {
+    async fn get(&mut self, key: &str) -> anyhow::Result<Option<Cow<[u8]>>> {
+        Ok(self
+            .get_bytes(key)
+            .await?
+            .map(|blob| Cow::Owned(blob.into())))
+    }
+    async fn get_bytes(&mut self, key: &str) -> anyhow::Result<Option<Bytes>> {
+        debug!("fetching blob {}", key);
+        let resp = self.client.get(self.url(key)?).await?;
+        match resp.status() {
+            hyper::StatusCode::NOT_FOUND => Ok(None),
+            status if status.is_success() => {
+                Ok(Some(hyper::body::to_bytes(resp.into_body()).await?))
+            }
+            status => Err(anyhow!(
+                "fetching blob {} failed with status {}",
+                key,
+                status
+            )),
+        }
+    }
+    async fn get_range(&mut self, key: &str, range: Range<u64>) -> anyhow::Result<Option<Vec<u8>>> {
+        // An empty range can't be spelled as a `Range` header.
+        if range.is_empty() {
+            return Ok(self.exists(key).await?.then(Vec::new));
+        }
+        debug!("fetching {:?} of blob {}", range, key);
+        let req = hyper::Request::get(self.url(key)?)
+            .header(
+                hyper::header::RANGE,
+                format!("bytes={}-{}", range.start, range.end - 1),
+            )
+            .body(hyper::Body::empty())?;
+        let resp = self.client.request(req).await?;
+        match resp.status() {
+            hyper::StatusCode::NOT_FOUND => Ok(None),
+            // The range starts past the end of the blob.
+            hyper::StatusCode::RANGE_NOT_SATISFIABLE => Ok(Some(Vec::new())),
+            hyper::StatusCode::PARTIAL_CONTENT => {
+                Ok(Some(hyper::body::to_bytes(resp.into_body()).await?.into()))
+            }
+            // The server ignored the range and sent the whole blob.
+            hyper::StatusCode::OK => {
+                let blob = hyper::body::to_bytes(resp.into_body()).await?;
+                let end = range.end.min(blob.len() as u64);
+                let start = range.start.min(end);
+                Ok(Some(blob[start as usize..end as usize].to_vec()))
+            }
+            status => Err(anyhow!(
+                "fetching {:?} of blob {} failed with status {}",
+                range,
+                key,
+                status
+            )),
+        }
+    }
+    async fn put(&mut self, key: &str, blob: &[u8]) -> anyhow::Result<()> {
+        self.put_owned(key, blob.to_vec()).await
+    }
+    async fn put_owned(&mut self, key: &str, blob: Vec<u8>) -> anyhow::Result<()> {
+        debug!("pushing blob {}", key);
+        let req = hyper::Request::put(self.url(key)?).body(hyper::Body::from(blob))?;
+        let resp = self.client.request(req).await?;
+        match resp.status() {
+            status if status.is_success() => Ok(()),
+            status @ (hyper::StatusCode::METHOD_NOT_ALLOWED
+            | hyper::StatusCode::NOT_IMPLEMENTED) => Err(anyhow!(
+                "{} doesn't accept writes (PUT {} failed with status {})",
+                self.base_url,
+                key,
+                status
+            )),
+            status => Err(anyhow!(
+                "pushing blob {} failed with status {}",
+                key,
+                status
+            )),
+        }
+    }
+    async fn exists(&mut self, key: &str) -> anyhow::Result<bool> {
+        let req = hyper::Request::head(self.url(key)?).body(hyper::Body::empty())?;
+        let resp = self.client.request(req).await?;
+        match resp.status() {
+            hyper::StatusCode::NOT_FOUND => Ok(false),
+            status if status.is_success() => Ok(true),
+            status => Err(anyhow!("HEAD {} failed with status {}", key, status)),
+        }
+    }
+    fn describe(&self) -> String {
+        format!("HttpStore({})", self.base_url)
+    }
+    fn capabilities(&self) -> BlobstoreCapabilities {
+        BlobstoreCapabilities {
+            ranged_reads: true,
+            ..BlobstoreCapabilities::default()
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct Prefixed<B: Blobstore> {
     underlying: B,
-    prefix: String,
+    prefix: Prefix,
 }
 #[async_trait]
 impl<B: Blobstore> Blobstore for Prefixed<B> {
     async fn get(&mut self, key: &str) -> anyhow::Result<Option<Cow<[u8]>>> {
+        self.underlying.get(&self.prefix.join(key)).await
+    }
+    async fn get_bytes(&mut self, key: &str) -> anyhow::Result<Option<Bytes>> {
+        self.underlying.get_bytes(&self.prefix.join(key)).await
+    }
+    async fn get_stream(
+        &mut self,
+        key: &str,
+    ) -> anyhow::Result<Option<Box<dyn AsyncRead + Unpin + Send>>> {
+        self.underlying.get_stream(&self.prefix.join(key)).await
+    }
+    async fn get_range(&mut self, key: &str, range: Range<u64>) -> anyhow::Result<Option<Vec<u8>>> {
         self.underlying
-            .get(&format!("{}/{}", self.prefix, key))
+            .get_range(&self.prefix.join(key), range)
             .await
     }
+    async fn get_many(&mut self, keys: &[String]) -> anyhow::Result<Vec<Option<Vec<u8>>>> {
+        let keys: Vec<String> = keys.iter().map(|key| self.prefix.join(key)).collect();
+        self.underlying.get_many(&keys).await
+    }
     async fn put(&mut self, key: &str, blob: &[u8]) -> anyhow::Result<()> {
+        self.underlying.put(&self.prefix.join(key), blob).await
+    }
+    async fn put_owned(&mut self, key: &str, blob: Vec<u8>) -> anyhow::Result<()> {
+        self.underlying
+            .put_owned(&self.prefix.join(key), blob)
+            .await
+    }
+    async fn put_with_metadata(
+        &mut self,
+        key: &str,
+        blob: Vec<u8>,
+        metadata: &HashMap<String, String>,
+    ) -> anyhow::Result<()> {
+        self.underlying
+            .put_with_metadata(&self.prefix.join(key), blob, metadata)
+            .await
+    }
+    async fn put_stream(
+        &mut self,
+        key: &str,
+        body: Box<dyn AsyncRead + Unpin + Send + Sync>,
+        len: Option<u64>,
+    ) -> anyhow::Result<()> {
+        self.underlying
+            .put_stream(&self.prefix.join(key), body, len)
+            .await
+    }
+    async fn exists(&mut self, key: &str) -> anyhow::Result<bool> {
+        self.underlying.exists(&self.prefix.join(key)).await
+    }
+    async fn list(&mut self, prefix: &str) -> anyhow::Result<Vec<String>> {
+        let keys = self.underlying.list(&self.prefix.join(prefix)).await?;
+        Ok(keys
+            .into_iter()
+            .filter_map(|key| self.prefix.strip(&key).map(str::to_owned))
+            .collect())
+    }
+    async fn delete(&mut self, key: &str) -> anyhow::Result<()> {
+        self.underlying.delete(&self.prefix.join(key)).await
+    }
+    async fn rename(&mut self, src: &str, dst: &str) -> anyhow::Result<()> {
+        self.underlying
+            .rename(&self.prefix.join(src), &self.prefix.join(dst))
+            .await
+    }
+    async fn copy(&mut self, src: &str, dst: &str) -> anyhow::Result<()> {
+        self.underlying
+            .copy(&self.prefix.join(src), &self.prefix.join(dst))
+            .await
+    }
+    async fn put_if_changed(&mut self, key: &str, blob: &[u8]) -> anyhow::Result<bool> {
+        self.underlying
+            .put_if_changed(&self.prefix.join(key), blob)
+            .await
+    }
+    async fn put_if_match(&mut self, key: &str, blob: &[u8], etag: &str) -> anyhow::Result<bool> {
         self.underlying
-            .put(&format!("{}/{}", self.prefix, key), blob)
+            .put_if_match(&self.prefix.join(key), blob, etag)
             .await
     }
-}
+    async fn put_if_absent(&mut self, key: &str, blob: &[u8]) -> anyhow::Result<bool> {
+        self.underlying
+            .put_if_absent(&self.prefix.join(key), blob)
+            .await
+    }
+    fn describe(&self) -> String {
+        format!(
+            "Prefixed({:?}) -> {}",
+            self.prefix.as_str(),
+            self.underlying.describe()
+        )
+    }
+    fn capabilities(&self) -> BlobstoreCapabilities {
+        self.underlying.capabilities()
+    }
+}
+
+// Cached blobs are kept as `Bytes` so that handing them out never copies them: `get` lends them
+// straight out of the cache and `get_bytes` clones the handle. This can make a huge difference.
+// When scanning ~250k items (stored across 16 blocks), not copying the cached blocks reduced
+// runtime from
+//    just scan  46.35s user 0.41s system 92% cpu 50.583 total
+// to
+//    just scan  0.60s user 0.29s system 11% cpu 7.749 total
+#[derive(Debug)]
+pub struct Caching<B: Blobstore> {
+    underlying: B,
+    cache: LruCache<String, Cached>,
+    adaptive: Option<Adaptive>,
+    /// How long an entry stays valid, or `None` for as long as it stays cached.
+    ttl: Option<Duration>,
+    /// The most `bytes` may reach, if the cache is bounded by size rather than entries.
+    max_bytes: Option<usize>,
+    /// The total `Cached::cost` of every entry.
+    bytes: usize,
+    stats: CacheStats,
+}
+
+/// Counts of what a `Caching` has done since it was created.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub inserts: u64,
+    /// Entries dropped to make room, not counting those replaced or invalidated.
+    pub evictions: u64,
+}
+
+impl CacheStats {
+    /// The fraction of lookups served from the cache, or 0 before any lookups.
+    pub fn hit_ratio(&self) -> f64 {
+        match self.hits + self.misses {
+            0 => 0.0,
+            lookups => self.hits as f64 / lookups as f64,
+        }
+    }
+}
+
+impl std::ops::AddAssign for CacheStats {
+    fn add_assign(&mut self, other: CacheStats) {
+        self.hits += other.hits;
+        self.misses += other.misses;
+        self.inserts += other.inserts;
+        self.evictions += other.evictions;
+    }
+}
+
+impl std::fmt::Display for CacheStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} hits, {} misses ({:.1}% hit), {} inserts, {} evictions",
+            self.hits,
+            self.misses,
+            self.hit_ratio() * 100.0,
+            self.inserts,
+            self.evictions
+        )
+    }
+}
+
+// A blob as fetched by `Caching`, with `None` for one that didn't exist.
+#[derive(Debug)]
+struct Cached {
+    blob: Option<Bytes>,
+    fetched_at: Instant,
+}
+
+impl Cached {
+    fn new(blob: Option<Bytes>) -> Self {
+        Cached {
+            blob,
+            fetched_at: Instant::now(),
+        }
+    }
+
+    // What an entry counts against `max_bytes`. The key is included so that cached misses take
+    // up room too.
+    fn cost(&self, key: &str) -> usize {
+        key.len() + self.blob.as_ref().map_or(0, Bytes::len)
+    }
+}
+
+impl<B: Blobstore> Caching<B> {
+    pub fn stats(&self) -> CacheStats {
+        self.stats
+    }
+
+    // Readies the cache for a lookup of `key`: expires its entry if it's stale, and lets an
+    // adaptive cache resize, which may evict entries.
+    fn prepare(&mut self, key: &str) {
+        self.expire(key);
+        if let Some(adaptive) = &mut self.adaptive {
+            let len = self.cache.len();
+            adaptive.observe(key, &mut self.cache);
+            self.stats.evictions += len.saturating_sub(self.cache.len()) as u64;
+        }
+    }
+
+    // Drops the entry for `key` if it has outlived the TTL, so that it's fetched again.
+    fn expire(&mut self, key: &str) {
+        let Some(ttl) = self.ttl else {
+            return;
+        };
+        if self
+            .cache
+            .peek(key)
+            .is_some_and(|cached| cached.fetched_at.elapsed() >= ttl)
+        {
+            self.invalidate(key);
+        }
+    }
+
+    fn insert(&mut self, key: String, cached: Cached) {
+        let cost = cached.cost(&key);
+        if self.max_bytes.is_some_and(|max_bytes| cost > max_bytes) {
+            debug!("blob {} is too big to cache ({} bytes)", key, cost);
+            self.invalidate(&key);
+            return;
+        }
+        self.stats.inserts += 1;
+        // `push` hands back whatever it displaced: an older entry for `key`, or the LRU entry.
+        if let Some((old_key, old)) = self.cache.push(key.clone(), cached) {
+            self.bytes -= old.cost(&old_key);
+            if old_key != key {
+                self.stats.evictions += 1;
+            }
+        }
+        self.bytes += cost;
+        let Some(max_bytes) = self.max_bytes else {
+            return;
+        };
+        while self.bytes > max_bytes {
+            let Some((evicted_key, evicted)) = self.cache.pop_lru() else {
+                break;
+            };
+            self.bytes -= evicted.cost(&evicted_key);
+            self.stats.evictions += 1;
+        }
+    }
+
+    /// Drops the cached entry for `key`, if any, so that the next read fetches it again. The
+    /// underlying store is untouched.
+    pub fn invalidate(&mut self, key: &str) {
+        if let Some(cached) = self.cache.pop(key) {
+            self.bytes -= cached.cost(key);
+        }
+    }
+
+    /// Drops every cached entry, e.g. before reusing the store for another dataset. The
+    /// underlying store is untouched.
+    pub fn clear(&mut self) {
+        self.cache.clear();
+        self.bytes = 0;
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct AdaptiveCacheArgs {
+    pub min: usize,
+    pub max: usize,
+    /// The cache grows while its hit ratio is below this, as long as growing would help.
+    pub target_hit_ratio: f64,
+}
+
+// How many lookups to observe between resizes.
+const ADAPT_WINDOW: usize = 64;
+
+// Tracks the recent hit ratio of a `Caching` and resizes it. Keys evicted from the cache are
+// remembered (up to `args.max` of them) as "ghosts": a miss on a ghost is one a bigger cache
+// would have served, so the cache only grows when that happens. A workload with no reuse at all
+// never produces ghost hits and the cache shrinks back to `args.min`.
+#[derive(Debug)]
+struct Adaptive {
+    args: AdaptiveCacheArgs,
+    ghosts: LruCache<String, ()>,
+    lookups: usize,
+    hits: usize,
+    ghost_hits: usize,
+}
+
+impl Adaptive {
+    fn observe<V>(&mut self, key: &str, cache: &mut LruCache<String, V>) {
+        self.lookups += 1;
+        if cache.contains(key) {
+            self.hits += 1;
+        } else {
+            if self.ghosts.pop(key).is_some() {
+                self.ghost_hits += 1;
+            }
+            if cache.len() == cache.cap().get() {
+                if let Some((evicted, _)) = cache.peek_lru() {
+                    self.ghosts.put(evicted.clone(), ());
+                }
+            }
+        }
+        if self.lookups < ADAPT_WINDOW {
+            return;
+        }
+
+        let hit_ratio = self.hits as f64 / self.lookups as f64;
+        let cap = cache.cap().get();
+        let new_cap = if hit_ratio < self.args.target_hit_ratio && self.ghost_hits > 0 {
+            (cap * 2).min(self.args.max)
+        } else if self.hits == 0 && self.ghost_hits == 0 {
+            (cap / 2).max(self.args.min)
+        } else {
+            cap
+        };
+        if new_cap != cap {
+            debug!(
+                "resizing cache from {} to {} (hit ratio {:.2})",
+                cap, new_cap, hit_ratio
+            );
+            cache.resize(NonZeroUsize::new(new_cap).unwrap());
+        }
+        self.lookups = 0;
+        self.hits = 0;
+        self.ghost_hits = 0;
+    }
+}
+
+#[async_trait]
+impl<B: Blobstore> Blobstore for Caching<B> {
+    async fn get<'a>(&'a mut self, key: &str) -> anyhow::Result<Option<Cow<'a, [u8]>>> {
+        let blob = self.get_bytes(key).await?;
+        // That left the blob at the front of the cache, so it can be lent out from there, unless
+        // it was too big to cache.
+        if let Some(cached) = self.cache.peek(key) {
+            return Ok(cached.blob.as_deref().map(Cow::Borrowed));
+        }
+        Ok(blob.map(|blob| Cow::Owned(blob.into())))
+    }
+    async fn get_bytes(&mut self, key: &str) -> anyhow::Result<Option<Bytes>> {
+        self.prepare(key);
+        if let Some(cached) = self.cache.get(key) {
+            self.stats.hits += 1;
+            return Ok(cached.blob.clone());
+        }
+        self.stats.misses += 1;
+        let blob = self.underlying.get_bytes(key).await?;
+        self.insert(key.to_owned(), Cached::new(blob.clone()));
+        Ok(blob)
+    }
+    async fn get_many(&mut self, keys: &[String]) -> anyhow::Result<Vec<Option<Vec<u8>>>> {
+        let mut blobs = Vec::with_capacity(keys.len());
+        let mut misses = Vec::new();
+        for (i, key) in keys.iter().enumerate() {
+            self.prepare(key);
+            match self.cache.get(key) {
+                Some(cached) => {
+                    self.stats.hits += 1;
+                    blobs.push(cached.blob.as_deref().map(<[u8]>::to_vec));
+                }
+                None => {
+                    self.stats.misses += 1;
+                    blobs.push(None);
+                    misses.push(i);
+                }
+            }
+        }
+        if misses.is_empty() {
+            return Ok(blobs);
+        }
+        let missed: Vec<String> = misses.iter().map(|&i| keys[i].clone()).collect();
+        let fetched = self.underlying.get_many(&missed).await?;
+        for (i, blob) in misses.into_iter().zip(fetched) {
+            let cached = Cached::new(blob.as_deref().map(Bytes::copy_from_slice));
+            self.insert(keys[i].clone(), cached);
+            blobs[i] = blob;
+        }
+        Ok(blobs)
+    }
+    // Puts write through: once the underlying put succeeds, the blob is cached as written. A
+    // failed put may still have replaced the old contents, so their entry is dropped either way.
+    async fn put(&mut self, key: &str, blob: &[u8]) -> anyhow::Result<()> {
+        self.invalidate(key);
+        self.underlying.put(key, blob).await?;
+        self.insert(
+            key.to_owned(),
+            Cached::new(Some(Bytes::copy_from_slice(blob))),
+        );
+        Ok(())
+    }
+    async fn put_owned(&mut self, key: &str, blob: Vec<u8>) -> anyhow::Result<()> {
+        self.invalidate(key);
+        let cached = Bytes::copy_from_slice(&blob);
+        self.underlying.put_owned(key, blob).await?;
+        self.insert(key.to_owned(), Cached::new(Some(cached)));
+        Ok(())
+    }
+    async fn put_with_metadata(
+        &mut self,
+        key: &str,
+        blob: Vec<u8>,
+        metadata: &HashMap<String, String>,
+    ) -> anyhow::Result<()> {
+        self.invalidate(key);
+        let cached = Bytes::copy_from_slice(&blob);
+        self.underlying
+            .put_with_metadata(key, blob, metadata)
+            .await?;
+        self.insert(key.to_owned(), Cached::new(Some(cached)));
+        Ok(())
+    }
+    async fn put_stream(
+        &mut self,
+        key: &str,
+        body: Box<dyn AsyncRead + Unpin + Send + Sync>,
+        len: Option<u64>,
+    ) -> anyhow::Result<()> {
+        // The body isn't kept, so the next read fetches it.
+        self.invalidate(key);
+        self.underlying.put_stream(key, body, len).await
+    }
+    async fn put_if_changed(&mut self, key: &str, blob: &[u8]) -> anyhow::Result<bool> {
+        self.invalidate(key);
+        let changed = self.underlying.put_if_changed(key, blob).await?;
+        // Changed or not, the stored blob is now `blob`.
+        self.insert(
+            key.to_owned(),
+            Cached::new(Some(Bytes::copy_from_slice(blob))),
+        );
+        Ok(changed)
+    }
+    async fn put_if_match(&mut self, key: &str, blob: &[u8], etag: &str) -> anyhow::Result<bool> {
+        // Anything guarded by an ETag changes, so don't keep serving the old contents.
+        self.invalidate(key);
+        let matched = self.underlying.put_if_match(key, blob, etag).await?;
+        if matched {
+            self.insert(
+                key.to_owned(),
+                Cached::new(Some(Bytes::copy_from_slice(blob))),
+            );
+        }
+        Ok(matched)
+    }
+    async fn put_if_absent(&mut self, key: &str, blob: &[u8]) -> anyhow::Result<bool> {
+        // A cached miss is wrong once the write lands, and a refused one means someone else's
+        // blob is there.
+        self.invalidate(key);
+        let written = self.underlying.put_if_absent(key, blob).await?;
+        if written {
+            self.insert(
+                key.to_owned(),
+                Cached::new(Some(Bytes::copy_from_slice(blob))),
+            );
+        }
+        Ok(written)
+    }
+    async fn list(&mut self, prefix: &str) -> anyhow::Result<Vec<String>> {
+        self.underlying.list(prefix).await
+    }
+    async fn exists(&mut self, key: &str) -> anyhow::Result<bool> {
+        // Peek, so that checking for a blob doesn't keep it cached.
+        self.expire(key);
+        if self
+            .cache
+            .peek(key)
+            .is_some_and(|cached| cached.blob.is_some())
+        {
+            return Ok(true);
+        }
+        self.underlying.exists(key).await
+    }
+    async fn delete(&mut self, key: &str) -> anyhow::Result<()> {
+        self.invalidate(key);
+        self.underlying.delete(key).await
+    }
+    async fn rename(&mut self, src: &str, dst: &str) -> anyhow::Result<()> {
+        self.invalidate(src);
+        self.invalidate(dst);
+        self.underlying.rename(src, dst).await
+    }
+    async fn copy(&mut self, src: &str, dst: &str) -> anyhow::Result<()> {
+        self.invalidate(dst);
+        self.underlying.copy(src, dst).await
+    }
+    fn describe(&self) -> String {
+        let mut capacity = match (&self.adaptive, self.max_bytes) {
+            (Some(adaptive), _) => format!("{}..={}", adaptive.args.min, adaptive.args.max),
+            (None, Some(max_bytes)) => units::human_bytes(max_bytes as u64),
+            (None, None) => self.cache.cap().to_string(),
+        };
+        if let Some(ttl) = self.ttl {
+            capacity += &format!(", ttl {}", humantime::format_duration(ttl));
+        }
+        format!("Caching({}) -> {}", capacity, self.underlying.describe())
+    }
+    fn capabilities(&self) -> BlobstoreCapabilities {
+        self.underlying.capabilities()
+    }
+    fn cache_stats(&self) -> Option<CacheStats> {
+        Some(self.stats)
+    }
+}
+
+// A corrupted read is often a flaky transfer rather than a bad object, so a blob whose contents
+// don't match their expected checksum is fetched again before giving up. Blobs not in `expected`
+// are passed through unchecked. This must sit outside any `Caching`, or the re-fetch would just
+// return the same cached bytes.
+#[derive(Debug)]
+pub struct ChecksumRetrying<B: Blobstore> {
+    underlying: B,
+    expected: HashMap<String, String>,
+    attempts: usize,
+    budget: Option<RetryBudget>,
+}
+
+impl<B: Blobstore> ChecksumRetrying<B> {
+    /// Only re-fetches while `budget` allows it. Hand clones of one budget to every decorator
+    /// in a run so that they draw on it together.
+    pub fn with_budget(self, budget: RetryBudget) -> Self {
+        Self {
+            budget: Some(budget),
+            ..self
+        }
+    }
+}
+
+/// How many retries the binaries' `--retry-budget` allows before any requests have earned them.
+pub const DEFAULT_RETRY_BURST: usize = 10;
+
+/// Limits retries to a fraction of all requests, shared between everyone holding a clone, so
+/// that when failures are widespread a run fails fast instead of retrying everything. This is a
+/// token bucket: each first attempt earns `ratio` of a retry, each retry spends a whole one, and
+/// at most `burst` can be saved up.
+#[derive(Debug, Clone)]
+pub struct RetryBudget {
+    ratio: f64,
+    burst: f64,
+    tokens: Arc<Mutex<f64>>,
+}
+
+impl RetryBudget {
+    /// Allows about `ratio` (e.g. 0.1) retries per request, starting with `burst` to spend.
+    pub fn new(ratio: f64, burst: usize) -> Self {
+        Self {
+            ratio,
+            burst: burst as f64,
+            tokens: Arc::new(Mutex::new(burst as f64)),
+        }
+    }
+
+    /// Notes a first attempt at an operation.
+    pub fn record_request(&self) {
+        let mut tokens = self.tokens.lock().unwrap();
+        *tokens = (*tokens + self.ratio).min(self.burst);
+    }
+
+    /// Spends a retry, if there's one left. A `false` means the caller should give up.
+    pub fn try_retry(&self) -> bool {
+        let mut tokens = self.tokens.lock().unwrap();
+        if *tokens < 1.0 {
+            return false;
+        }
+        *tokens -= 1.0;
+        true
+    }
+}
+
+#[async_trait]
+impl<B: Blobstore> Blobstore for ChecksumRetrying<B> {
+    async fn get(&mut self, key: &str) -> anyhow::Result<Option<Cow<[u8]>>> {
+        let Some(expected) = self.expected.get(key) else {
+            return self.underlying.get(key).await;
+        };
+        if let Some(budget) = &self.budget {
+            budget.record_request();
+        }
+        let mut actual = String::new();
+        for attempt in 1..=self.attempts.max(1) {
+            let exhausted = attempt > 1 && !self.budget.as_ref().map_or(true, |b| b.try_retry());
+            if exhausted {
+                return Err(anyhow!(
+                    "blob {} has sha256 {}, expected {} (the retry budget is exhausted)",
+                    key,
+                    actual,
+                    expected
+                ));
+            }
+            let Some(blob) = self.underlying.get(key).await? else {
+                return Ok(None);
+            };
+            actual = hex::encode(ring::digest::digest(&ring::digest::SHA256, &blob));
+            if actual == *expected {
+                return Ok(Some(Cow::Owned(blob.into_owned())));
+            }
+            debug!("blob {} failed its checksum (attempt {})", key, attempt);
+        }
+        Err(anyhow!(
+            "blob {} has sha256 {}, expected {} (after {} attempts)",
+            key,
+            actual,
+            expected,
+            self.attempts.max(1)
+        ))
+    }
+    async fn put(&mut self, key: &str, blob: &[u8]) -> anyhow::Result<()> {
+        self.underlying.put(key, blob).await
+    }
+    async fn put_owned(&mut self, key: &str, blob: Vec<u8>) -> anyhow::Result<()> {
+        self.underlying.put_owned(key, blob).await
+    }
+    async fn put_with_metadata(
+        &mut self,
+        key: &str,
+        blob: Vec<u8>,
+        metadata: &HashMap<String, String>,
+    ) -> anyhow::Result<()> {
+        self.underlying.put_with_metadata(key, blob, metadata).await
+    }
+    async fn put_if_changed(&mut self, key: &str, blob: &[u8]) -> anyhow::Result<bool> {
+        self.underlying.put_if_changed(key, blob).await
+    }
+    async fn put_if_match(&mut self, key: &str, blob: &[u8], etag: &str) -> anyhow::Result<bool> {
+        self.underlying.put_if_match(key, blob, etag).await
+    }
+    async fn put_if_absent(&mut self, key: &str, blob: &[u8]) -> anyhow::Result<bool> {
+        self.underlying.put_if_absent(key, blob).await
+    }
+    async fn list(&mut self, prefix: &str) -> anyhow::Result<Vec<String>> {
+        self.underlying.list(prefix).await
+    }
+    async fn exists(&mut self, key: &str) -> anyhow::Result<bool> {
+        self.underlying.exists(key).await
+    }
+    async fn delete(&mut self, key: &str) -> anyhow::Result<()> {
+        self.underlying.delete(key).await
+    }
+    async fn rename(&mut self, src: &str, dst: &str) -> anyhow::Result<()> {
+        self.underlying.rename(src, dst).await
+    }
+    async fn copy(&mut self, src: &str, dst: &str) -> anyhow::Result<()> {
+        self.underlying.copy(src, dst).await
+    }
+    fn describe(&self) -> String {
+        format!(
+            "ChecksumRetrying({}) -> {}",
+            self.attempts,
+            self.underlying.describe()
+        )
+    }
+    fn capabilities(&self) -> BlobstoreCapabilities {
+        // Checksums cover whole blobs.
+        BlobstoreCapabilities {
+            ranged_reads: false,
+            ..self.underlying.capabilities()
+        }
+    }
+}
+
+/// S3 error codes that mean "try again later" rather than that the request was wrong.
+const TRANSIENT_CODES: &[&str] = &[
+    "InternalError",
+    "RequestTimeout",
+    "ServiceUnavailable",
+    "SlowDown",
+    "Throttling",
+    "ThrottlingException",
+    "RequestLimitExceeded",
+];
+
+/// Whether `err` looks like it could go away on its own: a timeout, a dropped connection,
+/// throttling or a 5xx from S3. Anything else (a missing bucket, a malformed key, denied access)
+/// would just fail again.
+pub fn is_transient(err: &anyhow::Error) -> bool {
+    err.chain().any(|cause| {
+        let sdk_error = sdk_error_transience::<GetObjectError>(cause)
+            .or_else(|| sdk_error_transience::<PutObjectError>(cause))
+            .or_else(|| sdk_error_transience::<HeadObjectError>(cause))
+            .or_else(|| sdk_error_transience::<ListObjectsV2Error>(cause))
+            .or_else(|| sdk_error_transience::<CopyObjectError>(cause))
+            .or_else(|| sdk_error_transience::<CreateMultipartUploadError>(cause))
+            .or_else(|| sdk_error_transience::<UploadPartError>(cause))
+            .or_else(|| sdk_error_transience::<CompleteMultipartUploadError>(cause));
+        if let Some(transient) = sdk_error {
+            transient
+        } else if let Some(err) = cause.downcast_ref::<GetObjectError>() {
+            err.code()
+                .is_some_and(|code| TRANSIENT_CODES.contains(&code))
+        } else if let Some(err) = cause.downcast_ref::<PutObjectError>() {
+            err.code()
+                .is_some_and(|code| TRANSIENT_CODES.contains(&code))
+        } else if let Some(err) = cause.downcast_ref::<ConnectorError>() {
+            err.is_io() || err.is_timeout()
+        } else if let Some(err) = cause.downcast_ref::<io::Error>() {
+            matches!(
+                err.kind(),
+                io::ErrorKind::TimedOut
+                    | io::ErrorKind::Interrupted
+                    | io::ErrorKind::ConnectionReset
+                    | io::ErrorKind::ConnectionAborted
+                    | io::ErrorKind::BrokenPipe
+                    | io::ErrorKind::UnexpectedEof
+            )
+        } else {
+            false
+        }
+    })
+}
+
+// Whether `cause` is transient, if it's an `SdkError` of an `E`-failing operation.
+fn sdk_error_transience<E>(cause: &(dyn std::error::Error + 'static)) -> Option<bool>
+where
+    E: ProvideErrorMetadata + std::error::Error + 'static,
+{
+    cause
+        .downcast_ref::<SdkError<E, HttpResponse>>()
+        .map(sdk_error_is_transient)
+}
+
+fn sdk_error_is_transient<E: ProvideErrorMetadata>(err: &SdkError<E, HttpResponse>) -> bool {
+    match err {
+        SdkError::TimeoutError(_) | SdkError::ResponseError(_) => true,
+        SdkError::DispatchFailure(failure) => failure.is_io() || failure.is_timeout(),
+        SdkError::ServiceError(service) => {
+            let status = service.raw().status().as_u16();
+            status == 429
+                || status >= 500
+                || service
+                    .err()
+                    .code()
+                    .is_some_and(|code| TRANSIENT_CODES.contains(&code))
+        }
+        _ => false,
+    }
+}
+
+/// The longest `Retrying` waits between two attempts.
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(20);
+
+// Retries reads, writes, `exists` and `list` on transient errors. A clean miss isn't an error,
+// so it's returned straight away. Operations that can't be replayed (`put_stream`) or aren't
+// idempotent (`delete`, `rename`, the conditional puts) are passed through once. With a
+// `RetryBudget`, retries stop once the run has spent it.
+#[derive(Debug, Clone)]
+pub struct Retrying<B: Blobstore> {
+    underlying: B,
+    max_attempts: usize,
+    base_delay: Duration,
+    budget: Option<RetryBudget>,
+}
+
+impl<B: Blobstore> Retrying<B> {
+    /// Waits about `base_delay` before the first retry, doubling for each one after that.
+    pub fn with_base_delay(self, base_delay: Duration) -> Self {
+        Self { base_delay, ..self }
+    }
+
+    /// Only retries while `budget` allows it. Hand clones of one budget to every decorator in a
+    /// run so that they draw on it together.
+    pub fn with_budget(self, budget: RetryBudget) -> Self {
+        Self {
+            budget: Some(budget),
+            ..self
+        }
+    }
+
+    // Notes the first attempt at an operation, which earns the budget a fraction of a retry.
+    fn record_request(&self) {
+        if let Some(budget) = &self.budget {
+            budget.record_request();
+        }
+    }
+
+    // Backs off after `attempt` failed with `err`, or gives `err` back if it's not worth
+    // retrying.
+    async fn retry_after(&self, attempt: usize, err: anyhow::Error) -> anyhow::Result<()> {
+        if !is_transient(&err) {
+            return Err(err);
+        }
+        if attempt >= self.max_attempts {
+            return Err(err.context(format!("giving up after {} attempts", attempt)));
+        }
+        if !self.budget.as_ref().is_none_or(RetryBudget::try_retry) {
+            return Err(err.context("the retry budget is exhausted"));
+        }
+        // Half of the delay is fixed and half is random, so that clients that failed together
+        // don't all retry together.
+        let delay = self
+            .base_delay
+            .saturating_mul(1 << (attempt - 1).min(16))
+            .min(MAX_RETRY_DELAY);
+        let delay = delay / 2 + delay.mul_f64(rand::thread_rng().gen::<f64>() / 2.0);
+        debug!(
+            "attempt {} failed, retrying in {:?}: {:#}",
+            attempt, delay, err
+        );
+        tokio::time::sleep(delay).await;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl<B: Blobstore> Blobstore for Retrying<B> {
+    async fn get(&mut self, key: &str) -> anyhow::Result<Option<Cow<[u8]>>> {
+        Ok(self
+            .get_bytes(key)
+            .await?
+            .map(|blob| Cow::Owned(blob.into())))
+    }
+    async fn get_bytes(&mut self, key: &str) -> anyhow::Result<Option<Bytes>> {
+        self.record_request();
+        let mut attempt = 1;
+        loop {
+            match self.underlying.get_bytes(key).await {
+                Ok(blob) => return Ok(blob),
+                Err(err) => self.retry_after(attempt, err).await?,
+            }
+            attempt += 1;
+        }
+    }
+    async fn get_many(&mut self, keys: &[String]) -> anyhow::Result<Vec<Option<Vec<u8>>>> {
+        self.record_request();
+        let mut attempt = 1;
+        loop {
+            match self.underlying.get_many(keys).await {
+                Ok(blobs) => return Ok(blobs),
+                Err(err) => self.retry_after(attempt, err).await?,
+            }
+            attempt += 1;
+        }
+    }
+    async fn get_stream(
+        &mut self,
+        key: &str,
+    ) -> anyhow::Result<Option<Box<dyn AsyncRead + Unpin + Send>>> {
+        self.underlying.get_stream(key).await
+    }
+    async fn get_range(&mut self, key: &str, range: Range<u64>) -> anyhow::Result<Option<Vec<u8>>> {
+        self.record_request();
+        let mut attempt = 1;
+        loop {
+            match self.underlying.get_range(key, range.clone()).await {
+                Ok(blob) => return Ok(blob),
+                Err(err) => self.retry_after(attempt, err).await?,
+            }
+            attempt += 1;
+        }
+    }
+    async fn put(&mut self, key: &str, blob: &[u8]) -> anyhow::Result<()> {
+        self.record_request();
+        let mut attempt = 1;
+        loop {
+            match self.underlying.put(key, blob).await {
+                Ok(()) => return Ok(()),
+                Err(err) => self.retry_after(attempt, err).await?,
+            }
+            attempt += 1;
+        }
+    }
+    async fn put_owned(&mut self, key: &str, blob: Vec<u8>) -> anyhow::Result<()> {
+        // Every attempt needs the blob, so there's nothing to gain from handing it over.
+        self.put(key, &blob).await
+    }
+    async fn put_with_metadata(
+        &mut self,
+        key: &str,
+        blob: Vec<u8>,
+        metadata: &HashMap<String, String>,
+    ) -> anyhow::Result<()> {
+        self.record_request();
+        let mut attempt = 1;
+        loop {
+            match self
+                .underlying
+                .put_with_metadata(key, blob.clone(), metadata)
+                .await
+            {
+                Ok(()) => return Ok(()),
+                Err(err) => self.retry_after(attempt, err).await?,
+            }
+            attempt += 1;
+        }
+    }
+    async fn put_stream(
+        &mut self,
+        key: &str,
+        body: Box<dyn AsyncRead + Unpin + Send + Sync>,
+        len: Option<u64>,
+    ) -> anyhow::Result<()> {
+        self.underlying.put_stream(key, body, len).await
+    }
+    async fn exists(&mut self, key: &str) -> anyhow::Result<bool> {
+        self.record_request();
+        let mut attempt = 1;
+        loop {
+            match self.underlying.exists(key).await {
+                Ok(exists) => return Ok(exists),
+                Err(err) => self.retry_after(attempt, err).await?,
+            }
+            attempt += 1;
+        }
+    }
+    async fn list(&mut self, prefix: &str) -> anyhow::Result<Vec<String>> {
+        self.record_request();
+        let mut attempt = 1;
+        loop {
+            match self.underlying.list(prefix).await {
+                Ok(keys) => return Ok(keys),
+                Err(err) => self.retry_after(attempt, err).await?,
+            }
+            attempt += 1;
+        }
+    }
+    async fn delete(&mut self, key: &str) -> anyhow::Result<()> {
+        self.underlying.delete(key).await
+    }
+    async fn rename(&mut self, src: &str, dst: &str) -> anyhow::Result<()> {
+        self.underlying.rename(src, dst).await
+    }
+    async fn copy(&mut self, src: &str, dst: &str) -> anyhow::Result<()> {
+        self.underlying.copy(src, dst).await
+    }
+    async fn put_if_changed(&mut self, key: &str, blob: &[u8]) -> anyhow::Result<bool> {
+        self.underlying.put_if_changed(key, blob).await
+    }
+    async fn put_if_match(&mut self, key: &str, blob: &[u8], etag: &str) -> anyhow::Result<bool> {
+        self.underlying.put_if_match(key, blob, etag).await
+    }
+    async fn put_if_absent(&mut self, key: &str, blob: &[u8]) -> anyhow::Result<bool> {
+        self.underlying.put_if_absent(key, blob).await
+    }
+    fn describe(&self) -> String {
+        format!(
+            "Retrying({}) -> {}",
+            self.max_attempts,
+            self.underlying.describe()
+        )
+    }
+    fn capabilities(&self) -> BlobstoreCapabilities {
+        self.underlying.capabilities()
+    }
+}
+
+/// Totals counted by a `Metered` store.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub struct MeteredStats {
+    pub gets: u64,
+    /// Gets of blobs that didn't exist.
+    pub misses: u64,
+    pub puts: u64,
+    pub bytes_read: u64,
+    /// Bytes of buffered puts; streamed ones aren't counted.
+    pub bytes_written: u64,
+}
+
+impl std::fmt::Display for MeteredStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} gets ({} missed, {} read), {} puts ({} written)",
+            self.gets,
+            self.misses,
+            units::human_bytes(self.bytes_read),
+            self.puts,
+            units::human_bytes(self.bytes_written)
+        )
+    }
+}
+
+/// Reads of one key counted by a `Metered` store, with `Metered::with_key_stats`.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub struct KeyStats {
+    /// Including gets of the key while it didn't exist.
+    pub gets: u64,
+    pub bytes_read: u64,
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    gets: AtomicU64,
+    misses: AtomicU64,
+    puts: AtomicU64,
+    bytes_read: AtomicU64,
+    bytes_written: AtomicU64,
+    // Only tallied with `Metered::with_key_stats`.
+    by_key: Option<Mutex<HashMap<String, KeyStats>>>,
+}
+
+impl Counters {
+    fn got(&self, key: &str, len: Option<usize>) {
+        self.gets.fetch_add(1, Ordering::Relaxed);
+        match len {
+            Some(len) => self.bytes_read.fetch_add(len as u64, Ordering::Relaxed),
+            None => self.misses.fetch_add(1, Ordering::Relaxed),
+        };
+        if let Some(by_key) = &self.by_key {
+            let mut by_key = by_key.lock().unwrap();
+            let stats = by_key.entry(key.to_owned()).or_default();
+            stats.gets += 1;
+            stats.bytes_read += len.unwrap_or_default() as u64;
+        }
+    }
+
+    fn put(&self, len: usize) {
+        self.puts.fetch_add(1, Ordering::Relaxed);
+        self.bytes_written.fetch_add(len as u64, Ordering::Relaxed);
+    }
+}
+
+// Counts gets, puts and their bytes. Clones share their counters, so a store can be snapshotted
+// after clones of it have been handed out (e.g. boxed into an `S3BlockReader`).
+#[derive(Debug, Clone)]
+pub struct Metered<B: Blobstore> {
+    underlying: B,
+    counters: Arc<Counters>,
+}
+
+impl<B: Blobstore> Metered<B> {
+    /// Also totals the gets and bytes read of each key, for `Metered::key_stats`. This starts the
+    /// counts afresh, so call it before handing out clones.
+    pub fn with_key_stats(self) -> Self {
+        Metered {
+            underlying: self.underlying,
+            counters: Arc::new(Counters {
+                by_key: Some(Mutex::default()),
+                ..Counters::default()
+            }),
+        }
+    }
+
+    /// The totals so far of each key that was read, across every clone. Empty unless the store
+    /// was built `with_key_stats`.
+    pub fn key_stats(&self) -> HashMap<String, KeyStats> {
+        match &self.counters.by_key {
+            Some(by_key) => by_key.lock().unwrap().clone(),
+            None => HashMap::new(),
+        }
+    }
+
+    /// The totals so far, across every clone.
+    pub fn snapshot(&self) -> MeteredStats {
+        MeteredStats {
+            gets: self.counters.gets.load(Ordering::Relaxed),
+            misses: self.counters.misses.load(Ordering::Relaxed),
+            puts: self.counters.puts.load(Ordering::Relaxed),
+            bytes_read: self.counters.bytes_read.load(Ordering::Relaxed),
+            bytes_written: self.counters.bytes_written.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[async_trait]
+impl<B: Blobstore> Blobstore for Metered<B> {
+    async fn get(&mut self, key: &str) -> anyhow::Result<Option<Cow<[u8]>>> {
+        let blob = self.underlying.get(key).await?;
+        self.counters.got(key, blob.as_ref().map(|blob| blob.len()));
+        Ok(blob)
+    }
+    async fn get_bytes(&mut self, key: &str) -> anyhow::Result<Option<Bytes>> {
+        let blob = self.underlying.get_bytes(key).await?;
+        self.counters.got(key, blob.as_ref().map(Bytes::len));
+        Ok(blob)
+    }
+    async fn get_many(&mut self, keys: &[String]) -> anyhow::Result<Vec<Option<Vec<u8>>>> {
+        let blobs = self.underlying.get_many(keys).await?;
+        for (key, blob) in keys.iter().zip(&blobs) {
+            self.counters.got(key, blob.as_ref().map(Vec::len));
+        }
+        Ok(blobs)
+    }
+    async fn get_stream(
+        &mut self,
+        key: &str,
+    ) -> anyhow::Result<Option<Box<dyn AsyncRead + Unpin + Send>>> {
+        // The bytes haven't been read yet, so only the get itself is counted.
+        let stream = self.underlying.get_stream(key).await?;
+        self.counters.got(key, stream.as_ref().map(|_| 0));
+        Ok(stream)
+    }
+    async fn get_range(&mut self, key: &str, range: Range<u64>) -> anyhow::Result<Option<Vec<u8>>> {
+        let blob = self.underlying.get_range(key, range).await?;
+        self.counters.got(key, blob.as_ref().map(Vec::len));
+        Ok(blob)
+    }
+    async fn put(&mut self, key: &str, blob: &[u8]) -> anyhow::Result<()> {
+        self.underlying.put(key, blob).await?;
+        self.counters.put(blob.len());
+        Ok(())
+    }
+    async fn put_owned(&mut self, key: &str, blob: Vec<u8>) -> anyhow::Result<()> {
+        let len = blob.len();
+        self.underlying.put_owned(key, blob).await?;
+        self.counters.put(len);
+        Ok(())
+    }
+    async fn put_with_metadata(
+        &mut self,
+        key: &str,
+        blob: Vec<u8>,
+        metadata: &HashMap<String, String>,
+    ) -> anyhow::Result<()> {
+        let len = blob.len();
+        self.underlying
+            .put_with_metadata(key, blob, metadata)
+            .await?;
+        self.counters.put(len);
+        Ok(())
+    }
+    async fn put_stream(
+        &mut self,
+        key: &str,
+        body: Box<dyn AsyncRead + Unpin + Send + Sync>,
+        len: Option<u64>,
+    ) -> anyhow::Result<()> {
+        self.underlying.put_stream(key, body, len).await?;
+        self.counters.put(0);
+        Ok(())
+    }
+    async fn put_if_changed(&mut self, key: &str, blob: &[u8]) -> anyhow::Result<bool> {
+        let changed = self.underlying.put_if_changed(key, blob).await?;
+        if changed {
+            self.counters.put(blob.len());
+        }
+        Ok(changed)
+    }
+    async fn put_if_match(&mut self, key: &str, blob: &[u8], etag: &str) -> anyhow::Result<bool> {
+        let matched = self.underlying.put_if_match(key, blob, etag).await?;
+        if matched {
+            self.counters.put(blob.len());
+        }
+        Ok(matched)
+    }
+    async fn put_if_absent(&mut self, key: &str, blob: &[u8]) -> anyhow::Result<bool> {
+        let written = self.underlying.put_if_absent(key, blob).await?;
+        if written {
+            self.counters.put(blob.len());
+        }
+        Ok(written)
+    }
+    async fn exists(&mut self, key: &str) -> anyhow::Result<bool> {
+        self.underlying.exists(key).await
+    }
+    async fn list(&mut self, prefix: &str) -> anyhow::Result<Vec<String>> {
+        self.underlying.list(prefix).await
+    }
+    async fn delete(&mut self, key: &str) -> anyhow::Result<()> {
+        self.underlying.delete(key).await
+    }
+    async fn rename(&mut self, src: &str, dst: &str) -> anyhow::Result<()> {
+        self.underlying.rename(src, dst).await
+    }
+    async fn copy(&mut self, src: &str, dst: &str) -> anyhow::Result<()> {
+        self.underlying.copy(src, dst).await
+    }
+    fn describe(&self) -> String {
+        format!("Metered -> {}", self.underlying.describe())
+    }
+    fn capabilities(&self) -> BlobstoreCapabilities {
+        self.underlying.capabilities()
+    }
+}
+
+// Errors are shared between everyone waiting on an operation, so they're wrapped in an `Arc`.
+type Flight<T> = Shared<BoxFuture<'static, Result<T, Arc<anyhow::Error>>>>;
+type Flights<T> = Arc<Mutex<HashMap<String, Flight<T>>>>;
+
+/// Joins the operation in flight under `id`, or starts one with `op` if there isn't any. The
+/// operation forgets itself when it finishes, so later calls start afresh.
+fn join_flight<T: Clone + Send + Sync + 'static>(
+    flights: &Flights<T>,
+    id: String,
+    op: impl FnOnce() -> BoxFuture<'static, anyhow::Result<T>>,
+) -> Flight<T> {
+    let mut in_flight = flights.lock().unwrap();
+    if let Some(flight) = in_flight.get(&id) {
+        return flight.clone();
+    }
+    let op = op();
+    let (landed, landed_id) = (Arc::clone(flights), id.clone());
+    let flight = async move {
+        let result = op.await.map_err(Arc::new);
+        landed.lock().unwrap().remove(&landed_id);
+        result
+    }
+    .boxed()
+    .shared();
+    in_flight.insert(id, flight.clone());
+    flight
+}
+
+#[derive(Clone)]
+pub struct Coalescing<B: Blobstore> {
+    underlying: B,
+    gets: Flights<Option<Bytes>>,
+    // Keyed by the key and a digest of the contents, so only identical writes are merged.
+    puts: Flights<()>,
+}
+
+impl<B: Blobstore> std::fmt::Debug for Coalescing<B> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Coalescing")
+            .field("underlying", &self.underlying)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<B: Blobstore> Coalescing<B> {
+    // Waits for every put of `key` in flight to land (or fail), so a read doesn't see what it's
+    // replacing.
+    async fn await_puts(&self, key: &str) {
+        let prefix = format!("{}\0", key);
+        let puts: Vec<_> = self
+            .puts
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(id, _)| id.starts_with(&prefix))
+            .map(|(_, flight)| flight.clone())
+            .collect();
+        for put in puts {
+            let _ = put.await;
+        }
+    }
+
+    // Forgets any get of `key` in flight, so that reads after a write don't share a fetch that
+    // started before it.
+    fn forget_get(&self, key: &str) {
+        self.gets.lock().unwrap().remove(key);
+    }
+}
+
+#[async_trait]
+impl<B: Blobstore + Clone + 'static> Blobstore for Coalescing<B> {
+    async fn get(&mut self, key: &str) -> anyhow::Result<Option<Cow<[u8]>>> {
+        Ok(self
+            .get_bytes(key)
+            .await?
+            .map(|blob| Cow::Owned(blob.to_vec())))
+    }
+    async fn get_bytes(&mut self, key: &str) -> anyhow::Result<Option<Bytes>> {
+        self.await_puts(key).await;
+        let mut underlying = self.underlying.clone();
+        let owned_key = key.to_owned();
+        let flight = join_flight(&self.gets, key.to_owned(), move || {
+            async move { underlying.get_bytes(&owned_key).await }.boxed()
+        });
+        flight.await.map_err(|err| anyhow!("{:#}", err))
+    }
+    async fn get_many(&mut self, keys: &[String]) -> anyhow::Result<Vec<Option<Vec<u8>>>> {
+        for key in keys {
+            self.await_puts(key).await;
+        }
+        self.underlying.get_many(keys).await
+    }
+    async fn get_stream(
+        &mut self,
+        key: &str,
+    ) -> anyhow::Result<Option<Box<dyn AsyncRead + Unpin + Send>>> {
+        self.await_puts(key).await;
+        self.underlying.get_stream(key).await
+    }
+    async fn get_range(&mut self, key: &str, range: Range<u64>) -> anyhow::Result<Option<Vec<u8>>> {
+        self.await_puts(key).await;
+        self.underlying.get_range(key, range).await
+    }
+    async fn put(&mut self, key: &str, blob: &[u8]) -> anyhow::Result<()> {
+        self.put_owned(key, blob.to_vec()).await
+    }
+    async fn put_owned(&mut self, key: &str, blob: Vec<u8>) -> anyhow::Result<()> {
+        let digest = hex::encode(ring::digest::digest(&ring::digest::SHA256, &blob));
+        let mut underlying = self.underlying.clone();
+        let owned_key = key.to_owned();
+        let flight = join_flight(&self.puts, format!("{}\0{}", key, digest), move || {
+            async move { underlying.put_owned(&owned_key, blob).await }.boxed()
+        });
+        let result = flight.await.map_err(|err| anyhow!("{:#}", err));
+        self.forget_get(key);
+        result
+    }
+    async fn put_with_metadata(
+        &mut self,
+        key: &str,
+        blob: Vec<u8>,
+        metadata: &HashMap<String, String>,
+    ) -> anyhow::Result<()> {
+        // Only identical puts with identical metadata share a flight.
+        let digest = hex::encode(ring::digest::digest(&ring::digest::SHA256, &blob));
+        let sorted: BTreeMap<_, _> = metadata.iter().collect();
+        let id = format!("{}\0{}\0{:?}", key, digest, sorted);
+        let mut underlying = self.underlying.clone();
+        let (owned_key, metadata) = (key.to_owned(), metadata.clone());
+        let flight = join_flight(&self.puts, id, move || {
+            async move {
+                underlying
+                    .put_with_metadata(&owned_key, blob, &metadata)
+                    .await
+            }
+            .boxed()
+        });
+        let result = flight.await.map_err(|err| anyhow!("{:#}", err));
+        self.forget_get(key);
+        result
+    }
+    async fn put_stream(
+        &mut self,
+        key: &str,
+        body: Box<dyn AsyncRead + Unpin + Send + Sync>,
+        len: Option<u64>,
+    ) -> anyhow::Result<()> {
+        // A stream can't be shared, so it's never coalesced.
+        let result = self.underlying.put_stream(key, body, len).await;
+        self.forget_get(key);
+        result
+    }
+    async fn list(&mut self, prefix: &str) -> anyhow::Result<Vec<String>> {
+        self.underlying.list(prefix).await
+    }
+    async fn exists(&mut self, key: &str) -> anyhow::Result<bool> {
+        self.underlying.exists(key).await
+    }
+    async fn delete(&mut self, key: &str) -> anyhow::Result<()> {
+        self.underlying.delete(key).await
+    }
+    async fn rename(&mut self, src: &str, dst: &str) -> anyhow::Result<()> {
+        self.underlying.rename(src, dst).await
+    }
+    async fn copy(&mut self, src: &str, dst: &str) -> anyhow::Result<()> {
+        self.underlying.copy(src, dst).await
+    }
+    async fn put_if_changed(&mut self, key: &str, blob: &[u8]) -> anyhow::Result<bool> {
+        self.underlying.put_if_changed(key, blob).await
+    }
+    async fn put_if_match(&mut self, key: &str, blob: &[u8], etag: &str) -> anyhow::Result<bool> {
+        self.underlying.put_if_match(key, blob, etag).await
+    }
+    async fn put_if_absent(&mut self, key: &str, blob: &[u8]) -> anyhow::Result<bool> {
+        self.underlying.put_if_absent(key, blob).await
+    }
+    fn describe(&self) -> String {
+        format!("Coalescing -> {}", self.underlying.describe())
+    }
+    fn capabilities(&self) -> BlobstoreCapabilities {
+        self.underlying.capabilities()
+    }
+}
+
+/// A trained zstd dictionary, identified by the SHA-256 of its contents so that datasets can
+/// refer to a shared dictionary from their manifest.
+#[derive(Clone)]
+pub struct CompressionDict {
+    pub id: String,
+    data: Vec<u8>,
+}
+
+impl CompressionDict {
+    pub fn new(data: Vec<u8>) -> Self {
+        let id = hex::encode(ring::digest::digest(&ring::digest::SHA256, &data));
+        Self { id, data }
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Where a dataset keeps a copy of the dictionary with the given id.
+    pub fn key(id: &str) -> String {
+        format!("dict/{}", id)
+    }
+
+    pub async fn load(blob: &mut impl Blobstore, id: &str) -> anyhow::Result<Self> {
+        let dict = Self::new(blob.must_get(&Self::key(id)).await?.into_owned());
+        if dict.id != id {
+            return Err(anyhow!("dictionary {} has mismatched contents", id));
+        }
+        Ok(dict)
+    }
+}
+
+impl std::fmt::Debug for CompressionDict {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("CompressionDict").field(&self.id).finish()
+    }
+}
+
+/// A compression algorithm for `Compressed`. Each one's output starts with a header that
+/// identifies it, so reading a blob with the wrong codec is an error rather than garbage.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Codec {
+    #[default]
+    Zstd,
+    /// Much faster to decompress than zstd, for larger blobs. Ignores the level.
+    Lz4,
+    Gzip,
+}
+
+impl Codec {
+    const ALL: [Codec; 3] = [Codec::Zstd, Codec::Lz4, Codec::Gzip];
+
+    fn magic(self) -> &'static [u8] {
+        match self {
+            Codec::Zstd => &ZSTD_MAGIC,
+            Codec::Lz4 => &LZ4_LEGACY_MAGIC,
+            Codec::Gzip => &GZIP_MAGIC,
+        }
+    }
+
+    /// The codec `blob` was compressed with, going by its header.
+    pub fn detect(blob: &[u8]) -> Option<Codec> {
+        Self::ALL
+            .into_iter()
+            .find(|codec| blob.starts_with(codec.magic()))
+    }
+
+    /// Decompresses `blob` (read from `key`), first checking that it was written with this
+    /// codec. For blobs read without `Compressed`, such as a compressed index. Blobs that
+    /// `Compressed::with_min_size` stored raw are accepted whatever the codec.
+    pub fn decode(
+        self,
+        key: &str,
+        blob: &[u8],
+        dict: Option<&CompressionDict>,
+    ) -> anyhow::Result<Vec<u8>> {
+        if let Some(raw) = blob.strip_prefix(&RAW_MAGIC) {
+            return Ok(raw.to_vec());
+        }
+        match Codec::detect(blob) {
+            Some(codec) if codec == self => {}
+            Some(other) => bail!(
+                "blob {} was compressed with {}, but is being read as {}",
+                key,
+                other,
+                self
+            ),
+            None => bail!("blob {} isn't {}-compressed", key, self),
+        }
+        match self {
+            Codec::Zstd => zstd_decode(blob, dict),
+            Codec::Lz4 => lz4_decode(blob),
+            Codec::Gzip => gzip_decode(blob),
+        }
+    }
+}
+
+impl std::fmt::Display for Codec {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Codec::Zstd => "zstd",
+            Codec::Lz4 => "lz4",
+            Codec::Gzip => "gzip",
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Compressed<B: Blobstore> {
+    underlying: B,
+    codec: Codec,
+    /// Only used by zstd.
+    dict: Option<CompressionDict>,
+    level: i32,
+    min_size: usize,
+}
+
+// Marks a blob that `Compressed` stored as is, because it was too small to be worth compressing.
+// Distinct from every codec's header, so any reader can tell the two apart.
+const RAW_MAGIC: [u8; 4] = *b"S3KR";
+
+impl<B: Blobstore> Compressed<B> {
+    /// Compresses at `level` instead (see `Blobstore::with_compression_level`).
+    pub fn with_level(self, level: i32) -> Self {
+        Self { level, ..self }
+    }
+
+    /// Stores blobs smaller than `min_size` bytes uncompressed (behind a 4-byte marker), since
+    /// a compression frame's overhead can make tiny blobs bigger. `Compressed` readers need no
+    /// setting to match; a `MaybeCompressed` one needs `with_raw_marker`.
+    pub fn with_min_size(self, min_size: usize) -> Self {
+        Self { min_size, ..self }
+    }
+
+    fn encode(&self, blob: &[u8]) -> anyhow::Result<Vec<u8>> {
+        if blob.len() < self.min_size {
+            let mut raw = Vec::with_capacity(RAW_MAGIC.len() + blob.len());
+            raw.extend_from_slice(&RAW_MAGIC);
+            raw.extend_from_slice(blob);
+            return Ok(raw);
+        }
+        match self.codec {
+            Codec::Zstd => zstd_encode(blob, self.dict.as_ref(), self.level),
+            Codec::Lz4 => lz4_encode(blob),
+            Codec::Gzip => gzip_encode(blob, self.level),
+        }
+    }
+}
+
+fn zstd_encode(blob: &[u8], dict: Option<&CompressionDict>, level: i32) -> anyhow::Result<Vec<u8>> {
+    let Some(dict) = dict else {
+        return Ok(zstd::encode_all(io::Cursor::new(blob), level)?);
+    };
+    let mut encoder = zstd::stream::write::Encoder::with_dictionary(Vec::new(), level, &dict.data)?;
+    encoder.write_all(blob)?;
+    Ok(encoder.finish()?)
+}
+
+fn zstd_decode(blob: &[u8], dict: Option<&CompressionDict>) -> anyhow::Result<Vec<u8>> {
+    let Some(dict) = dict else {
+        return Ok(zstd::decode_all(io::Cursor::new(blob))?);
+    };
+    let mut decoder = zstd::stream::read::Decoder::with_dictionary(blob, &dict.data)?;
+    let mut decoded = Vec::new();
+    decoder.read_to_end(&mut decoded)?;
+    Ok(decoded)
+}
+
+const GZIP_MAGIC: [u8; 2] = [0x1F, 0x8B];
+
+fn gzip_encode(blob: &[u8], level: i32) -> anyhow::Result<Vec<u8>> {
+    // gzip's levels run from 1 to 9; 0 means the default, as it does for zstd.
+    let compression = match level {
+        0 => flate2::Compression::default(),
+        level => flate2::Compression::new(level.clamp(1, 9) as u32),
+    };
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), compression);
+    encoder.write_all(blob)?;
+    Ok(encoder.finish()?)
+}
+
+fn gzip_decode(blob: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let mut decoded = Vec::new();
+    flate2::read::GzDecoder::new(blob).read_to_end(&mut decoded)?;
+    Ok(decoded)
+}
+
+// lz4 blobs use the "legacy" frame format (what `lz4 -l` writes): a magic number, then blocks
+// that each decompress to at most 8 MiB, each preceded by its compressed length.
+const LZ4_LEGACY_MAGIC: [u8; 4] = [0x02, 0x21, 0x4C, 0x18];
+const LZ4_LEGACY_BLOCK_SIZE: usize = 8 << 20;
+
+fn lz4_encode(blob: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let mut encoded = LZ4_LEGACY_MAGIC.to_vec();
+    for chunk in blob.chunks(LZ4_LEGACY_BLOCK_SIZE) {
+        let len = chunk.len() as c_int;
+        // SAFETY: a pure function of its argument.
+        let bound = unsafe { lz4_sys::LZ4_compressBound(len) };
+        let mut block = vec![0u8; bound as usize];
+        // SAFETY: `chunk` has `len` readable bytes and `block` has `bound` writable ones.
+        let written = unsafe {
+            lz4_sys::LZ4_compress_default(
+                chunk.as_ptr().cast(),
+                block.as_mut_ptr().cast(),
+                len,
+                bound,
+            )
+        };
+        if written <= 0 {
+            bail!("lz4 failed to compress a block of {} bytes", chunk.len());
+        }
+        encoded.extend((written as u32).to_le_bytes());
+        encoded.extend_from_slice(&block[..written as usize]);
+    }
+    Ok(encoded)
+}
+
+fn lz4_decode(blob: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let mut rest = &blob[LZ4_LEGACY_MAGIC.len()..];
+    let mut decoded = Vec::new();
+    while !rest.is_empty() {
+        let Some((len, tail)) = rest.split_first_chunk::<4>() else {
+            bail!("truncated lz4 block header");
+        };
+        let len = u32::from_le_bytes(*len) as usize;
+        if tail.len() < len {
+            bail!("truncated lz4 block");
+        }
+        let (block, tail) = tail.split_at(len);
+        let start = decoded.len();
+        decoded.resize(start + LZ4_LEGACY_BLOCK_SIZE, 0);
+        // SAFETY: `block` has `len` readable bytes and `decoded[start..]` has a whole block's
+        // worth of writable ones; lz4 never writes past the capacity it's given.
+        let n = unsafe {
+            lz4_sys::LZ4_decompress_safe(
+                block.as_ptr().cast(),
+                decoded[start..].as_mut_ptr().cast(),
+                c_int::try_from(len)?,
+                LZ4_LEGACY_BLOCK_SIZE as c_int,
+            )
+        };
+        if n < 0 {
+            bail!("corrupt lz4 block");
+        }
+        decoded.truncate(start + n as usize);
+        rest = tail;
+    }
+    Ok(decoded)
+}
+
+#[async_trait]
+impl<B: Blobstore> Blobstore for Compressed<B> {
+    async fn get(&mut self, key: &str) -> anyhow::Result<Option<Cow<[u8]>>> {
+        let Some(blob) = self.underlying.get(key).await? else {
+            return Ok(None);
+        };
+        debug!("decompressing blob {}", key);
+        let decoded = self.codec.decode(key, &blob, self.dict.as_ref())?;
+        Ok(Some(Cow::Owned(decoded)))
+    }
+    async fn get_many(&mut self, keys: &[String]) -> anyhow::Result<Vec<Option<Vec<u8>>>> {
+        let blobs = self.underlying.get_many(keys).await?;
+        keys.iter()
+            .zip(blobs)
+            .map(|(key, blob)| {
+                blob.map(|blob| self.codec.decode(key, &blob, self.dict.as_ref()))
+                    .transpose()
+            })
+            .collect()
+    }
+    async fn put(&mut self, key: &str, blob: &[u8]) -> anyhow::Result<()> {
+        let encoded = self.encode(blob)?;
+        self.underlying.put_owned(key, encoded).await
+    }
+    async fn put_with_metadata(
+        &mut self,
+        key: &str,
+        blob: Vec<u8>,
+        metadata: &HashMap<String, String>,
+    ) -> anyhow::Result<()> {
+        let encoded = self.encode(&blob)?;
+        self.underlying
+            .put_with_metadata(key, encoded, metadata)
+            .await
+    }
+    async fn put_if_changed(&mut self, key: &str, blob: &[u8]) -> anyhow::Result<bool> {
+        // Each codec's output is deterministic for a given input and level, so comparing the
+        // encoded bytes is equivalent to comparing the plaintext.
+        let encoded = self.encode(blob)?;
+        self.underlying.put_if_changed(key, &encoded).await
+    }
+    async fn put_if_match(&mut self, key: &str, blob: &[u8], etag: &str) -> anyhow::Result<bool> {
+        // The ETag is that of the stored, compressed blob.
+        let encoded = self.encode(blob)?;
+        self.underlying.put_if_match(key, &encoded, etag).await
+    }
+    async fn put_if_absent(&mut self, key: &str, blob: &[u8]) -> anyhow::Result<bool> {
+        let encoded = self.encode(blob)?;
+        self.underlying.put_if_absent(key, &encoded).await
+    }
+    async fn list(&mut self, prefix: &str) -> anyhow::Result<Vec<String>> {
+        self.underlying.list(prefix).await
+    }
+    async fn exists(&mut self, key: &str) -> anyhow::Result<bool> {
+        self.underlying.exists(key).await
+    }
+    async fn delete(&mut self, key: &str) -> anyhow::Result<()> {
+        self.underlying.delete(key).await
+    }
+    async fn rename(&mut self, src: &str, dst: &str) -> anyhow::Result<()> {
+        self.underlying.rename(src, dst).await
+    }
+    async fn copy(&mut self, src: &str, dst: &str) -> anyhow::Result<()> {
+        self.underlying.copy(src, dst).await
+    }
+    fn describe(&self) -> String {
+        let mut codec = self.codec.to_string();
+        if self.level != 0 {
+            codec.push_str(&format!(", level={}", self.level));
+        }
+        if let Some(dict) = &self.dict {
+            codec.push_str(&format!(", dict={}", dict.id));
+        }
+        if self.min_size != 0 {
+            codec.push_str(&format!(", min_size={}", self.min_size));
+        }
+        format!("Compressed({}) -> {}", codec, self.underlying.describe())
+    }
+    fn capabilities(&self) -> BlobstoreCapabilities {
+        // A byte range of the plaintext doesn't correspond to a range of the compressed blob.
+        BlobstoreCapabilities {
+            ranged_reads: false,
+            ..self.underlying.capabilities()
+        }
+    }
+}
+
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xB5, 0x2F, 0xFD];
+
+// Reads both compressed and uncompressed blobs, for datasets that are partway through a migration
+// to compression. A blob is decompressed only if it starts with the zstd frame magic *and* decodes
+// cleanly; anything else is passed through as-is. Raw data that happens to be a valid zstd frame
+// is indistinguishable from a compressed blob, so this is unsafe for arbitrary binary payloads.
+// Writes are always compressed.
+#[derive(Debug)]
+pub struct MaybeCompressed<B: Blobstore> {
+    underlying: B,
+    raw_marker: bool,
+}
+
+impl<B: Blobstore> MaybeCompressed<B> {
+    /// Also recognizes blobs that `Compressed::with_min_size` stored raw, and strips their
+    /// marker. Only for stores whose writer used it (see `Manifest::raw_blocks`), since a legacy
+    /// raw blob that happens to start with the marker would lose its first bytes.
+    pub fn with_raw_marker(self) -> Self {
+        Self {
+            raw_marker: true,
+            ..self
+        }
+    }
+}
+
+#[async_trait]
+impl<B: Blobstore> Blobstore for MaybeCompressed<B> {
+    async fn get<'a>(&'a mut self, key: &str) -> anyhow::Result<Option<Cow<'a, [u8]>>> {
+        let Some(blob) = self.underlying.get(key).await? else {
+            return Ok(None);
+        };
+        if self.raw_marker {
+            if let Some(raw) = blob.strip_prefix(&RAW_MAGIC) {
+                return Ok(Some(Cow::Owned(raw.to_vec())));
+            }
+        }
+        if blob.starts_with(&ZSTD_MAGIC) {
+            match zstd::decode_all(io::Cursor::new(&blob[..])) {
+                Ok(decoded) => {
+                    debug!("decompressing blob {}", key);
+                    return Ok(Some(Cow::Owned(decoded)));
+                }
+                Err(err) => debug!("blob {} is not a valid zstd frame: {}", key, err),
+            }
+        }
+        Ok(Some(blob))
+    }
+    async fn put(&mut self, key: &str, blob: &[u8]) -> anyhow::Result<()> {
+        let encoded = zstd::encode_all(io::Cursor::new(blob), 0)?;
+        self.underlying.put_owned(key, encoded).await
+    }
+    async fn put_with_metadata(
+        &mut self,
+        key: &str,
+        blob: Vec<u8>,
+        metadata: &HashMap<String, String>,
+    ) -> anyhow::Result<()> {
+        let encoded = zstd::encode_all(io::Cursor::new(blob), 0)?;
+        self.underlying
+            .put_with_metadata(key, encoded, metadata)
+            .await
+    }
+    async fn put_if_changed(&mut self, key: &str, blob: &[u8]) -> anyhow::Result<bool> {
+        // zstd's output is deterministic, so comparing the encoded bytes compares the plaintext.
+        let encoded = zstd::encode_all(io::Cursor::new(blob), 0)?;
+        self.underlying.put_if_changed(key, &encoded).await
+    }
+    async fn put_if_match(&mut self, key: &str, blob: &[u8], etag: &str) -> anyhow::Result<bool> {
+        // The ETag is that of the stored, compressed blob.
+        let encoded = zstd::encode_all(io::Cursor::new(blob), 0)?;
+        self.underlying.put_if_match(key, &encoded, etag).await
+    }
+    async fn put_if_absent(&mut self, key: &str, blob: &[u8]) -> anyhow::Result<bool> {
+        let encoded = zstd::encode_all(io::Cursor::new(blob), 0)?;
+        self.underlying.put_if_absent(key, &encoded).await
+    }
+    async fn list(&mut self, prefix: &str) -> anyhow::Result<Vec<String>> {
+        self.underlying.list(prefix).await
+    }
+    async fn exists(&mut self, key: &str) -> anyhow::Result<bool> {
+        self.underlying.exists(key).await
+    }
+    async fn delete(&mut self, key: &str) -> anyhow::Result<()> {
+        self.underlying.delete(key).await
+    }
+    async fn rename(&mut self, src: &str, dst: &str) -> anyhow::Result<()> {
+        self.underlying.rename(src, dst).await
+    }
+    async fn copy(&mut self, src: &str, dst: &str) -> anyhow::Result<()> {
+        self.underlying.copy(src, dst).await
+    }
+    fn describe(&self) -> String {
+        format!("MaybeCompressed(zstd) -> {}", self.underlying.describe())
+    }
+    fn capabilities(&self) -> BlobstoreCapabilities {
+        BlobstoreCapabilities {
+            ranged_reads: false,
+            ..self.underlying.capabilities()
+        }
+    }
+}
+
+/// A checksum for `Checksummed`. Each one is stored with a one-byte tag, so a blob can be
+/// verified whichever algorithm it was written with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ChecksumAlgorithm {
+    /// Fast, with hardware support on most CPUs. Catches corruption, not tampering.
+    #[default]
+    Crc32c,
+    /// CRC-64/XZ. Fewer collisions than CRC32C among very many blobs.
+    Crc64,
+    /// The first 16 bytes of a SHA-256. Much slower, but collisions are out of reach.
+    Sha256,
+}
+
+impl ChecksumAlgorithm {
+    const ALL: [ChecksumAlgorithm; 3] = [
+        ChecksumAlgorithm::Crc32c,
+        ChecksumAlgorithm::Crc64,
+        ChecksumAlgorithm::Sha256,
+    ];
+
+    fn tag(self) -> u8 {
+        match self {
+            ChecksumAlgorithm::Crc32c => 1,
+            ChecksumAlgorithm::Crc64 => 2,
+            ChecksumAlgorithm::Sha256 => 3,
+        }
+    }
+
+    pub fn checksum(self, blob: &[u8]) -> Vec<u8> {
+        match self {
+            ChecksumAlgorithm::Crc32c => crc32c::crc32c(blob).to_le_bytes().to_vec(),
+            ChecksumAlgorithm::Crc64 => crc64(blob).to_le_bytes().to_vec(),
+            ChecksumAlgorithm::Sha256 => {
+                ring::digest::digest(&ring::digest::SHA256, blob).as_ref()[..16].to_vec()
+            }
+        }
+    }
+
+    fn len(self) -> usize {
+        match self {
+            ChecksumAlgorithm::Crc32c => 4,
+            ChecksumAlgorithm::Crc64 => 8,
+            ChecksumAlgorithm::Sha256 => 16,
+        }
+    }
+}
+
+impl std::fmt::Display for ChecksumAlgorithm {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            ChecksumAlgorithm::Crc32c => "crc32c",
+            ChecksumAlgorithm::Crc64 => "crc64",
+            ChecksumAlgorithm::Sha256 => "sha256",
+        })
+    }
+}
+
+// CRC-64/XZ: the ECMA-182 polynomial, reflected, with the register and output inverted.
+const CRC64_TABLE: [u64; 256] = {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u64;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0xC96C_5795_D787_0F42
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+fn crc64(blob: &[u8]) -> u64 {
+    let crc = blob.iter().fold(!0u64, |crc, &b| {
+        CRC64_TABLE[((crc ^ b as u64) & 0xFF) as usize] ^ (crc >> 8)
+    });
+    !crc
+}
+
+/// How a read through `Checksummed` fails when a blob's contents don't match its stored
+/// checksum. Find it with `anyhow::Error::downcast_ref` to tell corruption apart from other
+/// errors.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IntegrityError {
+    pub key: String,
+    pub algorithm: ChecksumAlgorithm,
+}
+
+impl std::fmt::Display for IntegrityError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "blob {} failed its {} checksum",
+            self.key, self.algorithm
+        )
+    }
+}
+
+impl std::error::Error for IntegrityError {}
+
+// Appends a checksum of each blob to it, followed by the algorithm's tag, and checks it on every
+// read. Reads accept any algorithm; `algorithm` is only what writes use.
+#[derive(Debug)]
+pub struct Checksummed<B: Blobstore> {
+    underlying: B,
+    algorithm: ChecksumAlgorithm,
+}
+
+impl<B: Blobstore> Checksummed<B> {
+    fn encode(&self, mut blob: Vec<u8>) -> Vec<u8> {
+        let checksum = self.algorithm.checksum(&blob);
+        blob.extend(checksum);
+        blob.push(self.algorithm.tag());
+        blob
+    }
+}
+
+// The length of the contents of `blob` (read from `key`), once its trailer has been checked.
+fn verified_len(key: &str, blob: &[u8]) -> anyhow::Result<usize> {
+    let Some((&tag, rest)) = blob.split_last() else {
+        bail!("blob {} is empty, so has no checksum", key);
+    };
+    let Some(algorithm) = ChecksumAlgorithm::ALL.into_iter().find(|a| a.tag() == tag) else {
+        bail!("blob {} has an unknown checksum tag {}", key, tag);
+    };
+    let Some(len) = rest.len().checked_sub(algorithm.len()) else {
+        bail!("blob {} is too short for its {} checksum", key, algorithm);
+    };
+    let (contents, checksum) = rest.split_at(len);
+    if algorithm.checksum(contents) != checksum {
+        return Err(IntegrityError {
+            key: key.to_owned(),
+            algorithm,
+        }
+        .into());
+    }
+    Ok(len)
+}
+
+#[async_trait]
+impl<B: Blobstore> Blobstore for Checksummed<B> {
+    async fn get(&mut self, key: &str) -> anyhow::Result<Option<Cow<[u8]>>> {
+        let Some(blob) = self.underlying.get(key).await? else {
+            return Ok(None);
+        };
+        let len = verified_len(key, &blob)?;
+        Ok(Some(match blob {
+            Cow::Borrowed(blob) => Cow::Borrowed(&blob[..len]),
+            Cow::Owned(mut blob) => {
+                blob.truncate(len);
+                Cow::Owned(blob)
+            }
+        }))
+    }
+    async fn get_bytes(&mut self, key: &str) -> anyhow::Result<Option<Bytes>> {
+        let Some(blob) = self.underlying.get_bytes(key).await? else {
+            return Ok(None);
+        };
+        let len = verified_len(key, &blob)?;
+        Ok(Some(blob.slice(..len)))
+    }
+    async fn get_many(&mut self, keys: &[String]) -> anyhow::Result<Vec<Option<Vec<u8>>>> {
+        let blobs = self.underlying.get_many(keys).await?;
+        keys.iter()
+            .zip(blobs)
+            .map(|(key, blob)| {
+                blob.map(|mut blob| {
+                    blob.truncate(verified_len(key, &blob)?);
+                    Ok(blob)
+                })
+                .transpose()
+            })
+            .collect()
+    }
+    async fn put(&mut self, key: &str, blob: &[u8]) -> anyhow::Result<()> {
+        self.put_owned(key, blob.to_vec()).await
+    }
+    async fn put_owned(&mut self, key: &str, blob: Vec<u8>) -> anyhow::Result<()> {
+        let encoded = self.encode(blob);
+        self.underlying.put_owned(key, encoded).await
+    }
+    async fn put_with_metadata(
+        &mut self,
+        key: &str,
+        blob: Vec<u8>,
+        metadata: &HashMap<String, String>,
+    ) -> anyhow::Result<()> {
+        let encoded = self.encode(blob);
+        self.underlying
+            .put_with_metadata(key, encoded, metadata)
+            .await
+    }
+    async fn put_if_changed(&mut self, key: &str, blob: &[u8]) -> anyhow::Result<bool> {
+        // The trailer is a function of the contents, so comparing encoded blobs is equivalent.
+        let encoded = self.encode(blob.to_vec());
+        self.underlying.put_if_changed(key, &encoded).await
+    }
+    async fn put_if_match(&mut self, key: &str, blob: &[u8], etag: &str) -> anyhow::Result<bool> {
+        let encoded = self.encode(blob.to_vec());
+        self.underlying.put_if_match(key, &encoded, etag).await
+    }
+    async fn put_if_absent(&mut self, key: &str, blob: &[u8]) -> anyhow::Result<bool> {
+        let encoded = self.encode(blob.to_vec());
+        self.underlying.put_if_absent(key, &encoded).await
+    }
+    async fn list(&mut self, prefix: &str) -> anyhow::Result<Vec<String>> {
+        self.underlying.list(prefix).await
+    }
+    async fn exists(&mut self, key: &str) -> anyhow::Result<bool> {
+        self.underlying.exists(key).await
+    }
+    async fn delete(&mut self, key: &str) -> anyhow::Result<()> {
+        self.underlying.delete(key).await
+    }
+    async fn rename(&mut self, src: &str, dst: &str) -> anyhow::Result<()> {
+        self.underlying.rename(src, dst).await
+    }
+    async fn copy(&mut self, src: &str, dst: &str) -> anyhow::Result<()> {
+        self.underlying.copy(src, dst).await
+    }
+    fn describe(&self) -> String {
+        format!(
+            "Checksummed({}) -> {}",
+            self.algorithm,
+            self.underlying.describe()
+        )
+    }
+    fn capabilities(&self) -> BlobstoreCapabilities {
+        // A range can't be checked without the rest of the blob.
+        BlobstoreCapabilities {
+            ranged_reads: false,
+            ..self.underlying.capabilities()
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        borrow::Cow,
+        collections::HashMap,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc, Mutex,
+        },
+        time::Duration,
+    };
+
+    use crate::{
+        blob::{
+            content_etag, crc64, is_transient, AdaptiveCacheArgs, Blobstore, BlobstoreCapabilities,
+            CacheStats, Cached, ChecksumAlgorithm, Codec, CompressionDict, HttpStore,
+            IntegrityError, KeyStats, LocalFilesystem, MemoryStore, MeteredStats, PresignedStore,
+            RetryBudget, S3Client,
+        },
+        path::Prefix,
+    };
+    use async_trait::async_trait;
+    use aws_sdk_s3::{
+        config::{BehaviorVersion, Credentials, Region},
+        types::{ObjectCannedAcl, ServerSideEncryption, StorageClass},
+    };
+    use aws_smithy_runtime::client::http::test_util::{
+        capture_request, infallible_client_fn, CaptureRequestReceiver,
+    };
+    use tempfile::tempdir;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        sync::Semaphore,
+    };
+
+    #[tokio::test]
+    async fn get_not_found() -> anyhow::Result<()> {
+        let base = tempdir()?.into_path();
+        let mut fs = LocalFilesystem {
+            base: base.as_path().to_path_buf(),
+            sync_on_put: false,
+        };
+        assert_eq!(fs.get("any-key").await?, None);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn put_creates_parent_directories() -> anyhow::Result<()> {
+        let tmp = tempdir()?;
+        let mut fs = LocalFilesystem {
+            base: tmp.path().to_path_buf(),
+            sync_on_put: false,
+        };
+        fs.put("a/b/c.txt", b"nested").await?;
+        assert_eq!(std::fs::read(tmp.path().join("a/b/c.txt"))?, b"nested");
+        fs.copy("a/b/c.txt", "d/e.txt").await?;
+        fs.rename("d/e.txt", "f/g/h.txt").await?;
+        assert_eq!(fs.must_get("f/g/h.txt").await?, &b"nested"[..]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn garbage_path_errors() -> anyhow::Result<()> {
+        let base = tempdir()?.into_path();
+        let mut fs = LocalFilesystem {
+            base: base.as_path().to_path_buf(),
+            sync_on_put: false,
+        };
+        assert!(fs.get("/////").await.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn round_trip_test() -> anyhow::Result<()> {
+        let base = tempdir()?.into_path();
+        let mut fs = LocalFilesystem {
+            base: base.as_path().to_path_buf(),
+            sync_on_put: false,
+        };
+        let expected = "Hello, World!".as_bytes().to_vec();
+
+        fs.put("my-file.txt", &expected).await?;
+        let actual = fs.get("my-file.txt").await?;
+        assert_eq!(actual, Some(Cow::Borrowed(expected.as_slice())));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn local_ranged_reads() -> anyhow::Result<()> {
+        let base = tempdir()?;
+        let mut fs = LocalFilesystem {
+            base: base.path().to_path_buf(),
+            sync_on_put: false,
+        };
+        fs.put("my-file.txt", b"Hello, World!").await?;
+        assert_eq!(
+            fs.get_range("my-file.txt", 7..12).await?,
+            Some(b"World".to_vec())
+        );
+        assert_eq!(
+            fs.get_range("my-file.txt", 7..100).await?,
+            Some(b"World!".to_vec())
+        );
+        assert_eq!(fs.get_range("my-file.txt", 50..60).await?, Some(Vec::new()));
+        assert_eq!(fs.get_range("missing", 0..1).await?, None);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn synced_round_trip() -> anyhow::Result<()> {
+        let base = tempdir()?;
+        let mut fs = LocalFilesystem {
+            base: base.path().to_path_buf(),
+            sync_on_put: true,
+        };
+        fs.put("small", b"Hello, World!").await?;
+        let body: Vec<u8> = (0..10_000u32).flat_map(|i| i.to_le_bytes()).collect();
+        fs.put_stream(
+            "streamed",
+            Box::new(std::io::Cursor::new(body.clone())),
+            None,
+        )
+        .await?;
+        assert_eq!(fs.must_get("small").await?, &b"Hello, World!"[..]);
+        assert_eq!(fs.must_get("streamed").await?, body);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn put_stream_round_trip() -> anyhow::Result<()> {
+        let base = tempdir()?.into_path();
+        let mut fs = LocalFilesystem {
+            base: base.as_path().to_path_buf(),
+            sync_on_put: false,
+        };
+        let expected: Vec<u8> = (0..100_000u32).flat_map(|i| i.to_le_bytes()).collect();
+        let mut source = tempfile::NamedTempFile::new()?;
+        std::io::Write::write_all(&mut source, &expected)?;
+
+        let body = tokio::fs::File::open(source.path()).await?;
+        fs.put_stream("streamed.bin", Box::new(body), Some(expected.len() as u64))
+            .await?;
+        assert_eq!(fs.must_get("streamed.bin").await?, expected.as_slice());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn rename_moves_blob() -> anyhow::Result<()> {
+        let base = tempdir()?.into_path();
+        let mut fs = LocalFilesystem {
+            base: base.as_path().to_path_buf(),
+            sync_on_put: false,
+        };
+        fs.put("src.txt", b"moving").await?;
+        fs.put("dst.txt", b"stale").await?;
+
+        fs.rename("src.txt", "dst.txt").await?;
+        assert_eq!(fs.get("src.txt").await?, None);
+        assert_eq!(fs.must_get("dst.txt").await?, b"moving".as_slice());
+        assert!(fs.rename("src.txt", "dst.txt").await.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn s3_copies_server_side() -> anyhow::Result<()> {
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let seen = requests.clone();
+        let client = s3_client(infallible_client_fn(move |req| {
+            let copy_source = req
+                .headers()
+                .get("x-amz-copy-source")
+                .and_then(|v| v.to_str().ok())
+                .map(str::to_owned);
+            seen.lock()
+                .unwrap()
+                .push((req.method().to_string(), copy_source));
+            let body = "<CopyObjectResult><ETag>\"etag\"</ETag></CopyObjectResult>".to_owned();
+            hyper::Response::builder().status(200).body(body).unwrap()
+        }));
+        let mut s3 =
+            S3Client::new(client, "my-bucket").with_prefix(&prefix("2023-05-08T15:55:27+00:00"));
+        s3.copy("block/00", "block/01").await?;
+        // One request, and no GET: the bytes never pass through here.
+        let source = "my-bucket/2023-05-08T15%3A55%3A27%2B00%3A00/block/00";
+        assert_eq!(
+            *requests.lock().unwrap(),
+            [("PUT".to_owned(), Some(source.to_owned()))]
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn s3_stores_metadata_through_decorators() -> anyhow::Result<()> {
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let seen = requests.clone();
+        let client = s3_client(infallible_client_fn(move |req| {
+            let header = |name: &str| {
+                req.headers()
+                    .get(name)
+                    .and_then(|v| v.to_str().ok())
+                    .map(str::to_owned)
+            };
+            seen.lock().unwrap().push((
+                header("content-length"),
+                header("x-amz-meta-s3kv-len"),
+                header("x-amz-meta-s3kv-stored-len"),
+            ));
+            hyper::Response::builder()
+                .status(200)
+                .body(String::new())
+                .unwrap()
+        }));
+        let mut s3 = S3Client::new(client, "my-bucket")
+            .with_retry(2)
+            .with_compression()
+            .with_prefix(&prefix("dataset"))
+            .with_coalescing();
+        let metadata = HashMap::from([("s3kv-len".to_owned(), "1000".to_owned())]);
+        s3.put_with_metadata("block/00", vec![b'a'; 1000], &metadata)
+            .await?;
+
+        let requests = requests.lock().unwrap();
+        let [(content_length, len, stored_len)] = requests.as_slice() else {
+            panic!("expected one request, got {:?}", requests);
+        };
+        assert_eq!(len.as_deref(), Some("1000"));
+        // What's stored is the compressed blob.
+        assert_eq!(stored_len, content_length);
+        assert_ne!(content_length.as_deref(), Some("1000"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn put_if_changed_skips_identical() -> anyhow::Result<()> {
+        let base = tempdir()?.into_path();
+        let mut fs = LocalFilesystem {
+            base: base.as_path().to_path_buf(),
+            sync_on_put: false,
+        };
+
+        assert!(fs.put_if_changed("my-file.txt", b"first").await?);
+        assert!(!fs.put_if_changed("my-file.txt", b"first").await?);
+        assert!(fs.put_if_changed("my-file.txt", b"second").await?);
+        assert_eq!(fs.must_get("my-file.txt").await?, b"second".as_slice());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn put_if_match_rejects_stale_etags() -> anyhow::Result<()> {
+        let tmp = tempdir()?;
+        let mut fs = LocalFilesystem {
+            base: tmp.path().to_path_buf(),
+            sync_on_put: false,
+        };
+        assert!(
+            !fs.put_if_match("CURRENT", b"v1", &content_etag(b""))
+                .await?
+        );
+        fs.put("CURRENT", b"v1").await?;
+        let stale = content_etag(b"v1");
+
+        assert!(fs.put_if_match("CURRENT", b"v2", &stale).await?);
+        // Someone else's write already replaced v1, so this one is refused.
+        assert!(!fs.put_if_match("CURRENT", b"v3", &stale).await?);
+        assert_eq!(fs.must_get("CURRENT").await?, b"v2".as_slice());
+        assert!(
+            fs.put_if_match("CURRENT", b"v3", &content_etag(b"v2"))
+                .await?
+        );
+        assert_eq!(fs.must_get("CURRENT").await?, b"v3".as_slice());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn s3_puts_send_the_content_type() -> anyhow::Result<()> {
+        let content_types = Arc::new(Mutex::new(Vec::new()));
+        let seen = content_types.clone();
+        let client = s3_client(infallible_client_fn(move |req| {
+            let content_type = req
+                .headers()
+                .get("content-type")
+                .and_then(|v| v.to_str().ok())
+                .map(str::to_owned);
+            seen.lock().unwrap().push(content_type);
+            hyper::Response::builder()
+                .status(200)
+                .body(String::new())
+                .unwrap()
+        }));
+        let mut s3 = S3Client::new(client, "my-bucket").with_content_type("application/zstd");
+        s3.put("block/00", b"compressed").await?;
+        s3.put_owned("block/01", b"compressed".to_vec()).await?;
+        assert!(s3.put_if_absent("block/02", b"compressed").await?);
+        assert_eq!(
+            *content_types.lock().unwrap(),
+            vec![Some("application/zstd".to_owned()); 3]
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn s3_put_if_match_sends_if_match() -> anyhow::Result<()> {
+        let current = content_etag(b"v1");
+        let expected = current.clone();
+        let client = s3_client(infallible_client_fn(move |req| {
+            let matches = req
+                .headers()
+                .get("if-match")
+                .is_some_and(|etag| *etag == *expected);
+            let status = if matches { 200 } else { 412 };
+            hyper::Response::builder()
+                .status(status)
+                .body(String::new())
+                .unwrap()
+        }));
+        let mut s3 = S3Client::new(client, "my-bucket");
+        assert!(!s3.put_if_match("CURRENT", b"v2", "\"stale\"").await?);
+        assert!(s3.put_if_match("CURRENT", b"v2", &current).await?);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn s3_put_if_absent_sends_if_none_match() -> anyhow::Result<()> {
+        let existing = Arc::new(Mutex::new(Vec::new()));
+        let stored = existing.clone();
+        let client = s3_client(infallible_client_fn(move |req| {
+            assert_eq!(
+                req.headers()
+                    .get("if-none-match")
+                    .and_then(|v| v.to_str().ok()),
+                Some("*")
+            );
+            let mut stored = stored.lock().unwrap();
+            let key = req.uri().to_string();
+            let status = if stored.contains(&key) {
+                412
+            } else {
+                stored.push(key);
+                200
+            };
+            hyper::Response::builder()
+                .status(status)
+                .body(String::new())
+                .unwrap()
+        }));
+        let mut s3 = S3Client::new(client, "my-bucket");
+        assert!(s3.put_if_absent("block/00", b"first").await?);
+        assert!(!s3.put_if_absent("block/00", b"second").await?);
+        assert!(s3.put_if_absent("block/01", b"other").await?);
+        assert_eq!(existing.lock().unwrap().len(), 2);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn codecs() -> anyhow::Result<()> {
+        let tmp = tempdir()?;
+        let fs = || LocalFilesystem {
+            base: tmp.path().to_path_buf(),
+            sync_on_put: false,
+        };
+        let blob: Vec<u8> = (0..10_000u32)
+            .flat_map(|i| format!("record-{} ", i % 977).into_bytes())
+            .collect();
+        for codec in [Codec::Zstd, Codec::Lz4, Codec::Gzip] {
+            let key = codec.to_string();
+            let mut store = fs().with_codec(codec);
+            store.put(&key, &blob).await?;
+            store.put("empty", b"").await?;
+            assert_eq!(store.must_get(&key).await?, blob);
+            assert_eq!(store.must_get("empty").await?, b"".as_slice());
+            let stored = fs().must_get(&key).await?.into_owned();
+            assert!(stored.len() < blob.len() / 2);
+            assert_eq!(Codec::detect(&stored), Some(codec));
+            assert_eq!(
+                store.describe(),
+                format!("Compressed({}) -> {}", codec, fs().describe())
+            );
+        }
+
+        // Reading with another codec is refused rather than returning garbage.
+        let err = fs().with_codec(Codec::Lz4).get("gzip").await.unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "blob gzip was compressed with gzip, but is being read as lz4"
+        );
+        fs().put("raw", b"not compressed").await?;
+        let err = fs().with_compression().get("raw").await.unwrap_err();
+        assert_eq!(err.to_string(), "blob raw isn't zstd-compressed");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn compression_level() -> anyhow::Result<()> {
+        let tmp = tempdir()?;
+        let fs = || LocalFilesystem {
+            base: tmp.path().to_path_buf(),
+            sync_on_put: false,
+        };
+        let blob: Vec<u8> = (0..10_000u32)
+            .flat_map(|i| format!("record-{} ", i % 977).into_bytes())
+            .collect();
+        fs().with_compression().put("default", &blob).await?;
+        let mut smaller = fs().with_compression_level(19);
+        smaller.put("smaller", &blob).await?;
+        assert_eq!(
+            smaller.describe(),
+            format!("Compressed(zstd, level=19) -> {}", fs().describe())
+        );
+
+        let size = |key: &str| std::fs::metadata(tmp.path().join(key)).map(|m| m.len());
+        assert!(size("smaller")? < size("default")?);
+        // Frames record everything needed to decode them, so any reader can read either.
+        let mut reader = fs().with_compression();
+        assert_eq!(reader.must_get("smaller").await?, blob);
+        assert_eq!(reader.must_get("default").await?, blob);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn compression_detection() -> anyhow::Result<()> {
+        let base = tempdir()?.into_path();
+        let mut fs = LocalFilesystem {
+            base: base.as_path().to_path_buf(),
+            sync_on_put: false,
+        };
+        fs.put("raw", b"Hello, World!").await?;
+        fs.put("compressed", &zstd::encode_all(&b"Hello, World!"[..], 0)?)
+            .await?;
+        // Starts with the zstd magic number, but isn't a zstd frame.
+        let pathological = [0x28, 0xB5, 0x2F, 0xFD, 1, 2, 3];
+        fs.put("pathological", &pathological).await?;
+
+        let mut store = fs.with_compression_detection();
+        assert_eq!(store.must_get("raw").await?, b"Hello, World!".as_slice());
+        assert_eq!(
+            store.must_get("compressed").await?,
+            b"Hello, World!".as_slice()
+        );
+        assert_eq!(
+            store.must_get("pathological").await?,
+            pathological.as_slice()
+        );
+        assert_eq!(store.get("missing").await?, None);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn raw_markers_are_only_trusted_when_asked() -> anyhow::Result<()> {
+        let mut blobs = MemoryStore::default();
+        // A legacy raw blob that happens to start like a marked one.
+        blobs.put("legacy", b"S3KR is our tag").await?;
+        let mut marked = blobs.clone().with_compression().with_min_size(1024);
+        marked.put("tiny", b"tiny block").await?;
+        assert!(marked.underlying.map["tiny"].starts_with(b"S3KR"));
+
+        let mut store = marked.underlying.clone().with_compression_detection();
+        assert_eq!(
+            store.must_get("legacy").await?,
+            b"S3KR is our tag".as_slice()
+        );
+        let mut store = store
+            .underlying
+            .with_compression_detection()
+            .with_raw_marker();
+        assert_eq!(store.must_get("tiny").await?, b"tiny block".as_slice());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn conditional_puts_pass_through_checks_and_detection() -> anyhow::Result<()> {
+        let mut checked = MemoryStore::default().with_checksum_retries(HashMap::new(), 2);
+        assert!(checked.put_if_absent("a", b"v1").await?);
+        assert!(!checked.put_if_absent("a", b"v2").await?);
+        assert!(
+            !checked
+                .put_if_match("a", b"v2", &content_etag(b"v0"))
+                .await?
+        );
+        assert!(
+            checked
+                .put_if_match("a", b"v2", &content_etag(b"v1"))
+                .await?
+        );
+        assert!(!checked.put_if_changed("a", b"v2").await?);
+        assert_eq!(checked.must_get("a").await?, b"v2".as_slice());
+
+        let mut detected = MemoryStore::default().with_compression_detection();
+        assert!(detected.put_if_absent("a", b"v1").await?);
+        assert!(!detected.put_if_absent("a", b"v2").await?);
+        let etag = content_etag(&detected.underlying.map["a"]);
+        assert!(
+            !detected
+                .put_if_match("a", b"v2", &content_etag(b"v1"))
+                .await?
+        );
+        assert!(detected.put_if_match("a", b"v2", &etag).await?);
+        assert!(!detected.put_if_changed("a", b"v2").await?);
+        assert_eq!(detected.must_get("a").await?, b"v2".as_slice());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn shared_compression_dict() -> anyhow::Result<()> {
+        let samples: Vec<Vec<u8>> = (0..200)
+            .map(|i| {
+                format!(
+                    r#"{{"properties": {{"BLKLOT": "{:07}", "STREET": "MARKET"}}}}"#,
+                    i
+                )
+            })
+            .map(String::into_bytes)
+            .collect();
+        let dict = CompressionDict::new(zstd::dict::from_samples(&samples, 4096)?);
+
+        let base = tempdir()?.into_path();
+        for dataset in ["a", "b"] {
+            std::fs::create_dir(base.join(dataset))?;
+            let fs = LocalFilesystem {
+                base: base.as_path().to_path_buf(),
+                sync_on_put: false,
+            };
+            let mut store = fs
+                .with_compression_dict(Some(dict.clone()))
+                .with_prefix(&prefix(dataset));
+            store.put("record", &samples[7]).await?;
+            assert_eq!(store.must_get("record").await?, samples[7].as_slice());
+        }
+
+        // Without the dictionary, the blobs can't be read back.
+        let mut plain = LocalFilesystem {
+            base: base.as_path().to_path_buf(),
+            sync_on_put: false,
+        }
+        .with_compression();
+        assert!(plain.get("a/record").await.is_err());
+        Ok(())
+    }
+
+    #[derive(Default, Clone, Debug)]
+    struct Spystore {
+        fetches: Vec<String>,
+    }
+    #[async_trait]
+    impl Blobstore for Spystore {
+        async fn get(&mut self, key: &str) -> anyhow::Result<Option<Cow<[u8]>>> {
+            self.fetches.push(key.to_owned());
+            Ok(None)
+        }
+        async fn put(&mut self, _: &str, _: &[u8]) -> anyhow::Result<()> {
+            Ok(())
+        }
+    }
+    #[tokio::test]
+    async fn memory_store_matches_local_filesystem() -> anyhow::Result<()> {
+        let tmp = tempdir()?;
+        let mut local = LocalFilesystem {
+            base: tmp.path().to_path_buf(),
+            sync_on_put: false,
+        };
+        let mut memory = MemoryStore::default();
+        for store in [&mut local as &mut dyn Blobstore, &mut memory] {
+            assert_eq!(store.get("missing").await?, None);
+            assert!(!store.exists("missing").await?);
+            assert!(store.delete("missing").await.is_err());
+            store.put("b", b"bee").await?;
+            store.put("a", b"ay").await?;
+            store.put("ab", b"ay bee").await?;
+            assert_eq!(store.list("a").await?, ["a", "ab"]);
+            store.rename("b", "c").await?;
+            assert_eq!(store.get("b").await?, None);
+            assert_eq!(store.must_get("c").await?, &b"bee"[..]);
+            store.copy("c", "b").await?;
+            assert_eq!(store.must_get("b").await?, &b"bee"[..]);
+            assert_eq!(store.must_get("c").await?, &b"bee"[..]);
+            store.delete("b").await?;
+            assert!(store.copy("missing", "b").await.is_err());
+            assert!(!store.put_if_match("c", b"sea", "stale").await?);
+            assert!(
+                store
+                    .put_if_match("c", b"sea", &content_etag(b"bee"))
+                    .await?
+            );
+            assert!(!store.put_if_absent("c", b"clobbered").await?);
+            assert_eq!(store.must_get("c").await?, &b"sea"[..]);
+            assert!(store.put_if_absent("d", b"dee").await?);
+            assert_eq!(store.must_get("d").await?, &b"dee"[..]);
+            store.delete("d").await?;
+            store.delete("a").await?;
+            assert_eq!(store.list("").await?, ["ab", "c"]);
+        }
+
+        let snapshot = memory.clone();
+        memory.put("c", b"changed").await?;
+        assert_eq!(snapshot.map["c"], b"sea");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn metered_counts_what_the_cache_misses() -> anyhow::Result<()> {
+        let mut metered = MemoryStore::default().with_metrics();
+        // Put before caching, since puts through the cache would be served from it.
+        metered.put("a", b"12345").await?;
+        metered.put_owned("b", b"123".to_vec()).await?;
+        let mut blob = metered.clone().with_caching(4);
+        for _ in 0..3 {
+            assert_eq!(blob.must_get("a").await?, &b"12345"[..]);
+            assert_eq!(blob.get("missing").await?, None);
+        }
+        assert_eq!(
+            blob.get_many(&["a".to_owned(), "b".to_owned()])
+                .await?
+                .len(),
+            2
+        );
+
+        let stats = metered.snapshot();
+        assert_eq!(
+            stats,
+            MeteredStats {
+                gets: 3,
+                misses: 1,
+                puts: 2,
+                bytes_read: 8,
+                bytes_written: 8,
+            }
+        );
+        assert_eq!(
+            stats.to_string(),
+            "3 gets (1 missed, 8 B read), 2 puts (8 B written)"
+        );
+        assert!(metered.key_stats().is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn metered_totals_reads_by_key() -> anyhow::Result<()> {
+        let mut blob = MemoryStore::default().with_metrics().with_key_stats();
+        blob.put("a", b"12345").await?;
+        blob.put("b", b"123").await?;
+        let mut clone = blob.clone();
+        clone.must_get("a").await?;
+        assert_eq!(
+            clone.get_range("a", 1..3).await?.as_deref(),
+            Some(&b"23"[..])
+        );
+        blob.get_many(&["b".to_owned(), "missing".to_owned()])
+            .await?;
+
+        let by_key = blob.key_stats();
+        assert_eq!(by_key.len(), 3);
+        assert_eq!(
+            by_key["a"],
+            KeyStats {
+                gets: 2,
+                bytes_read: 7
+            }
+        );
+        assert_eq!(
+            by_key["b"],
+            KeyStats {
+                gets: 1,
+                bytes_read: 3
+            }
+        );
+        assert_eq!(
+            by_key["missing"],
+            KeyStats {
+                gets: 1,
+                bytes_read: 0
+            }
+        );
+        assert_eq!(blob.snapshot().bytes_read, 10);
+        Ok(())
+    }
+
+    // Serves `objects` over HTTP, honouring single `Range: bytes=a-b` headers and storing PUTs,
+    // the way a simple object server or CDN would. Returns the base URL.
+    fn serve_objects(objects: HashMap<String, Vec<u8>>) -> anyhow::Result<String> {
+        use hyper::{header, Body, Method, Request, Response, StatusCode};
+
+        let objects = Arc::new(std::sync::Mutex::new(objects));
+        let make_service = hyper::service::make_service_fn(move |_| {
+            let objects = Arc::clone(&objects);
+            async move {
+                anyhow::Ok(hyper::service::service_fn(move |req: Request<Body>| {
+                    let objects = Arc::clone(&objects);
+                    async move {
+                        let key = req.uri().path().trim_start_matches('/').to_owned();
+                        if req.method() == Method::PUT {
+                            let blob = hyper::body::to_bytes(req.into_body()).await?;
+                            objects.lock().unwrap().insert(key, blob.to_vec());
+                            return anyhow::Ok(Response::new(Body::empty()));
+                        }
+                        let Some(blob) = objects.lock().unwrap().get(&key).cloned() else {
+                            let resp = Response::builder().status(StatusCode::NOT_FOUND);
+                            return Ok(resp.body(Body::empty())?);
+                        };
+                        let range = req
+                            .headers()
+                            .get(header::RANGE)
+                            .and_then(|range| range.to_str().ok()?.strip_prefix("bytes="))
+                            .and_then(|range| range.split_once('-'));
+                        let Some((start, end)) = range else {
+                            return Ok(Response::new(Body::from(blob)));
+                        };
+                        let start: usize = start.parse()?;
+                        if start >= blob.len() {
+                            let resp =
+                                Response::builder().status(StatusCode::RANGE_NOT_SATISFIABLE);
+                            return Ok(resp.body(Body::empty())?);
+                        }
+                        let end = end.parse::<usize>()?.min(blob.len() - 1);
+                        let resp = Response::builder().status(StatusCode::PARTIAL_CONTENT);
+                        Ok(resp.body(Body::from(blob[start..=end].to_vec()))?)
+                    }
+                }))
+            }
+        });
+        let server = hyper::Server::try_bind(&([127, 0, 0, 1], 0).into())?.serve(make_service);
+        let url = format!("http://{}/objects", server.local_addr());
+        tokio::spawn(server);
+        Ok(url)
+    }
+
+    #[tokio::test]
+    async fn http_store_reads_ranges() -> anyhow::Result<()> {
+        let objects = HashMap::from([("objects/block".to_owned(), b"0123456789".to_vec())]);
+        let mut blob = HttpStore::new(serve_objects(objects)?);
+
+        assert_eq!(blob.must_get("block").await?, &b"0123456789"[..]);
+        assert_eq!(blob.get("missing").await?, None);
+        assert!(blob.exists("block").await?);
+        assert!(!blob.exists("missing").await?);
+
+        assert_eq!(blob.get_range("block", 2..5).await?, Some(b"234".to_vec()));
+        assert_eq!(blob.get_range("block", 8..20).await?, Some(b"89".to_vec()));
+        assert_eq!(blob.get_range("block", 12..20).await?, Some(Vec::new()));
+        assert_eq!(blob.get_range("block", 3..3).await?, Some(Vec::new()));
+        assert_eq!(blob.get_range("missing", 0..1).await?, None);
+
+        blob.put("added", b"new blob").await?;
+        assert_eq!(blob.must_get("added").await?, &b"new blob"[..]);
+        assert_eq!(blob.get_range("added", 4..8).await?, Some(b"blob".to_vec()));
+
+        // The default, for stores without ranged reads, agrees.
+        let mut memory = MemoryStore::default();
+        memory.put("block", b"0123456789").await?;
+        assert_eq!(
+            memory.get_range("block", 2..5).await?,
+            Some(b"234".to_vec())
+        );
+        assert_eq!(
+            memory.get_range("block", 8..20).await?,
+            Some(b"89".to_vec())
+        );
+        assert_eq!(memory.get_range("block", 12..20).await?, Some(Vec::new()));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn checksums_round_trip() -> anyhow::Result<()> {
+        assert_eq!(crc64(b"123456789"), 0x995D_C9BB_DF19_39FA);
+        for algorithm in ChecksumAlgorithm::ALL {
+            let mut blob = MemoryStore::default().with_checksum_algo(algorithm);
+            blob.put("block", b"some bytes").await?;
+            assert_eq!(
+                blob.underlying.map["block"].len(),
+                b"some bytes".len() + algorithm.len() + 1
+            );
+            assert_eq!(blob.must_get("block").await?, &b"some bytes"[..]);
+            assert_eq!(blob.get_bytes("block").await?.unwrap(), &b"some bytes"[..]);
+            let many = blob.get_many(&["block".to_owned()]).await?;
+            assert_eq!(many, [Some(b"some bytes".to_vec())]);
+
+            blob.underlying.map.get_mut("block").unwrap()[0] ^= 1;
+            let err = blob.get("block").await.unwrap_err();
+            assert_eq!(
+                err.to_string(),
+                format!("blob block failed its {} checksum", algorithm)
+            );
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn checksums_are_read_by_their_tag() -> anyhow::Result<()> {
+        let mut written = MemoryStore::default().with_checksum_algo(ChecksumAlgorithm::Sha256);
+        written.put("block", b"some bytes").await?;
+        let mut blob = written
+            .underlying
+            .with_checksum_algo(ChecksumAlgorithm::Crc32c);
+        assert_eq!(blob.must_get("block").await?, &b"some bytes"[..]);
+
+        blob.underlying.map.get_mut("block").unwrap().push(0xEE);
+        let err = blob.get("block").await.unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "blob block has an unknown checksum tag 238"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn corrupt_compressed_blobs_fail_their_integrity_check() -> anyhow::Result<()> {
+        let mut blob = MemoryStore::default().with_integrity().with_compression();
+        let contents = b"some bytes ".repeat(100);
+        blob.put("block", &contents).await?;
+        assert_eq!(blob.must_get("block").await?, contents.as_slice());
+
+        let stored = blob.underlying.underlying.map.get_mut("block").unwrap();
+        assert!(stored.len() < contents.len());
+        stored[3] ^= 0x01;
+        let err = blob.get("block").await.unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<IntegrityError>(),
+            Some(&IntegrityError {
+                key: "block".to_owned(),
+                algorithm: ChecksumAlgorithm::Crc32c,
+            })
+        );
+        assert_eq!(err.to_string(), "blob block failed its crc32c checksum");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn cached_entries_expire_after_their_ttl() -> anyhow::Result<()> {
+        let ttl = Duration::from_millis(200);
+        let mut blob = MemoryStore::default().with_caching_ttl(4, ttl);
+        assert_eq!(
+            blob.describe(),
+            "Caching(4, ttl 200ms) -> MemoryStore(0 blobs)"
+        );
+
+        // The miss is cached too, so a put underneath isn't seen until it expires.
+        assert_eq!(blob.get_bytes("block").await?, None);
+        blob.underlying.put("block", b"first").await?;
+        assert_eq!(blob.get_bytes("block").await?, None);
+        tokio::time::sleep(ttl).await;
+        assert_eq!(
+            blob.get_bytes("block").await?.as_deref(),
+            Some(&b"first"[..])
+        );
+
+        blob.underlying.put("block", b"second").await?;
+        assert_eq!(
+            blob.get_bytes("block").await?.as_deref(),
+            Some(&b"first"[..])
+        );
+        tokio::time::sleep(ttl).await;
+        assert_eq!(
+            blob.get_many(&["block".to_owned()]).await?,
+            [Some(b"second".to_vec())]
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn byte_bounded_cache_evicts_by_size() -> anyhow::Result<()> {
+        let mut store = MemoryStore::default();
+        for key in ["a", "b", "c"] {
+            store.put(key, &[0; 40]).await?;
+        }
+        store.put("huge", &[0; 200]).await?;
+        let metered = store.with_metrics();
+        let mut blob = metered.clone().with_caching_bytes(100);
+        assert_eq!(
+            blob.describe(),
+            "Caching(100 B) -> Metered -> MemoryStore(4 blobs)"
+        );
+
+        blob.get_bytes("a").await?;
+        blob.get_bytes("b").await?;
+        blob.get_bytes("a").await?;
+        assert_eq!(metered.snapshot().gets, 2);
+        // Three 41-byte entries don't fit, so the least recently used one goes.
+        blob.get_bytes("c").await?;
+        blob.get_bytes("a").await?;
+        assert_eq!(metered.snapshot().gets, 3);
+        blob.get_bytes("b").await?;
+        assert_eq!(metered.snapshot().gets, 4);
+
+        // Too big to cache at all: every read goes through, and still returns the blob.
+        assert_eq!(blob.must_get("huge").await?.len(), 200);
+        assert_eq!(blob.must_get("huge").await?.len(), 200);
+        assert_eq!(metered.snapshot().gets, 6);
+        assert!(blob.bytes <= 100);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn invalidated_entries_are_fetched_again() -> anyhow::Result<()> {
+        let mut store = MemoryStore::default();
+        for key in ["a", "b"] {
+            store.put(key, key.as_bytes()).await?;
+        }
+        let metered = store.with_metrics();
+        let mut blob = metered.clone().with_caching_bytes(100);
+        blob.get_bytes("a").await?;
+        blob.get_bytes("b").await?;
+        assert_eq!(metered.snapshot().gets, 2);
+
+        blob.invalidate("a");
+        blob.invalidate("missing");
+        blob.get_bytes("b").await?;
+        assert_eq!(metered.snapshot().gets, 2);
+        assert_eq!(blob.must_get("a").await?.as_ref(), b"a");
+        assert_eq!(metered.snapshot().gets, 3);
+
+        blob.clear();
+        assert_eq!(blob.bytes, 0);
+        blob.get_bytes("a").await?;
+        blob.get_bytes("b").await?;
+        assert_eq!(metered.snapshot().gets, 5);
+        assert_eq!(blob.underlying.underlying.map.len(), 2);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn cache_stats_count_hits_and_evictions() -> anyhow::Result<()> {
+        let mut store = MemoryStore::default();
+        for key in ["a", "b", "c"] {
+            store.put(key, key.as_bytes()).await?;
+        }
+        let mut blob = store.with_caching(2);
+        blob.get_bytes("a").await?;
+        blob.get_bytes("a").await?;
+        blob.get_bytes("b").await?;
+        // Evicts "a", the least recently used.
+        blob.get_bytes("c").await?;
+        blob.get_many(&["a".to_owned(), "c".to_owned()]).await?;
+        let stats = CacheStats {
+            hits: 2,
+            misses: 4,
+            inserts: 4,
+            evictions: 2,
+        };
+        assert_eq!(blob.stats(), stats);
+        assert_eq!(blob.cache_stats(), Some(stats));
+        assert_eq!(
+            stats.to_string(),
+            "2 hits, 4 misses (33.3% hit), 4 inserts, 2 evictions"
+        );
+        // Neither is invalidating one.
+        blob.delete("c").await?;
+        assert_eq!(blob.stats().evictions, 2);
+        assert_eq!(MemoryStore::default().cache_stats(), None);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn cached_bytes_are_shared() -> anyhow::Result<()> {
+        let tmp = tempdir()?;
+        let mut blob = LocalFilesystem {
+            base: tmp.path().to_path_buf(),
+            sync_on_put: false,
+        }
+        .with_caching(4);
+        blob.put("block", b"some bytes").await?;
+
+        let first = blob.get_bytes("block").await?.unwrap();
+        let second = blob.get_bytes("block").await?.unwrap();
+        assert_eq!(first, &b"some bytes"[..]);
+        assert_eq!(first.as_ptr(), second.as_ptr());
+        let lent = blob.get("block").await?.unwrap();
+        assert!(matches!(lent, Cow::Borrowed(_)));
+        assert_eq!(lent.as_ptr(), first.as_ptr());
+        assert_eq!(blob.get_bytes("missing").await?, None);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn streams_blobs() -> anyhow::Result<()> {
+        let tmp = tempdir()?;
+        std::fs::create_dir(tmp.path().join("dataset"))?;
+        let mut blob = LocalFilesystem {
+            base: tmp.path().to_path_buf(),
+            sync_on_put: false,
+        }
+        .with_prefix(&prefix("dataset"));
+        blob.put("block", b"some bytes").await?;
+
+        let mut contents = Vec::new();
+        let mut stream = blob.get_stream("block").await?.unwrap();
+        stream.read_to_end(&mut contents).await?;
+        assert_eq!(contents, b"some bytes");
+        assert!(blob.get_stream("missing").await?.is_none());
+
+        // Stores without a stream of their own fall back to reading from memory.
+        let mut cached = blob.with_caching(1);
+        let mut contents = Vec::new();
+        let mut stream = cached.get_stream("block").await?.unwrap();
+        stream.read_to_end(&mut contents).await?;
+        assert_eq!(contents, b"some bytes");
+        assert!(cached.get_stream("missing").await?.is_none());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn s3_streams_blobs() -> anyhow::Result<()> {
+        let client = s3_client(infallible_client_fn(|req| {
+            if req.uri().path().ends_with("/missing") {
+                let body = "<Error><Code>NoSuchKey</Code></Error>".to_owned();
+                return hyper::Response::builder().status(404).body(body).unwrap();
+            }
+            let body = "some bytes".to_owned();
+            hyper::Response::builder().status(200).body(body).unwrap()
+        }));
+        let mut s3 = S3Client::new(client, "my-bucket");
+        let mut contents = Vec::new();
+        let mut stream = s3.get_stream("block").await?.unwrap();
+        stream.read_to_end(&mut contents).await?;
+        assert_eq!(contents, b"some bytes");
+        assert!(s3.get_stream("missing").await?.is_none());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn s3_ranged_reads_send_a_range_header() -> anyhow::Result<()> {
+        let client = s3_client(infallible_client_fn(|req| {
+            if req.uri().path().ends_with("/missing") {
+                let body = "<Error><Code>NoSuchKey</Code></Error>".to_owned();
+                return hyper::Response::builder().status(404).body(body).unwrap();
+            }
+            match req.headers().get("range").and_then(|v| v.to_str().ok()) {
+                Some("bytes=7-11") => {
+                    let body = "World".to_owned();
+                    hyper::Response::builder().status(206).body(body).unwrap()
+                }
+                range => {
+                    let body = "<Error><Code>InvalidRange</Code></Error>".to_owned();
+                    assert_eq!(range, Some("bytes=50-59"));
+                    hyper::Response::builder().status(416).body(body).unwrap()
+                }
+            }
+        }));
+        let mut s3 = S3Client::new(client, "my-bucket");
+        assert_eq!(s3.get_range("block", 7..12).await?, Some(b"World".to_vec()));
+        assert_eq!(s3.get_range("block", 50..60).await?, Some(Vec::new()));
+        assert_eq!(s3.get_range("missing", 0..1).await?, None);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn lists_keys_by_prefix() -> anyhow::Result<()> {
+        let tmp = tempdir()?;
+        let blob = LocalFilesystem {
+            base: tmp.path().to_path_buf(),
+            sync_on_put: false,
+        };
+        std::fs::create_dir_all(tmp.path().join("dataset/blocks"))?;
+        let mut blob = blob.with_prefix(&prefix("dataset"));
+        for key in ["manifest", "blocks/001", "blocks/000"] {
+            blob.put(key, b"").await?;
+        }
+        std::fs::write(tmp.path().join("elsewhere"), b"")?;
+
+        assert_eq!(
+            blob.list("").await?,
+            ["blocks/000", "blocks/001", "manifest"]
+        );
+        assert_eq!(blob.list("blocks/").await?, ["blocks/000", "blocks/001"]);
+        assert!(blob.list("missing").await?.is_empty());
+        for key in blob.list("").await? {
+            assert!(blob.exists(&key).await?);
+        }
+        assert_eq!(blob.underlying.list("").await?.len(), 4);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn exists_without_fetching() -> anyhow::Result<()> {
+        let tmp = tempdir()?;
+        let mut blob = LocalFilesystem {
+            base: tmp.path().to_path_buf(),
+            sync_on_put: false,
+        };
+        std::fs::create_dir(tmp.path().join("dataset"))?;
+        blob.put("block", b"some bytes").await?;
+        assert!(blob.exists("block").await?);
+        assert!(!blob.exists("missing").await?);
+        assert!(!blob.exists("dataset").await?);
+        let mut prefixed = blob.with_prefix(&prefix("dataset"));
+        prefixed.put("block", b"some bytes").await?;
+        assert!(prefixed.exists("block").await?);
+
+        let mut cache = Spystore::default().with_caching(1);
+        let cached = Cached::new(Some(bytes::Bytes::from_static(b"bar")));
+        cache.cache.put("foo".to_owned(), cached);
+        assert!(cache.exists("foo").await?);
+        assert!(cache.underlying.fetches.is_empty());
+        assert!(!cache.exists("baz").await?);
+        assert_eq!(cache.underlying.fetches, vec!["baz"]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn caching_prevents_fetches() -> anyhow::Result<()> {
+        let blob = Spystore::default();
+        let mut cache = blob.with_caching(1);
+
+        let _ = cache.get("foo").await;
+        assert_eq!(cache.underlying.fetches, vec!["foo"]);
+
+        let _ = cache.get("foo").await;
+        assert_eq!(cache.underlying.fetches, vec!["foo"]);
+
+        let _ = cache.get("bar").await;
+        assert_eq!(cache.underlying.fetches, vec!["foo", "bar"]);
+        Ok(())
+    }
 
-// This implementation does some annoying things with `once_cell` and `Cow` to avoid cloning
-// the underlying blob every time it hands out the cached data. This can make a huge difference.
-// When scanning ~250k items (stored across 16 blocks), these shared-reference shenanigans reduced
-// runtime from
-//    just scan  46.35s user 0.41s system 92% cpu 50.583 total
-// to
-//    just scan  0.60s user 0.29s system 11% cpu 7.749 total
-#[derive(Debug)]
-pub struct Caching<B: Blobstore> {
-    underlying: B,
-    cache: LruCache<String, OnceCell<Option<Vec<u8>>>>,
-}
+    #[tokio::test]
+    async fn get_many_keeps_order_and_forwards_misses() -> anyhow::Result<()> {
+        let tmp = tempdir()?;
+        let mut blob = LocalFilesystem {
+            base: tmp.path().to_path_buf(),
+            sync_on_put: false,
+        }
+        .with_caching(8);
+        blob.put("a", b"apple").await?;
+        blob.put("b", b"banana").await?;
+        let keys = |keys: &[&str]| -> Vec<String> { keys.iter().map(|&k| k.to_owned()).collect() };
+        assert_eq!(
+            blob.get_many(&keys(&["b", "missing", "a"])).await?,
+            [Some(b"banana".to_vec()), None, Some(b"apple".to_vec())]
+        );
 
-#[async_trait]
-impl<B: Blobstore> Blobstore for Caching<B> {
-    async fn get<'a>(&'a mut self, key: &str) -> anyhow::Result<Option<Cow<'a, [u8]>>> {
-        let cell = self.cache.get_or_insert(key.to_owned(), OnceCell::new);
-        if let Some(v) = cell.get() {
-            let wrapped = v.as_ref().map(|inner| Cow::Borrowed(inner.as_slice()));
-            return Ok(wrapped);
-        }
-        let v: Option<Vec<u8>> = self.underlying.get(key).await?.map(|v| match v {
-            Cow::Borrowed(v) => v.to_vec(),
-            Cow::Owned(v) => v,
-        });
-        Ok(cell
-            .get_or_init(|| v)
-            .as_ref()
-            .map(|inner| Cow::Borrowed(inner.as_slice())))
+        let mut cache = Spystore::default().with_caching(8);
+        let _ = cache.get("foo").await;
+        cache.get_many(&keys(&["foo", "bar", "baz"])).await?;
+        assert_eq!(cache.underlying.fetches, vec!["foo", "bar", "baz"]);
+        cache.get_many(&keys(&["baz", "foo"])).await?;
+        assert_eq!(cache.underlying.fetches.len(), 3);
+        Ok(())
     }
-    async fn put(&mut self, key: &str, blob: &[u8]) -> anyhow::Result<()> {
-        self.underlying.put(key, blob).await
+
+    // Counts the operations that reach it, shared across clones. Each operation waits for a
+    // permit from `release`, so that concurrent callers are sure to overlap.
+    #[derive(Clone, Debug)]
+    struct Gatedstore {
+        gets: Arc<AtomicUsize>,
+        puts: Arc<AtomicUsize>,
+        gate: Arc<Semaphore>,
     }
-}
+    impl Gatedstore {
+        fn new() -> Self {
+            Self {
+                gets: Arc::default(),
+                puts: Arc::default(),
+                gate: Arc::new(Semaphore::new(0)),
+            }
+        }
+        async fn release(&self, operations: usize) {
+            self.gate.add_permits(operations);
+        }
+    }
+    #[async_trait]
+    impl Blobstore for Gatedstore {
+        async fn get(&mut self, _: &str) -> anyhow::Result<Option<Cow<[u8]>>> {
+            self.gets.fetch_add(1, Ordering::SeqCst);
+            self.gate.acquire().await?.forget();
+            Ok(Some(Cow::Borrowed(b"blob")))
+        }
+        async fn put(&mut self, _: &str, _: &[u8]) -> anyhow::Result<()> {
+            self.puts.fetch_add(1, Ordering::SeqCst);
+            self.gate.acquire().await?.forget();
+            Ok(())
+        }
+    }
+    #[tokio::test]
+    async fn coalesces_concurrent_operations() -> anyhow::Result<()> {
+        let backend = Gatedstore::new();
+        let store = backend.clone().with_coalescing();
+        let puts = (0..8).map(|_| {
+            let mut store = store.clone();
+            async move { store.put("block", b"contents").await }
+        });
+        let (results, ()) = tokio::join!(futures::future::join_all(puts), backend.release(1));
+        for result in results {
+            result?;
+        }
+        assert_eq!(backend.puts.load(Ordering::SeqCst), 1);
 
-#[derive(Debug)]
-pub struct Compressed<B: Blobstore> {
-    underlying: B,
-}
+        // Different contents for the same key aren't merged.
+        let (mut a, mut b) = (store.clone(), store.clone());
+        let (x, y, ()) = tokio::join!(
+            a.put("block", b"one"),
+            b.put("block", b"two"),
+            backend.release(2)
+        );
+        x?;
+        y?;
+        assert_eq!(backend.puts.load(Ordering::SeqCst), 3);
 
-#[async_trait]
-impl<B: Blobstore> Blobstore for Compressed<B> {
-    async fn get(&mut self, key: &str) -> anyhow::Result<Option<Cow<[u8]>>> {
-        let Some(blob) = self.underlying.get(key).await? else {
-            return Ok(None);
-        };
-        debug!("decompressing blob {}", key);
-        let decoded = zstd::decode_all(io::Cursor::new(blob))?;
-        Ok(Some(Cow::Owned(decoded)))
-    }
-    async fn put(&mut self, key: &str, blob: &[u8]) -> anyhow::Result<()> {
-        let encoded = zstd::encode_all(io::Cursor::new(blob), 0)?;
-        self.underlying.put(key, &encoded).await?;
+        let (mut a, mut b) = (store.clone(), store.clone());
+        let (x, y, ()) = tokio::join!(a.get("block"), b.get("block"), backend.release(1));
+        assert_eq!(x?.as_deref(), Some(&b"blob"[..]));
+        assert_eq!(y?.as_deref(), Some(&b"blob"[..]));
+        assert_eq!(backend.gets.load(Ordering::SeqCst), 1);
+
+        // Once an operation lands, the next one goes to the backend again.
+        backend.release(1).await;
+        store.clone().put("block", b"contents").await?;
+        assert_eq!(backend.puts.load(Ordering::SeqCst), 4);
         Ok(())
     }
-}
-
-#[cfg(test)]
-mod test {
-    use std::borrow::Cow;
 
-    use crate::blob::{Blobstore, LocalFilesystem};
-    use async_trait::async_trait;
-    use tempfile::tempdir;
+    // Stores blobs in a map shared across clones. Puts wait for a permit from `gate`.
+    #[derive(Clone, Debug)]
+    struct GatedPuts {
+        blobs: Arc<Mutex<HashMap<String, Vec<u8>>>>,
+        gate: Arc<Semaphore>,
+    }
+    #[async_trait]
+    impl Blobstore for GatedPuts {
+        async fn get(&mut self, key: &str) -> anyhow::Result<Option<Cow<[u8]>>> {
+            Ok(self.blobs.lock().unwrap().get(key).cloned().map(Cow::Owned))
+        }
+        async fn put(&mut self, key: &str, blob: &[u8]) -> anyhow::Result<()> {
+            self.gate.acquire().await?.forget();
+            self.blobs
+                .lock()
+                .unwrap()
+                .insert(key.to_owned(), blob.to_vec());
+            Ok(())
+        }
+    }
 
     #[tokio::test]
-    async fn get_not_found() -> anyhow::Result<()> {
-        let base = tempdir()?.into_path();
-        let mut fs = LocalFilesystem {
-            base: base.as_path().to_path_buf(),
+    async fn coalesced_reads_wait_for_overlapping_puts() -> anyhow::Result<()> {
+        let backend = GatedPuts {
+            blobs: Arc::new(Mutex::new(HashMap::from([(
+                "block".to_owned(),
+                b"old".to_vec(),
+            )]))),
+            gate: Arc::new(Semaphore::new(0)),
         };
-        assert_eq!(fs.get("any-key").await?, None);
+        let store = backend.clone().with_coalescing();
+        let (mut writer, mut reader, mut ranged) = (store.clone(), store.clone(), store.clone());
+        let release = async { backend.gate.add_permits(1) };
+        let (put, get, range, ()) = tokio::join!(
+            writer.put("block", b"new"),
+            reader.get_bytes("block"),
+            ranged.get_range("block", 1..3),
+            release
+        );
+        put?;
+        assert_eq!(get?.as_deref(), Some(&b"new"[..]));
+        assert_eq!(range?.as_deref(), Some(&b"ew"[..]));
         Ok(())
     }
 
     #[tokio::test]
-    async fn garbage_path_errors() -> anyhow::Result<()> {
-        let base = tempdir()?.into_path();
-        let mut fs = LocalFilesystem {
-            base: base.as_path().to_path_buf(),
-        };
-        assert!(fs.get("/////").await.is_err());
+    async fn coalescing_forwards_ranged_reads() -> anyhow::Result<()> {
+        let metered = MemoryStore {
+            map: HashMap::from([("block".to_owned(), b"0123456789".to_vec())]),
+        }
+        .with_metrics();
+        let mut store = metered.clone().with_coalescing();
+        assert_eq!(
+            store.get_range("block", 2..5).await?.as_deref(),
+            Some(&b"234"[..])
+        );
+        assert_eq!(
+            store.get_many(&["block".to_owned()]).await?,
+            [Some(b"0123456789".to_vec())]
+        );
+        // Only the range was downloaded for the ranged read.
+        assert_eq!(metered.snapshot().bytes_read, 13);
         Ok(())
     }
 
     #[tokio::test]
-    async fn round_trip_test() -> anyhow::Result<()> {
-        let base = tempdir()?.into_path();
-        let mut fs = LocalFilesystem {
-            base: base.as_path().to_path_buf(),
+    async fn adaptive_cache_sizing() -> anyhow::Result<()> {
+        let args = AdaptiveCacheArgs {
+            min: 4,
+            max: 128,
+            target_hit_ratio: 0.9,
         };
-        let expected = "Hello, World!".as_bytes().to_vec();
 
-        fs.put("my-file.txt", &expected).await?;
-        let actual = fs.get("my-file.txt").await?;
-        assert_eq!(actual, Some(Cow::Borrowed(expected.as_slice())));
+        // Cycling through a working set of 50 blobs: the cache grows until it holds them all.
+        let mut reuse = Spystore::default().with_adaptive_caching(args);
+        for i in 0..5_000 {
+            let _ = reuse.get(&format!("block-{}", i % 50)).await?;
+        }
+        assert!(reuse.cache.cap().get() >= 50);
+        assert!(reuse.cache.cap().get() <= 128);
+        let fetches = reuse.underlying.fetches.len();
+        for i in 0..50 {
+            let _ = reuse.get(&format!("block-{}", i)).await?;
+        }
+        assert_eq!(reuse.underlying.fetches.len(), fetches);
+
+        // Never reading the same blob twice: growing wouldn't help, so it doesn't.
+        let mut scan = Spystore::default().with_adaptive_caching(args);
+        for i in 0..5_000 {
+            let _ = scan.get(&format!("block-{}", i)).await?;
+        }
+        assert_eq!(scan.cache.cap().get(), 4);
         Ok(())
     }
 
-    #[derive(Default, Clone, Debug)]
-    struct Spystore {
-        fetches: Vec<String>,
+    // Returns corrupted contents for the first `corrupt_reads` reads of each blob.
+    #[derive(Debug, Default)]
+    struct Flaky {
+        blobs: HashMap<String, Vec<u8>>,
+        corrupt_reads: usize,
+        reads: usize,
     }
     #[async_trait]
-    impl Blobstore for Spystore {
+    impl Blobstore for Flaky {
         async fn get(&mut self, key: &str) -> anyhow::Result<Option<Cow<[u8]>>> {
-            self.fetches.push(key.to_owned());
-            Ok(None)
+            self.reads += 1;
+            let Some(blob) = self.blobs.get(key) else {
+                return Ok(None);
+            };
+            let mut blob = blob.clone();
+            if self.reads <= self.corrupt_reads {
+                blob[0] ^= 0xff;
+            }
+            Ok(Some(Cow::Owned(blob)))
         }
-        async fn put(&mut self, _: &str, _: &[u8]) -> anyhow::Result<()> {
+        async fn put(&mut self, key: &str, blob: &[u8]) -> anyhow::Result<()> {
+            self.blobs.insert(key.to_owned(), blob.to_vec());
             Ok(())
         }
     }
+
     #[tokio::test]
-    async fn caching_prevents_fetches() -> anyhow::Result<()> {
-        let blob = Spystore::default();
-        let mut cache = blob.with_caching(1);
+    async fn refetches_on_checksum_mismatch() -> anyhow::Result<()> {
+        let contents = b"Hello, World!".to_vec();
+        let sha256 = hex::encode(ring::digest::digest(&ring::digest::SHA256, &contents));
+        let expected = HashMap::from([("block".to_owned(), sha256)]);
 
-        let _ = cache.get("foo").await;
-        assert_eq!(cache.underlying.fetches, vec!["foo"]);
+        let mut transient = Flaky {
+            corrupt_reads: 1,
+            ..Flaky::default()
+        }
+        .with_checksum_retries(expected.clone(), 3);
+        transient.put("block", &contents).await?;
+        assert_eq!(transient.must_get("block").await?, contents);
+        assert_eq!(transient.underlying.reads, 2);
 
-        let _ = cache.get("foo").await;
-        assert_eq!(cache.underlying.fetches, vec!["foo"]);
+        let mut persistent = Flaky {
+            corrupt_reads: usize::MAX,
+            ..Flaky::default()
+        }
+        .with_checksum_retries(expected, 3);
+        persistent.put("block", &contents).await?;
+        let err = persistent.get("block").await.unwrap_err();
+        assert!(err.to_string().contains("after 3 attempts"), "{}", err);
+        assert_eq!(persistent.underlying.reads, 3);
 
-        let _ = cache.get("bar").await;
-        assert_eq!(cache.underlying.fetches, vec!["foo", "bar"]);
+        // Blobs without an expected checksum aren't checked.
+        persistent.put("other", &contents).await?;
+        assert!(persistent.get("other").await?.is_some());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn retry_budget_fails_fast() -> anyhow::Result<()> {
+        let contents = b"Hello, World!".to_vec();
+        let sha256 = hex::encode(ring::digest::digest(&ring::digest::SHA256, &contents));
+        let expected = HashMap::from([("block".to_owned(), sha256)]);
+
+        // Nothing is earned back, so only the one saved-up retry is ever made.
+        let budget = RetryBudget::new(0.0, 1);
+        let mut blob = Flaky {
+            corrupt_reads: usize::MAX,
+            ..Flaky::default()
+        }
+        .with_checksum_retries(expected, 5)
+        .with_budget(budget.clone());
+        blob.put("block", &contents).await?;
+
+        let err = blob.get("block").await.unwrap_err();
+        assert!(err.to_string().contains("retry budget"), "{}", err);
+        assert_eq!(blob.underlying.reads, 2);
+        // Once the budget is spent, failures surface on the first attempt.
+        assert!(blob.get("block").await.is_err());
+        assert_eq!(blob.underlying.reads, 3);
+        assert!(!budget.try_retry());
+
+        let budget = RetryBudget::new(0.5, 1);
+        assert!(budget.try_retry());
+        budget.record_request();
+        assert!(!budget.try_retry());
+        budget.record_request();
+        assert!(budget.try_retry());
+        Ok(())
+    }
+
+    // Times out on the first `timeouts` operations, and rejects keys containing spaces.
+    #[derive(Debug, Default)]
+    struct Unreliable {
+        blobs: MemoryStore,
+        timeouts: usize,
+        attempts: usize,
+    }
+    impl Unreliable {
+        fn attempt(&mut self, key: &str) -> anyhow::Result<()> {
+            self.attempts += 1;
+            if key.contains(' ') {
+                anyhow::bail!("malformed key {:?}", key);
+            }
+            if self.attempts <= self.timeouts {
+                return Err(std::io::Error::from(std::io::ErrorKind::TimedOut).into());
+            }
+            Ok(())
+        }
+    }
+    #[async_trait]
+    impl Blobstore for Unreliable {
+        async fn get(&mut self, key: &str) -> anyhow::Result<Option<Cow<[u8]>>> {
+            self.attempt(key)?;
+            self.blobs.get(key).await
+        }
+        async fn put(&mut self, key: &str, blob: &[u8]) -> anyhow::Result<()> {
+            self.attempt(key)?;
+            self.blobs.put(key, blob).await
+        }
+    }
+
+    #[tokio::test]
+    async fn cache_writes_through_successful_puts() -> anyhow::Result<()> {
+        let mut blob = Unreliable::default().with_caching(4);
+        blob.put("block", b"v1").await?;
+        assert_eq!(blob.must_get("block").await?, &b"v1"[..]);
+        assert_eq!(blob.underlying.attempts, 1);
+
+        // A failed put caches nothing, and drops what was cached.
+        blob.underlying.timeouts = 2;
+        assert!(blob.put("block", b"v2").await.is_err());
+        assert_eq!(blob.must_get("block").await?, &b"v1"[..]);
+        assert_eq!(blob.underlying.attempts, 3);
+
+        blob.put_owned("block", b"v3".to_vec()).await?;
+        assert_eq!(blob.must_get("block").await?, &b"v3"[..]);
+        assert_eq!(blob.underlying.attempts, 4);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn retries_transient_failures() -> anyhow::Result<()> {
+        let mut blob = Unreliable {
+            timeouts: 2,
+            ..Unreliable::default()
+        }
+        .with_retry(3)
+        .with_base_delay(Duration::from_millis(1));
+        blob.put("block", b"contents").await?;
+        assert_eq!(blob.underlying.attempts, 3);
+        assert_eq!(blob.must_get("block").await?, &b"contents"[..]);
+        assert_eq!(blob.underlying.attempts, 4);
+        // A miss is an answer, not a failure.
+        assert_eq!(blob.get("missing").await?, None);
+        assert_eq!(blob.underlying.attempts, 5);
+
+        // Permanent errors aren't retried.
+        let err = blob.get("bad key").await.unwrap_err();
+        assert_eq!(err.to_string(), "malformed key \"bad key\"");
+        assert_eq!(blob.underlying.attempts, 6);
+
+        // Nor is anything past `max_attempts`.
+        blob.underlying.timeouts = usize::MAX;
+        let err = blob.put("block", b"contents").await.unwrap_err();
+        assert_eq!(err.to_string(), "giving up after 3 attempts");
+        assert_eq!(blob.underlying.attempts, 9);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn retry_budget_limits_transient_retries() -> anyhow::Result<()> {
+        // Nothing is earned back, so only the two saved-up retries are ever made.
+        let budget = RetryBudget::new(0.0, 2);
+        let mut blob = Unreliable {
+            timeouts: usize::MAX,
+            ..Unreliable::default()
+        }
+        .with_retry(5)
+        .with_base_delay(Duration::from_millis(1))
+        .with_budget(budget.clone());
+        let err = blob.put("block", b"contents").await.unwrap_err();
+        assert_eq!(err.to_string(), "the retry budget is exhausted");
+        assert_eq!(blob.underlying.attempts, 3);
+
+        // Once the budget is spent, failures surface on the first attempt, even through another
+        // decorator sharing it.
+        let err = blob.get("block").await.unwrap_err();
+        assert_eq!(err.to_string(), "the retry budget is exhausted");
+        assert_eq!(blob.underlying.attempts, 4);
+        let mut other = Unreliable {
+            timeouts: usize::MAX,
+            ..Unreliable::default()
+        }
+        .with_retry(5)
+        .with_budget(budget);
+        assert!(other.get("block").await.is_err());
+        assert_eq!(other.underlying.attempts, 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn s3_throttling_is_transient() -> anyhow::Result<()> {
+        let client = s3_client(infallible_client_fn(|req| {
+            let (status, code) = match req.uri().path().rsplit('/').next() {
+                Some("throttled") => (503, "SlowDown"),
+                Some("broken") => (500, "InternalError"),
+                _ => (403, "AccessDenied"),
+            };
+            let body = format!("<Error><Code>{}</Code></Error>", code);
+            hyper::Response::builder()
+                .status(status)
+                .body(body)
+                .unwrap()
+        }));
+        // Without the SDK's own retries, so each request is made once.
+        let conf = client
+            .config()
+            .to_builder()
+            .retry_config(aws_sdk_s3::config::retry::RetryConfig::disabled())
+            .build();
+        let mut s3 = S3Client::new(aws_sdk_s3::Client::from_conf(conf), "bucket");
+
+        for key in ["throttled", "broken"] {
+            assert!(is_transient(&s3.get(key).await.unwrap_err()), "{}", key);
+            assert!(
+                is_transient(&s3.put(key, b"blob").await.unwrap_err()),
+                "{}",
+                key
+            );
+        }
+        assert!(!is_transient(&s3.get("denied").await.unwrap_err()));
+        assert!(!is_transient(&s3.put("denied", b"blob").await.unwrap_err()));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn s3_unavailable_heads_and_lists_are_retried() -> anyhow::Result<()> {
+        // The first two requests fail with a bare 503, as a HEAD's (bodiless) error does.
+        let requests = Arc::new(AtomicUsize::new(0));
+        let seen = requests.clone();
+        let client = s3_client(infallible_client_fn(move |req| {
+            if seen.fetch_add(1, Ordering::SeqCst) < 2 {
+                return hyper::Response::builder()
+                    .status(503)
+                    .body(String::new())
+                    .unwrap();
+            }
+            let body = match req.method().as_str() {
+                "HEAD" => String::new(),
+                _ => "<ListBucketResult><KeyCount>0</KeyCount></ListBucketResult>".to_owned(),
+            };
+            hyper::Response::builder().status(200).body(body).unwrap()
+        }));
+        let conf = client
+            .config()
+            .to_builder()
+            .retry_config(aws_sdk_s3::config::retry::RetryConfig::disabled())
+            .build();
+        let s3 = || {
+            S3Client::new(aws_sdk_s3::Client::from_conf(conf.clone()), "bucket")
+                .with_retry(3)
+                .with_base_delay(Duration::from_millis(1))
+        };
+
+        assert!(s3().exists("block/00").await?);
+        assert_eq!(requests.swap(0, Ordering::SeqCst), 3);
+        assert!(s3().list("block/").await?.is_empty());
+        assert_eq!(requests.load(Ordering::SeqCst), 3);
+        Ok(())
+    }
+
+    #[test]
+    fn capabilities() {
+        let local = || LocalFilesystem {
+            base: "/tmp/data".into(),
+            sync_on_put: false,
+        };
+        let (s3, _requests) = capturing_client();
+        let s3 = || S3Client::new(s3.clone(), "my-bucket");
+        let ranges_only = BlobstoreCapabilities {
+            ranged_reads: true,
+            ..BlobstoreCapabilities::default()
+        };
+        let everything = BlobstoreCapabilities {
+            ranged_reads: true,
+            server_side_copy: true,
+            conditional_puts: true,
+            multipart: true,
+            object_metadata: true,
+        };
+
+        assert_eq!(
+            Spystore::default().capabilities(),
+            BlobstoreCapabilities::default()
+        );
+        assert_eq!(local().capabilities(), ranges_only);
+        assert_eq!(
+            local().with_prefix(&prefix("foo")).capabilities(),
+            ranges_only
+        );
+        assert_eq!(local().with_caching(4).capabilities(), ranges_only);
+        assert_eq!(
+            local().with_compression().capabilities(),
+            BlobstoreCapabilities::default()
+        );
+
+        assert_eq!(s3().capabilities(), everything);
+        assert_eq!(
+            s3().with_prefix(&prefix("foo"))
+                .with_caching(4)
+                .capabilities(),
+            everything
+        );
+        assert_eq!(
+            s3().with_compression().capabilities(),
+            BlobstoreCapabilities {
+                ranged_reads: false,
+                ..everything
+            }
+        );
+        assert_eq!(
+            s3().with_compression_detection().capabilities(),
+            BlobstoreCapabilities {
+                ranged_reads: false,
+                ..everything
+            }
+        );
+        assert_eq!(
+            s3().with_checksum_retries(HashMap::new(), 2).capabilities(),
+            BlobstoreCapabilities {
+                ranged_reads: false,
+                ..everything
+            }
+        );
+    }
+
+    #[test]
+    fn describes_layering() {
+        let fs = LocalFilesystem {
+            base: "/tmp/data".into(),
+            sync_on_put: false,
+        };
+        let store = fs
+            .with_prefix(&prefix("foo"))
+            .with_prefix(&prefix("block"))
+            .with_compression()
+            .with_caching(16);
+        assert_eq!(
+            store.describe(),
+            r#"Caching(16) -> Compressed(zstd) -> Prefixed("block") -> Prefixed("foo") -> LocalFilesystem("/tmp/data")"#
+        );
+        assert_eq!(
+            Spystore::default().with_prefix(&prefix("foo")).describe(),
+            r#"Prefixed("foo") -> Spystore { fetches: [] }"#
+        );
+    }
+
+    // Serves `objects` over HTTP, one request per connection, until the test ends.
+    async fn serve(objects: HashMap<&'static str, &'static [u8]>) -> anyhow::Result<String> {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        tokio::spawn(async move {
+            while let Ok((mut conn, _)) = listener.accept().await {
+                let mut request = Vec::new();
+                let mut buf = [0; 1024];
+                while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+                    match conn.read(&mut buf).await {
+                        Ok(0) | Err(_) => break,
+                        Ok(n) => request.extend_from_slice(&buf[..n]),
+                    }
+                }
+                let request = String::from_utf8_lossy(&request);
+                let path = request.split_whitespace().nth(1).unwrap_or_default();
+                let (status, body) = match objects.get(path) {
+                    Some(body) => ("200 OK", *body),
+                    None => ("404 Not Found", &b""[..]),
+                };
+                let head = format!(
+                    "HTTP/1.1 {}\r\ncontent-length: {}\r\nconnection: close\r\n\r\n",
+                    status,
+                    body.len()
+                );
+                let _ = conn.write_all(head.as_bytes()).await;
+                let _ = conn.write_all(body).await;
+            }
+        });
+        Ok(format!("http://{}", addr))
+    }
+
+    #[tokio::test]
+    async fn presigned_reads() -> anyhow::Result<()> {
+        let base = serve(HashMap::from([
+            ("/data/block/00", &b"first block"[..]),
+            ("/data/manifest.json", &b"{}"[..]),
+        ]))
+        .await?;
+        let mut store = PresignedStore::new(move |key| format!("{}/data/{}", base, key));
+        assert_eq!(store.must_get("block/00").await?, &b"first block"[..]);
+        assert_eq!(
+            store.get_bytes("manifest.json").await?.as_deref(),
+            Some(&b"{}"[..])
+        );
+        assert_eq!(store.get("block/01").await?, None);
+        assert!(store.put("block/01", b"nope").await.is_err());
+
+        let mut prefixed = store.with_prefix(&prefix("block"));
+        assert_eq!(prefixed.must_get("00").await?, &b"first block"[..]);
         Ok(())
     }
 
     #[tokio::test]
     async fn prefix_smoke_test() -> anyhow::Result<()> {
-        let mut blob = Spystore::default().with_prefix("foo").with_prefix("bar");
+        let mut blob = Spystore::default()
+            .with_prefix(&prefix("foo"))
+            .with_prefix(&prefix("bar"));
 
         let _ = blob.get("baz").await;
         assert_eq!(blob.underlying.underlying.fetches, vec!["foo/bar/baz"]);
         Ok(())
     }
+
+    fn prefix(prefix: &str) -> Prefix {
+        Prefix::new(prefix).unwrap()
+    }
+
+    fn s3_client(http_client: impl aws_sdk_s3::config::HttpClient + 'static) -> aws_sdk_s3::Client {
+        let conf = aws_sdk_s3::Config::builder()
+            .behavior_version(BehaviorVersion::latest())
+            .region(Region::new("us-west-2"))
+            .credentials_provider(Credentials::new("akid", "secret", None, None, "test"))
+            .http_client(http_client)
+            .build();
+        aws_sdk_s3::Client::from_conf(conf)
+    }
+
+    fn capturing_client() -> (aws_sdk_s3::Client, CaptureRequestReceiver) {
+        let (http_client, requests) = capture_request(None);
+        (s3_client(http_client), requests)
+    }
+
+    #[tokio::test]
+    async fn s3_get_many_keeps_order() -> anyhow::Result<()> {
+        let client = s3_client(infallible_client_fn(|req| {
+            let key = req.uri().path().rsplit('/').next().unwrap_or_default();
+            if key == "missing" {
+                let body = "<Error><Code>NoSuchKey</Code></Error>".to_owned();
+                return hyper::Response::builder().status(404).body(body).unwrap();
+            }
+            let body = format!("contents of {}", key);
+            hyper::Response::builder().status(200).body(body).unwrap()
+        }));
+        let mut s3 = S3Client {
+            get_many_concurrency: 2,
+            ..S3Client::new(client, "my-bucket")
+        };
+        let keys: Vec<String> = ["k0", "k1", "missing", "k3", "k4"]
+            .iter()
+            .map(|&k| k.to_owned())
+            .collect();
+        let blobs = s3.get_many(&keys).await?;
+        let expected: Vec<Option<Vec<u8>>> = keys
+            .iter()
+            .map(|k| (k != "missing").then(|| format!("contents of {}", k).into_bytes()))
+            .collect();
+        assert_eq!(blobs, expected);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn s3_list_follows_continuation_tokens() -> anyhow::Result<()> {
+        let page = |keys: &[&str], next: Option<&str>| {
+            let contents: String = keys
+                .iter()
+                .map(|k| format!("<Contents><Key>{}</Key></Contents>", k))
+                .collect();
+            let next = next.map_or(String::new(), |token| {
+                format!("<NextContinuationToken>{}</NextContinuationToken>", token)
+            });
+            format!(
+                "<ListBucketResult><IsTruncated>{}</IsTruncated>{}{}</ListBucketResult>",
+                !next.is_empty(),
+                next,
+                contents
+            )
+        };
+        let first = page(&["blocks/000", "blocks/001"], Some("page-2"));
+        let second = page(&["blocks/002"], None);
+        let client = s3_client(infallible_client_fn(move |req| {
+            let query = req.uri().query().unwrap_or_default();
+            assert!(query.contains("prefix=blocks"), "{}", query);
+            let body = if query.contains("continuation-token=page-2") {
+                second.clone()
+            } else {
+                first.clone()
+            };
+            hyper::Response::builder().status(200).body(body).unwrap()
+        }));
+        let mut s3 = S3Client::new(client, "my-bucket");
+        assert_eq!(
+            s3.list("blocks").await?,
+            ["blocks/000", "blocks/001", "blocks/002"]
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn s3_put_sets_acl() -> anyhow::Result<()> {
+        let (client, requests) = capturing_client();
+        let mut s3 = S3Client {
+            acl: Some(ObjectCannedAcl::BucketOwnerFullControl),
+            ..S3Client::new(client, "my-bucket")
+        };
+
+        s3.put("my-key", b"Hello, World!").await?;
+        let req = requests.expect_request();
+        assert_eq!(
+            req.headers().get("x-amz-acl"),
+            Some("bucket-owner-full-control")
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn s3_put_sets_kms_encryption() -> anyhow::Result<()> {
+        let (client, requests) = capturing_client();
+        let mut s3 = S3Client {
+            sse: Some(ServerSideEncryption::AwsKms),
+            kms_key_id: Some("my-key-id".to_owned()),
+            ..S3Client::new(client, "my-bucket")
+        };
+
+        s3.put("my-key", b"Hello, World!").await?;
+        let req = requests.expect_request();
+        assert_eq!(
+            req.headers().get("x-amz-server-side-encryption"),
+            Some("aws:kms")
+        );
+        assert_eq!(
+            req.headers()
+                .get("x-amz-server-side-encryption-aws-kms-key-id"),
+            Some("my-key-id")
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn s3_put_sets_storage_class() -> anyhow::Result<()> {
+        let (client, requests) = capturing_client();
+        let mut s3 = S3Client {
+            storage_class: Some(StorageClass::StandardIa),
+            ..S3Client::new(client, "my-bucket")
+        };
+
+        s3.put("my-key", b"Hello, World!").await?;
+        let req = requests.expect_request();
+        assert_eq!(
+            req.headers().get("x-amz-storage-class"),
+            Some("STANDARD_IA")
+        );
+        Ok(())
+    }
+
+    type Recorded = Arc<Mutex<Vec<(String, String, Vec<u8>)>>>;
+
+    // Serves the multipart upload API, failing the upload of part `fail_part` if it's set, and
+    // records each request's method, URI and body.
+    fn multipart_client(fail_part: Option<&'static str>) -> (aws_sdk_s3::Client, Recorded) {
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let recorded = requests.clone();
+        let client = s3_client(infallible_client_fn(move |req| {
+            let uri = req.uri().to_string();
+            let body = req.body().bytes().unwrap_or_default().to_vec();
+            recorded
+                .lock()
+                .unwrap()
+                .push((req.method().to_string(), uri.clone(), body));
+            let response = hyper::Response::builder();
+            if uri.contains("uploads") {
+                let body = "<InitiateMultipartUploadResult><UploadId>upload-1</UploadId>\
+                            </InitiateMultipartUploadResult>";
+                return response.status(200).body(body.to_owned()).unwrap();
+            }
+            if let Some(part) = uri.split("partNumber=").nth(1) {
+                let part = part.split('&').next().unwrap_or_default();
+                if Some(part) == fail_part {
+                    let body = "<Error><Code>InvalidPart</Code></Error>".to_owned();
+                    return response.status(400).body(body).unwrap();
+                }
+                let etag = format!("\"etag-{}\"", part);
+                return response.header("etag", etag).body(String::new()).unwrap();
+            }
+            let body = "<CompleteMultipartUploadResult></CompleteMultipartUploadResult>";
+            response.status(200).body(body.to_owned()).unwrap()
+        }));
+        (client, requests)
+    }
+
+    #[tokio::test]
+    async fn s3_large_puts_are_multipart() -> anyhow::Result<()> {
+        let (client, requests) = multipart_client(None);
+        let mut s3 = S3Client {
+            multipart_threshold: 8,
+            multipart_part_size: 4,
+            ..S3Client::new(client, "my-bucket")
+        };
+
+        s3.put("small", b"12345678").await?;
+        s3.put("large", b"0123456789").await?;
+        let requests = requests.lock().unwrap();
+        let methods: Vec<&str> = requests.iter().map(|(m, _, _)| m.as_str()).collect();
+        assert_eq!(methods, ["PUT", "POST", "PUT", "PUT", "PUT", "POST"]);
+        assert_eq!(requests[0].2, b"12345678");
+        let mut parts: Vec<&[u8]> = requests[2..5]
+            .iter()
+            .map(|(_, _, body)| body.as_slice())
+            .collect();
+        parts.sort();
+        assert_eq!(parts, [&b"0123"[..], b"4567", b"89"]);
+        let complete = String::from_utf8(requests[5].2.clone())?;
+        let last = "<ETag>&quot;etag-3&quot;</ETag><PartNumber>3</PartNumber></Part>\
+                    </CompleteMultipartUpload>";
+        assert!(complete.ends_with(last), "{}", complete);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn s3_failed_multipart_puts_are_aborted() -> anyhow::Result<()> {
+        let (client, requests) = multipart_client(Some("2"));
+        let mut s3 = S3Client {
+            multipart_threshold: 8,
+            multipart_part_size: 4,
+            ..S3Client::new(client, "my-bucket")
+        };
+
+        let err = s3.put("large", b"0123456789").await.unwrap_err();
+        assert_eq!(err.to_string(), "uploading part 2 of large");
+        let requests = requests.lock().unwrap();
+        let (method, uri, _) = requests.last().unwrap();
+        assert_eq!(method, "DELETE");
+        assert!(uri.contains("uploadId=upload-1"), "{}", uri);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn s3_put_sends_content_md5() -> anyhow::Result<()> {
+        let (client, requests) = capturing_client();
+        let mut s3 = S3Client {
+            content_md5: true,
+            ..S3Client::new(client, "my-bucket")
+        };
+
+        s3.put("my-key", b"Hello, World!").await?;
+        let req = requests.expect_request();
+        assert_eq!(
+            req.headers().get("content-md5"),
+            Some("ZajifYh5KDgxtmS9i38K1A==")
+        );
+
+        let (client, requests) = capturing_client();
+        S3Client::new(client, "my-bucket")
+            .put("my-key", b"Hello, World!")
+            .await?;
+        assert_eq!(requests.expect_request().headers().get("content-md5"), None);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn s3_exists_sends_head() -> anyhow::Result<()> {
+        let (client, requests) = capturing_client();
+        let mut s3 = S3Client::new(client, "my-bucket");
+
+        assert!(s3.exists("my-key").await?);
+        let req = requests.expect_request();
+        assert_eq!(req.method(), "HEAD");
+        assert!(req.uri().ends_with("/my-key"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn s3_put_owned_uploads_identical_bytes() -> anyhow::Result<()> {
+        let (client, requests) = capturing_client();
+        let mut s3 = S3Client::new(client, "my-bucket");
+
+        let blob = b"Hello, World!".to_vec();
+        s3.put_owned("my-key", blob.clone()).await?;
+        let req = requests.expect_request();
+        assert_eq!(req.body().bytes(), Some(blob.as_slice()));
+        Ok(())
+    }
 }
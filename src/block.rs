@@ -1,14 +1,22 @@
-use std::io::{Cursor, Read};
+use std::{
+    io::{Cursor, Read},
+    time::{SystemTime, UNIX_EPOCH},
+};
 
+use anyhow::{anyhow, bail, Context};
 use async_trait::async_trait;
 
 use hex::ToHex;
 use integer_encoding::{VarInt, VarIntReader, VarIntWriter};
 use tracing::debug;
 
-use crate::blob::Blobstore;
+use crate::{
+    blob::{Blobstore, CacheStats},
+    manifest::BlockEntry,
+    path::{Key, Prefix},
+};
 
-#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+#[derive(PartialEq, Eq, Hash, Debug, Clone, Copy, Default)]
 pub struct Location {
     pub block_id: usize,
     pub offset: usize,
@@ -32,6 +40,68 @@ impl Location {
     }
 }
 
+/// One line of `etl --emit-locations`: the key, with tabs, newlines and other non-printable
+/// bytes escaped, then the block id and offset, separated by tabs.
+pub fn location_line(key: &[u8], loc: &Location) -> String {
+    format!("{}\t{}\t{}\n", key.escape_ascii(), loc.block_id, loc.offset)
+}
+
+// Optional fields of an index value, written after the `Location` as (tag, varint) pairs.
+// `Location::decode` stops after the location, so older readers ignore them.
+const TAG_LINE_NUMBER: u64 = 1;
+const TAG_EXPIRES_AT: u64 = 2;
+
+/// The value stored in the index for each key.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub struct IndexValue {
+    pub location: Location,
+    /// The (1-based) line of the input file the record was read from.
+    pub line_number: Option<usize>,
+    /// When the record expires, in seconds since the Unix epoch.
+    pub expires_at: Option<u64>,
+}
+impl IndexValue {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = self.location.encode();
+        if let Some(line_number) = self.line_number {
+            buf.extend(TAG_LINE_NUMBER.encode_var_vec());
+            buf.extend(line_number.encode_var_vec());
+        }
+        if let Some(expires_at) = self.expires_at {
+            buf.extend(TAG_EXPIRES_AT.encode_var_vec());
+            buf.extend(expires_at.encode_var_vec());
+        }
+        buf
+    }
+    pub fn decode(buf: &[u8]) -> anyhow::Result<Self> {
+        let mut cursor = std::io::Cursor::new(buf);
+        let mut value = IndexValue {
+            location: Location {
+                block_id: cursor.read_varint()?,
+                offset: cursor.read_varint()?,
+            },
+            line_number: None,
+            expires_at: None,
+        };
+        while (cursor.position() as usize) < buf.len() {
+            let tag: u64 = cursor.read_varint()?;
+            match tag {
+                TAG_LINE_NUMBER => value.line_number = Some(cursor.read_varint()?),
+                TAG_EXPIRES_AT => value.expires_at = Some(cursor.read_varint()?),
+                other => return Err(anyhow!("unknown index value tag {}", other)),
+            }
+        }
+        Ok(value)
+    }
+
+    pub fn is_expired(&self, now: SystemTime) -> bool {
+        let now = now
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_secs());
+        self.expires_at.is_some_and(|expires_at| expires_at <= now)
+    }
+}
+
 #[async_trait]
 pub trait BlockWriter {
     async fn append(&mut self, item: &[u8]) -> anyhow::Result<Location>;
@@ -43,15 +113,55 @@ pub trait BlockReader {
     async fn fetch(&mut self, loc: &Location) -> anyhow::Result<Vec<u8>>;
 }
 
+/// Where a dataset's blocks are stored, relative to the dataset's prefix.
+pub fn block_prefix() -> Prefix {
+    Prefix::new("block").expect("a valid prefix")
+}
+
+/// The object name of a block, relative to the block prefix.
+pub fn block_name(block_id: usize) -> Key {
+    Key::new(block_id.encode_var_vec().encode_hex::<String>()).expect("hex is never empty")
+}
+
+/// The block that `name` is the object name of, or `None` if it isn't one `block_name` gives.
+pub fn block_id(name: &str) -> Option<usize> {
+    let bytes = hex::decode(name).ok()?;
+    match usize::decode_var(&bytes)? {
+        (block_id, len) if len == bytes.len() => Some(block_id),
+        _ => None,
+    }
+}
+
+// A footered block ends with the offset of each of its records, then the number of records, all
+// as little-endian u32s. Fixed-width, unlike the varint framing, so it can be read from the end.
+const FOOTER_WORD: usize = std::mem::size_of::<u32>();
+
+// How much of a block a ranged read of one record asks for up front: its varint header and,
+// for most records, all of the record, so that only bigger ones need a second request.
+const FIRST_RANGE_LEN: u64 = 4 << 10;
+
 pub struct S3BlockWriter {
     underlying: Box<dyn Blobstore>,
     buf: Vec<u8>,
     block_size: usize,
     cur: Location,
+    blocks: Vec<BlockEntry>,
+    footer: Option<Vec<u32>>,
+    spanning: bool,
+    max_blocks: Option<usize>,
 }
 pub struct S3BlockWriterArgs {
     pub client: Box<dyn Blobstore>,
     pub block_size: usize,
+    /// End each block with a footer listing its record offsets, so readers can find the nth
+    /// record without walking the framing. The footer isn't counted against `block_size`.
+    pub footer: bool,
+    /// Let records larger than the space left in a block continue into the following blocks,
+    /// instead of each record having to fit in a single block.
+    pub spanning: bool,
+    /// Fail the write rather than store more than this many blocks in total, counting any
+    /// resumed ones. Guards against a `block_size` far too small for the data.
+    pub max_blocks: Option<usize>,
 }
 impl S3BlockWriter {
     pub fn new(args: S3BlockWriterArgs) -> Self {
@@ -60,18 +170,48 @@ impl S3BlockWriter {
             buf: Vec::with_capacity(args.block_size),
             block_size: args.block_size,
             cur: Location::default(),
+            blocks: Vec::new(),
+            footer: args.footer.then(Vec::new),
+            spanning: args.spanning,
+            max_blocks: args.max_blocks,
         }
     }
+
+    /// Like `new`, but carries on after `blocks`, the blocks of an existing dataset, so that
+    /// appended records land in new blocks instead of overwriting them.
+    pub fn resume(args: S3BlockWriterArgs, blocks: Vec<BlockEntry>) -> Self {
+        let mut writer = Self::new(args);
+        writer.cur.block_id = blocks.last().map_or(0, |entry| entry.block_id + 1);
+        writer.blocks = blocks;
+        writer
+    }
+
+    /// Every block flushed so far, for recording in the `Manifest`.
+    pub fn blocks(&self) -> &[BlockEntry] {
+        &self.blocks
+    }
+
+    /// Gives back the store the blocks were written to, e.g. to read them back with an
+    /// `S3BlockReader` in tests. Call `flush` first.
+    pub fn into_client(self) -> Box<dyn Blobstore> {
+        self.underlying
+    }
 }
 
 #[async_trait]
 impl BlockWriter for S3BlockWriter {
     async fn append(&mut self, item: &[u8]) -> anyhow::Result<Location> {
+        if self.spanning {
+            return self.append_spanning(item).await;
+        }
         let size = item.len().required_space();
         if self.cur.offset + size + item.len() > self.block_size {
             self.flush().await?;
         }
         let loc = self.cur;
+        if let Some(offsets) = &mut self.footer {
+            offsets.push(u32::try_from(loc.offset)?);
+        }
         self.buf.write_varint(item.len())?;
         self.buf.extend_from_slice(item);
         self.cur.offset += size + item.len();
@@ -82,10 +222,31 @@ impl BlockWriter for S3BlockWriter {
         if self.buf.is_empty() {
             return Ok(());
         }
-        let name: String = self.cur.block_id.encode_var_vec().encode_hex();
+        if let Some(max_blocks) = self.max_blocks {
+            if self.blocks.len() >= max_blocks {
+                bail!(
+                    "writing block {} would exceed the limit of {} blocks; use a larger block size",
+                    self.blocks.len() + 1,
+                    max_blocks
+                );
+            }
+        }
+        let name = block_name(self.cur.block_id);
         debug!("pushing block {}", name);
-        self.underlying.put(&name, &self.buf).await?;
-        self.buf.clear();
+        if let Some(offsets) = &mut self.footer {
+            for offset in offsets.iter() {
+                self.buf.extend(offset.to_le_bytes());
+            }
+            self.buf.extend(u32::try_from(offsets.len())?.to_le_bytes());
+            offsets.clear();
+        }
+        let entry = BlockEntry::new(self.cur.block_id, &self.buf);
+        let metadata = entry.metadata();
+        self.blocks.push(entry);
+        let buf = std::mem::replace(&mut self.buf, Vec::with_capacity(self.block_size));
+        self.underlying
+            .put_with_metadata(&name, buf, &metadata)
+            .await?;
         self.cur = Location {
             block_id: self.cur.block_id + 1,
             offset: 0,
@@ -94,30 +255,799 @@ impl BlockWriter for S3BlockWriter {
     }
 }
 
+impl S3BlockWriter {
+    async fn append_spanning(&mut self, item: &[u8]) -> anyhow::Result<Location> {
+        if self.buf.is_empty() {
+            self.begin_block(0)?;
+        }
+        // Start on a fresh block rather than leave nothing but the length behind in this one.
+        let size = item.len().required_space();
+        if self.cur.offset + size + item.len().min(1) > self.block_size
+            && self.cur.offset > 0usize.required_space()
+        {
+            self.flush().await?;
+            self.begin_block(0)?;
+        }
+        let loc = self.cur;
+        if let Some(offsets) = &mut self.footer {
+            offsets.push(u32::try_from(loc.offset)?);
+        }
+        self.buf.write_varint(item.len())?;
+        self.cur.offset += size;
+
+        let mut rest = item;
+        // Always make progress, even if `block_size` is too small to hold anything.
+        let mut room = self.block_size.saturating_sub(self.cur.offset).max(1);
+        loop {
+            let (head, tail) = rest.split_at(room.min(rest.len()));
+            self.buf.extend_from_slice(head);
+            self.cur.offset += head.len();
+            rest = tail;
+            if rest.is_empty() {
+                return Ok(loc);
+            }
+            self.flush().await?;
+            // Sizing the count by `rest` overestimates its width, so the continuation fits.
+            room = self
+                .block_size
+                .saturating_sub(rest.len().required_space())
+                .clamp(1, rest.len());
+            self.begin_block(room)?;
+        }
+    }
+
+    // With spanning records, every block starts with a varint count of the bytes that continue
+    // the previous block's last record; the records that start in the block follow them.
+    fn begin_block(&mut self, continued: usize) -> anyhow::Result<()> {
+        self.buf.write_varint(continued)?;
+        self.cur.offset += continued.required_space();
+        Ok(())
+    }
+}
+
 pub struct S3BlockReader {
     underlying: Box<dyn Blobstore>,
+    footer: bool,
+    spanning: bool,
 }
 pub struct S3BlockReaderArgs {
     pub client: Box<dyn Blobstore>,
+    /// Whether the blocks were written with a footer (see `Manifest::block_footer`).
+    pub footer: bool,
+    /// Whether records may continue across blocks (see `Manifest::spanning_records`).
+    pub spanning: bool,
 }
 impl S3BlockReader {
     pub fn new(args: S3BlockReaderArgs) -> Self {
         Self {
             underlying: args.client,
+            footer: args.footer,
+            spanning: args.spanning,
         }
     }
-}
-#[async_trait]
-impl BlockReader for S3BlockReader {
-    async fn fetch(&mut self, loc: &Location) -> anyhow::Result<Vec<u8>> {
-        let name: String = loc.block_id.encode_var_vec().encode_hex();
-        let block = self.underlying.must_get(&name).await?;
 
-        let mut cursor = Cursor::new(block);
+    /// How the reader's block cache is doing, if its client is a `Caching`.
+    pub fn cache_stats(&self) -> Option<CacheStats> {
+        self.underlying.cache_stats()
+    }
+
+    /// Reads a record that may continue past the end of its block, following it through as
+    /// many blocks as it takes.
+    async fn fetch_spanning(&mut self, loc: &Location) -> anyhow::Result<Vec<u8>> {
+        let block = self.underlying.must_get(&block_name(loc.block_id)).await?;
+        let body = records_of(&block, self.footer)?;
+        let mut cursor = Cursor::new(body);
         cursor.set_position(loc.offset as u64);
         let record_size: usize = cursor.read_varint()?;
+        let start = cursor.position() as usize;
+        let end = body.len().min(start + record_size);
+        let mut record = body[start..end].to_vec();
+
+        let mut block_id = loc.block_id;
+        while record.len() < record_size {
+            block_id += 1;
+            let block = self.underlying.must_get(&block_name(block_id)).await?;
+            let body = records_of(&block, self.footer)?;
+            let mut cursor = Cursor::new(body);
+            let continued: usize = cursor.read_varint()?;
+            let start = cursor.position() as usize;
+            if continued == 0 || continued > record_size - record.len() {
+                return Err(anyhow!(
+                    "block {} continues {} bytes of a record with {} left",
+                    block_id,
+                    continued,
+                    record_size - record.len()
+                ));
+            }
+            let chunk = body
+                .get(start..start + continued)
+                .ok_or_else(|| anyhow!("block {} is truncated", block_id))?;
+            record.extend_from_slice(chunk);
+        }
+        Ok(record)
+    }
+
+    /// The `index`th record of a footered block, located through the footer.
+    pub async fn fetch_nth(&mut self, block_id: usize, index: usize) -> anyhow::Result<Vec<u8>> {
+        if !self.footer {
+            return Err(anyhow!("blocks were written without a footer"));
+        }
+        // The footer's length isn't known up front, so this fetches the whole block; wrap the
+        // store in `Caching` to make repeated lookups into one block cheap.
+        let block = self.underlying.must_get(&block_name(block_id)).await?;
+        let (records, offsets) = split_footer(&block)?;
+        let offset = *offsets.get(index).ok_or_else(|| {
+            anyhow!(
+                "block {} has {} records, no record {}",
+                block_id,
+                offsets.len(),
+                index
+            )
+        })?;
+        if self.spanning {
+            return self.fetch_spanning(&Location { block_id, offset }).await;
+        }
+        read_record(records, offset)
+    }
+}
+/// What `S3BlockReader::prewarm` loaded.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub struct Prewarmed {
+    pub blocks: usize,
+    pub bytes: u64,
+}
+
+impl S3BlockReader {
+    /// Fetches each of `block_ids` without decoding it, so that a `Caching` store holds them
+    /// before any reads are measured. Stops at the first block that doesn't exist, so `0..n`
+    /// is fine for a dataset with no manifest to list its blocks.
+    pub async fn prewarm(
+        &mut self,
+        block_ids: impl Iterator<Item = usize> + Send,
+    ) -> anyhow::Result<Prewarmed> {
+        let mut prewarmed = Prewarmed::default();
+        for block_id in block_ids {
+            let Some(block) = self.underlying.get_bytes(&block_name(block_id)).await? else {
+                break;
+            };
+            prewarmed.blocks += 1;
+            prewarmed.bytes += block.len() as u64;
+        }
+        Ok(prewarmed)
+    }
+
+    /// Every record in a block, in order, or `None` if there's no such block. Blocks are
+    /// numbered contiguously from 0, so this can recover a dataset without its index.
+    pub async fn fetch_block(
+        &mut self,
+        block_id: usize,
+    ) -> anyhow::Result<Option<Vec<(Location, Vec<u8>)>>> {
+        let Some(block) = self.underlying.get(&block_name(block_id)).await? else {
+            return Ok(None);
+        };
+        let records = records_of(&block, self.footer)?;
+        if !self.spanning {
+            return Ok(Some(decode_block(block_id, records)?));
+        }
+
+        // Skip the tail of the previous block's record, then walk the records that start here;
+        // only the last of them can run on into the next block.
+        let mut cursor = Cursor::new(records);
+        let continued: usize = cursor.read_varint()?;
+        cursor.set_position(cursor.position() + continued as u64);
+        let mut complete = Vec::new();
+        let mut spilled = None;
+        while (cursor.position() as usize) < records.len() {
+            let offset = cursor.position() as usize;
+            let record_size: usize = cursor.read_varint()?;
+            let loc = Location { block_id, offset };
+            if cursor.position() as usize + record_size > records.len() {
+                spilled = Some(loc);
+                break;
+            }
+            let mut record = vec![0; record_size];
+            cursor.read_exact(&mut record)?;
+            complete.push((loc, record));
+        }
+        if let Some(loc) = spilled {
+            let record = self.fetch_spanning(&loc).await?;
+            complete.push((loc, record));
+        }
+        Ok(Some(complete))
+    }
+}
+
+/// The records section of a block, without its footer.
+fn records_of(block: &[u8], footer: bool) -> anyhow::Result<&[u8]> {
+    Ok(if footer {
+        split_footer(block)?.0
+    } else {
+        block
+    })
+}
+
+/// Splits a footered block into its records and the offset of each.
+fn split_footer(block: &[u8]) -> anyhow::Result<(&[u8], Vec<usize>)> {
+    let word = |at: usize| -> usize {
+        let mut buf = [0; FOOTER_WORD];
+        buf.copy_from_slice(&block[at..at + FOOTER_WORD]);
+        u32::from_le_bytes(buf) as usize
+    };
+    let count_at = block
+        .len()
+        .checked_sub(FOOTER_WORD)
+        .ok_or_else(|| anyhow!("block is too short to have a footer"))?;
+    let count = word(count_at);
+    let footer_at = count
+        .checked_mul(FOOTER_WORD)
+        .and_then(|len| count_at.checked_sub(len))
+        .ok_or_else(|| anyhow!("block footer claims {} records, too many to fit", count))?;
+    let offsets = (0..count)
+        .map(|i| word(footer_at + i * FOOTER_WORD))
+        .collect();
+    Ok((&block[..footer_at], offsets))
+}
+
+impl S3BlockReader {
+    /// Reads the record at `loc` with ranged reads of just its header and bytes, rather than
+    /// fetching its whole block.
+    async fn fetch_range(&mut self, loc: &Location) -> anyhow::Result<Vec<u8>> {
+        let name = block_name(loc.block_id);
+        let offset = loc.offset as u64;
+        let head = self
+            .underlying
+            .get_range(&name, offset..offset + FIRST_RANGE_LEN)
+            .await?
+            .ok_or_else(|| anyhow!("no such blob: {}", name))?;
+        let (record_size, header_len) = usize::decode_var(&head)
+            .ok_or_else(|| anyhow!("no record header at offset {}", loc.offset))?;
+        let mut record = head[header_len..].to_vec();
+        record.truncate(record_size);
+        if record.len() < record_size {
+            let start = offset + (header_len + record.len()) as u64;
+            let end = offset + (header_len + record_size) as u64;
+            let rest = self
+                .underlying
+                .get_range(&name, start..end)
+                .await?
+                .ok_or_else(|| anyhow!("no such blob: {}", name))?;
+            record.extend(rest);
+        }
+        if record.len() < record_size {
+            return Err(anyhow!(
+                "record is truncated: expected {} bytes, found {}",
+                record_size,
+                record.len()
+            ));
+        }
+        Ok(record)
+    }
+}
+
+fn read_record(block: &[u8], offset: usize) -> anyhow::Result<Vec<u8>> {
+    let mut cursor = Cursor::new(block);
+    cursor.set_position(offset as u64);
+    let record_size: usize = cursor.read_varint()?;
+    let mut record = vec![0; record_size];
+    cursor.read_exact(&mut record)?;
+    Ok(record)
+}
+
+/// Walks the framing of a block, splitting it into its records.
+pub fn decode_block(block_id: usize, block: &[u8]) -> anyhow::Result<Vec<(Location, Vec<u8>)>> {
+    let mut cursor = Cursor::new(block);
+    let mut records = Vec::new();
+    while (cursor.position() as usize) < block.len() {
+        let offset = cursor.position() as usize;
+        let record_size: usize = cursor.read_varint()?;
         let mut record = vec![0; record_size];
         cursor.read_exact(&mut record)?;
-        Ok(record)
+        records.push((Location { block_id, offset }, record));
+    }
+    Ok(records)
+}
+
+#[async_trait]
+impl BlockReader for S3BlockReader {
+    async fn fetch(&mut self, loc: &Location) -> anyhow::Result<Vec<u8>> {
+        let name = block_name(loc.block_id);
+        let record = if self.spanning {
+            self.fetch_spanning(loc).await
+        } else if self.underlying.capabilities().ranged_reads {
+            self.fetch_range(loc).await
+        } else {
+            match self.underlying.must_get(&name).await {
+                Ok(block) => read_record(&block, loc.offset),
+                Err(err) => Err(err),
+            }
+        };
+        record.with_context(|| format!("fetching record at {:?} from block {}", loc, name))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+    use tempfile::tempdir;
+
+    use crate::{
+        blob::{Blobstore, Codec, LocalFilesystem, MemoryStore},
+        block::{
+            block_id, block_name, BlockReader, BlockWriter, IndexValue, Location, Prewarmed,
+            S3BlockReader, S3BlockReaderArgs, S3BlockWriter, S3BlockWriterArgs,
+        },
+        manifest::BlockEntry,
+    };
+
+    #[test]
+    fn index_value_round_trip() -> anyhow::Result<()> {
+        for line_number in [None, Some(1), Some(127), Some(128), Some(1_000_000)] {
+            for expires_at in [None, Some(1_700_000_000)] {
+                let value = IndexValue {
+                    location: Location {
+                        block_id: 3,
+                        offset: 4_567,
+                    },
+                    line_number,
+                    expires_at,
+                };
+                assert_eq!(IndexValue::decode(&value.encode())?, value);
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn block_names_map_back_to_block_ids() {
+        for id in [0, 1, 127, 128, 1_000_000] {
+            assert_eq!(block_id(block_name(id).as_str()), Some(id));
+        }
+        assert_eq!(block_id("index"), None);
+        // `8001` is 128 with a trailing byte.
+        assert_eq!(block_id("800101"), None);
+    }
+
+    #[test]
+    fn index_value_is_backwards_compatible() -> anyhow::Result<()> {
+        let location = Location {
+            block_id: 12,
+            offset: 345,
+        };
+        let plain = IndexValue::decode(&location.encode())?;
+        assert_eq!(plain.location, location);
+        assert_eq!(plain.line_number, None);
+
+        let tagged = IndexValue {
+            location,
+            line_number: Some(42),
+            expires_at: Some(1_700_000_000),
+        };
+        assert_eq!(Location::decode(&tagged.encode())?, location);
+        Ok(())
+    }
+
+    #[test]
+    fn expiry() -> anyhow::Result<()> {
+        let now = UNIX_EPOCH + Duration::from_secs(1_000);
+        let forever = IndexValue::default();
+        let live = IndexValue {
+            expires_at: Some(2_000),
+            ..IndexValue::default()
+        };
+        let expired = IndexValue {
+            expires_at: Some(500),
+            ..IndexValue::default()
+        };
+        assert!(!forever.is_expired(now));
+        assert!(!live.is_expired(now));
+        assert!(expired.is_expired(now));
+        assert!(live.is_expired(SystemTime::now()));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn round_trips_through_memory() -> anyhow::Result<()> {
+        let mut writer = S3BlockWriter::new(S3BlockWriterArgs {
+            client: Box::new(MemoryStore::default()),
+            block_size: 16,
+            footer: true,
+            spanning: false,
+            max_blocks: None,
+        });
+        let mut locs = Vec::new();
+        for record in ["one", "two", "three", "four"] {
+            locs.push(writer.append(record.as_bytes()).await?);
+        }
+        writer.flush().await?;
+
+        let mut reader = S3BlockReader::new(S3BlockReaderArgs {
+            client: writer.into_client(),
+            footer: true,
+            spanning: false,
+        });
+        assert_eq!(reader.fetch(&locs[2]).await?, b"three");
+        assert_eq!(reader.fetch_nth(locs[3].block_id, 0).await?, b"four");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn prewarmed_reads_stay_in_the_cache() -> anyhow::Result<()> {
+        let tmp = tempdir()?;
+        let store = LocalFilesystem {
+            base: tmp.path().to_path_buf(),
+            sync_on_put: false,
+        }
+        .with_metrics();
+        let mut writer = S3BlockWriter::new(S3BlockWriterArgs {
+            client: Box::new(store.clone()),
+            block_size: 16,
+            footer: false,
+            spanning: false,
+            max_blocks: None,
+        });
+        let mut locs = Vec::new();
+        for i in 0..10 {
+            locs.push(writer.append(format!("record-{}", i).as_bytes()).await?);
+        }
+        writer.flush().await?;
+        let num_blocks = writer.blocks().len();
+        let bytes: usize = writer.blocks().iter().map(|block| block.len).sum();
+
+        let mut reader = S3BlockReader::new(S3BlockReaderArgs {
+            // With room for the miss that ends the walk, which is cached too.
+            client: Box::new(store.clone().with_caching(num_blocks + 1)),
+            footer: false,
+            spanning: false,
+        });
+        // Asking for more blocks than exist stops at the last one.
+        let prewarmed = reader.prewarm(0..num_blocks + 5).await?;
+        assert_eq!(
+            prewarmed,
+            Prewarmed {
+                blocks: num_blocks,
+                bytes: bytes as u64,
+            }
+        );
+        // One fetch per block, plus the miss that ended the walk.
+        let fetched = store.snapshot().gets;
+        assert_eq!(fetched, num_blocks as u64 + 1);
+
+        for (i, loc) in locs.iter().enumerate() {
+            assert_eq!(reader.fetch(loc).await?, format!("record-{}", i).as_bytes());
+        }
+        assert_eq!(store.snapshot().gets, fetched);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn resumed_writer_appends_blocks() -> anyhow::Result<()> {
+        let tmp = tempdir()?;
+        let store = || LocalFilesystem {
+            base: tmp.path().to_path_buf(),
+            sync_on_put: false,
+        };
+        let args = || S3BlockWriterArgs {
+            client: Box::new(store()),
+            block_size: 16,
+            footer: false,
+            spanning: false,
+            max_blocks: None,
+        };
+        let mut writer = S3BlockWriter::new(args());
+        let first = writer.append(b"first-record").await?;
+        writer.flush().await?;
+
+        let mut writer = S3BlockWriter::resume(args(), writer.blocks().to_vec());
+        let second = writer.append(b"second-record").await?;
+        writer.flush().await?;
+        assert_eq!(second.block_id, first.block_id + 1);
+        let ids: Vec<usize> = writer.blocks().iter().map(|b| b.block_id).collect();
+        assert_eq!(ids, [0, 1]);
+
+        let mut reader = S3BlockReader::new(S3BlockReaderArgs {
+            client: Box::new(store()),
+            footer: false,
+            spanning: false,
+        });
+        assert_eq!(reader.fetch(&first).await?, b"first-record");
+        assert_eq!(reader.fetch(&second).await?, b"second-record");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn block_only_scan() -> anyhow::Result<()> {
+        let tmp = tempdir()?;
+        let store = || LocalFilesystem {
+            base: tmp.path().to_path_buf(),
+            sync_on_put: false,
+        };
+        let mut writer = S3BlockWriter::new(S3BlockWriterArgs {
+            client: Box::new(store()),
+            block_size: 16,
+            footer: false,
+            spanning: false,
+            max_blocks: None,
+        });
+        let records: Vec<String> = (0..20).map(|i| format!("record-{}", i)).collect();
+        let mut locations = Vec::new();
+        for record in &records {
+            locations.push(writer.append(record.as_bytes()).await?);
+        }
+        writer.flush().await?;
+        assert!(writer.blocks().len() > 1);
+
+        // No index was written, so the blocks themselves are all there is to go on.
+        let mut reader = S3BlockReader::new(S3BlockReaderArgs {
+            client: Box::new(store()),
+            footer: false,
+            spanning: false,
+        });
+        let mut scanned = Vec::new();
+        let mut block_id = 0;
+        while let Some(block) = reader.fetch_block(block_id).await? {
+            scanned.extend(block);
+            block_id += 1;
+        }
+        assert_eq!(block_id, writer.blocks().len());
+
+        let expected: Vec<(Location, Vec<u8>)> = locations
+            .into_iter()
+            .zip(records.iter().map(|r| r.as_bytes().to_vec()))
+            .collect();
+        assert_eq!(scanned, expected);
+        for (loc, record) in &expected {
+            assert_eq!(&reader.fetch(loc).await?, record);
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn footered_blocks() -> anyhow::Result<()> {
+        let tmp = tempdir()?;
+        let store = || LocalFilesystem {
+            base: tmp.path().to_path_buf(),
+            sync_on_put: false,
+        };
+        let mut writer = S3BlockWriter::new(S3BlockWriterArgs {
+            client: Box::new(store()),
+            block_size: 64,
+            footer: true,
+            spanning: false,
+            max_blocks: None,
+        });
+        let records: Vec<String> = (0..20).map(|i| format!("record-{}", i)).collect();
+        let mut locations = Vec::new();
+        for record in &records {
+            locations.push(writer.append(record.as_bytes()).await?);
+        }
+        writer.flush().await?;
+        assert!(writer.blocks().len() > 1);
+
+        let mut reader = S3BlockReader::new(S3BlockReaderArgs {
+            client: Box::new(store()),
+            footer: true,
+            spanning: false,
+        });
+        let mut nth = 0;
+        for (loc, record) in locations.iter().zip(&records) {
+            if loc.offset == 0 {
+                nth = 0;
+            }
+            assert_eq!(
+                reader.fetch_nth(loc.block_id, nth).await?,
+                record.as_bytes()
+            );
+            // Offsets are unchanged by the footer, so index lookups still work.
+            assert_eq!(reader.fetch(loc).await?, record.as_bytes());
+            nth += 1;
+        }
+        assert!(reader.fetch_nth(0, 100).await.is_err());
+
+        let mut scanned = Vec::new();
+        let mut block_id = 0;
+        while let Some(block) = reader.fetch_block(block_id).await? {
+            scanned.extend(block.into_iter().map(|(_, record)| record));
+            block_id += 1;
+        }
+        let expected: Vec<Vec<u8>> = records.iter().map(|r| r.as_bytes().to_vec()).collect();
+        assert_eq!(scanned, expected);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn spanning_records() -> anyhow::Result<()> {
+        for footer in [false, true] {
+            let tmp = tempdir()?;
+            let store = || LocalFilesystem {
+                base: tmp.path().to_path_buf(),
+                sync_on_put: false,
+            };
+            let mut writer = S3BlockWriter::new(S3BlockWriterArgs {
+                client: Box::new(store()),
+                block_size: 32,
+                footer,
+                spanning: true,
+                max_blocks: None,
+            });
+            let big: Vec<u8> = (0..200).map(|i| i as u8).collect();
+            let records = vec![
+                b"small".to_vec(),
+                big.clone(),
+                b"after".to_vec(),
+                big[..40].to_vec(),
+                Vec::new(),
+            ];
+            let mut locations = Vec::new();
+            for record in &records {
+                locations.push(writer.append(record).await?);
+            }
+            writer.flush().await?;
+            // The big record alone needs more than six blocks.
+            assert!(writer.blocks().len() > 6);
+
+            let mut reader = S3BlockReader::new(S3BlockReaderArgs {
+                client: Box::new(store()),
+                footer,
+                spanning: true,
+            });
+            for (loc, record) in locations.iter().zip(&records) {
+                assert_eq!(&reader.fetch(loc).await?, record);
+            }
+
+            let mut scanned = Vec::new();
+            let mut block_id = 0;
+            while let Some(block) = reader.fetch_block(block_id).await? {
+                scanned.extend(block);
+                block_id += 1;
+            }
+            let expected: Vec<(Location, Vec<u8>)> = locations.into_iter().zip(records).collect();
+            assert_eq!(scanned, expected);
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn ranged_fetches_read_only_the_record() -> anyhow::Result<()> {
+        let tmp = tempdir()?;
+        let store = LocalFilesystem {
+            base: tmp.path().to_path_buf(),
+            sync_on_put: false,
+        }
+        .with_metrics();
+        let mut writer = S3BlockWriter::new(S3BlockWriterArgs {
+            client: Box::new(store.clone()),
+            block_size: 1 << 20,
+            footer: true,
+            spanning: false,
+            max_blocks: None,
+        });
+        let big = vec![b'x'; 10_000];
+        let small = writer.append(b"small").await?;
+        let spread = writer.append(&big).await?;
+        for i in 0..1000 {
+            writer.append(format!("filler-{}", i).as_bytes()).await?;
+        }
+        writer.flush().await?;
+
+        let mut reader = S3BlockReader::new(S3BlockReaderArgs {
+            client: Box::new(store.clone()),
+            footer: true,
+            spanning: false,
+        });
+        let before = store.snapshot();
+        assert_eq!(reader.fetch(&small).await?, b"small");
+        // A record longer than the first read takes a second one for the rest.
+        assert_eq!(reader.fetch(&spread).await?, big);
+        let after = store.snapshot();
+        assert_eq!(after.gets - before.gets, 3);
+        assert!(
+            after.bytes_read - before.bytes_read < 20_000,
+            "{} bytes read",
+            after.bytes_read - before.bytes_read
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn tiny_blocks_are_stored_raw() -> anyhow::Result<()> {
+        let tmp = tempdir()?;
+        let store = || LocalFilesystem {
+            base: tmp.path().to_path_buf(),
+            sync_on_put: false,
+        };
+        let mut writer = S3BlockWriter::new(S3BlockWriterArgs {
+            client: Box::new(store().with_compression().with_min_size(64)),
+            block_size: 1024,
+            footer: false,
+            spanning: false,
+            max_blocks: None,
+        });
+        let big = vec![b'a'; 1020];
+        let locs = [writer.append(&big).await?, writer.append(b"tail").await?];
+        writer.flush().await?;
+        assert_eq!(writer.blocks().len(), 2);
+
+        let stored = |block_id| std::fs::read(tmp.path().join(block_name(block_id).as_str()));
+        assert_eq!(Codec::detect(&stored(0)?), Some(Codec::Zstd));
+        assert!(stored(0)?.len() < big.len());
+        let tail = stored(1)?;
+        assert_eq!(Codec::detect(&tail), None);
+        assert!(tail.ends_with(b"tail"));
+
+        let mut reader = S3BlockReader::new(S3BlockReaderArgs {
+            client: Box::new(store().with_compression()),
+            footer: false,
+            spanning: false,
+        });
+        assert_eq!(reader.fetch(&locs[0]).await?, big);
+        assert_eq!(reader.fetch(&locs[1]).await?, b"tail");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn too_many_blocks_abort_the_write() -> anyhow::Result<()> {
+        let store = MemoryStore::default().with_metrics();
+        let mut writer = S3BlockWriter::resume(
+            S3BlockWriterArgs {
+                client: Box::new(store.clone()),
+                block_size: 16,
+                footer: false,
+                spanning: false,
+                max_blocks: Some(3),
+            },
+            vec![BlockEntry::new(0, b"existing")],
+        );
+        // Each record fills a block of its own.
+        for record in ["record-1", "record-2", "record-3"] {
+            writer.append(record.as_bytes()).await?;
+        }
+        let err = writer.flush().await.unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "writing block 4 would exceed the limit of 3 blocks; use a larger block size"
+        );
+        assert_eq!(writer.blocks().len(), 3);
+        assert_eq!(store.snapshot().puts, 2);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn fetch_errors_name_the_record() -> anyhow::Result<()> {
+        let tmp = tempdir()?;
+        let store = || LocalFilesystem {
+            base: tmp.path().to_path_buf(),
+            sync_on_put: false,
+        };
+        let mut writer = S3BlockWriter::new(S3BlockWriterArgs {
+            client: Box::new(store()),
+            block_size: 64,
+            footer: false,
+            spanning: false,
+            max_blocks: None,
+        });
+        writer.append(b"only record").await?;
+        writer.flush().await?;
+
+        let mut reader = S3BlockReader::new(S3BlockReaderArgs {
+            client: Box::new(store()),
+            footer: false,
+            spanning: false,
+        });
+        for loc in [
+            Location {
+                block_id: 0,
+                offset: 500,
+            },
+            Location {
+                block_id: 7,
+                offset: 0,
+            },
+        ] {
+            let err = format!("{:#}", reader.fetch(&loc).await.unwrap_err());
+            assert!(err.contains(&format!("{:?}", loc)), "{}", err);
+            assert!(err.contains(block_name(loc.block_id).as_str()), "{}", err);
+        }
+        Ok(())
     }
 }
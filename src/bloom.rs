@@ -0,0 +1,143 @@
+use anyhow::bail;
+
+use crate::blob::Blobstore;
+
+/// The default for `etl --bloom-false-positive-rate`.
+pub const DEFAULT_FALSE_POSITIVE_RATE: f64 = 0.01;
+
+/// What a `BloomFilter` knows about a key.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum Membership {
+    /// The key is definitely not in the dataset.
+    Absent,
+    /// The key is probably in the dataset; only the index can say for sure.
+    Maybe,
+}
+
+/// A Bloom filter over every key in a dataset's index, so that most lookups of absent keys can
+/// be answered without downloading the index. Stored as `index/keys.bloom`: the number of hash
+/// functions as a little-endian u32, then the bits.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct BloomFilter {
+    bits: Vec<u8>,
+    num_hashes: u32,
+}
+
+impl BloomFilter {
+    pub const KEY: &'static str = "index/keys.bloom";
+
+    /// An empty filter sized for `num_keys` keys to be reported `Maybe` when absent about
+    /// `false_positive_rate` of the time.
+    pub fn new(num_keys: usize, false_positive_rate: f64) -> Self {
+        let num_keys = num_keys.max(1) as f64;
+        let ln2 = std::f64::consts::LN_2;
+        let num_bits = (-num_keys * false_positive_rate.ln() / (ln2 * ln2)).ceil();
+        let num_bytes = ((num_bits / 8.0).ceil() as usize).max(1);
+        let num_hashes = ((num_bytes * 8) as f64 / num_keys * ln2).round();
+        BloomFilter {
+            bits: vec![0; num_bytes],
+            num_hashes: num_hashes.clamp(1.0, 32.0) as u32,
+        }
+    }
+
+    pub fn insert(&mut self, key: &[u8]) {
+        for bit in self.bit_indexes(key) {
+            self.bits[bit / 8] |= 1 << (bit % 8);
+        }
+    }
+
+    pub fn contains(&self, key: &[u8]) -> Membership {
+        let present = self
+            .bit_indexes(key)
+            .all(|bit| self.bits[bit / 8] & (1 << (bit % 8)) != 0);
+        if present {
+            Membership::Maybe
+        } else {
+            Membership::Absent
+        }
+    }
+
+    // Derives the filter's hash functions from two halves of the key's SHA-256, by double
+    // hashing. SHA-256 rather than `std`'s hasher, so a stored filter means the same thing to
+    // every build.
+    fn bit_indexes(&self, key: &[u8]) -> impl Iterator<Item = usize> {
+        let digest = ring::digest::digest(&ring::digest::SHA256, key);
+        let digest = digest.as_ref();
+        let h1 = u64::from_le_bytes(digest[..8].try_into().unwrap());
+        let h2 = u64::from_le_bytes(digest[8..16].try_into().unwrap());
+        let num_bits = self.bits.len() as u64 * 8;
+        (0..self.num_hashes as u64)
+            .map(move |i| (h1.wrapping_add(i.wrapping_mul(h2)) % num_bits) as usize)
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(4 + self.bits.len());
+        buf.extend(self.num_hashes.to_le_bytes());
+        buf.extend_from_slice(&self.bits);
+        buf
+    }
+
+    pub fn decode(buf: &[u8]) -> anyhow::Result<Self> {
+        let Some((num_hashes, bits)) = buf.split_first_chunk::<4>() else {
+            bail!("bloom filter is truncated: {} bytes", buf.len());
+        };
+        let num_hashes = u32::from_le_bytes(*num_hashes);
+        if num_hashes == 0 || bits.is_empty() {
+            bail!(
+                "bloom filter is malformed: {} hashes over {} bytes",
+                num_hashes,
+                bits.len()
+            );
+        }
+        Ok(BloomFilter {
+            bits: bits.to_vec(),
+            num_hashes,
+        })
+    }
+
+    /// Reads the filter from a dataset, if it has one. Only datasets written with
+    /// `etl --bloom-filter` do.
+    pub async fn load(blob: &mut impl Blobstore) -> anyhow::Result<Option<Self>> {
+        match blob.get(Self::KEY).await? {
+            Some(buf) => Ok(Some(Self::decode(&buf)?)),
+            None => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::bloom::{BloomFilter, Membership};
+
+    #[test]
+    fn present_keys_are_always_maybe() -> anyhow::Result<()> {
+        let mut filter = BloomFilter::new(1000, 0.01);
+        for i in 0..1000 {
+            filter.insert(format!("present-{}", i).as_bytes());
+        }
+        let filter = BloomFilter::decode(&filter.encode())?;
+        for i in 0..1000 {
+            let key = format!("present-{}", i);
+            assert_eq!(
+                filter.contains(key.as_bytes()),
+                Membership::Maybe,
+                "{}",
+                key
+            );
+        }
+
+        let maybes = (0..10_000)
+            .filter(|i| filter.contains(format!("absent-{}", i).as_bytes()) == Membership::Maybe)
+            .count();
+        // About 1% are expected; allow plenty of slack.
+        assert!(maybes < 300, "{} false positives", maybes);
+        Ok(())
+    }
+
+    #[test]
+    fn malformed_filters_are_errors() {
+        assert!(BloomFilter::decode(&[1, 0]).is_err());
+        assert!(BloomFilter::decode(&[0, 0, 0, 0, 0xff]).is_err());
+        assert!(BloomFilter::decode(&[1, 0, 0, 0]).is_err());
+    }
+}
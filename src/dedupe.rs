@@ -0,0 +1,110 @@
+use std::num::NonZeroUsize;
+
+use anyhow::anyhow;
+use lru::LruCache;
+
+use crate::block::{BlockWriter, Location};
+
+/// The default for `etl --dedupe-cache-size`.
+pub const DEFAULT_CACHE_SIZE: usize = 1 << 20;
+
+/// Stores each distinct record once: a record identical to one already written is given that
+/// record's `Location` instead of being appended again. Records are remembered by their SHA-256
+/// in an LRU of bounded size, so a duplicate whose original has been forgotten is stored again.
+pub struct Deduper {
+    seen: LruCache<[u8; 32], Location>,
+    deduplicated: usize,
+}
+
+impl Deduper {
+    /// Remembers up to `capacity` distinct records (about 80 bytes each).
+    pub fn new(capacity: usize) -> anyhow::Result<Self> {
+        let capacity = NonZeroUsize::new(capacity)
+            .ok_or_else(|| anyhow!("the dedupe cache must hold at least one record"))?;
+        Ok(Deduper {
+            seen: LruCache::new(capacity),
+            deduplicated: 0,
+        })
+    }
+
+    /// Where `record` is stored: where an identical record already is, or else wherever
+    /// `writer` appends it.
+    pub async fn append(
+        &mut self,
+        writer: &mut impl BlockWriter,
+        record: &[u8],
+    ) -> anyhow::Result<Location> {
+        let digest = ring::digest::digest(&ring::digest::SHA256, record);
+        let digest: [u8; 32] = digest.as_ref().try_into()?;
+        if let Some(&loc) = self.seen.get(&digest) {
+            self.deduplicated += 1;
+            return Ok(loc);
+        }
+        let loc = writer.append(record).await?;
+        self.seen.put(digest, loc);
+        Ok(loc)
+    }
+
+    /// How many records were given an existing `Location` rather than stored.
+    pub fn deduplicated(&self) -> usize {
+        self.deduplicated
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        blob::MemoryStore,
+        block::{
+            BlockReader, BlockWriter, S3BlockReader, S3BlockReaderArgs, S3BlockWriter,
+            S3BlockWriterArgs,
+        },
+        dedupe::Deduper,
+    };
+
+    #[tokio::test]
+    async fn duplicate_records_share_a_location() -> anyhow::Result<()> {
+        let mut writer = S3BlockWriter::new(S3BlockWriterArgs {
+            client: Box::new(MemoryStore::default()),
+            // Each record fills a block of its own.
+            block_size: 16,
+            footer: false,
+            spanning: false,
+            max_blocks: None,
+        });
+        let mut deduper = Deduper::new(4)?;
+        let first = deduper.append(&mut writer, b"same body").await?;
+        let second = deduper.append(&mut writer, b"same body").await?;
+        writer.flush().await?;
+        assert_eq!(first, second);
+        assert_eq!(writer.blocks().len(), 1);
+        assert_eq!(deduper.deduplicated(), 1);
+
+        let mut reader = S3BlockReader::new(S3BlockReaderArgs {
+            client: writer.into_client(),
+            footer: false,
+            spanning: false,
+        });
+        assert_eq!(reader.fetch(&first).await?, b"same body");
+        assert_eq!(reader.fetch(&second).await?, b"same body");
+        assert!(Deduper::new(0).is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn forgotten_records_are_stored_again() -> anyhow::Result<()> {
+        let mut writer = S3BlockWriter::new(S3BlockWriterArgs {
+            client: Box::new(MemoryStore::default()),
+            block_size: 16,
+            footer: false,
+            spanning: false,
+            max_blocks: None,
+        });
+        let mut deduper = Deduper::new(1)?;
+        let a = deduper.append(&mut writer, b"a").await?;
+        deduper.append(&mut writer, b"b").await?;
+        assert_ne!(deduper.append(&mut writer, b"a").await?, a);
+        assert_eq!(deduper.deduplicated(), 0);
+        Ok(())
+    }
+}
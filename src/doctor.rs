@@ -0,0 +1,436 @@
+use std::{collections::BTreeSet, fmt, io::Write};
+
+use tracing::debug;
+
+use crate::{
+    blob::{Blobstore, Codec},
+    block::{block_name, block_prefix, BlockReader, IndexValue, S3BlockReader, S3BlockReaderArgs},
+    index,
+    manifest::Manifest,
+    path::Key,
+};
+
+/// Why a check failed, and what to do about it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Failure {
+    pub problem: String,
+    pub hint: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    /// Passed, with a short note on what was found.
+    Pass(String),
+    Fail(Failure),
+    /// Not run, because a check it depends on failed.
+    Skipped,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Check {
+    pub name: &'static str,
+    pub outcome: Outcome,
+}
+
+impl Check {
+    pub fn pass(name: &'static str, note: impl Into<String>) -> Self {
+        Self {
+            name,
+            outcome: Outcome::Pass(note.into()),
+        }
+    }
+    pub fn fail(name: &'static str, problem: impl Into<String>, hint: impl Into<String>) -> Self {
+        Self {
+            name,
+            outcome: Outcome::Fail(Failure {
+                problem: problem.into(),
+                hint: hint.into(),
+            }),
+        }
+    }
+    pub fn skipped(name: &'static str) -> Self {
+        Self {
+            name,
+            outcome: Outcome::Skipped,
+        }
+    }
+
+    pub fn passed(&self) -> bool {
+        matches!(self.outcome, Outcome::Pass(_))
+    }
+}
+
+/// The results of a series of checks, in the order they ran.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Checklist {
+    pub checks: Vec<Check>,
+}
+
+impl Checklist {
+    pub fn push(&mut self, check: Check) {
+        self.checks.push(check);
+    }
+
+    /// The outcome of the check called `name`, if it ran.
+    pub fn outcome(&self, name: &str) -> Option<&Outcome> {
+        self.checks
+            .iter()
+            .find(|check| check.name == name)
+            .map(|check| &check.outcome)
+    }
+
+    pub fn all_passed(&self) -> bool {
+        self.checks.iter().all(Check::passed)
+    }
+}
+
+impl fmt::Display for Checklist {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for check in &self.checks {
+            match &check.outcome {
+                Outcome::Pass(note) => writeln!(f, "[pass] {}: {}", check.name, note)?,
+                Outcome::Fail(failure) => {
+                    writeln!(f, "[FAIL] {}: {}", check.name, failure.problem)?;
+                    writeln!(f, "       hint: {}", failure.hint)?;
+                }
+                Outcome::Skipped => writeln!(f, "[skip] {}", check.name)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+pub const MANIFEST: &str = "manifest";
+pub const INDEX: &str = "index";
+pub const BLOCKS: &str = "blocks";
+pub const CODEC: &str = "codec";
+
+/// Checks the dataset in `blob` (which should already be scoped to the dataset's prefix): that
+/// its manifest parses, that the index version exists and is a valid SST, that the blocks
+/// behind up to `sample` of the index's records exist, and that those records decode with the
+/// manifest's codec.
+pub async fn check_dataset<B: Blobstore + 'static>(
+    mut blob: B,
+    index_version: &str,
+    sample: usize,
+) -> Checklist {
+    let mut checklist = Checklist::default();
+
+    let manifest = match Manifest::load(&mut blob).await {
+        Ok(Some(manifest)) => {
+            checklist.push(Check::pass(
+                MANIFEST,
+                format!("{} blocks", manifest.blocks.len()),
+            ));
+            Some(manifest)
+        }
+        Ok(None) => {
+            checklist.push(Check::fail(
+                MANIFEST,
+                format!("no {} under the prefix", Manifest::KEY),
+                "check --prefix; datasets written by an older etl may have no manifest",
+            ));
+            None
+        }
+        Err(err) => {
+            checklist.push(Check::fail(
+                MANIFEST,
+                format!("{} is unreadable: {:#}", Manifest::KEY, err),
+                "rerun etl to rewrite the dataset",
+            ));
+            None
+        }
+    };
+
+    let index_name = match index::index_key(index_version) {
+        Ok(name) => name,
+        Err(err) => {
+            checklist.push(Check::fail(
+                INDEX,
+                format!("can't name the index: {:#}", err),
+                "pass an --index-version as published by etl",
+            ));
+            return checklist;
+        }
+    };
+    let db_dir = match tempfile::TempDir::new() {
+        Ok(dir) => dir,
+        Err(err) => {
+            checklist.push(Check::fail(
+                INDEX,
+                format!("can't create a scratch directory: {}", err),
+                "check that the temp directory is writable",
+            ));
+            return checklist;
+        }
+    };
+    let compressed = manifest
+        .as_ref()
+        .is_some_and(|manifest| manifest.compressed_index);
+    let db = match load_index(&mut blob, &index_name, compressed, db_dir.path()).await {
+        Ok(Some((db, len))) => {
+            checklist.push(Check::pass(
+                INDEX,
+                format!("{} ({} bytes)", index_name, len),
+            ));
+            Some(db)
+        }
+        Ok(None) => {
+            checklist.push(Check::fail(
+                INDEX,
+                format!("no {} under the prefix", index_name),
+                "check --index-version (`scan --list-index-versions` lists them), or rerun etl",
+            ));
+            None
+        }
+        Err(err) => {
+            checklist.push(Check::fail(
+                INDEX,
+                format!("{} is not a usable index: {:#}", index_name, err),
+                "the upload may have been interrupted; rerun etl",
+            ));
+            None
+        }
+    };
+    let Some(db) = db else {
+        checklist.push(Check::skipped(BLOCKS));
+        checklist.push(Check::skipped(CODEC));
+        return checklist;
+    };
+
+    let values = match sample_index(&db, sample) {
+        Ok(values) => values,
+        Err(err) => {
+            checklist.push(Check::fail(
+                BLOCKS,
+                format!("the index has unreadable entries: {:#}", err),
+                "rerun etl to rewrite the index",
+            ));
+            checklist.push(Check::skipped(CODEC));
+            return checklist;
+        }
+    };
+
+    let block_ids: BTreeSet<usize> = values.iter().map(|v| v.location.block_id).collect();
+    let mut missing = Vec::new();
+    for &block_id in &block_ids {
+        let key = format!("block/{}", block_name(block_id));
+        debug!("checking that {} exists", key);
+        match blob.get(&key).await {
+            Ok(Some(_)) => {}
+            Ok(None) => missing.push(block_id),
+            Err(err) => {
+                checklist.push(Check::fail(
+                    BLOCKS,
+                    format!("can't read block {}: {:#}", block_id, err),
+                    "check read access to the block/ prefix",
+                ));
+                checklist.push(Check::skipped(CODEC));
+                return checklist;
+            }
+        }
+    }
+    if !missing.is_empty() {
+        checklist.push(Check::fail(
+            BLOCKS,
+            format!(
+                "{} of {} sampled blocks are missing (e.g. block {})",
+                missing.len(),
+                block_ids.len(),
+                missing[0]
+            ),
+            "the dataset is incomplete; rerun etl, or run verify to list every missing block",
+        ));
+        checklist.push(Check::skipped(CODEC));
+        return checklist;
+    }
+    checklist.push(Check::pass(
+        BLOCKS,
+        format!("{} sampled blocks exist", block_ids.len()),
+    ));
+
+    let compression_dict = match &manifest {
+        Some(manifest) => manifest.compression_dict(&mut blob).await,
+        None => Ok(None),
+    };
+    let compression_dict = match compression_dict {
+        Ok(dict) => dict,
+        Err(err) => {
+            checklist.push(Check::fail(
+                CODEC,
+                format!("can't load the compression dictionary: {:#}", err),
+                "the dataset's dict/ prefix is missing or damaged; rerun etl",
+            ));
+            return checklist;
+        }
+    };
+    let mut reader = S3BlockReader::new(S3BlockReaderArgs {
+        client: Box::new(
+            blob.with_prefix(&block_prefix())
+                .with_compression_dict(compression_dict),
+        ),
+        footer: manifest.as_ref().is_some_and(|m| m.block_footer),
+        spanning: manifest.as_ref().is_some_and(|m| m.spanning_records),
+    });
+    for value in &values {
+        if let Err(err) = reader.fetch(&value.location).await {
+            checklist.push(Check::fail(
+                CODEC,
+                format!("{:#}", err),
+                "blocks don't decode with the manifest's codec; was the dataset written by a \
+                 different version of etl?",
+            ));
+            return checklist;
+        }
+    }
+    checklist.push(Check::pass(
+        CODEC,
+        format!("{} sampled records decode", values.len()),
+    ));
+    checklist
+}
+
+// Returns the ingested index and the size of the SST, or `None` if there's no such index.
+async fn load_index(
+    blob: &mut impl Blobstore,
+    name: &Key,
+    compressed: bool,
+    dir: &std::path::Path,
+) -> anyhow::Result<Option<(rocksdb::DB, usize)>> {
+    let Some(body) = blob.get(name).await? else {
+        return Ok(None);
+    };
+    let mut index_file = tempfile::NamedTempFile::new()?;
+    if compressed {
+        index_file.write_all(&Codec::Zstd.decode(name, &body, None)?)?;
+    } else {
+        index_file.write_all(&body)?;
+    }
+    index_file.flush()?;
+    let db = index::ingest(index_file.path(), &index::db_options(), dir)?;
+    Ok(Some((db, body.len())))
+}
+
+// Up to `sample` index values, spread evenly across the index.
+fn sample_index(db: &rocksdb::DB, sample: usize) -> anyhow::Result<Vec<IndexValue>> {
+    let total = db.iterator(rocksdb::IteratorMode::Start).count();
+    let stride = (total / sample.max(1)).max(1);
+    let mut values = Vec::new();
+    for entry in db
+        .iterator(rocksdb::IteratorMode::Start)
+        .step_by(stride)
+        .take(sample)
+    {
+        let (_, v) = entry?;
+        values.push(IndexValue::decode(&v)?);
+    }
+    Ok(values)
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use tempfile::tempdir;
+
+    use crate::{
+        blob::{Blobstore, LocalFilesystem},
+        block::{block_prefix, BlockWriter, IndexValue, S3BlockWriter, S3BlockWriterArgs},
+        doctor::{check_dataset, Outcome, BLOCKS, CODEC, INDEX, MANIFEST},
+        index::db_options,
+        manifest::Manifest,
+    };
+
+    async fn write_dataset(base: &Path) -> anyhow::Result<()> {
+        let store = || LocalFilesystem {
+            base: base.to_path_buf(),
+            sync_on_put: false,
+        };
+        std::fs::create_dir_all(base.join("block"))?;
+        std::fs::create_dir_all(base.join("index"))?;
+        let mut writer = S3BlockWriter::new(S3BlockWriterArgs {
+            client: Box::new(store().with_prefix(&block_prefix()).with_compression()),
+            block_size: 64,
+            footer: false,
+            spanning: false,
+            max_blocks: None,
+        });
+        let db_opts = db_options();
+        let mut index = rocksdb::SstFileWriter::create(&db_opts);
+        index.open(base.join("index/default.sst"))?;
+        for i in 0..40 {
+            let location = writer.append(format!("record-{}", i).as_bytes()).await?;
+            let value = IndexValue {
+                location,
+                ..IndexValue::default()
+            };
+            index.put(format!("key-{:02}", i), value.encode())?;
+        }
+        writer.flush().await?;
+        index.finish()?;
+        let manifest = Manifest {
+            block_size: 64,
+            blocks: writer.blocks().to_vec(),
+            ..Manifest::default()
+        };
+        store().put(Manifest::KEY, &manifest.encode()?).await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn healthy_dataset_passes() -> anyhow::Result<()> {
+        let base = tempdir()?;
+        write_dataset(base.path()).await?;
+        let store = LocalFilesystem {
+            base: base.path().to_path_buf(),
+            sync_on_put: false,
+        };
+        let checklist = check_dataset(store, "default", 8).await;
+        assert!(checklist.all_passed(), "{}", checklist);
+        assert_eq!(checklist.checks.len(), 4);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn missing_index_is_diagnosed() -> anyhow::Result<()> {
+        let base = tempdir()?;
+        write_dataset(base.path()).await?;
+        std::fs::remove_file(base.path().join("index/default.sst"))?;
+        let store = LocalFilesystem {
+            base: base.path().to_path_buf(),
+            sync_on_put: false,
+        };
+        let checklist = check_dataset(store, "default", 8).await;
+        assert!(matches!(
+            checklist.outcome(MANIFEST),
+            Some(Outcome::Pass(_))
+        ));
+        let Some(Outcome::Fail(failure)) = checklist.outcome(INDEX) else {
+            panic!("expected the index check to fail:\n{}", checklist);
+        };
+        assert!(failure.problem.contains("index/default.sst"));
+        assert!(failure.hint.contains("--index-version"));
+        assert_eq!(checklist.outcome(BLOCKS), Some(&Outcome::Skipped));
+        assert_eq!(checklist.outcome(CODEC), Some(&Outcome::Skipped));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn missing_block_is_diagnosed() -> anyhow::Result<()> {
+        let base = tempdir()?;
+        write_dataset(base.path()).await?;
+        std::fs::remove_file(
+            base.path()
+                .join("block")
+                .join(crate::block::block_name(1).as_str()),
+        )?;
+        let store = LocalFilesystem {
+            base: base.path().to_path_buf(),
+            sync_on_put: false,
+        };
+        let checklist = check_dataset(store, "default", 100).await;
+        assert!(matches!(checklist.outcome(INDEX), Some(Outcome::Pass(_))));
+        assert!(matches!(checklist.outcome(BLOCKS), Some(Outcome::Fail(_))));
+        Ok(())
+    }
+}
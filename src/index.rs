@@ -0,0 +1,1185 @@
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, HashMap},
+    future::Future,
+    io::Write,
+    path::Path,
+    time::{Duration, SystemTime},
+};
+
+use anyhow::{bail, Context};
+use tracing::debug;
+
+use crate::{
+    blob::{Blobstore, Codec},
+    block::{BlockReader, IndexValue, Location},
+    path::Key,
+};
+
+/// Where version `version` of a dataset's index is stored, relative to the dataset's prefix.
+pub fn index_key(version: &str) -> anyhow::Result<Key> {
+    Key::new(format!("index/{}.sst", version))
+}
+
+/// The RocksDB options readers use for the local copy of an index.
+pub fn db_options() -> rocksdb::Options {
+    let mut db_opts = rocksdb::Options::default();
+    db_opts.create_if_missing(true);
+    db_opts.set_compression_type(rocksdb::DBCompressionType::Zstd);
+    db_opts
+}
+
+/// `db_options` for a DB that index entries are `merge`d into rather than `put`, such as the one
+/// `etl --index-db` keeps between runs. Merging a key that's already there replaces its value.
+pub fn merge_db_options() -> rocksdb::Options {
+    let mut db_opts = db_options();
+    db_opts.set_merge_operator_associative("last_writer_wins", last_writer_wins);
+    db_opts
+}
+
+fn last_writer_wins(
+    _key: &[u8],
+    existing: Option<&[u8]>,
+    operands: &rocksdb::MergeOperands,
+) -> Option<Vec<u8>> {
+    operands.iter().last().or(existing).map(<[u8]>::to_vec)
+}
+
+/// A conservative cap on how many SST files a reader DB keeps open, so that hosts with a low
+/// descriptor limit don't run out.
+pub const DEFAULT_MAX_OPEN_FILES: i32 = 256;
+
+/// `db_options`, holding at most `max_open_files` files open at once (-1 for no limit).
+pub fn reader_db_options(max_open_files: i32) -> rocksdb::Options {
+    let mut db_opts = db_options();
+    db_opts.set_max_open_files(max_open_files);
+    db_opts
+}
+
+/// Downloads the index SST `name` and ingests it into a new DB at `dir`. If `compressed` (see
+/// `Manifest::compressed_index`), the SST is decompressed before it's written out for ingest.
+pub async fn download(
+    blob: &mut impl Blobstore,
+    name: &Key,
+    compressed: bool,
+    db_opts: &rocksdb::Options,
+    dir: &Path,
+) -> anyhow::Result<rocksdb::DB> {
+    debug!("downloading index {}", name);
+    let mut index_body = blob.must_get(name).await?;
+    if compressed {
+        debug!("decompressing index {}", name);
+        index_body = Cow::Owned(Codec::Zstd.decode(name, &index_body, None)?);
+    }
+    let mut index_file = tempfile::NamedTempFile::new()?;
+    index_file.write_all(&index_body)?;
+    index_file.flush()?;
+    ingest(index_file.path(), db_opts, dir)
+}
+
+/// Ingests a local copy of an index SST into a new DB at `dir`.
+pub fn ingest(
+    index_file: &Path,
+    db_opts: &rocksdb::Options,
+    dir: &Path,
+) -> anyhow::Result<rocksdb::DB> {
+    let db = rocksdb::DB::open(db_opts, dir)?;
+    debug!("ingesting index {:?}", index_file);
+    db.ingest_external_file(vec![index_file])?;
+    Ok(db)
+}
+
+/// Checks that the keys of the SST at `sst` are strictly increasing, which
+/// `ingest_external_file` assumes but doesn't verify. The SST is ingested on its own into a
+/// scratch DB at `scratch`, whose only file iterates in the order its keys were written.
+pub fn check_sorted_sst(sst: &Path, scratch: &Path) -> anyhow::Result<()> {
+    let db = ingest(sst, &db_options(), scratch)?;
+    check_sorted(range(&db, None, None, false)).with_context(|| format!("checking {:?}", sst))
+}
+
+/// Errors with the first pair of keys in `entries` that aren't strictly increasing.
+pub fn check_sorted(entries: impl Iterator<Item = Entry>) -> anyhow::Result<()> {
+    let mut prev: Option<Box<[u8]>> = None;
+    for entry in entries {
+        let (key, _) = entry?;
+        if let Some(prev) = &prev {
+            if key <= *prev {
+                bail!(
+                    "keys are out of order: {:?} is followed by {:?}",
+                    String::from_utf8_lossy(prev),
+                    String::from_utf8_lossy(&key)
+                );
+            }
+        }
+        prev = Some(key);
+    }
+    Ok(())
+}
+
+/// Makes sure a DB about to be created at `dir` won't be mixed with the entries of an existing
+/// one. A missing or empty directory is fine; a non-empty one is an error unless `overwrite` is
+/// set, in which case it is removed first.
+pub fn prepare_output(dir: &Path, overwrite: bool) -> anyhow::Result<()> {
+    let non_empty = match std::fs::read_dir(dir) {
+        Ok(mut entries) => entries.next().is_some(),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => false,
+        Err(err) => return Err(err.into()),
+    };
+    if !non_empty {
+        return Ok(());
+    }
+    if !overwrite {
+        bail!(
+            "{:?} already exists and is not empty; pass --overwrite to replace it",
+            dir
+        );
+    }
+    debug!("removing existing DB at {:?}", dir);
+    std::fs::remove_dir_all(dir)?;
+    Ok(())
+}
+
+// Marks a cached DB as completely ingested, so an interrupted run isn't mistaken for a good one.
+const INGESTED_MARKER: &str = "S3KV_INGESTED";
+
+/// Like `download`, but keeps the ingested DB under `cache_dir`, keyed by the index name and its
+/// ETag. If a complete DB for the same ETag is already there, it is reused without downloading.
+pub async fn open_cached(
+    blob: &mut impl Blobstore,
+    name: &Key,
+    compressed: bool,
+    etag: &str,
+    db_opts: &rocksdb::Options,
+    cache_dir: &Path,
+) -> anyhow::Result<rocksdb::DB> {
+    let mut ctx = ring::digest::Context::new(&ring::digest::SHA256);
+    ctx.update(name.as_bytes());
+    ctx.update(b"\0");
+    ctx.update(etag.as_bytes());
+    let dir = cache_dir.join(hex::encode(ctx.finish()));
+
+    if dir.join(INGESTED_MARKER).exists() {
+        debug!("reusing cached index {} at {:?}", name, dir);
+        return Ok(rocksdb::DB::open(db_opts, &dir)?);
+    }
+    if dir.exists() {
+        std::fs::remove_dir_all(&dir)?;
+    }
+    std::fs::create_dir_all(&dir)?;
+    let db = download(blob, name, compressed, db_opts, &dir).await?;
+    db.flush()?;
+    std::fs::write(dir.join(INGESTED_MARKER), etag)?;
+    Ok(db)
+}
+
+/// A key and value read from an index.
+pub type Entry = Result<(Box<[u8]>, Box<[u8]>), rocksdb::Error>;
+
+/// Iterates the entries of `db` in `[lower, upper)`, in key order or, with `reverse`, from the
+/// largest key down.
+pub fn range<'a>(
+    db: &'a rocksdb::DB,
+    lower: Option<&[u8]>,
+    upper: Option<&[u8]>,
+    reverse: bool,
+) -> impl Iterator<Item = Entry> + 'a {
+    let mut read_opts = rocksdb::ReadOptions::default();
+    if let Some(lower) = lower {
+        read_opts.set_iterate_lower_bound(lower);
+    }
+    if let Some(upper) = upper {
+        read_opts.set_iterate_upper_bound(upper);
+    }
+    let mode = if reverse {
+        rocksdb::IteratorMode::End
+    } else {
+        rocksdb::IteratorMode::Start
+    };
+    db.iterator_opt(mode, read_opts)
+}
+
+/// Picks up to `n - 1` keys that split the entries of `db` in `[lower, upper)` into `n` ranges of
+/// roughly equal size. The keys are strictly increasing; each starts a range.
+pub fn split_points(
+    db: &rocksdb::DB,
+    n: usize,
+    lower: Option<&[u8]>,
+    upper: Option<&[u8]>,
+) -> anyhow::Result<Vec<Vec<u8>>> {
+    let bounded = || range(db, lower, upper, false);
+    let mut total = 0;
+    for entry in bounded() {
+        entry?;
+        total += 1;
+    }
+    let mut points = Vec::new();
+    if n < 2 || total < 2 {
+        return Ok(points);
+    }
+    // Entry `i * total / n` starts the i-th range. With more ranges than entries some of these
+    // coincide, and those ranges are dropped.
+    let mut starts: Vec<usize> = (1..n).map(|i| i * total / n).filter(|&i| i > 0).collect();
+    starts.dedup();
+    let mut starts = starts.into_iter().peekable();
+    for (idx, entry) in bounded().enumerate() {
+        let Some(&start) = starts.peek() else {
+            break;
+        };
+        if idx == start {
+            points.push(entry?.0.to_vec());
+            starts.next();
+        }
+    }
+    Ok(points)
+}
+
+/// Copies every live record of `index` in `[lower, upper)` into `out` as `key -> record`,
+/// fetching records through `blocks`. Keys already in `out` are skipped without a fetch, so an
+/// interrupted copy picks up where it left off. Returns how many records were copied.
+pub async fn materialize(
+    index: &rocksdb::DB,
+    blocks: &mut impl BlockReader,
+    out: &rocksdb::DB,
+    lower: Option<&[u8]>,
+    upper: Option<&[u8]>,
+    include_expired: bool,
+) -> anyhow::Result<usize> {
+    let mut write_opts = rocksdb::WriteOptions::default();
+    write_opts.disable_wal(true);
+    let mut copied = 0;
+    for entry in range(index, lower, upper, false) {
+        let (k, v) = entry?;
+        let value = IndexValue::decode(&v)?;
+        if !include_expired && value.is_expired(SystemTime::now()) {
+            continue;
+        }
+        if out.get(&k)?.is_some() {
+            continue;
+        }
+        let record = blocks.fetch(&value.location).await?;
+        out.put_opt(&k, record, &write_opts)?;
+        copied += 1;
+    }
+    Ok(copied)
+}
+
+/// How many of the live records in a range of an index one block holds, and the offsets they
+/// span. Records spread over many blocks cost many block reads.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct BlockUsage {
+    pub block_id: usize,
+    pub records: usize,
+    pub min_offset: usize,
+    pub max_offset: usize,
+}
+
+/// The `BlockUsage` of every block holding a live record of `index` in `[lower, upper)`, in
+/// block order. Only the index is read.
+pub fn block_usage(
+    index: &rocksdb::DB,
+    lower: Option<&[u8]>,
+    upper: Option<&[u8]>,
+    include_expired: bool,
+) -> anyhow::Result<Vec<BlockUsage>> {
+    let mut usage: BTreeMap<usize, BlockUsage> = BTreeMap::new();
+    for entry in range(index, lower, upper, false) {
+        let (_, v) = entry?;
+        let value = IndexValue::decode(&v)?;
+        if !include_expired && value.is_expired(SystemTime::now()) {
+            continue;
+        }
+        let Location { block_id, offset } = value.location;
+        let block = usage.entry(block_id).or_insert(BlockUsage {
+            block_id,
+            records: 0,
+            min_offset: offset,
+            max_offset: offset,
+        });
+        block.records += 1;
+        block.min_offset = block.min_offset.min(offset);
+        block.max_offset = block.max_offset.max(offset);
+    }
+    Ok(usage.into_values().collect())
+}
+
+/// Writes a copy of `index` to the SST at `sst` with every location in `mapping` replaced by
+/// where it maps to, e.g. after records have been moved into new blocks. Entries at a location
+/// not in `mapping` are copied as they are, and the rest of each value is kept. Returns how many
+/// entries were remapped.
+pub fn remap(
+    index: &rocksdb::DB,
+    mapping: &HashMap<Location, Location>,
+    sst: &Path,
+) -> anyhow::Result<usize> {
+    let db_opts = db_options();
+    let mut writer = rocksdb::SstFileWriter::create(&db_opts);
+    writer.open(sst)?;
+    let mut remapped = 0;
+    let mut written = 0;
+    // The DB iterates in key order, which is the order an SST must be written in.
+    for entry in range(index, None, None, false) {
+        let (k, v) = entry?;
+        let mut value = IndexValue::decode(&v)?;
+        match mapping.get(&value.location) {
+            Some(&location) => {
+                value.location = location;
+                writer.put(&k, value.encode())?;
+                remapped += 1;
+            }
+            None => writer.put(&k, &v)?,
+        }
+        written += 1;
+    }
+    if written == 0 {
+        bail!("can't remap an empty index");
+    }
+    writer.finish()?;
+    Ok(remapped)
+}
+
+/// Which value `merge_ssts` keeps for a key that more than one input has.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum ConflictPolicy {
+    /// The value from the first input with the key.
+    FirstWins,
+    /// The value from the last input with the key, as if the inputs were written in order.
+    LastWins,
+}
+
+/// Merges the SSTs at `inputs` into a single SST at `output`. Each input is read on its own,
+/// so their key ranges may overlap: a key in several inputs is written once, with the value
+/// `policy` picks. Returns how many keys were in more than one input.
+pub fn merge_ssts(
+    inputs: &[impl AsRef<Path>],
+    output: &Path,
+    policy: ConflictPolicy,
+) -> anyhow::Result<usize> {
+    let scratch = tempfile::TempDir::new()?;
+    let db_opts = db_options();
+    let dbs = inputs
+        .iter()
+        .enumerate()
+        .map(|(i, input)| {
+            ingest(
+                input.as_ref(),
+                &db_opts,
+                &scratch.path().join(i.to_string()),
+            )
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let mut iters: Vec<_> = dbs.iter().map(|db| range(db, None, None, false)).collect();
+    // The next entry of each input, in input order.
+    let mut heads = iters
+        .iter_mut()
+        .map(|iter| iter.next().transpose())
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut writer = rocksdb::SstFileWriter::create(&db_opts);
+    writer.open(output)?;
+    let mut conflicts = 0;
+    let mut written = 0;
+    while let Some(key) = heads.iter().flatten().map(|(k, _)| k).min().cloned() {
+        let mut value = None;
+        let mut holders = 0;
+        for (head, iter) in heads.iter_mut().zip(&mut iters) {
+            let Some((_, v)) = head.take_if(|(k, _)| *k == key) else {
+                continue;
+            };
+            holders += 1;
+            if value.is_none() || policy == ConflictPolicy::LastWins {
+                value = Some(v);
+            }
+            *head = iter.next().transpose()?;
+        }
+        if holders > 1 {
+            conflicts += 1;
+        }
+        writer.put(&key, value.expect("some input holds the smallest key"))?;
+        written += 1;
+    }
+    if written == 0 {
+        bail!("can't merge inputs with no entries");
+    }
+    writer.finish()?;
+    Ok(conflicts)
+}
+
+/// The ETag of an index object, used to detect when it has been republished.
+pub async fn etag(
+    client: &aws_sdk_s3::Client,
+    bucket: &str,
+    key: &str,
+) -> anyhow::Result<Option<String>> {
+    let head = client.head_object().bucket(bucket).key(key).send().await?;
+    Ok(head.e_tag().map(str::to_owned))
+}
+
+/// Where `scan --follow` is in an index that's appended to between polls: the ETag of the copy
+/// it last loaded, and the last key it emitted, just past which every reload resumes. So only
+/// keys that sort after everything emitted so far are followed; an append that adds a key
+/// before the cursor never emits it, like `tail -f` ignoring edits above the end of a file.
+#[derive(PartialEq, Eq, Debug, Clone, Default)]
+pub struct Follower {
+    etag: Option<String>,
+    cursor: Option<Vec<u8>>,
+}
+
+impl Follower {
+    /// Follows an index whose current ETag is `etag`.
+    pub fn new(etag: Option<String>) -> Self {
+        Self { etag, cursor: None }
+    }
+
+    pub fn etag(&self) -> Option<&str> {
+        self.etag.as_deref()
+    }
+
+    /// The lower bound for the next scan: `start` until something has been emitted, and after
+    /// that the smallest key after the cursor, which is it with a zero byte appended.
+    pub fn lower_bound(&self, start: Option<&[u8]>) -> Option<Vec<u8>> {
+        match &self.cursor {
+            Some(cursor) => Some([cursor.as_slice(), &[0]].concat()),
+            None => start.map(<[u8]>::to_vec),
+        }
+    }
+
+    /// Records `last`, the last key a scan emitted, if it emitted any.
+    pub fn emitted(&mut self, last: Option<Vec<u8>>) {
+        if last.is_some() {
+            self.cursor = last;
+        }
+    }
+
+    /// Checks `head` for the index's ETag every `interval` until it differs from the last one
+    /// loaded, and remembers the new one. The index should then be reloaded.
+    pub async fn wait_for_change<F, Fut>(
+        &mut self,
+        interval: Duration,
+        mut head: F,
+    ) -> anyhow::Result<()>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = anyhow::Result<Option<String>>>,
+    {
+        loop {
+            tokio::time::sleep(interval).await;
+            let latest = head().await?;
+            if latest != self.etag {
+                debug!("index changed ({:?} -> {:?}), reloading", self.etag, latest);
+                self.etag = latest;
+                return Ok(());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{borrow::Cow, collections::HashMap, path::PathBuf, time::Duration};
+
+    use async_trait::async_trait;
+    use tempfile::tempdir;
+
+    use crate::{
+        blob::{Blobstore, LocalFilesystem},
+        block::{
+            location_line, BlockReader, BlockWriter, IndexValue, Location, S3BlockReader,
+            S3BlockReaderArgs, S3BlockWriter, S3BlockWriterArgs,
+        },
+        index::{
+            block_usage, check_sorted, check_sorted_sst, db_options, download, index_key, ingest,
+            materialize, merge_db_options, merge_ssts, open_cached, prepare_output, range,
+            reader_db_options, remap, split_points, BlockUsage, ConflictPolicy, Entry, Follower,
+        },
+        path::Key,
+    };
+
+    #[derive(Debug)]
+    struct Counting {
+        underlying: LocalFilesystem,
+        gets: usize,
+    }
+    #[async_trait]
+    impl Blobstore for Counting {
+        async fn get(&mut self, key: &str) -> anyhow::Result<Option<Cow<[u8]>>> {
+            self.gets += 1;
+            self.underlying.get(key).await
+        }
+        async fn put(&mut self, key: &str, blob: &[u8]) -> anyhow::Result<()> {
+            self.underlying.put(key, blob).await
+        }
+    }
+
+    #[tokio::test]
+    async fn cached_index_is_reused() -> anyhow::Result<()> {
+        let base = tempdir()?;
+        let sst = base.path().join("default.sst");
+        let db_opts = db_options();
+        let mut writer = rocksdb::SstFileWriter::create(&db_opts);
+        writer.open(&sst)?;
+        writer.put(b"key", b"value")?;
+        writer.finish()?;
+
+        let mut store = Counting {
+            underlying: LocalFilesystem {
+                base: base.path().to_path_buf(),
+                sync_on_put: false,
+            },
+            gets: 0,
+        };
+        let cache = tempdir()?;
+        {
+            let db = open_cached(
+                &mut store,
+                &Key::new("default.sst")?,
+                false,
+                "v1",
+                &db_opts,
+                cache.path(),
+            )
+            .await?;
+            assert_eq!(db.get(b"key")?, Some(b"value".to_vec()));
+        }
+        assert_eq!(store.gets, 1);
+
+        {
+            let db = open_cached(
+                &mut store,
+                &Key::new("default.sst")?,
+                false,
+                "v1",
+                &db_opts,
+                cache.path(),
+            )
+            .await?;
+            assert_eq!(db.get(b"key")?, Some(b"value".to_vec()));
+        }
+        assert_eq!(store.gets, 1);
+
+        // A new ETag means the index changed, so it's downloaded again.
+        open_cached(
+            &mut store,
+            &Key::new("default.sst")?,
+            false,
+            "v2",
+            &db_opts,
+            cache.path(),
+        )
+        .await?;
+        assert_eq!(store.gets, 2);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn local_index_matches_download() -> anyhow::Result<()> {
+        let base = tempdir()?;
+        let sst = base.path().join("default.sst");
+        let db_opts = db_options();
+        let mut writer = rocksdb::SstFileWriter::create(&db_opts);
+        writer.open(&sst)?;
+        for i in 0..10 {
+            writer.put(format!("key-{}", i), format!("value-{}", i))?;
+        }
+        writer.finish()?;
+
+        let mut store = LocalFilesystem {
+            base: base.path().to_path_buf(),
+            sync_on_put: false,
+        };
+        let downloaded_dir = tempdir()?;
+        let downloaded = download(
+            &mut store,
+            &Key::new("default.sst")?,
+            false,
+            &db_opts,
+            downloaded_dir.path(),
+        )
+        .await?;
+        let local_dir = tempdir()?;
+        let local = ingest(&sst, &db_opts, local_dir.path())?;
+
+        let entries = |db: &rocksdb::DB| -> anyhow::Result<Vec<(Vec<u8>, Vec<u8>)>> {
+            db.iterator(rocksdb::IteratorMode::Start)
+                .map(|entry| {
+                    let (k, v) = entry?;
+                    Ok((k.to_vec(), v.to_vec()))
+                })
+                .collect()
+        };
+        let expected = entries(&downloaded)?;
+        assert_eq!(expected.len(), 10);
+        assert_eq!(entries(&local)?, expected);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn compressed_index_scans_the_same() -> anyhow::Result<()> {
+        let base = tempdir()?;
+        let sst = base.path().join("raw.sst");
+        let db_opts = db_options();
+        let mut writer = rocksdb::SstFileWriter::create(&db_opts);
+        writer.open(&sst)?;
+        for i in 0..100 {
+            writer.put(format!("key-{:03}", i), format!("value-{}", i))?;
+        }
+        writer.finish()?;
+        let mut store = LocalFilesystem {
+            base: base.path().to_path_buf(),
+            sync_on_put: false,
+        };
+        store
+            .clone()
+            .with_compression()
+            .put_owned("compressed.sst", std::fs::read(&sst)?)
+            .await?;
+        assert_ne!(
+            std::fs::read(base.path().join("compressed.sst"))?,
+            std::fs::read(&sst)?
+        );
+
+        let scan = |db: &rocksdb::DB| -> anyhow::Result<Vec<Box<[u8]>>> {
+            range(db, Some(b"key-010"), Some(b"key-020"), false)
+                .map(|entry| Ok(entry?.1))
+                .collect()
+        };
+        let raw_dir = tempdir()?;
+        let raw = download(
+            &mut store,
+            &Key::new("raw.sst")?,
+            false,
+            &db_opts,
+            raw_dir.path(),
+        )
+        .await?;
+        let compressed_dir = tempdir()?;
+        let compressed = download(
+            &mut store,
+            &Key::new("compressed.sst")?,
+            true,
+            &db_opts,
+            compressed_dir.path(),
+        )
+        .await?;
+        let expected = scan(&raw)?;
+        assert_eq!(expected.len(), 10);
+        assert_eq!(scan(&compressed)?, expected);
+
+        // Reading a raw index as compressed is an error, not garbage.
+        let dir = tempdir()?;
+        assert!(download(
+            &mut store,
+            &Key::new("raw.sst")?,
+            true,
+            &db_opts,
+            dir.path()
+        )
+        .await
+        .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn index_keys_are_validated() -> anyhow::Result<()> {
+        assert_eq!(index_key("default")?.as_str(), "index/default.sst");
+        assert!(index_key("/default").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn limits_open_files() -> anyhow::Result<()> {
+        let base = tempdir()?;
+        let sst = base.path().join("default.sst");
+        let db_opts = db_options();
+        let mut writer = rocksdb::SstFileWriter::create(&db_opts);
+        writer.open(&sst)?;
+        for i in 0..100 {
+            writer.put(format!("key-{:03}", i), format!("value-{}", i))?;
+        }
+        writer.finish()?;
+
+        let db_dir = tempdir()?;
+        let db = ingest(&sst, &reader_db_options(8), db_dir.path())?;
+        for i in 0..100 {
+            let value = db.get(format!("key-{:03}", i))?;
+            assert_eq!(value, Some(format!("value-{}", i).into_bytes()));
+        }
+
+        // RocksDB records the options a DB was opened with alongside it.
+        let mut applied = false;
+        for entry in std::fs::read_dir(db_dir.path())? {
+            let entry = entry?;
+            if entry.file_name().to_string_lossy().starts_with("OPTIONS-") {
+                let options = std::fs::read_to_string(entry.path())?;
+                applied |= options.contains("max_open_files=8\n");
+            }
+        }
+        assert!(applied);
+        Ok(())
+    }
+
+    #[test]
+    fn reverse_range() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let db = rocksdb::DB::open(&db_options(), dir.path())?;
+        for i in 0..20 {
+            db.put(format!("key-{:02}", i), b"")?;
+        }
+        let keys = |lower: Option<&[u8]>, upper: Option<&[u8]>, limit: usize| {
+            range(&db, lower, upper, true)
+                .take(limit)
+                .map(|entry| Ok(String::from_utf8(entry?.0.to_vec())?))
+                .collect::<anyhow::Result<Vec<String>>>()
+        };
+        assert_eq!(keys(None, None, 3)?, ["key-19", "key-18", "key-17"]);
+        // Bounds still apply: the upper bound is exclusive, the lower inclusive.
+        assert_eq!(
+            keys(Some(b"key-03"), Some(b"key-06"), 10)?,
+            ["key-05", "key-04", "key-03"]
+        );
+        let forward: Vec<_> = range(&db, None, None, false).collect::<Result<_, _>>()?;
+        let mut backward: Vec<_> = range(&db, None, None, true).collect::<Result<_, _>>()?;
+        backward.reverse();
+        assert_eq!(forward, backward);
+        Ok(())
+    }
+
+    // One pass of `scan --follow` over `db`, returning the keys it emits.
+    fn follow_once(follower: &mut Follower, db: &rocksdb::DB) -> anyhow::Result<Vec<String>> {
+        let lower = follower.lower_bound(None);
+        let keys = range(db, lower.as_deref(), None, false)
+            .map(|entry| Ok(String::from_utf8(entry?.0.to_vec())?))
+            .collect::<anyhow::Result<Vec<String>>>()?;
+        follower.emitted(keys.last().map(|key| key.clone().into_bytes()));
+        Ok(keys)
+    }
+
+    #[tokio::test]
+    async fn follower_emits_appended_keys() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let db = rocksdb::DB::open(&db_options(), dir.path())?;
+        for i in 0..3 {
+            db.put(format!("key-{:02}", i), b"")?;
+        }
+        let mut follower = Follower::new(Some("v1".to_owned()));
+        assert_eq!(
+            follow_once(&mut follower, &db)?,
+            ["key-00", "key-01", "key-02"]
+        );
+        // Nothing new, and the cursor stays put.
+        assert!(follow_once(&mut follower, &db)?.is_empty());
+
+        let mut heads = vec![Some("v2".to_owned()), Some("v1".to_owned())];
+        follower
+            .wait_for_change(Duration::ZERO, || {
+                let head = heads.pop().unwrap();
+                async move { Ok(head) }
+            })
+            .await?;
+        assert!(heads.is_empty());
+        assert_eq!(follower.etag(), Some("v2"));
+
+        db.put("key-03", b"")?;
+        db.put("key-04", b"")?;
+        assert_eq!(follow_once(&mut follower, &db)?, ["key-03", "key-04"]);
+        Ok(())
+    }
+
+    #[test]
+    fn follower_never_emits_keys_before_the_cursor() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let db = rocksdb::DB::open(&db_options(), dir.path())?;
+        db.put("b", b"")?;
+        db.put("d", b"")?;
+        let mut follower = Follower::new(None);
+        assert_eq!(follow_once(&mut follower, &db)?, ["b", "d"]);
+
+        // "a" and "c" sort before the cursor, so only the keys after "d" are emitted.
+        for key in ["a", "c", "d\0", "e"] {
+            db.put(key, b"")?;
+        }
+        assert_eq!(
+            follower.lower_bound(None).as_deref(),
+            Some(b"d\0".as_slice())
+        );
+        assert_eq!(follow_once(&mut follower, &db)?, ["d\0", "e"]);
+        Ok(())
+    }
+
+    #[test]
+    fn merged_entries_replace_older_ones() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let db_dir = dir.path().join("index-db");
+        {
+            let db = rocksdb::DB::open(&merge_db_options(), &db_dir)?;
+            db.merge("a", "old-a")?;
+            db.merge("b", "old-b")?;
+        }
+        // A later run appends to the same DB.
+        let db = rocksdb::DB::open(&merge_db_options(), &db_dir)?;
+        db.merge("b", "new-b")?;
+        db.merge("c", "new-c")?;
+
+        let sst = dir.path().join("index.sst");
+        let opts = db_options();
+        let mut writer = rocksdb::SstFileWriter::create(&opts);
+        writer.open(&sst)?;
+        for entry in range(&db, None, None, false) {
+            let (k, v) = entry?;
+            writer.put(k, v)?;
+        }
+        writer.finish()?;
+
+        // Readers only see the exported SST.
+        let reader = ingest(&sst, &db_options(), &dir.path().join("reader"))?;
+        assert_eq!(reader.get("a")?.as_deref(), Some(&b"old-a"[..]));
+        assert_eq!(reader.get("b")?.as_deref(), Some(&b"new-b"[..]));
+        assert_eq!(reader.get("c")?.as_deref(), Some(&b"new-c"[..]));
+        Ok(())
+    }
+
+    #[test]
+    fn sort_check() -> anyhow::Result<()> {
+        let entries = |keys: &[&str]| -> Vec<Entry> {
+            keys.iter()
+                .map(|k| Ok((k.as_bytes().into(), Box::default())))
+                .collect()
+        };
+        check_sorted(entries(&["a", "b", "c"]).into_iter())?;
+        let err = check_sorted(entries(&["a", "c", "b", "d"]).into_iter()).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            r#"keys are out of order: "c" is followed by "b""#
+        );
+        // Repeated keys aren't strictly increasing either.
+        assert!(check_sorted(entries(&["a", "a"]).into_iter()).is_err());
+
+        let dir = tempdir()?;
+        let sst = dir.path().join("index.sst");
+        let opts = db_options();
+        let mut writer = rocksdb::SstFileWriter::create(&opts);
+        writer.open(&sst)?;
+        for i in 0..10 {
+            writer.put(format!("key-{:02}", i), b"")?;
+        }
+        writer.finish()?;
+        check_sorted_sst(&sst, &dir.path().join("scratch"))?;
+        Ok(())
+    }
+
+    #[test]
+    fn refuses_to_clobber() -> anyhow::Result<()> {
+        let base = tempdir()?;
+        let output = base.path().join("db");
+        prepare_output(&output, false)?;
+
+        std::fs::create_dir(&output)?;
+        prepare_output(&output, false)?;
+
+        std::fs::write(output.join("CURRENT"), "MANIFEST-000001")?;
+        let err = prepare_output(&output, false).unwrap_err();
+        assert!(err.to_string().contains("--overwrite"), "{}", err);
+        assert!(output.join("CURRENT").exists());
+
+        prepare_output(&output, true)?;
+        assert!(!output.exists());
+        Ok(())
+    }
+
+    #[test]
+    fn partitions_cover_the_keyspace() -> anyhow::Result<()> {
+        let base = tempdir()?;
+        let sst = base.path().join("default.sst");
+        let db_opts = db_options();
+        let mut writer = rocksdb::SstFileWriter::create(&db_opts);
+        writer.open(&sst)?;
+        for i in 0..103 {
+            writer.put(format!("key-{:04}", i), b"")?;
+        }
+        writer.finish()?;
+        let dir = tempdir()?;
+        let db = ingest(&sst, &db_opts, dir.path())?;
+
+        let scan = |lower: Option<&[u8]>, upper: Option<&[u8]>| -> anyhow::Result<Vec<Vec<u8>>> {
+            let mut read_opts = rocksdb::ReadOptions::default();
+            if let Some(lower) = lower {
+                read_opts.set_iterate_lower_bound(lower);
+            }
+            if let Some(upper) = upper {
+                read_opts.set_iterate_upper_bound(upper);
+            }
+            db.iterator_opt(rocksdb::IteratorMode::Start, read_opts)
+                .map(|entry| Ok(entry?.0.to_vec()))
+                .collect()
+        };
+
+        for (n, lower, upper) in [
+            (4, None, None),
+            (7, Some(&b"key-0010"[..]), Some(&b"key-0090"[..])),
+            (200, None, None),
+            (1, None, None),
+        ] {
+            let points = split_points(&db, n, lower, upper)?;
+            assert!(points.len() < n.max(1));
+            let mut bounds: Vec<Option<&[u8]>> = vec![lower];
+            bounds.extend(points.iter().map(|p| Some(p.as_slice())));
+            bounds.push(upper);
+
+            let mut union = Vec::new();
+            for range in bounds.windows(2) {
+                let part = scan(range[0], range[1])?;
+                assert!(!part.is_empty());
+                union.extend(part);
+            }
+            assert_eq!(union, scan(lower, upper)?);
+        }
+        assert_eq!(split_points(&db, 4, None, None)?.len(), 3);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn emitted_locations_match_index() -> anyhow::Result<()> {
+        let base = tempdir()?;
+        let mut writer = S3BlockWriter::new(S3BlockWriterArgs {
+            client: Box::new(LocalFilesystem {
+                base: base.path().to_path_buf(),
+                sync_on_put: false,
+            }),
+            block_size: 32,
+            footer: false,
+            spanning: false,
+            max_blocks: None,
+        });
+        let index_dir = tempdir()?;
+        let index = rocksdb::DB::open(&db_options(), index_dir.path())?;
+        let mut emitted = Vec::new();
+        let mut keys: Vec<Vec<u8>> = (0..20)
+            .rev()
+            .map(|i| format!("key-{:02}", i).into())
+            .collect();
+        keys.push(b"tab\tand\xffbyte".to_vec());
+        for key in &keys {
+            let location = writer.append(b"some record").await?;
+            emitted.push(location_line(key, &location));
+            let value = IndexValue {
+                location,
+                ..IndexValue::default()
+            };
+            index.put(key, value.encode())?;
+        }
+        writer.flush().await?;
+        assert!(emitted.iter().all(|line| line.matches('\t').count() == 2));
+
+        let mut looked_up = Vec::new();
+        for entry in range(&index, None, None, false) {
+            let (k, v) = entry?;
+            looked_up.push(location_line(&k, &IndexValue::decode(&v)?.location));
+        }
+        emitted.sort();
+        looked_up.sort();
+        assert_eq!(emitted, looked_up);
+        Ok(())
+    }
+
+    #[test]
+    fn block_usage_groups_records_by_block() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let index = rocksdb::DB::open(&db_options(), dir.path())?;
+        // Four records to a block, ten bytes apart.
+        for i in 0..10 {
+            let value = IndexValue {
+                location: Location {
+                    block_id: i / 4,
+                    offset: (i % 4) * 10,
+                },
+                line_number: None,
+                expires_at: None,
+            };
+            index.put(format!("k{:02}", i), value.encode())?;
+        }
+        let usage = |block_id, records, min_offset, max_offset| BlockUsage {
+            block_id,
+            records,
+            min_offset,
+            max_offset,
+        };
+        assert_eq!(
+            block_usage(&index, Some(b"k02"), Some(b"k09"), false)?,
+            [usage(0, 2, 20, 30), usage(1, 4, 0, 30), usage(2, 1, 0, 0)]
+        );
+        assert_eq!(block_usage(&index, None, None, false)?.len(), 3);
+        assert_eq!(block_usage(&index, Some(b"x"), None, false)?, []);
+        Ok(())
+    }
+
+    #[test]
+    fn merged_ssts_resolve_conflicts_by_policy() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let db_opts = db_options();
+        let write_sst = |name: &str, entries: &[(&str, &str)]| -> anyhow::Result<PathBuf> {
+            let path = dir.path().join(name);
+            let mut writer = rocksdb::SstFileWriter::create(&db_opts);
+            writer.open(&path)?;
+            for (k, v) in entries {
+                writer.put(k, v)?;
+            }
+            writer.finish()?;
+            Ok(path)
+        };
+        let inputs = [
+            write_sst("a.sst", &[("k1", "a1"), ("k2", "a2")])?,
+            write_sst("b.sst", &[("k2", "b2"), ("k3", "b3")])?,
+        ];
+        let merged = |policy, name: &str| -> anyhow::Result<Vec<(String, String)>> {
+            let output = dir.path().join(name);
+            assert_eq!(merge_ssts(&inputs, &output, policy)?, 1);
+            let db = ingest(
+                &output,
+                &db_options(),
+                &dir.path().join(format!("{}.db", name)),
+            )?;
+            range(&db, None, None, false)
+                .map(|entry| {
+                    let (k, v) = entry?;
+                    Ok((
+                        String::from_utf8(k.to_vec())?,
+                        String::from_utf8(v.to_vec())?,
+                    ))
+                })
+                .collect()
+        };
+        let expected = |k2: &str| {
+            [("k1", "a1"), ("k2", k2), ("k3", "b3")]
+                .map(|(k, v)| (k.to_owned(), v.to_owned()))
+                .to_vec()
+        };
+        assert_eq!(
+            merged(ConflictPolicy::LastWins, "last.sst")?,
+            expected("b2")
+        );
+        assert_eq!(
+            merged(ConflictPolicy::FirstWins, "first.sst")?,
+            expected("a2")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn remapped_index_points_at_the_new_locations() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let index = rocksdb::DB::open(&db_options(), dir.path())?;
+        let at = |block_id, offset| Location { block_id, offset };
+        for i in 0..6 {
+            let value = IndexValue {
+                location: at(i / 3, (i % 3) * 10),
+                line_number: Some(i + 1),
+                expires_at: None,
+            };
+            index.put(format!("k{}", i), value.encode())?;
+        }
+        // Block 0 is compacted into block 7; block 1 stays where it is.
+        let mapping: HashMap<Location, Location> =
+            (0..3).map(|i| (at(0, i * 10), at(7, i * 5))).collect();
+
+        let out = tempdir()?;
+        let sst = out.path().join("remapped.sst");
+        assert_eq!(remap(&index, &mapping, &sst)?, 3);
+        let remapped = ingest(&sst, &db_options(), &out.path().join("db"))?;
+        let values = range(&remapped, None, None, false)
+            .map(|entry| {
+                let (k, v) = entry?;
+                Ok((k.to_vec(), IndexValue::decode(&v)?))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let expected: Vec<_> = [
+            at(7, 0),
+            at(7, 5),
+            at(7, 10),
+            at(1, 0),
+            at(1, 10),
+            at(1, 20),
+        ]
+        .into_iter()
+        .enumerate()
+        .map(|(i, location)| {
+            let value = IndexValue {
+                location,
+                line_number: Some(i + 1),
+                expires_at: None,
+            };
+            (format!("k{}", i).into_bytes(), value)
+        })
+        .collect();
+        assert_eq!(values, expected);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn materialized_copy_matches_scan() -> anyhow::Result<()> {
+        let base = tempdir()?;
+        let store = || LocalFilesystem {
+            base: base.path().to_path_buf(),
+            sync_on_put: false,
+        };
+        let mut writer = S3BlockWriter::new(S3BlockWriterArgs {
+            client: Box::new(store()),
+            block_size: 64,
+            footer: false,
+            spanning: false,
+            max_blocks: None,
+        });
+        let index_dir = tempdir()?;
+        let index = rocksdb::DB::open(&db_options(), index_dir.path())?;
+        for i in 0..30 {
+            let location = writer.append(format!("record-{}", i).as_bytes()).await?;
+            let value = IndexValue {
+                location,
+                ..IndexValue::default()
+            };
+            index.put(format!("key-{:02}", i), value.encode())?;
+        }
+        writer.flush().await?;
+
+        let mut reader = S3BlockReader::new(S3BlockReaderArgs {
+            client: Box::new(store()),
+            footer: false,
+            spanning: false,
+        });
+        let mut scanned = Vec::new();
+        for entry in index.iterator(rocksdb::IteratorMode::Start) {
+            let (k, v) = entry?;
+            let record = reader.fetch(&IndexValue::decode(&v)?.location).await?;
+            scanned.push((k.to_vec(), record));
+        }
+
+        let out_dir = tempdir()?;
+        {
+            let out = rocksdb::DB::open(&db_options(), out_dir.path())?;
+            let upper = b"key-10".as_slice();
+            assert_eq!(
+                materialize(&index, &mut reader, &out, None, Some(upper), false).await?,
+                10
+            );
+            // Resuming copies only what's missing.
+            assert_eq!(
+                materialize(&index, &mut reader, &out, None, None, false).await?,
+                20
+            );
+            assert_eq!(
+                materialize(&index, &mut reader, &out, None, None, false).await?,
+                0
+            );
+            out.flush()?;
+        }
+
+        let out = rocksdb::DB::open(&db_options(), out_dir.path())?;
+        let copied = out
+            .iterator(rocksdb::IteratorMode::Start)
+            .map(|entry| {
+                let (k, v) = entry?;
+                Ok((k.to_vec(), v.to_vec()))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        assert_eq!(copied, scanned);
+        Ok(())
+    }
+}
@@ -0,0 +1,222 @@
+use std::{fmt, str::FromStr};
+
+use anyhow::anyhow;
+use base64::Engine;
+
+/// How a key given on the command line is spelled, so that binary keys can be expressed.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub enum KeyEncoding {
+    #[default]
+    Utf8,
+    Hex,
+    Base64,
+    /// Comma-separated segments of a composite key, encoded with `encode_tuple`.
+    Tuple,
+}
+
+impl KeyEncoding {
+    pub fn decode(&self, input: &str) -> anyhow::Result<Vec<u8>> {
+        let key = match self {
+            KeyEncoding::Utf8 => input.as_bytes().to_vec(),
+            KeyEncoding::Hex => hex::decode(input)?,
+            KeyEncoding::Base64 => base64::engine::general_purpose::STANDARD.decode(input)?,
+            KeyEncoding::Tuple => {
+                let segments: Vec<&[u8]> = input.split(',').map(str::as_bytes).collect();
+                encode_tuple(&segments)
+            }
+        };
+        Ok(key)
+    }
+}
+
+// Segments of a composite key are escaped and terminated so that comparing the encoded bytes
+// gives the same order as comparing the segments one by one: 0x00 within a segment becomes
+// 0x00 0xFF, and each segment ends with 0x00 0x01, which sorts below any continuation.
+const ESCAPE: u8 = 0x00;
+const ESCAPED_NUL: u8 = 0xFF;
+const TERMINATOR: u8 = 0x01;
+
+/// Encodes a composite key so that the encoded keys sort in lexicographic tuple order.
+pub fn encode_tuple<S: AsRef<[u8]>>(segments: &[S]) -> Vec<u8> {
+    let mut buf = Vec::new();
+    for segment in segments {
+        for &b in segment.as_ref() {
+            buf.push(b);
+            if b == ESCAPE {
+                buf.push(ESCAPED_NUL);
+            }
+        }
+        buf.extend([ESCAPE, TERMINATOR]);
+    }
+    buf
+}
+
+/// The smallest key that sorts after every key starting with `prefix`, for use as an exclusive
+/// upper bound. There is none if the prefix is empty or all 0xFF.
+pub fn prefix_upper_bound(prefix: &[u8]) -> Option<Vec<u8>> {
+    let last = prefix.iter().rposition(|&b| b != 0xFF)?;
+    let mut upper = prefix[..=last].to_vec();
+    upper[last] += 1;
+    Some(upper)
+}
+
+/// The inverse of `encode_tuple`.
+pub fn decode_tuple(buf: &[u8]) -> anyhow::Result<Vec<Vec<u8>>> {
+    let mut segments = Vec::new();
+    let mut cur = Vec::new();
+    let mut bytes = buf.iter();
+    while let Some(&b) = bytes.next() {
+        if b != ESCAPE {
+            cur.push(b);
+            continue;
+        }
+        match bytes.next() {
+            Some(&ESCAPED_NUL) => cur.push(ESCAPE),
+            Some(&TERMINATOR) => segments.push(std::mem::take(&mut cur)),
+            other => return Err(anyhow!("malformed tuple key: bad escape {:?}", other)),
+        }
+    }
+    if !cur.is_empty() {
+        return Err(anyhow!("malformed tuple key: unterminated segment"));
+    }
+    Ok(segments)
+}
+
+impl fmt::Display for KeyEncoding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KeyEncoding::Utf8 => write!(f, "utf8"),
+            KeyEncoding::Hex => write!(f, "hex"),
+            KeyEncoding::Base64 => write!(f, "base64"),
+            KeyEncoding::Tuple => write!(f, "tuple"),
+        }
+    }
+}
+
+impl FromStr for KeyEncoding {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "utf8" => Ok(KeyEncoding::Utf8),
+            "hex" => Ok(KeyEncoding::Hex),
+            "base64" => Ok(KeyEncoding::Base64),
+            "tuple" => Ok(KeyEncoding::Tuple),
+            _ => Err(anyhow!("unrecognized key encoding: {}", s)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{collections::BTreeSet, ops::Bound};
+
+    use crate::key::{decode_tuple, encode_tuple, prefix_upper_bound, KeyEncoding};
+
+    #[test]
+    fn decodes_bounds() -> anyhow::Result<()> {
+        assert_eq!(KeyEncoding::Utf8.decode("abc")?, b"abc");
+        assert_eq!(KeyEncoding::Hex.decode("00ff10")?, [0x00, 0xff, 0x10]);
+        assert_eq!(KeyEncoding::Base64.decode("AP8Q")?, [0x00, 0xff, 0x10]);
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_malformed() {
+        assert!(KeyEncoding::Hex.decode("0").is_err());
+        assert!(KeyEncoding::Hex.decode("zz").is_err());
+        assert!(KeyEncoding::Base64.decode("***").is_err());
+    }
+
+    #[test]
+    fn parses() -> anyhow::Result<()> {
+        for encoding in [
+            KeyEncoding::Utf8,
+            KeyEncoding::Hex,
+            KeyEncoding::Base64,
+            KeyEncoding::Tuple,
+        ] {
+            assert_eq!(encoding.to_string().parse::<KeyEncoding>()?, encoding);
+        }
+        assert!("latin1".parse::<KeyEncoding>().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn tuples_sort_in_tuple_order() -> anyhow::Result<()> {
+        let tuples: Vec<Vec<&[u8]>> = vec![
+            vec![b"a"],
+            vec![b"a", b""],
+            vec![b"a", b"b"],
+            vec![b"a\0"],
+            vec![b"a\0", b"z"],
+            vec![b"a\0\0"],
+            vec![b"ab"],
+            vec![b"ab", b"a"],
+            vec![b"b"],
+            vec![b"us-east", b"123"],
+            vec![b"us-west", b"1"],
+            vec![b"us-west", b"12"],
+            vec![b"us-west\xff"],
+        ];
+        let mut sorted = tuples.clone();
+        sorted.sort();
+        assert_eq!(sorted, tuples);
+
+        let encoded: Vec<Vec<u8>> = tuples.iter().map(|t| encode_tuple(t)).collect();
+        let mut sorted = encoded.clone();
+        sorted.sort();
+        assert_eq!(sorted, encoded);
+
+        for (tuple, key) in tuples.iter().zip(&encoded) {
+            assert_eq!(&decode_tuple(key)?, tuple);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn tuple_bounds() -> anyhow::Result<()> {
+        assert_eq!(
+            KeyEncoding::Tuple.decode("us-west,123")?,
+            encode_tuple(&["us-west", "123"])
+        );
+        assert!(decode_tuple(b"abc").is_err());
+        assert!(decode_tuple(b"a\0\x02").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn prefix_bounds() {
+        let keys: BTreeSet<&[u8]> = [
+            &b"2023-12-31"[..],
+            b"2024-",
+            b"2024-01-01",
+            b"2024-12-31\xff",
+            b"2024.",
+            b"2025-01-01",
+            b"\xff\xff",
+            b"\xff\xff\x00",
+        ]
+        .into_iter()
+        .collect();
+        for prefix in [&b"2024-"[..], b"2024", b"\xff", b"\xff\xff", b""] {
+            let matching: Vec<&[u8]> = keys
+                .iter()
+                .filter(|key| key.starts_with(prefix))
+                .copied()
+                .collect();
+            let upper = prefix_upper_bound(prefix);
+            let upper: Bound<&[u8]> = match &upper {
+                Some(upper) => Bound::Excluded(upper),
+                None => Bound::Unbounded,
+            };
+            let scanned: Vec<&[u8]> = keys
+                .range::<[u8], _>((Bound::Included(prefix), upper))
+                .copied()
+                .collect();
+            assert_eq!(scanned, matching, "prefix {:?}", prefix);
+        }
+        assert_eq!(prefix_upper_bound(b"ab\xff"), Some(b"ac".to_vec()));
+        assert_eq!(prefix_upper_bound(b"\xff\xff"), None);
+    }
+}
@@ -1,2 +1,116 @@
 pub mod blob;
 pub mod block;
+pub mod bloom;
+pub mod dedupe;
+pub mod doctor;
+pub mod index;
+pub mod key;
+pub mod limit;
+pub mod lines;
+pub mod lint;
+pub mod manifest;
+pub mod output;
+pub mod partition;
+pub mod path;
+pub mod record;
+pub mod sort;
+pub mod sparse;
+pub mod stats;
+pub mod units;
+pub mod watch;
+
+use aws_config::{meta::region::RegionProviderChain, BehaviorVersion, SdkConfig};
+use aws_sdk_s3::config::Region;
+
+/// Loads the AWS configuration the binaries share: credentials from the usual places, and
+/// `region`. With `endpoint_url`, requests go there instead of to AWS, e.g. to a local MinIO or
+/// LocalStack.
+pub async fn load_aws_config(region: &str, endpoint_url: Option<&str>) -> SdkConfig {
+    let region_provider = RegionProviderChain::first_try(Region::new(region.to_owned()));
+    let mut loader = aws_config::defaults(BehaviorVersion::v2024_03_28()).region(region_provider);
+    if let Some(endpoint_url) = endpoint_url {
+        loader = loader.endpoint_url(endpoint_url);
+    }
+    loader.load().await
+}
+
+/// An S3 client for `config`. With `force_path_style`, buckets are addressed by path
+/// (`endpoint/bucket/key`) rather than by subdomain, as MinIO and many proxies require. If it's
+/// `None`, buckets behind a custom endpoint are, since such servers rarely have a DNS name per
+/// bucket, and buckets on AWS aren't.
+pub fn s3_client_for(config: &SdkConfig, force_path_style: Option<bool>) -> aws_sdk_s3::Client {
+    let path_style = force_path_style.unwrap_or(config.endpoint_url().is_some());
+    let s3_config = aws_sdk_s3::config::Builder::from(config)
+        .force_path_style(path_style)
+        .build();
+    aws_sdk_s3::Client::from_conf(s3_config)
+}
+
+/// `s3_client_for(&load_aws_config(region, endpoint_url), force_path_style)`.
+pub async fn s3_client(
+    region: &str,
+    endpoint_url: Option<&str>,
+    force_path_style: Option<bool>,
+) -> aws_sdk_s3::Client {
+    s3_client_for(
+        &load_aws_config(region, endpoint_url).await,
+        force_path_style,
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use aws_config::{BehaviorVersion, SdkConfig};
+    use aws_sdk_s3::config::{Credentials, Region, SharedCredentialsProvider};
+    use aws_smithy_runtime::client::http::test_util::capture_request;
+
+    // The URI `s3_client_for(config, force_path_style)` sends a GetObject of `bucket/key` to.
+    async fn get_object_uri(endpoint_url: Option<&str>, force_path_style: Option<bool>) -> String {
+        let (http_client, requests) = capture_request(None);
+        let mut config = SdkConfig::builder()
+            .behavior_version(BehaviorVersion::latest())
+            .region(Region::new("us-west-2"))
+            .credentials_provider(SharedCredentialsProvider::new(Credentials::new(
+                "akid", "secret", None, None, "test",
+            )))
+            .http_client(http_client);
+        if let Some(endpoint_url) = endpoint_url {
+            config = config.endpoint_url(endpoint_url);
+        }
+        let client = crate::s3_client_for(&config.build(), force_path_style);
+        let _ = client.get_object().bucket("bucket").key("key").send().await;
+        requests.expect_request().uri().to_owned()
+    }
+
+    #[tokio::test]
+    async fn custom_endpoints_are_path_style() {
+        let uri = get_object_uri(Some("http://localhost:9000"), None).await;
+        assert!(
+            uri.starts_with("http://localhost:9000/bucket/key?"),
+            "{}",
+            uri
+        );
+        let uri = get_object_uri(Some("http://localhost:9000"), Some(false)).await;
+        assert!(
+            uri.starts_with("http://bucket.localhost:9000/key?"),
+            "{}",
+            uri
+        );
+    }
+
+    #[tokio::test]
+    async fn path_style_can_be_forced() {
+        let uri = get_object_uri(None, None).await;
+        assert!(
+            uri.starts_with("https://bucket.s3.us-west-2.amazonaws.com/key?"),
+            "{}",
+            uri
+        );
+        let uri = get_object_uri(None, Some(true)).await;
+        assert!(
+            uri.starts_with("https://s3.us-west-2.amazonaws.com/bucket/key?"),
+            "{}",
+            uri
+        );
+    }
+}
@@ -0,0 +1,112 @@
+use std::{
+    fmt,
+    str::FromStr,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use anyhow::anyhow;
+
+/// What a `ByteLimit` counts.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub enum LimitUnit {
+    /// Bytes of output, as printed.
+    #[default]
+    Output,
+    /// Bytes of the raw records, regardless of how they're printed.
+    Records,
+}
+
+impl fmt::Display for LimitUnit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LimitUnit::Output => write!(f, "output"),
+            LimitUnit::Records => write!(f, "records"),
+        }
+    }
+}
+
+impl FromStr for LimitUnit {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "output" => Ok(LimitUnit::Output),
+            "records" => Ok(LimitUnit::Records),
+            _ => Err(anyhow!("unrecognized limit unit: {}", s)),
+        }
+    }
+}
+
+/// A byte budget shared by everything emitting records for one scan, possibly concurrently.
+/// The record that crosses the limit still counts as emitted, so a scan stopped by the limit has
+/// emitted at least `limit` bytes, and stops at the first record that got it there.
+#[derive(Debug)]
+pub struct ByteLimit {
+    limit: u64,
+    used: AtomicU64,
+}
+
+impl ByteLimit {
+    pub fn new(limit: u64) -> Self {
+        Self {
+            limit,
+            used: AtomicU64::new(0),
+        }
+    }
+
+    /// Whether the limit has been reached, i.e. no more records should be emitted.
+    pub fn reached(&self) -> bool {
+        self.used() >= self.limit
+    }
+
+    pub fn add(&self, bytes: u64) {
+        self.used.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    pub fn used(&self) -> u64 {
+        self.used.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::limit::{ByteLimit, LimitUnit};
+
+    #[test]
+    fn stops_at_the_first_record_crossing_the_limit() {
+        let records = [40, 40, 40, 40, 40];
+        let limit = ByteLimit::new(100);
+        let mut emitted = Vec::new();
+        for record in records {
+            if limit.reached() {
+                break;
+            }
+            emitted.push(record);
+            limit.add(record);
+        }
+        assert_eq!(emitted, vec![40, 40, 40]);
+        assert_eq!(limit.used(), 120);
+        assert!(limit.used() >= 100);
+        assert!(limit.used() - emitted.last().unwrap() < 100);
+    }
+
+    #[test]
+    fn exact_limit() {
+        let limit = ByteLimit::new(80);
+        assert!(!limit.reached());
+        limit.add(40);
+        assert!(!limit.reached());
+        limit.add(40);
+        assert!(limit.reached());
+        assert!(ByteLimit::new(0).reached());
+    }
+
+    #[test]
+    fn parses() -> anyhow::Result<()> {
+        for unit in [LimitUnit::Output, LimitUnit::Records] {
+            assert_eq!(unit.to_string().parse::<LimitUnit>()?, unit);
+        }
+        assert!("lines".parse::<LimitUnit>().is_err());
+        Ok(())
+    }
+}
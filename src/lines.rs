@@ -0,0 +1,161 @@
+use std::io::{self, BufRead};
+
+use tracing::debug;
+
+/// The default for `--max-line-bytes`: far more than any sane record, but little enough to hold
+/// in memory.
+pub const DEFAULT_MAX_LINE_BYTES: usize = 64 << 20;
+
+/// Reads numbered lines like `BufRead::lines`, but never buffers more than `max_bytes` of any
+/// one line, so a corrupt input with no newlines can't exhaust memory. A longer line is read
+/// past without keeping it, and is either skipped (and counted) or an error.
+pub struct BoundedLines<R> {
+    reader: R,
+    max_bytes: usize,
+    skip_long_lines: bool,
+    line_number: usize,
+    skipped: usize,
+}
+
+impl<R: BufRead> BoundedLines<R> {
+    pub fn new(reader: R, max_bytes: usize, skip_long_lines: bool) -> Self {
+        Self {
+            reader,
+            max_bytes,
+            skip_long_lines,
+            line_number: 0,
+            skipped: 0,
+        }
+    }
+
+    /// How many lines have been read so far, including skipped ones.
+    pub fn line_number(&self) -> usize {
+        self.line_number
+    }
+
+    /// How many lines were skipped for being too long.
+    pub fn skipped(&self) -> usize {
+        self.skipped
+    }
+
+    // Reads the rest of a line into `line`, keeping at most `max_bytes` of it. Returns the
+    // line's full length, or `None` at the end of the input.
+    fn read_line(&mut self, line: &mut Vec<u8>) -> io::Result<Option<usize>> {
+        let mut len = 0;
+        let mut terminated = false;
+        while !terminated {
+            let available = self.reader.fill_buf()?;
+            if available.is_empty() {
+                break;
+            }
+            let (chunk, used) = match available.iter().position(|&b| b == b'\n') {
+                Some(i) => {
+                    terminated = true;
+                    (&available[..i], i + 1)
+                }
+                None => (available, available.len()),
+            };
+            let room = (self.max_bytes + 1).saturating_sub(line.len());
+            line.extend_from_slice(&chunk[..chunk.len().min(room)]);
+            len += chunk.len();
+            self.reader.consume(used);
+        }
+        if !terminated && len == 0 {
+            return Ok(None);
+        }
+        // Only a line that was kept whole can be known to end in `\r\n`.
+        if line.len() == len && line.last() == Some(&b'\r') {
+            line.pop();
+            len -= 1;
+        }
+        Ok(Some(len))
+    }
+}
+
+impl<R: BufRead> Iterator for BoundedLines<R> {
+    /// The (1-based) line number and the line, without its line ending.
+    type Item = anyhow::Result<(usize, String)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let mut line = Vec::new();
+            let len = match self.read_line(&mut line) {
+                Ok(Some(len)) => len,
+                Ok(None) => return None,
+                Err(err) => return Some(Err(err.into())),
+            };
+            self.line_number += 1;
+            if len <= self.max_bytes {
+                return Some(match String::from_utf8(line) {
+                    Ok(line) => Ok((self.line_number, line)),
+                    Err(err) => Err(err.into()),
+                });
+            }
+            if !self.skip_long_lines {
+                return Some(Err(anyhow::anyhow!(
+                    "line {} is {} bytes, more than the limit of {}",
+                    self.line_number,
+                    len,
+                    self.max_bytes
+                )));
+            }
+            debug!("skipping line {} ({} bytes)", self.line_number, len);
+            self.skipped += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{BufReader, Cursor, Read};
+
+    use crate::lines::BoundedLines;
+
+    #[test]
+    fn matches_buf_read_lines() -> anyhow::Result<()> {
+        let input = "first\r\nsecond\n\nlast";
+        let lines =
+            BoundedLines::new(Cursor::new(input), 16, false).collect::<anyhow::Result<Vec<_>>>()?;
+        let expected: Vec<(usize, String)> = input
+            .lines()
+            .enumerate()
+            .map(|(i, line)| (i + 1, line.to_owned()))
+            .collect();
+        assert_eq!(lines, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn skips_enormous_lines() -> anyhow::Result<()> {
+        // 64 MiB with no newline, generated as it's read rather than held in memory.
+        let enormous = std::io::repeat(b'x').take(64 << 20);
+        let input = Cursor::new("before\n")
+            .chain(enormous)
+            .chain(Cursor::new("\nafter\n"));
+        let mut lines = BoundedLines::new(BufReader::new(input), 1024, true);
+        let kept = (&mut lines).collect::<anyhow::Result<Vec<_>>>()?;
+        assert_eq!(kept, [(1, "before".to_owned()), (3, "after".to_owned())]);
+        assert_eq!(lines.skipped(), 1);
+
+        // Without skipping, the first long line is an error.
+        let input = Cursor::new("ok\n").chain(std::io::repeat(b'x').take(2048));
+        let mut lines = BoundedLines::new(BufReader::new(input), 1024, false);
+        assert_eq!(lines.next().unwrap()?, (1, "ok".to_owned()));
+        let err = lines.next().unwrap().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "line 2 is 2048 bytes, more than the limit of 1024"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn lines_at_the_limit_are_kept() -> anyhow::Result<()> {
+        let line = "y".repeat(1024);
+        let input = format!("{}\r\n{}x\n", line, line);
+        let lines: Vec<_> = BoundedLines::new(Cursor::new(input), 1024, true).collect();
+        assert_eq!(lines.len(), 1);
+        assert_eq!(lines[0].as_ref().unwrap().1, line);
+        Ok(())
+    }
+}
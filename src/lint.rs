@@ -0,0 +1,251 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    fmt,
+};
+
+use crate::record;
+
+/// How many offending line numbers to keep for each kind of problem.
+const SAMPLE_SIZE: usize = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Problem {
+    BadJson,
+    InvalidRecord,
+    MissingKey,
+    DuplicateKey,
+    Oversized,
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Problem::BadJson => write!(f, "bad JSON"),
+            Problem::InvalidRecord => write!(f, "invalid record"),
+            Problem::MissingKey => write!(f, "missing key"),
+            Problem::DuplicateKey => write!(f, "duplicate key"),
+            Problem::Oversized => write!(f, "oversized record"),
+        }
+    }
+}
+
+pub struct LintArgs {
+    pub key_fields: Vec<String>,
+    /// Fields `etl --drop-field` would remove before keys are extracted.
+    pub drop_fields: Vec<String>,
+    /// Also require every record to be an object containing `required_fields`, as with
+    /// `etl --strict-json`.
+    pub strict_json: bool,
+    pub required_fields: Vec<String>,
+    /// Records longer than this (e.g. the block size) are reported as oversized.
+    pub max_record_size: usize,
+}
+
+/// Checks input lines the same way `etl` would ingest them, collecting every problem instead of
+/// stopping at the first.
+pub struct Linter {
+    args: LintArgs,
+    seen: HashSet<Vec<u8>>,
+    report: LintReport,
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct ProblemSummary {
+    pub count: usize,
+    /// The (1-based) line numbers of the first few occurrences.
+    pub sample: Vec<usize>,
+}
+
+#[derive(Debug, Default)]
+pub struct LintReport {
+    pub lines: usize,
+    pub problems: BTreeMap<Problem, ProblemSummary>,
+}
+
+impl Linter {
+    pub fn new(args: LintArgs) -> Self {
+        Self {
+            args,
+            seen: HashSet::new(),
+            report: LintReport::default(),
+        }
+    }
+
+    pub fn check(&mut self, line: &str) {
+        self.report.lines += 1;
+        let line_number = self.report.lines;
+        if line.len() > self.args.max_record_size {
+            self.report.add(Problem::Oversized, line_number);
+        }
+        let Ok(mut parsed) = serde_json::from_str::<serde_json::Value>(line) else {
+            self.report.add(Problem::BadJson, line_number);
+            return;
+        };
+        record::drop_fields(&mut parsed, &self.args.drop_fields);
+        if self.args.strict_json && record::validate(&parsed, &self.args.required_fields).is_err() {
+            self.report.add(Problem::InvalidRecord, line_number);
+        }
+        match record::key(&parsed, &self.args.key_fields) {
+            Ok(key) => {
+                if !self.seen.insert(key) {
+                    self.report.add(Problem::DuplicateKey, line_number);
+                }
+            }
+            Err(_) => self.report.add(Problem::MissingKey, line_number),
+        }
+    }
+
+    /// Counts the lines up to `line_number` that haven't been checked as oversized: they were
+    /// too long to read at all (see `lines::BoundedLines`).
+    pub fn oversized_through(&mut self, line_number: usize) {
+        while self.report.lines < line_number {
+            self.report.lines += 1;
+            self.report.add(Problem::Oversized, self.report.lines);
+        }
+    }
+
+    pub fn finish(self) -> LintReport {
+        self.report
+    }
+}
+
+impl LintReport {
+    fn add(&mut self, problem: Problem, line_number: usize) {
+        let summary = self.problems.entry(problem).or_default();
+        summary.count += 1;
+        if summary.sample.len() < SAMPLE_SIZE {
+            summary.sample.push(line_number);
+        }
+    }
+
+    pub fn count(&self, problem: Problem) -> usize {
+        self.problems
+            .get(&problem)
+            .map_or(0, |summary| summary.count)
+    }
+
+    pub fn num_problems(&self) -> usize {
+        self.problems.values().map(|summary| summary.count).sum()
+    }
+
+    pub fn is_clean(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+impl fmt::Display for LintReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "checked {} lines", self.lines)?;
+        for (problem, summary) in &self.problems {
+            let sample: Vec<String> = summary.sample.iter().map(usize::to_string).collect();
+            writeln!(
+                f,
+                "{}: {} (lines {}{})",
+                problem,
+                summary.count,
+                sample.join(", "),
+                if summary.count > summary.sample.len() {
+                    ", ..."
+                } else {
+                    ""
+                }
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::lint::{LintArgs, Linter, Problem};
+
+    #[test]
+    fn reports_dirty_input() {
+        let mut linter = Linter::new(LintArgs {
+            key_fields: vec!["properties.BLKLOT".to_owned()],
+            drop_fields: vec![],
+            strict_json: true,
+            required_fields: vec!["geometry".to_owned()],
+            max_record_size: 80,
+        });
+        let padding = "x".repeat(100);
+        let lines = [
+            r#"{"properties": {"BLKLOT": "1"}, "geometry": null}"#.to_owned(),
+            r#"{"properties": {"BLKLOT": "2"}, "geometry": null}"#.to_owned(),
+            "not json".to_owned(),
+            r#"{"properties": {"BLKLOT": "1"}, "geometry": null}"#.to_owned(),
+            r#"{"properties": {}, "geometry": null}"#.to_owned(),
+            r#"{"properties": {"BLKLOT": "3"}}"#.to_owned(),
+            format!(
+                r#"{{"properties": {{"BLKLOT": "4"}}, "geometry": "{}"}}"#,
+                padding
+            ),
+            "{".to_owned(),
+            r#"{"properties": {"BLKLOT": "2"}, "geometry": null}"#.to_owned(),
+        ];
+        for line in &lines {
+            linter.check(line);
+        }
+        let report = linter.finish();
+
+        assert_eq!(report.lines, 9);
+        assert_eq!(report.count(Problem::BadJson), 2);
+        assert_eq!(report.count(Problem::DuplicateKey), 2);
+        assert_eq!(report.count(Problem::MissingKey), 1);
+        assert_eq!(report.count(Problem::InvalidRecord), 1);
+        assert_eq!(report.count(Problem::Oversized), 1);
+        assert_eq!(report.num_problems(), 7);
+        assert_eq!(report.problems[&Problem::BadJson].sample, vec![3, 8]);
+        assert_eq!(report.problems[&Problem::DuplicateKey].sample, vec![4, 9]);
+        assert!(!report.is_clean());
+    }
+
+    #[test]
+    fn unreadable_lines_are_oversized() {
+        let mut linter = Linter::new(LintArgs {
+            key_fields: vec!["id".to_owned()],
+            drop_fields: vec![],
+            strict_json: false,
+            required_fields: vec![],
+            max_record_size: 1024,
+        });
+        linter.check(r#"{"id": 1}"#);
+        linter.oversized_through(3);
+        linter.check(r#"{"id": 2}"#);
+        linter.oversized_through(4);
+        let report = linter.finish();
+
+        assert_eq!(report.lines, 4);
+        assert_eq!(report.problems[&Problem::Oversized].sample, vec![2, 3]);
+    }
+
+    #[test]
+    fn clean_input() {
+        let mut linter = Linter::new(LintArgs {
+            key_fields: vec!["id".to_owned()],
+            drop_fields: vec![],
+            strict_json: false,
+            required_fields: vec![],
+            max_record_size: 1_000,
+        });
+        linter.check(r#"{"id": 1}"#);
+        linter.check(r#"{"id": 2}"#);
+        let report = linter.finish();
+        assert!(report.is_clean());
+        assert_eq!(report.to_string(), "checked 2 lines\n");
+    }
+
+    #[test]
+    fn keys_come_from_transformed_records() {
+        let mut linter = Linter::new(LintArgs {
+            key_fields: vec!["id".to_owned()],
+            drop_fields: vec!["id".to_owned()],
+            strict_json: false,
+            required_fields: vec![],
+            max_record_size: 1_000,
+        });
+        linter.check(r#"{"id": 1}"#);
+        linter.check(r#"{"id": 2}"#);
+        assert_eq!(linter.finish().count(Problem::MissingKey), 2);
+    }
+}
@@ -0,0 +1,361 @@
+use std::collections::HashMap;
+
+use anyhow::{anyhow, bail};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    blob::{Blobstore, CompressionDict, STORED_LEN_METADATA},
+    block::block_name,
+    partition::Partitioner,
+};
+
+// The object metadata a block's entry is stored under, so that `BlockEntry::verify_head` can
+// check the block without downloading it.
+const LEN_METADATA: &str = "s3kv-len";
+const SHA256_METADATA: &str = "s3kv-sha256";
+
+/// Describes a dataset as written by `etl`. Stored as JSON at `<prefix>/manifest.json`.
+#[derive(PartialEq, Eq, Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Manifest {
+    pub block_size: usize,
+    pub blocks: Vec<BlockEntry>,
+    /// The id of the zstd dictionary blocks were compressed with, if any. A copy is stored in
+    /// the dataset at `CompressionDict::key(id)`.
+    #[serde(default)]
+    pub compression_dict: Option<String>,
+    /// Whether each block ends with a footer of record offsets (see `S3BlockWriterArgs::footer`).
+    #[serde(default)]
+    pub block_footer: bool,
+    /// Whether records may continue across blocks (see `S3BlockWriterArgs::spanning`).
+    #[serde(default)]
+    pub spanning_records: bool,
+    /// Whether the index SSTs (`index/<version>.sst`) are stored zstd-compressed, as written by
+    /// `etl --compress-index`. Sparse index parts are always stored raw.
+    #[serde(default)]
+    pub compressed_index: bool,
+    /// Whether some blocks may be stored raw behind the marker `Compressed::with_min_size`
+    /// writes, as `etl --min-compress-size` does. Readers that sniff for compression trust the
+    /// marker only if so.
+    #[serde(default)]
+    pub raw_blocks: bool,
+    /// How the index was also published in shards (`etl --partitioner`), for point lookups to
+    /// download only the shard a key belongs to (see `Partitioner::get`). Like
+    /// `compressed_index`, this describes the index most recently published.
+    #[serde(default)]
+    pub partitioner: Option<Partitioner>,
+}
+
+impl Manifest {
+    pub const KEY: &'static str = "manifest.json";
+
+    pub fn encode(&self) -> anyhow::Result<Vec<u8>> {
+        Ok(serde_json::to_vec_pretty(self)?)
+    }
+    pub fn decode(buf: &[u8]) -> anyhow::Result<Self> {
+        Ok(serde_json::from_slice(buf)?)
+    }
+
+    /// Reads the manifest from a dataset, if it has one. Older datasets may not.
+    pub async fn load(blob: &mut impl Blobstore) -> anyhow::Result<Option<Self>> {
+        match blob.get(Self::KEY).await? {
+            Some(buf) => Ok(Some(Self::decode(&buf)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// The expected SHA-256 of each block, keyed by block name, for
+    /// `Blobstore::with_checksum_retries`.
+    pub fn block_checksums(&self) -> HashMap<String, String> {
+        self.blocks
+            .iter()
+            .map(|entry| (block_name(entry.block_id).into(), entry.sha256.clone()))
+            .collect()
+    }
+
+    /// Checks that blocks written with these settings can be appended to the dataset, which
+    /// they can only if all of its blocks then agree on them. Errors name each setting that
+    /// differs.
+    pub fn check_appendable(
+        &self,
+        block_size: usize,
+        block_footer: bool,
+        spanning_records: bool,
+        compression_dict: Option<&str>,
+    ) -> anyhow::Result<()> {
+        let mut mismatches = Vec::new();
+        if block_size != self.block_size {
+            mismatches.push(format!(
+                "--block-size {} (the dataset's is {})",
+                block_size, self.block_size
+            ));
+        }
+        if block_footer != self.block_footer {
+            mismatches.push(format!(
+                "block footers {} (the dataset's are {})",
+                on_off(block_footer),
+                on_off(self.block_footer)
+            ));
+        }
+        if spanning_records != self.spanning_records {
+            mismatches.push(format!(
+                "spanning records {} (the dataset's are {})",
+                on_off(spanning_records),
+                on_off(self.spanning_records)
+            ));
+        }
+        if compression_dict != self.compression_dict.as_deref() {
+            mismatches.push(format!(
+                "compression dictionary {:?} (the dataset's is {:?})",
+                compression_dict, self.compression_dict
+            ));
+        }
+        if !mismatches.is_empty() {
+            bail!(
+                "its blocks were written differently; appending would mix {}",
+                mismatches.join(", ")
+            );
+        }
+        Ok(())
+    }
+
+    /// Loads the compression dictionary the manifest refers to, if any.
+    pub async fn compression_dict(
+        &self,
+        blob: &mut impl Blobstore,
+    ) -> anyhow::Result<Option<CompressionDict>> {
+        match &self.compression_dict {
+            Some(id) => Ok(Some(CompressionDict::load(blob, id).await?)),
+            None => Ok(None),
+        }
+    }
+}
+
+/// The length and SHA-256 of a block's (uncompressed) contents, captured as it was flushed.
+#[derive(PartialEq, Eq, Debug, Clone, Serialize, Deserialize)]
+pub struct BlockEntry {
+    pub block_id: usize,
+    pub len: usize,
+    pub sha256: String,
+}
+
+impl BlockEntry {
+    pub fn new(block_id: usize, contents: &[u8]) -> Self {
+        Self {
+            block_id,
+            len: contents.len(),
+            sha256: sha256_hex(contents),
+        }
+    }
+
+    /// Checks that `contents` are exactly what was recorded for this block.
+    pub fn verify(&self, contents: &[u8]) -> anyhow::Result<()> {
+        if contents.len() != self.len {
+            bail!(
+                "block {} has length {}, expected {}",
+                self.block_id,
+                contents.len(),
+                self.len
+            );
+        }
+        let actual = sha256_hex(contents);
+        if actual != self.sha256 {
+            bail!(
+                "block {} has sha256 {}, expected {}",
+                self.block_id,
+                actual,
+                self.sha256
+            );
+        }
+        Ok(())
+    }
+
+    /// This entry as object metadata, which `S3BlockWriter` stores with the block.
+    pub fn metadata(&self) -> HashMap<String, String> {
+        HashMap::from([
+            (LEN_METADATA.to_owned(), self.len.to_string()),
+            (SHA256_METADATA.to_owned(), self.sha256.clone()),
+        ])
+    }
+
+    /// Checks, from a `HEAD` of the stored block, that it's the block recorded here: that the
+    /// metadata it was stored with matches this entry and that it's still the length it was stored
+    /// at. Blocks stored without metadata (by `Blobstore::put_owned`) always fail.
+    pub fn verify_head(
+        &self,
+        content_length: u64,
+        metadata: &HashMap<String, String>,
+    ) -> anyhow::Result<()> {
+        let field = |key: &str| {
+            metadata.get(key).ok_or_else(|| {
+                anyhow!(
+                    "block {} has no {} metadata, so can only be checked by downloading it",
+                    self.block_id,
+                    key
+                )
+            })
+        };
+        let stored_len: u64 = field(STORED_LEN_METADATA)?.parse()?;
+        if content_length != stored_len {
+            bail!(
+                "block {} is {} bytes, but was stored as {}",
+                self.block_id,
+                content_length,
+                stored_len
+            );
+        }
+        let len: usize = field(LEN_METADATA)?.parse()?;
+        if len != self.len {
+            bail!(
+                "block {} was written with length {}, expected {}",
+                self.block_id,
+                len,
+                self.len
+            );
+        }
+        let sha256 = field(SHA256_METADATA)?;
+        if *sha256 != self.sha256 {
+            bail!(
+                "block {} was written with sha256 {}, expected {}",
+                self.block_id,
+                sha256,
+                self.sha256
+            );
+        }
+        Ok(())
+    }
+}
+
+fn on_off(on: bool) -> &'static str {
+    if on {
+        "on"
+    } else {
+        "off"
+    }
+}
+
+fn sha256_hex(contents: &[u8]) -> String {
+    hex::encode(ring::digest::digest(&ring::digest::SHA256, contents))
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use crate::{
+        blob::{Blobstore, LocalFilesystem, STORED_LEN_METADATA},
+        block::{BlockWriter, S3BlockWriter, S3BlockWriterArgs},
+        manifest::{BlockEntry, Manifest},
+        partition::Partitioner,
+    };
+    use tempfile::tempdir;
+
+    #[test]
+    fn round_trip() -> anyhow::Result<()> {
+        let manifest = Manifest {
+            block_size: 100,
+            blocks: vec![BlockEntry::new(0, b"hello"), BlockEntry::new(1, b"world")],
+            compression_dict: Some("abc123".to_owned()),
+            block_footer: true,
+            spanning_records: true,
+            compressed_index: true,
+            raw_blocks: true,
+            partitioner: Some(Partitioner::PrefixLen(2)),
+        };
+        assert_eq!(Manifest::decode(&manifest.encode()?)?, manifest);
+        Ok(())
+    }
+
+    #[test]
+    fn checks_stored_metadata() -> anyhow::Result<()> {
+        let entry = BlockEntry::new(3, b"hello");
+        let mut metadata = entry.metadata();
+        metadata.insert(STORED_LEN_METADATA.to_owned(), "12".to_owned());
+        entry.verify_head(12, &metadata)?;
+        // Truncated, or overwritten by a different block.
+        assert!(entry.verify_head(11, &metadata).is_err());
+        assert!(BlockEntry::new(3, b"world")
+            .verify_head(12, &metadata)
+            .is_err());
+        // Stored without any metadata.
+        assert!(entry.verify_head(12, &HashMap::new()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn appends_must_match_the_block_layout() -> anyhow::Result<()> {
+        let existing = Manifest {
+            block_size: 100,
+            compression_dict: Some("abc123".to_owned()),
+            ..Manifest::default()
+        };
+        existing.check_appendable(100, false, false, Some("abc123"))?;
+
+        let err = existing
+            .check_appendable(200, false, false, Some("abc123"))
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "its blocks were written differently; appending would mix --block-size 200 (the \
+             dataset's is 100)"
+        );
+        for (footer, spanning, dict) in [
+            (true, false, Some("abc123")),
+            (false, true, Some("abc123")),
+            (false, false, None),
+            (false, false, Some("def456")),
+        ] {
+            assert!(existing
+                .check_appendable(100, footer, spanning, dict)
+                .is_err());
+        }
+        // Every mismatch is named.
+        let err = existing
+            .check_appendable(200, true, false, None)
+            .unwrap_err();
+        assert!(err.to_string().contains("--block-size 200"), "{}", err);
+        assert!(err.to_string().contains("block footers on"), "{}", err);
+        assert!(
+            err.to_string().contains("compression dictionary None"),
+            "{}",
+            err
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn detects_tampered_block() -> anyhow::Result<()> {
+        let base = tempdir()?.into_path();
+        let mut writer = S3BlockWriter::new(S3BlockWriterArgs {
+            client: Box::new(LocalFilesystem {
+                base: base.as_path().to_path_buf(),
+                sync_on_put: false,
+            }),
+            block_size: 16,
+            footer: false,
+            spanning: false,
+            max_blocks: None,
+        });
+        writer.append(b"first record").await?;
+        writer.append(b"second record").await?;
+        writer.flush().await?;
+        let blocks = writer.blocks().to_vec();
+        assert_eq!(blocks.len(), 2);
+
+        let mut fs = LocalFilesystem {
+            base: base.as_path().to_path_buf(),
+            sync_on_put: false,
+        };
+        for entry in &blocks {
+            let name = crate::block::block_name(entry.block_id);
+            entry.verify(&fs.must_get(&name).await?)?;
+        }
+
+        let name = crate::block::block_name(1);
+        let mut tampered = fs.must_get(&name).await?.into_owned();
+        tampered[3] ^= 0xff;
+        fs.put(&name, &tampered).await?;
+        let err = blocks[1].verify(&fs.must_get(&name).await?).unwrap_err();
+        assert!(err.to_string().contains("sha256"), "{}", err);
+        Ok(())
+    }
+}
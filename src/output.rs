@@ -0,0 +1,168 @@
+use std::{
+    fmt,
+    io::{self, BufRead, Read, Write},
+    str::FromStr,
+};
+
+use anyhow::{anyhow, bail};
+use integer_encoding::{VarIntReader, VarIntWriter};
+
+/// How `scan` writes the entries it emits.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub enum OutputFormat {
+    /// `key -> record`, one per line (or per `--output-separator`). Records must be UTF-8, and
+    /// any containing the separator can't be told apart from two entries.
+    #[default]
+    Text,
+    /// Each entry is the key and then the record, each preceded by its length in bytes as an
+    /// unsigned LEB128 varint, with nothing in between entries. Lossless for arbitrary bytes;
+    /// read it back with `read_framed`.
+    Framed,
+}
+
+impl fmt::Display for OutputFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OutputFormat::Text => write!(f, "text"),
+            OutputFormat::Framed => write!(f, "framed"),
+        }
+    }
+}
+
+impl FromStr for OutputFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "text" => Ok(OutputFormat::Text),
+            "framed" => Ok(OutputFormat::Framed),
+            _ => Err(anyhow!("unrecognized output format: {}", s)),
+        }
+    }
+}
+
+/// What `OutputFormat::Text` writes after each entry. As given on the command line, `\0`, `\n`,
+/// `\t`, `\r` and `\\` stand for the bytes they do in Rust strings, so that e.g. NUL can be
+/// passed at all.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct Separator(pub Vec<u8>);
+
+impl FromStr for Separator {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let mut separator = Vec::new();
+        let mut chars = s.chars();
+        while let Some(c) = chars.next() {
+            if c != '\\' {
+                let mut buf = [0; 4];
+                separator.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+                continue;
+            }
+            separator.push(match chars.next() {
+                Some('0') => b'\0',
+                Some('n') => b'\n',
+                Some('t') => b'\t',
+                Some('r') => b'\r',
+                Some('\\') => b'\\',
+                Some(other) => bail!("unrecognized escape in separator: \\{}", other),
+                None => bail!("separator ends with a lone backslash"),
+            });
+        }
+        Ok(Separator(separator))
+    }
+}
+
+/// Writes one `OutputFormat::Framed` entry. Returns how many bytes were written.
+pub fn write_framed(out: &mut impl Write, key: &[u8], record: &[u8]) -> io::Result<usize> {
+    let mut written = 0;
+    for part in [key, record] {
+        written += out.write_varint(part.len())?;
+        out.write_all(part)?;
+        written += part.len();
+    }
+    Ok(written)
+}
+
+/// Reads the next entry written by `write_framed`, or `None` at the end of the input.
+pub fn read_framed(input: &mut impl BufRead) -> anyhow::Result<Option<(Vec<u8>, Vec<u8>)>> {
+    if input.fill_buf()?.is_empty() {
+        return Ok(None);
+    }
+    let key = read_part(input)?;
+    let record = read_part(input)?;
+    Ok(Some((key, record)))
+}
+
+fn read_part(input: &mut impl BufRead) -> anyhow::Result<Vec<u8>> {
+    let len: usize = input.read_varint()?;
+    // Read through `take` rather than into a buffer of `len` bytes, so that a corrupt length
+    // can't allocate more than the input holds.
+    let mut part = Vec::new();
+    input.take(len as u64).read_to_end(&mut part)?;
+    if part.len() != len {
+        bail!(
+            "truncated entry: expected {} bytes, found {}",
+            len,
+            part.len()
+        );
+    }
+    Ok(part)
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use crate::output::{read_framed, write_framed, Separator};
+
+    #[test]
+    fn framed_entries_round_trip() -> anyhow::Result<()> {
+        let entries: Vec<(Vec<u8>, Vec<u8>)> = vec![
+            (b"plain".to_vec(), br#"{"a": 1}"#.to_vec()),
+            (b"multi\nline".to_vec(), b"first\nsecond\n".to_vec()),
+            (b"nul\0key".to_vec(), b"\0\0binary\xff\0".to_vec()),
+            (b"empty".to_vec(), Vec::new()),
+            (b"big".to_vec(), vec![b'\n'; 1000]),
+        ];
+        let mut out = Vec::new();
+        let mut written = 0;
+        for (key, record) in &entries {
+            written += write_framed(&mut out, key, record)?;
+        }
+        assert_eq!(written, out.len());
+
+        let mut input = Cursor::new(out);
+        let mut parsed = Vec::new();
+        while let Some(entry) = read_framed(&mut input)? {
+            parsed.push(entry);
+        }
+        assert_eq!(parsed, entries);
+        Ok(())
+    }
+
+    #[test]
+    fn truncated_entries_are_errors() -> anyhow::Result<()> {
+        let mut out = Vec::new();
+        write_framed(&mut out, b"key", b"record")?;
+        out.pop();
+        let err = read_framed(&mut Cursor::new(out)).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "truncated entry: expected 6 bytes, found 5"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn separators() -> anyhow::Result<()> {
+        let parse = |s: &str| s.parse::<Separator>().map(|separator| separator.0);
+        assert_eq!(parse("\\0")?, b"\0");
+        assert_eq!(parse("\\n--\\t")?, b"\n--\t");
+        assert_eq!(parse("\\\\")?, b"\\");
+        assert_eq!(parse("§")?, "§".as_bytes());
+        assert!(parse("\\x").is_err());
+        assert!(parse("a\\").is_err());
+        Ok(())
+    }
+}
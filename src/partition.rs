@@ -0,0 +1,320 @@
+use std::{
+    collections::{btree_map::Entry, BTreeMap, HashSet},
+    fmt,
+    io::Write,
+    str::FromStr,
+};
+
+use anyhow::{anyhow, bail};
+use rocksdb::SstFileWriter;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use tracing::debug;
+
+use crate::{blob::Blobstore, index, path::Key};
+
+/// Decides which shard a key belongs to. Writers and readers of a sharded dataset must agree on
+/// the partitioner, so it round-trips through its string form (e.g. `hash:7:16`) for recording
+/// alongside the data.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum Partitioner {
+    /// One shard per value of the first key byte. Empty keys go to shard 0.
+    FirstByte,
+    /// One shard per distinct `n`-byte key prefix, zero-padded for shorter keys. `n` is at most 7,
+    /// so that `num_shards` fits in a `u64`.
+    PrefixLen(usize),
+    /// Keys are spread over `shards` shards by a seeded hash.
+    Hash { seed: u64, shards: u64 },
+}
+
+impl Partitioner {
+    pub fn num_shards(&self) -> u64 {
+        match *self {
+            Partitioner::FirstByte => 256,
+            Partitioner::PrefixLen(n) => 1 << (8 * n),
+            Partitioner::Hash { shards, .. } => shards,
+        }
+    }
+
+    pub fn shard(&self, key: &[u8]) -> u64 {
+        match *self {
+            Partitioner::FirstByte => key.first().copied().unwrap_or(0) as u64,
+            Partitioner::PrefixLen(n) => (0..n).fold(0, |acc, i| {
+                (acc << 8) | key.get(i).copied().unwrap_or(0) as u64
+            }),
+            Partitioner::Hash { seed, shards } => {
+                // SHA-256 rather than `DefaultHasher`, which is not stable across Rust releases.
+                let mut ctx = ring::digest::Context::new(&ring::digest::SHA256);
+                ctx.update(&seed.to_be_bytes());
+                ctx.update(key);
+                let digest = ctx.finish();
+                let mut head = [0; 8];
+                head.copy_from_slice(&digest.as_ref()[..8]);
+                u64::from_be_bytes(head) % shards
+            }
+        }
+    }
+
+    /// The encoded `IndexValue` of `key`, found by downloading just the shard of index version
+    /// `version` that `key` belongs to, as published by `ShardedIndexWriter`.
+    pub async fn get(
+        &self,
+        blob: &mut impl Blobstore,
+        version: &str,
+        key: &[u8],
+        db_opts: &rocksdb::Options,
+    ) -> anyhow::Result<Option<Vec<u8>>> {
+        let name = shard_key(version, self.shard(key))?;
+        debug!("downloading index shard {}", name);
+        // Empty shards aren't stored.
+        let Some(body) = blob.get(&name).await? else {
+            return Ok(None);
+        };
+        let mut shard_file = tempfile::NamedTempFile::new()?;
+        shard_file.write_all(&body)?;
+        shard_file.flush()?;
+        let db_dir = tempfile::TempDir::new()?;
+        let db = index::ingest(shard_file.path(), db_opts, db_dir.path())?;
+        Ok(db.get(key)?)
+    }
+}
+
+/// Where shard `shard` of index version `version` is stored, relative to the dataset's prefix.
+pub fn shard_key(version: &str, shard: u64) -> anyhow::Result<Key> {
+    Key::new(format!("index/{}/shard-{}.sst", version, shard))
+}
+
+/// Splits an index into one SST per non-empty shard of a `Partitioner`, from entries pushed in
+/// key order, keeping them in local SSTs until they're published. Every non-empty shard's SST is
+/// open until then, so partitioners with very many shards only suit small indexes.
+pub struct ShardedIndexWriter<'a> {
+    partitioner: Partitioner,
+    db_opts: &'a rocksdb::Options,
+    dir: tempfile::TempDir,
+    shards: BTreeMap<u64, SstFileWriter<'a>>,
+}
+
+impl<'a> ShardedIndexWriter<'a> {
+    pub fn new(partitioner: Partitioner, db_opts: &'a rocksdb::Options) -> anyhow::Result<Self> {
+        Ok(Self {
+            partitioner,
+            db_opts,
+            dir: tempfile::TempDir::new()?,
+            shards: BTreeMap::new(),
+        })
+    }
+
+    /// Adds the next entry. Keys must be strictly increasing, as in an SST.
+    pub fn push(&mut self, key: &[u8], value: &[u8]) -> anyhow::Result<()> {
+        let shard = self.partitioner.shard(key);
+        let writer = match self.shards.entry(shard) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                let mut writer = SstFileWriter::create(self.db_opts);
+                writer.open(self.dir.path().join(format!("{}.sst", shard)))?;
+                entry.insert(writer)
+            }
+        };
+        Ok(writer.put(key, value)?)
+    }
+
+    /// Uploads every non-empty shard as `version`, then removes the shards of `version` that an
+    /// earlier run left and this one doesn't have, which readers would otherwise consult. Returns
+    /// how many shards were uploaded.
+    pub async fn publish(
+        mut self,
+        blob: &mut impl Blobstore,
+        version: &str,
+    ) -> anyhow::Result<usize> {
+        let mut published = HashSet::new();
+        for (&shard, writer) in &mut self.shards {
+            writer.finish()?;
+            let name = shard_key(version, shard)?;
+            debug!("pushing index shard {}", name);
+            let path = self.dir.path().join(format!("{}.sst", shard));
+            blob.put_owned(&name, std::fs::read(path)?).await?;
+            published.insert(name.to_string());
+        }
+        for name in blob.list(&format!("index/{}/shard-", version)).await? {
+            if !published.contains(&name) {
+                debug!("removing the stale index shard {}", name);
+                blob.delete(&name).await?;
+            }
+        }
+        Ok(published.len())
+    }
+}
+
+impl fmt::Display for Partitioner {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Partitioner::FirstByte => write!(f, "first-byte"),
+            Partitioner::PrefixLen(n) => write!(f, "prefix-len:{}", n),
+            Partitioner::Hash { seed, shards } => write!(f, "hash:{}:{}", seed, shards),
+        }
+    }
+}
+
+// Serialized in its string form, so that a manifest reads the same as the `--partitioner` flag.
+impl Serialize for Partitioner {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Partitioner {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+impl FromStr for Partitioner {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let parts: Vec<&str> = s.split(':').collect();
+        let partitioner = match parts.as_slice() {
+            ["first-byte"] => Partitioner::FirstByte,
+            ["prefix-len", n] => {
+                let n: usize = n.parse()?;
+                if n == 0 || n > 7 {
+                    bail!("prefix length must be between 1 and 7, got {}", n);
+                }
+                Partitioner::PrefixLen(n)
+            }
+            ["hash", seed, shards] => {
+                let shards: u64 = shards.parse()?;
+                if shards == 0 {
+                    bail!("hash partitioner needs at least one shard");
+                }
+                Partitioner::Hash {
+                    seed: seed.parse()?,
+                    shards,
+                }
+            }
+            _ => return Err(anyhow!("unrecognized partitioner: {}", s)),
+        };
+        Ok(partitioner)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        blob::{Blobstore, MemoryStore},
+        index::db_options,
+        manifest::Manifest,
+        partition::{shard_key, Partitioner, ShardedIndexWriter},
+    };
+
+    #[test]
+    fn first_byte() {
+        let p = Partitioner::FirstByte;
+        assert_eq!(p.shard(b"abc"), b'a' as u64);
+        assert_eq!(p.shard(b"\xff"), 255);
+        assert_eq!(p.shard(b""), 0);
+    }
+
+    #[test]
+    fn prefix_len() {
+        let p = Partitioner::PrefixLen(2);
+        assert_eq!(p.num_shards(), 65536);
+        assert_eq!(p.shard(b"\x01\x02\x03"), 0x0102);
+        assert_eq!(p.shard(b"\x01"), 0x0100);
+    }
+
+    #[test]
+    fn hash_stays_in_range() {
+        let p = Partitioner::Hash { seed: 7, shards: 5 };
+        for i in 0..1000 {
+            assert!(p.shard(format!("key-{}", i).as_bytes()) < 5);
+        }
+        let other_seed = Partitioner::Hash { seed: 8, shards: 5 };
+        assert!((0..100).any(|i| {
+            let key = format!("key-{}", i);
+            p.shard(key.as_bytes()) != other_seed.shard(key.as_bytes())
+        }));
+    }
+
+    #[test]
+    fn writer_and_reader_agree() -> anyhow::Result<()> {
+        for writer in [
+            Partitioner::FirstByte,
+            Partitioner::PrefixLen(3),
+            Partitioner::Hash {
+                seed: 42,
+                shards: 16,
+            },
+        ] {
+            let reader: Partitioner = writer.to_string().parse()?;
+            assert_eq!(reader, writer);
+            for key in ["", "a", "0001001", "zzzzzzzz"] {
+                assert_eq!(reader.shard(key.as_bytes()), writer.shard(key.as_bytes()));
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn largest_prefix_len_fits() -> anyhow::Result<()> {
+        let p: Partitioner = "prefix-len:7".parse()?;
+        assert_eq!(p.num_shards(), 1 << 56);
+        assert_eq!(p.shard(b"\xff\xff\xff\xff\xff\xff\xff\xff"), (1 << 56) - 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn sharded_index_round_trips_through_the_manifest() -> anyhow::Result<()> {
+        let mut blob = MemoryStore::default();
+        let writer_manifest = Manifest {
+            partitioner: Some(Partitioner::Hash { seed: 3, shards: 4 }),
+            ..Manifest::default()
+        };
+        blob.put(Manifest::KEY, &writer_manifest.encode()?).await?;
+        // Left by an earlier run that used more shards.
+        blob.put(&shard_key("default", 9)?, b"stale").await?;
+
+        let db_opts = db_options();
+        let mut writer = ShardedIndexWriter::new(writer_manifest.partitioner.unwrap(), &db_opts)?;
+        for i in 0..100 {
+            let key = format!("key-{:03}", i);
+            writer.push(key.as_bytes(), format!("value of {}", key).as_bytes())?;
+        }
+        assert_eq!(writer.publish(&mut blob, "default").await?, 4);
+        assert!(!blob.exists(&shard_key("default", 9)?).await?);
+
+        let reader = Manifest::load(&mut blob)
+            .await?
+            .unwrap()
+            .partitioner
+            .unwrap();
+        assert_eq!(reader, writer_manifest.partitioner.unwrap());
+        let value = reader
+            .get(&mut blob, "default", b"key-042", &db_opts)
+            .await?;
+        assert_eq!(value.as_deref(), Some(b"value of key-042".as_slice()));
+        assert_eq!(
+            reader
+                .get(&mut blob, "default", b"key-042x", &db_opts)
+                .await?,
+            None
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_malformed() {
+        for s in [
+            "",
+            "hash:1",
+            "hash:1:0",
+            "prefix-len:0",
+            "prefix-len:8",
+            "prefix-len:9",
+            "bogus",
+        ] {
+            assert!(s.parse::<Partitioner>().is_err(), "{}", s);
+        }
+    }
+}
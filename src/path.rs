@@ -0,0 +1,153 @@
+use std::{fmt, ops::Deref, str::FromStr};
+
+use anyhow::bail;
+
+/// The name of a blob, e.g. a block's name relative to the block prefix. Never empty, and never
+/// starts or ends with a slash, so it can always be joined onto a `Prefix`.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Clone)]
+pub struct Key(String);
+
+/// A `/`-separated namespace that keys are stored under, like a dataset's `--prefix`. Held to
+/// the same rules as `Key`, so joining the two gives exactly one separator between them.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Clone)]
+pub struct Prefix(String);
+
+fn validate(what: &str, s: &str) -> anyhow::Result<()> {
+    if s.is_empty() {
+        bail!("{} is empty", what);
+    }
+    if s.starts_with('/') || s.ends_with('/') {
+        bail!("{} {:?} starts or ends with a slash", what, s);
+    }
+    if s.contains("//") {
+        bail!("{} {:?} has an empty segment", what, s);
+    }
+    Ok(())
+}
+
+impl Key {
+    pub fn new(key: impl Into<String>) -> anyhow::Result<Self> {
+        let key = key.into();
+        validate("key", &key)?;
+        Ok(Key(key))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Prefix {
+    pub fn new(prefix: impl Into<String>) -> anyhow::Result<Self> {
+        let prefix = prefix.into();
+        validate("prefix", &prefix)?;
+        Ok(Prefix(prefix))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// The full name of `key` under this prefix. `key` is always taken as relative, so any
+    /// leading slashes it has are dropped rather than doubled.
+    pub fn join(&self, key: impl AsRef<str>) -> String {
+        format!("{}/{}", self.0, key.as_ref().trim_start_matches('/'))
+    }
+
+    /// `key` relative to this prefix, or `None` if it isn't under it.
+    pub fn strip<'a>(&self, key: &'a str) -> Option<&'a str> {
+        key.strip_prefix(self.0.as_str())?.strip_prefix('/')
+    }
+}
+
+impl Deref for Key {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Deref for Prefix {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl AsRef<str> for Key {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl AsRef<str> for Prefix {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<Key> for String {
+    fn from(key: Key) -> String {
+        key.0
+    }
+}
+
+impl fmt::Display for Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl fmt::Display for Prefix {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl FromStr for Key {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        Key::new(s)
+    }
+}
+
+impl FromStr for Prefix {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        Prefix::new(s)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::path::{Key, Prefix};
+
+    #[test]
+    fn rejects_malformed_paths() -> anyhow::Result<()> {
+        for bad in ["", "/", "/foo", "foo/", "foo//bar"] {
+            assert!(Key::new(bad).is_err(), "{:?}", bad);
+            assert!(Prefix::new(bad).is_err(), "{:?}", bad);
+        }
+        assert_eq!(
+            Prefix::new("foo/").unwrap_err().to_string(),
+            r#"prefix "foo/" starts or ends with a slash"#
+        );
+        assert_eq!(Key::new("block/0a")?.as_str(), "block/0a");
+        assert_eq!("a/b".parse::<Prefix>()?.to_string(), "a/b");
+        Ok(())
+    }
+
+    #[test]
+    fn joins_with_one_separator() -> anyhow::Result<()> {
+        let prefix = Prefix::new("datasets/foo")?;
+        assert_eq!(prefix.join(Key::new("block/0a")?), "datasets/foo/block/0a");
+        assert_eq!(prefix.join("/manifest.json"), "datasets/foo/manifest.json");
+        assert_eq!(prefix.strip("datasets/foo/block/0a"), Some("block/0a"));
+        assert_eq!(prefix.strip("datasets/foobar/block/0a"), None);
+        Ok(())
+    }
+}
@@ -0,0 +1,124 @@
+use anyhow::{anyhow, bail};
+use serde_json::Value;
+
+/// Looks up a dot-separated path (e.g. `properties.BLKLOT`) in a JSON value.
+pub fn lookup<'a>(value: &'a Value, path: &str) -> Option<&'a Value> {
+    path.split('.')
+        .try_fold(value, |cur, segment| cur.as_object()?.get(segment))
+}
+
+/// Removes the field at each dot-separated path, if present.
+pub fn drop_fields(value: &mut Value, paths: &[String]) {
+    for path in paths {
+        let (parent, field) = match path.rsplit_once('.') {
+            Some((parent, field)) => (lookup_mut(value, parent), field),
+            None => (Some(&mut *value), path.as_str()),
+        };
+        if let Some(Value::Object(parent)) = parent {
+            parent.remove(field);
+        }
+    }
+}
+
+fn lookup_mut<'a>(value: &'a mut Value, path: &str) -> Option<&'a mut Value> {
+    path.split('.')
+        .try_fold(value, |cur, segment| cur.as_object_mut()?.get_mut(segment))
+}
+
+/// Checks that a record is a JSON object containing every one of the `required` paths.
+pub fn validate(value: &Value, required: &[String]) -> anyhow::Result<()> {
+    if !value.is_object() {
+        bail!("record is not a JSON object");
+    }
+    for path in required {
+        lookup(value, path).ok_or_else(|| anyhow!("record is missing field {}", path))?;
+    }
+    Ok(())
+}
+
+/// The index key of a record: the value at `fields[0]`, or if there are several fields, their
+/// values encoded as a tuple with `key::encode_tuple`. String values are used as-is and anything
+/// else as its JSON text.
+pub fn key(value: &Value, fields: &[String]) -> anyhow::Result<Vec<u8>> {
+    let mut segments = Vec::with_capacity(fields.len());
+    for field in fields {
+        let segment = match lookup(value, field) {
+            Some(Value::String(s)) => s.clone(),
+            Some(other) => other.to_string(),
+            None => bail!("record is missing key field {}", field),
+        };
+        segments.push(segment);
+    }
+    match segments.as_slice() {
+        [single] => Ok(single.as_bytes().to_vec()),
+        _ => Ok(crate::key::encode_tuple(&segments)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use crate::{
+        key::encode_tuple,
+        record::{drop_fields, key, lookup, validate},
+    };
+
+    #[test]
+    fn lookup_nested() {
+        let v = json!({"properties": {"BLKLOT": "0001001"}});
+        assert_eq!(lookup(&v, "properties.BLKLOT"), Some(&json!("0001001")));
+        assert_eq!(lookup(&v, "properties.MISSING"), None);
+        assert_eq!(lookup(&v, "properties.BLKLOT.deeper"), None);
+    }
+
+    #[test]
+    fn strict_rejects_non_objects() {
+        assert!(validate(&json!([1, 2, 3]), &[]).is_err());
+        assert!(validate(&json!("hello"), &[]).is_err());
+        assert!(validate(&json!({}), &[]).is_ok());
+    }
+
+    #[test]
+    fn strict_rejects_missing_fields() {
+        let required = vec!["properties.BLKLOT".to_owned(), "geometry".to_owned()];
+        let good = json!({"properties": {"BLKLOT": "0001001"}, "geometry": null});
+        let no_key = json!({"properties": {}, "geometry": null});
+        let no_geometry = json!({"properties": {"BLKLOT": "0001001"}});
+        assert!(validate(&good, &required).is_ok());
+        assert!(validate(&no_key, &required).is_err());
+        assert!(validate(&no_geometry, &required).is_err());
+    }
+
+    #[test]
+    fn keys() -> anyhow::Result<()> {
+        let v = json!({"region": "us-west", "id": 123});
+        assert_eq!(key(&v, &["region".to_owned()])?, b"us-west");
+        assert_eq!(
+            key(&v, &["region".to_owned(), "id".to_owned()])?,
+            encode_tuple(&["us-west", "123"])
+        );
+        assert!(key(&v, &["missing".to_owned()]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn drops_fields() -> anyhow::Result<()> {
+        let mut v = json!({
+            "properties": {"BLKLOT": "0001001", "secret": "hunter2"},
+            "ssn": "123-45-6789",
+        });
+        drop_fields(
+            &mut v,
+            &[
+                "properties.secret".to_owned(),
+                "ssn".to_owned(),
+                "properties.missing".to_owned(),
+                "nowhere.at.all".to_owned(),
+            ],
+        );
+        assert_eq!(v, json!({"properties": {"BLKLOT": "0001001"}}));
+        assert_eq!(key(&v, &["properties.BLKLOT".to_owned()])?, b"0001001");
+        Ok(())
+    }
+}
@@ -0,0 +1,185 @@
+use std::{
+    cmp::Reverse,
+    collections::BinaryHeap,
+    fs::File,
+    io::{BufReader, BufWriter, ErrorKind, Read, Seek, SeekFrom, Write},
+};
+
+use integer_encoding::{VarIntReader, VarIntWriter};
+use tracing::debug;
+
+/// Sorts key/value pairs that may not fit in memory. Entries are buffered up to `buffer_size`
+/// bytes, then sorted and spilled to a temporary run file; `finish` k-way merges the runs.
+///
+/// As with writing into a RocksDB, the most recently pushed value wins when a key repeats.
+pub struct ExternalSorter {
+    buffer: Vec<(Vec<u8>, Vec<u8>)>,
+    buffer_bytes: usize,
+    buffer_size: usize,
+    runs: Vec<File>,
+}
+
+impl ExternalSorter {
+    pub fn new(buffer_size: usize) -> Self {
+        Self {
+            buffer: Vec::new(),
+            buffer_bytes: 0,
+            buffer_size,
+            runs: Vec::new(),
+        }
+    }
+
+    pub fn push(&mut self, key: &[u8], value: &[u8]) -> anyhow::Result<()> {
+        self.buffer_bytes += key.len() + value.len();
+        self.buffer.push((key.to_vec(), value.to_vec()));
+        if self.buffer_bytes >= self.buffer_size {
+            self.spill()?;
+        }
+        Ok(())
+    }
+
+    fn spill(&mut self) -> anyhow::Result<()> {
+        // A stable sort keeps pushes of the same key in order, so the last of each group wins.
+        self.buffer.sort_by(|a, b| a.0.cmp(&b.0));
+        debug!(
+            "spilling run {} ({} entries)",
+            self.runs.len(),
+            self.buffer.len()
+        );
+        let mut out = BufWriter::new(tempfile::tempfile()?);
+        let mut entries = self.buffer.drain(..).peekable();
+        while let Some((k, v)) = entries.next() {
+            if entries.peek().is_some_and(|(next, _)| *next == k) {
+                continue;
+            }
+            out.write_varint(k.len())?;
+            out.write_all(&k)?;
+            out.write_varint(v.len())?;
+            out.write_all(&v)?;
+        }
+        let mut file = out.into_inner()?;
+        file.seek(SeekFrom::Start(0))?;
+        self.runs.push(file);
+        self.buffer_bytes = 0;
+        Ok(())
+    }
+
+    /// Returns every distinct key, in sorted order, with its most recently pushed value.
+    pub fn finish(mut self) -> anyhow::Result<Merged> {
+        if !self.buffer.is_empty() {
+            self.spill()?;
+        }
+        let mut merged = Merged {
+            readers: self.runs.into_iter().map(BufReader::new).collect(),
+            heads: BinaryHeap::new(),
+        };
+        for run in 0..merged.readers.len() {
+            merged.advance(run)?;
+        }
+        Ok(merged)
+    }
+}
+
+// (key, run, value), ordered so the smallest key comes first and, for equal keys, the latest run
+// (that is, the newest value) comes first.
+type Head = Reverse<(Vec<u8>, Reverse<usize>, Vec<u8>)>;
+
+pub struct Merged {
+    readers: Vec<BufReader<File>>,
+    heads: BinaryHeap<Head>,
+}
+
+impl Merged {
+    fn advance(&mut self, run: usize) -> anyhow::Result<()> {
+        let reader = &mut self.readers[run];
+        let key_len: usize = match reader.read_varint() {
+            Ok(n) => n,
+            Err(err) if err.kind() == ErrorKind::UnexpectedEof => return Ok(()),
+            Err(err) => return Err(err.into()),
+        };
+        let mut key = vec![0; key_len];
+        reader.read_exact(&mut key)?;
+        let value_len: usize = reader.read_varint()?;
+        let mut value = vec![0; value_len];
+        reader.read_exact(&mut value)?;
+        self.heads.push(Reverse((key, Reverse(run), value)));
+        Ok(())
+    }
+
+    fn next_entry(&mut self) -> anyhow::Result<Option<(Vec<u8>, Vec<u8>)>> {
+        let Some(Reverse((key, Reverse(run), value))) = self.heads.pop() else {
+            return Ok(None);
+        };
+        self.advance(run)?;
+        // Older values for the same key sort right behind the newest one; drop them.
+        while let Some(Reverse((next, Reverse(stale), _))) = self.heads.peek() {
+            if *next != key {
+                break;
+            }
+            let stale = *stale;
+            self.heads.pop();
+            self.advance(stale)?;
+        }
+        Ok(Some((key, value)))
+    }
+}
+
+impl Iterator for Merged {
+    type Item = anyhow::Result<(Vec<u8>, Vec<u8>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_entry().transpose()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeMap;
+
+    use rand::{Rng, SeedableRng};
+
+    use crate::sort::ExternalSorter;
+
+    #[test]
+    fn matches_single_pass() -> anyhow::Result<()> {
+        let mut prng = rand::rngs::SmallRng::seed_from_u64(42);
+        // A BTreeMap with overwrites is the in-memory equivalent of the single RocksDB build.
+        let mut expected = BTreeMap::new();
+        let mut sorter = ExternalSorter::new(1_000);
+        for i in 0..5_000 {
+            let key = format!("key-{:05}", prng.gen_range(0..2_000));
+            let value = format!("value-{}", i);
+            expected.insert(key.clone().into_bytes(), value.clone().into_bytes());
+            sorter.push(key.as_bytes(), value.as_bytes())?;
+        }
+
+        let actual = sorter.finish()?.collect::<anyhow::Result<Vec<_>>>()?;
+        assert_eq!(actual, expected.into_iter().collect::<Vec<_>>());
+        Ok(())
+    }
+
+    #[test]
+    fn later_runs_win() -> anyhow::Result<()> {
+        // A tiny buffer puts every push in its own run.
+        let mut sorter = ExternalSorter::new(1);
+        sorter.push(b"b", b"1")?;
+        sorter.push(b"a", b"1")?;
+        sorter.push(b"b", b"2")?;
+        sorter.push(b"b", b"3")?;
+        let actual = sorter.finish()?.collect::<anyhow::Result<Vec<_>>>()?;
+        assert_eq!(
+            actual,
+            vec![
+                (b"a".to_vec(), b"1".to_vec()),
+                (b"b".to_vec(), b"3".to_vec())
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn empty() -> anyhow::Result<()> {
+        assert_eq!(ExternalSorter::new(10).finish()?.count(), 0);
+        Ok(())
+    }
+}
@@ -0,0 +1,233 @@
+use std::{io::Read, path::PathBuf};
+
+use anyhow::bail;
+use integer_encoding::{VarIntReader, VarIntWriter};
+use rocksdb::SstFileWriter;
+use tracing::debug;
+
+use crate::{blob::Blobstore, index, path::Key};
+
+/// A two-level version of an index: its entries split in key order into mini-indexes ("parts")
+/// of at most `entries_per_part` entries each, plus this sparse index of the first key of each
+/// part. A point lookup binary-searches the sparse index and downloads only the part that could
+/// hold the key, instead of the whole keyspace.
+///
+/// For index version `v`, the sparse index is stored at `index/v.sparse` (each first key
+/// preceded by its length as a varint) and part `n` at `index/v/n.sst`. Parts are SSTs, just
+/// like the monolithic `index/v.sst` published next to them.
+#[derive(PartialEq, Eq, Debug, Clone, Default)]
+pub struct SparseIndex {
+    pub first_keys: Vec<Vec<u8>>,
+}
+
+impl SparseIndex {
+    pub fn key(version: &str) -> anyhow::Result<Key> {
+        Key::new(format!("index/{}.sparse", version))
+    }
+
+    pub fn part_key(version: &str, part: usize) -> anyhow::Result<Key> {
+        Key::new(format!("index/{}/{}.sst", version, part))
+    }
+
+    /// The part that would hold `key`, or `None` if `key` sorts before every part.
+    pub fn part_for(&self, key: &[u8]) -> Option<usize> {
+        self.first_keys
+            .partition_point(|first| first.as_slice() <= key)
+            .checked_sub(1)
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        for first in &self.first_keys {
+            buf.write_varint(first.len()).unwrap();
+            buf.extend_from_slice(first);
+        }
+        buf
+    }
+
+    pub fn decode(mut buf: &[u8]) -> anyhow::Result<Self> {
+        let mut first_keys = Vec::new();
+        while !buf.is_empty() {
+            let len: usize = buf.read_varint()?;
+            let mut first = Vec::new();
+            (&mut buf).take(len as u64).read_to_end(&mut first)?;
+            if first.len() != len {
+                bail!("sparse index is truncated");
+            }
+            first_keys.push(first);
+        }
+        Ok(SparseIndex { first_keys })
+    }
+
+    /// Reads the sparse index of `version` from a dataset, if it has one. Only datasets written
+    /// with `etl --index-part-entries` do.
+    pub async fn load(blob: &mut impl Blobstore, version: &str) -> anyhow::Result<Option<Self>> {
+        match blob.get(&Self::key(version)?).await? {
+            Some(buf) => Ok(Some(Self::decode(&buf)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// The encoded `IndexValue` of `key`, found by downloading just the part that could
+    /// hold it.
+    pub async fn get(
+        &self,
+        blob: &mut impl Blobstore,
+        version: &str,
+        key: &[u8],
+        db_opts: &rocksdb::Options,
+    ) -> anyhow::Result<Option<Vec<u8>>> {
+        let Some(part) = self.part_for(key) else {
+            return Ok(None);
+        };
+        let db_dir = tempfile::TempDir::new()?;
+        let name = Self::part_key(version, part)?;
+        let db = index::download(blob, &name, false, db_opts, db_dir.path()).await?;
+        Ok(db.get(key)?)
+    }
+}
+
+/// Builds the parts of a `SparseIndex` from entries pushed in key order, keeping them in local
+/// SSTs until they're published.
+pub struct SparseIndexWriter {
+    entries_per_part: usize,
+    db_opts: rocksdb::Options,
+    dir: tempfile::TempDir,
+    buf: Vec<(Vec<u8>, Vec<u8>)>,
+    parts: Vec<PathBuf>,
+    index: SparseIndex,
+}
+
+impl SparseIndexWriter {
+    pub fn new(entries_per_part: usize, db_opts: rocksdb::Options) -> anyhow::Result<Self> {
+        if entries_per_part == 0 {
+            bail!("a sparse index part must hold at least one entry");
+        }
+        Ok(Self {
+            entries_per_part,
+            db_opts,
+            dir: tempfile::TempDir::new()?,
+            buf: Vec::new(),
+            parts: Vec::new(),
+            index: SparseIndex::default(),
+        })
+    }
+
+    /// Adds the next entry. Keys must be strictly increasing, as in an SST.
+    pub fn push(&mut self, key: &[u8], value: &[u8]) -> anyhow::Result<()> {
+        self.buf.push((key.to_vec(), value.to_vec()));
+        if self.buf.len() >= self.entries_per_part {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> anyhow::Result<()> {
+        let Some((first, _)) = self.buf.first() else {
+            return Ok(());
+        };
+        self.index.first_keys.push(first.clone());
+        let path = self.dir.path().join(format!("{}.sst", self.parts.len()));
+        let mut writer = SstFileWriter::create(&self.db_opts);
+        writer.open(&path)?;
+        for (k, v) in self.buf.drain(..) {
+            writer.put(k, v)?;
+        }
+        writer.finish()?;
+        self.parts.push(path);
+        Ok(())
+    }
+
+    /// Uploads every part and then the sparse index for them as `version`, so that readers
+    /// never see a sparse index whose parts are missing.
+    pub async fn publish(
+        mut self,
+        blob: &mut impl Blobstore,
+        version: &str,
+    ) -> anyhow::Result<SparseIndex> {
+        self.flush()?;
+        for (part, path) in self.parts.iter().enumerate() {
+            let name = SparseIndex::part_key(version, part)?;
+            debug!("pushing index part {}", name);
+            blob.put_owned(&name, std::fs::read(path)?).await?;
+        }
+        let name = SparseIndex::key(version)?;
+        debug!("pushing sparse index {}", name);
+        blob.put(&name, &self.index.encode()).await?;
+        Ok(self.index)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::borrow::Cow;
+
+    use async_trait::async_trait;
+
+    use crate::{
+        blob::{Blobstore, MemoryStore},
+        index::db_options,
+        sparse::{SparseIndex, SparseIndexWriter},
+    };
+
+    // Remembers the key of every read.
+    #[derive(Debug, Default)]
+    struct Recording {
+        underlying: MemoryStore,
+        gets: Vec<String>,
+    }
+    #[async_trait]
+    impl Blobstore for Recording {
+        async fn get(&mut self, key: &str) -> anyhow::Result<Option<Cow<[u8]>>> {
+            self.gets.push(key.to_owned());
+            self.underlying.get(key).await
+        }
+        async fn put(&mut self, key: &str, blob: &[u8]) -> anyhow::Result<()> {
+            self.underlying.put(key, blob).await
+        }
+    }
+
+    #[test]
+    fn finds_the_part_holding_a_key() -> anyhow::Result<()> {
+        let index = SparseIndex {
+            first_keys: vec![b"b".to_vec(), b"d".to_vec(), b"f".to_vec()],
+        };
+        assert_eq!(index.part_for(b"a"), None);
+        assert_eq!(index.part_for(b"b"), Some(0));
+        assert_eq!(index.part_for(b"c"), Some(0));
+        assert_eq!(index.part_for(b"d"), Some(1));
+        assert_eq!(index.part_for(b"zzz"), Some(2));
+        assert_eq!(SparseIndex::decode(&index.encode())?, index);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn point_gets_download_one_part() -> anyhow::Result<()> {
+        let mut blob = Recording::default();
+        let mut writer = SparseIndexWriter::new(100, db_options())?;
+        for i in 0..1000 {
+            let key = format!("key-{:04}", i);
+            writer.push(key.as_bytes(), format!("value of {}", key).as_bytes())?;
+        }
+        writer.publish(&mut blob, "default").await?;
+
+        let index = SparseIndex::load(&mut blob, "default").await?.unwrap();
+        assert_eq!(index.first_keys.len(), 10);
+        let value = index
+            .get(&mut blob, "default", b"key-0456", &db_options())
+            .await?;
+        assert_eq!(value.as_deref(), Some(b"value of key-0456".as_slice()));
+        assert_eq!(blob.gets, ["index/default.sparse", "index/default/4.sst"]);
+
+        let missing = index
+            .get(&mut blob, "default", b"key-0456x", &db_options())
+            .await?;
+        assert_eq!(missing, None);
+        assert_eq!(
+            index.get(&mut blob, "default", b"a", &db_options()).await?,
+            None
+        );
+        assert_eq!(blob.gets.len(), 3);
+        Ok(())
+    }
+}
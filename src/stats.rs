@@ -0,0 +1,146 @@
+use std::{
+    fmt,
+    time::{Duration, Instant},
+};
+
+use hdrhistogram::Histogram;
+
+/// Fires on a fixed wall-clock schedule. Callers pass in the current time, so the schedule can be
+/// driven by a fake clock in tests.
+#[derive(Debug, Clone, Copy)]
+pub struct Interval {
+    period: Duration,
+    next: Instant,
+}
+impl Interval {
+    pub fn new(period: Duration, start: Instant) -> Self {
+        Self {
+            period,
+            next: start + period,
+        }
+    }
+
+    /// Whether the interval has elapsed as of `now`. Ticks that were missed entirely (because the
+    /// caller was busy) are skipped rather than all firing at once.
+    pub fn tick(&mut self, now: Instant) -> bool {
+        if now < self.next {
+            return false;
+        }
+        while self.next <= now {
+            self.next += self.period;
+        }
+        true
+    }
+}
+
+/// Latency statistics, both since the start and since the last report.
+#[derive(Debug)]
+pub struct LatencyStats {
+    total: Histogram<u64>,
+    window: Histogram<u64>,
+    last_report: Instant,
+}
+impl LatencyStats {
+    pub fn new(start: Instant) -> anyhow::Result<Self> {
+        Ok(Self {
+            total: Histogram::new(5)?,
+            window: Histogram::new(5)?,
+            last_report: start,
+        })
+    }
+
+    pub fn record(&mut self, latency: Duration) -> anyhow::Result<()> {
+        let nanos = latency.as_nanos() as u64;
+        self.total.record(nanos)?;
+        self.window.record(nanos)?;
+        Ok(())
+    }
+
+    /// How many latencies have been recorded since the last report.
+    pub fn window_len(&self) -> u64 {
+        self.window.len()
+    }
+
+    /// Summarizes everything up to `now` and starts a new window.
+    pub fn report(&mut self, now: Instant) -> Report {
+        let elapsed = now.saturating_duration_since(self.last_report);
+        let report = Report {
+            fetches: self.total.len(),
+            per_sec: self.window.len() as f64 / elapsed.as_secs_f64().max(f64::EPSILON),
+            mean_us: self.total.mean() * 1e-3,
+            p99_us: self.total.value_at_quantile(0.99) as f64 * 1e-3,
+            window_p99_us: self.window.value_at_quantile(0.99) as f64 * 1e-3,
+        };
+        self.window.reset();
+        self.last_report = now;
+        report
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Report {
+    pub fetches: u64,
+    /// Throughput since the previous report.
+    pub per_sec: f64,
+    pub mean_us: f64,
+    pub p99_us: f64,
+    /// The p99 of just the latencies since the previous report.
+    pub window_p99_us: f64,
+}
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "fetches={} rate={:.1}/s mean={:.1}us p99={:.1}us window_p99={:.1}us",
+            self.fetches, self.per_sec, self.mean_us, self.p99_us, self.window_p99_us
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, Instant};
+
+    use crate::stats::{Interval, LatencyStats};
+
+    #[test]
+    fn interval_fires_on_schedule() {
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        let mut interval = Interval::new(Duration::from_secs(1), start);
+
+        assert!(!interval.tick(at(0)));
+        assert!(!interval.tick(at(999)));
+        assert!(interval.tick(at(1_000)));
+        assert!(!interval.tick(at(1_500)));
+        assert!(interval.tick(at(2_100)));
+        // A long stall fires once, then resumes the original schedule.
+        assert!(interval.tick(at(5_300)));
+        assert!(!interval.tick(at(5_900)));
+        assert!(interval.tick(at(6_000)));
+    }
+
+    #[test]
+    fn reports_window_and_total() -> anyhow::Result<()> {
+        let start = Instant::now();
+        let mut stats = LatencyStats::new(start)?;
+        for _ in 0..10 {
+            stats.record(Duration::from_micros(100))?;
+        }
+        let first = stats.report(start + Duration::from_secs(2));
+        assert_eq!(first.fetches, 10);
+        assert!((first.per_sec - 5.0).abs() < 1e-9);
+        assert!((first.window_p99_us - 100.0).abs() < 1.0);
+        assert_eq!(stats.window_len(), 0);
+
+        for _ in 0..4 {
+            stats.record(Duration::from_micros(1_000))?;
+        }
+        let second = stats.report(start + Duration::from_secs(4));
+        assert_eq!(second.fetches, 14);
+        assert!((second.per_sec - 2.0).abs() < 1e-9);
+        assert!((second.window_p99_us - 1_000.0).abs() < 1.0);
+        assert!(second.mean_us < second.window_p99_us);
+        Ok(())
+    }
+}
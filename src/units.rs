@@ -0,0 +1,49 @@
+const UNITS: [&str; 6] = ["KiB", "MiB", "GiB", "TiB", "PiB", "EiB"];
+
+/// Formats a byte count with binary (1024-based) units, e.g. `1.5 MiB`.
+pub fn human_bytes(n: u64) -> String {
+    if n < 1024 {
+        return format!("{} B", n);
+    }
+    let mut value = n as f64 / 1024.0;
+    let mut unit = 0;
+    // Step up a unit whenever rounding would otherwise print something like "1024.0 KiB".
+    while (value * 10.0).round() >= 10240.0 && unit + 1 < UNITS.len() {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", value, UNITS[unit])
+}
+
+/// Formats a byte count either raw or with `human_bytes`.
+pub fn format_bytes(n: u64, human_readable: bool) -> String {
+    if human_readable {
+        human_bytes(n)
+    } else {
+        n.to_string()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::units::{format_bytes, human_bytes};
+
+    #[test]
+    fn boundaries() {
+        assert_eq!(human_bytes(0), "0 B");
+        assert_eq!(human_bytes(1023), "1023 B");
+        assert_eq!(human_bytes(1024), "1.0 KiB");
+        assert_eq!(human_bytes(1536), "1.5 KiB");
+        assert_eq!(human_bytes(1_048_575), "1.0 MiB");
+        assert_eq!(human_bytes(1_048_576), "1.0 MiB");
+        assert_eq!(human_bytes(1_073_741_824), "1.0 GiB");
+        assert_eq!(human_bytes(5 * 1_073_741_824 + 536_870_912), "5.5 GiB");
+        assert_eq!(human_bytes(u64::MAX), "16.0 EiB");
+    }
+
+    #[test]
+    fn raw() {
+        assert_eq!(format_bytes(1_048_576, false), "1048576");
+        assert_eq!(format_bytes(1_048_576, true), "1.0 MiB");
+    }
+}
@@ -0,0 +1,179 @@
+use std::collections::BTreeMap;
+
+use crate::units;
+
+/// The size of every object under a prefix at one point in time, as `inspect --watch` polls it.
+#[derive(PartialEq, Eq, Debug, Clone, Default)]
+pub struct Listing {
+    pub objects: BTreeMap<String, u64>,
+}
+
+impl Listing {
+    /// Lists every object in `bucket` under `prefix` (or the whole bucket), page by page.
+    pub async fn fetch(
+        client: &aws_sdk_s3::Client,
+        bucket: &str,
+        prefix: Option<&str>,
+    ) -> anyhow::Result<Self> {
+        let mut pages = client
+            .list_objects_v2()
+            .bucket(bucket)
+            .set_prefix(prefix.map(str::to_owned))
+            .into_paginator()
+            .send();
+        let mut objects = BTreeMap::new();
+        while let Some(page) = pages.next().await {
+            for object in page?.contents() {
+                let key = object.key().unwrap_or_default().to_owned();
+                objects.insert(key, object.size().unwrap_or_default() as u64);
+            }
+        }
+        Ok(Listing { objects })
+    }
+
+    pub fn total_bytes(&self) -> u64 {
+        self.objects.values().sum()
+    }
+
+    /// What changed between `prev` and this listing.
+    pub fn since(&self, prev: &Listing) -> Delta {
+        let new_objects = self
+            .objects
+            .keys()
+            .filter(|key| !prev.objects.contains_key(*key))
+            .count();
+        let removed_objects = prev
+            .objects
+            .keys()
+            .filter(|key| !self.objects.contains_key(*key))
+            .count();
+        Delta {
+            new_objects,
+            removed_objects,
+            count: self.objects.len(),
+            total_bytes: self.total_bytes(),
+            bytes_added: self.total_bytes() as i64 - prev.total_bytes() as i64,
+        }
+    }
+}
+
+/// The change between two `Listing`s, along with the totals as of the later one.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub struct Delta {
+    pub new_objects: usize,
+    pub removed_objects: usize,
+    pub count: usize,
+    pub total_bytes: u64,
+    /// Negative if objects were removed or shrank.
+    pub bytes_added: i64,
+}
+
+impl Delta {
+    /// One line for a poll, e.g. `+3 new, -0 removed; 12 objects, 4.0 KiB (+1.5 KiB)`.
+    pub fn summary(&self, human_readable: bool) -> String {
+        let sign = if self.bytes_added < 0 { "-" } else { "+" };
+        format!(
+            "+{} new, -{} removed; {} objects, {} ({}{})",
+            self.new_objects,
+            self.removed_objects,
+            self.count,
+            units::format_bytes(self.total_bytes, human_readable),
+            sign,
+            units::format_bytes(self.bytes_added.unsigned_abs(), human_readable)
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    use aws_config::BehaviorVersion;
+    use aws_sdk_s3::config::{Credentials, Region};
+    use aws_smithy_runtime::client::http::test_util::infallible_client_fn;
+
+    use crate::watch::{Delta, Listing};
+
+    // Serves `ListObjectsV2` from `polls`: the pages each successive listing sees, chained by
+    // continuation tokens that are just page numbers.
+    fn listing_client(polls: Vec<Vec<Vec<(&'static str, u64)>>>) -> aws_sdk_s3::Client {
+        let poll = Arc::new(AtomicUsize::new(0));
+        let http_client = infallible_client_fn(move |req| {
+            let uri = req.uri().to_string();
+            let page: usize = uri
+                .split("continuation-token=")
+                .nth(1)
+                .and_then(|token| token.split('&').next())
+                .map_or(0, |token| token.parse().unwrap());
+            let pages = &polls[poll.load(Ordering::SeqCst)];
+            let truncated = page + 1 < pages.len();
+            if !truncated {
+                poll.fetch_add(1, Ordering::SeqCst);
+            }
+            let mut body = format!("<ListBucketResult><IsTruncated>{}</IsTruncated>", truncated);
+            if truncated {
+                body += &format!(
+                    "<NextContinuationToken>{}</NextContinuationToken>",
+                    page + 1
+                );
+            }
+            for (key, size) in &pages[page] {
+                body += &format!(
+                    "<Contents><Key>{}</Key><Size>{}</Size></Contents>",
+                    key, size
+                );
+            }
+            body += "</ListBucketResult>";
+            hyper::Response::builder().status(200).body(body).unwrap()
+        });
+        let conf = aws_sdk_s3::Config::builder()
+            .behavior_version(BehaviorVersion::latest())
+            .region(Region::new("us-west-2"))
+            .credentials_provider(Credentials::new("akid", "secret", None, None, "test"))
+            .http_client(http_client)
+            .build();
+        aws_sdk_s3::Client::from_conf(conf)
+    }
+
+    #[tokio::test]
+    async fn reports_objects_added_between_polls() -> anyhow::Result<()> {
+        let client = listing_client(vec![
+            vec![vec![("data/block/00", 100)]],
+            vec![
+                vec![("data/block/00", 100), ("data/block/01", 200)],
+                vec![("data/block/02", 50)],
+            ],
+            vec![vec![("data/block/01", 200), ("data/block/02", 50)]],
+        ]);
+
+        let first = Listing::fetch(&client, "my-bucket", Some("data")).await?;
+        assert_eq!(first.objects.len(), 1);
+
+        let second = Listing::fetch(&client, "my-bucket", Some("data")).await?;
+        let delta = second.since(&first);
+        assert_eq!(
+            delta,
+            Delta {
+                new_objects: 2,
+                removed_objects: 0,
+                count: 3,
+                total_bytes: 350,
+                bytes_added: 250,
+            }
+        );
+        assert_eq!(
+            delta.summary(false),
+            "+2 new, -0 removed; 3 objects, 350 (+250)"
+        );
+
+        let third = Listing::fetch(&client, "my-bucket", Some("data")).await?;
+        assert_eq!(
+            third.since(&second).summary(false),
+            "+0 new, -1 removed; 2 objects, 250 (-100)"
+        );
+        Ok(())
+    }
+}